@@ -0,0 +1,277 @@
+//! Importing Adobe Swatch Exchange (`.ase`) and Photoshop swatch (`.aco`) files. CMYK, Lab, HSB
+//! and grayscale swatches are converted to sRGB, and every color is classified with
+//! [`classify`](palette::classify).
+//!
+//! ```no_run
+//! use mapped::palette::Palette;
+//!
+//! let brand = Palette::load_ase("brand.ase")?;
+//! println!("{brand}: {} colors", brand.len());
+//! # Ok::<(), mapped::palette::PaletteError>(())
+//! ```
+
+use super::palette::{self, Palette, PaletteError, Rgbx};
+use std::path::Path;
+
+const TRUNCATED: PaletteError = PaletteError::InvalidFile("unexpected end of file");
+
+impl Palette {
+    /// Reads an Adobe Swatch Exchange file. The name of the first group, if any, names the
+    /// palette, and swatch names name the colors.
+    pub fn from_ase(bytes: &[u8]) -> Result<Self, PaletteError> {
+        let mut file = Reader(bytes);
+        if file.take(4)? != b"ASEF" {
+            return Err(PaletteError::InvalidFile("missing ASEF signature"));
+        }
+        if file.u16()? != 1 {
+            return Err(PaletteError::InvalidFile("unsupported ASE version"));
+        }
+        file.take(2)?;
+        let (mut name, mut swatches) = (None, Vec::new());
+        for _ in 0..file.u32()? {
+            let kind = file.u16()?;
+            let len = file.u32()? as usize;
+            let mut block = Reader(file.take(len)?);
+            match kind {
+                // Group start
+                0xc001 => {
+                    let group = block.name()?;
+                    name = name.or(Some(group));
+                }
+                // Color entry
+                0x0001 => {
+                    let swatch = block.name()?;
+                    let model = block.take(4)?;
+                    let mut values =
+                        |n| -> Result<Vec<f32>, _> { (0..n).map(|_| block.f32()).collect() };
+                    let [r, g, b, _] = match model {
+                        b"RGB " => rgb(&values(3)?),
+                        b"CMYK" => cmyk(&values(4)?),
+                        b"LAB " => {
+                            let v = values(3)?;
+                            palette::from_lab([v[0] * 100., v[1], v[2]])
+                        }
+                        b"Gray" => rgb(&[values(1)?[0]; 3]),
+                        _ => return Err(PaletteError::InvalidFile("unsupported color model")),
+                    };
+                    swatches.push((r, g, b, swatch));
+                }
+                _ => {}
+            }
+        }
+        Ok(build(name, swatches))
+    }
+
+    pub fn load_ase<P: AsRef<Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_ase(&std::fs::read(path)?)
+    }
+
+    /// Reads a Photoshop color swatch file. Swatch names are only present in version 2 files,
+    /// which start with the unnamed version 1 data; the version 2 section is preferred.
+    pub fn from_aco(bytes: &[u8]) -> Result<Self, PaletteError> {
+        let mut file = Reader(bytes);
+        let mut swatches = Vec::new();
+        while !file.0.is_empty() {
+            let version = file.u16()?;
+            if !(1..=2).contains(&version) {
+                return Err(PaletteError::InvalidFile("unsupported ACO version"));
+            }
+            swatches.clear();
+            for _ in 0..file.u16()? {
+                let space = file.u16()?;
+                let [w, x, y, z] = [file.u16()?, file.u16()?, file.u16()?, file.u16()?];
+                let [r, g, b, _] = match space {
+                    0 => rgb(&[w, x, y].map(|c| c as f32 / 65535.)),
+                    1 => palette::from_hsv([
+                        w as f32 / 65536. * 360.,
+                        x as f32 / 65535.,
+                        y as f32 / 65535.,
+                    ]),
+                    // 0 is full ink
+                    2 => cmyk(&[w, x, y, z].map(|c| 1. - c as f32 / 65535.)),
+                    7 => palette::from_lab([
+                        w as f32 / 100.,
+                        x as i16 as f32 / 100.,
+                        y as i16 as f32 / 100.,
+                    ]),
+                    // 10000 is black
+                    8 => rgb(&[1. - w as f32 / 10000.; 3]),
+                    _ => return Err(PaletteError::InvalidFile("unsupported color space")),
+                };
+                let name = match version {
+                    2 => {
+                        let len = file.u32()? as usize;
+                        file.utf16(len)?
+                    }
+                    _ => String::new(),
+                };
+                swatches.push((r, g, b, name));
+            }
+            if version == 2 {
+                break;
+            }
+        }
+        Ok(build(None, swatches))
+    }
+
+    pub fn load_aco<P: AsRef<Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_aco(&std::fs::read(path)?)
+    }
+}
+
+fn rgb(v: &[f32]) -> [u8; 4] {
+    let [r, g, b] = [v[0], v[1], v[2]].map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+    [r, g, b, 255]
+}
+
+fn cmyk(v: &[f32]) -> [u8; 4] {
+    rgb(&[0, 1, 2].map(|i| (1. - v[i]) * (1. - v[3])))
+}
+
+fn build(name: Option<String>, swatches: Vec<(u8, u8, u8, String)>) -> Palette {
+    let colors: Vec<Rgbx> = swatches
+        .iter()
+        .map(|&(r, g, b, _)| Rgbx(r, g, b, palette::classify(&[r, g, b, 255])))
+        .collect();
+    let palette = match name.filter(|n| !n.is_empty()) {
+        Some(name) => Palette::new(colors).named(name),
+        None => Palette::new(colors),
+    };
+    swatches
+        .into_iter()
+        .enumerate()
+        .filter(|(_, s)| !s.3.is_empty())
+        .fold(palette, |palette, (i, s)| palette.name_color(i, s.3))
+}
+
+// Big-endian cursor over the unread bytes
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PaletteError> {
+        if n > self.0.len() {
+            return Err(TRUNCATED);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, PaletteError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, PaletteError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, PaletteError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /// An ASE name, prefixed with its length.
+    fn name(&mut self) -> Result<String, PaletteError> {
+        let len = self.u16()? as usize;
+        self.utf16(len)
+    }
+
+    /// A string of `len` UTF-16 code units, including the terminating null.
+    fn utf16(&mut self, len: usize) -> Result<String, PaletteError> {
+        let units: Vec<u16> = self
+            .take(len * 2)?
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .take_while(|&u| u != 0)
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{palette::ColorClass, rgbx};
+
+    fn utf16(s: &str) -> Vec<u8> {
+        let units: Vec<u16> = s.encode_utf16().chain([0]).collect();
+        let mut bytes = (units.len() as u16).to_be_bytes().to_vec();
+        bytes.extend(units.iter().flat_map(|u| u.to_be_bytes()));
+        bytes
+    }
+
+    fn block(kind: u16, data: Vec<u8>) -> Vec<u8> {
+        let mut bytes = kind.to_be_bytes().to_vec();
+        bytes.extend((data.len() as u32).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn color(name: &str, model: &[u8; 4], values: &[f32]) -> Vec<u8> {
+        let mut data = utf16(name);
+        data.extend(model);
+        data.extend(values.iter().flat_map(|v| v.to_be_bytes()));
+        data.extend(2u16.to_be_bytes());
+        block(0x0001, data)
+    }
+
+    #[test]
+    fn swatch_exchange() {
+        let mut ase = b"ASEF\0\x01\0\0\0\0\0\x05".to_vec();
+        ase.extend(block(0xc001, utf16("Brand")));
+        ase.extend(color("Ruby", b"RGB ", &[0.75, 0.38, 0.42]));
+        ase.extend(color("", b"CMYK", &[0., 0., 0., 1.]));
+        ase.extend(color("Paper", b"LAB ", &[1., 0., 0.]));
+        ase.extend(block(0xc002, Vec::new()));
+        let palette = Palette::from_ase(&ase).unwrap();
+        assert_eq!(palette.name(), Some("Brand"));
+        assert_eq!(
+            palette.colors(),
+            [
+                rgbx!(191, 97, 107, r),
+                rgbx!(0, 0, 0, gy),
+                rgbx!(255, 255, 255, w)
+            ]
+        );
+        assert_eq!(palette.color_name(0), Some("Ruby"));
+        assert_eq!(palette.color_name(1), None);
+        assert!(Palette::from_ase(&ase[..ase.len() - 3]).is_err());
+        assert!(Palette::from_ase(b"GIMP").is_err());
+    }
+
+    #[test]
+    fn photoshop_swatches() {
+        let entries: [(u16, [u16; 4], &str); 3] = [
+            (0, [0xbfbf, 0x6161, 0x6a6a, 0], "Ruby"),
+            (2, [0xffff, 0xffff, 0xffff, 0], "Black"),
+            (8, [0, 0, 0, 0], "White"),
+        ];
+        let mut aco = Vec::new();
+        for version in [1u16, 2] {
+            aco.extend(version.to_be_bytes());
+            aco.extend((entries.len() as u16).to_be_bytes());
+            for (space, values, name) in entries {
+                aco.extend(space.to_be_bytes());
+                aco.extend(values.iter().flat_map(|v| v.to_be_bytes()));
+                if version == 2 {
+                    let units: Vec<u16> = name.encode_utf16().chain([0]).collect();
+                    aco.extend((units.len() as u32).to_be_bytes());
+                    aco.extend(units.iter().flat_map(|u| u.to_be_bytes()));
+                }
+            }
+        }
+        let palette = Palette::from_aco(&aco).unwrap();
+        assert_eq!(
+            palette.colors(),
+            [
+                rgbx!(191, 97, 106, r),
+                rgbx!(0, 0, 0, gy),
+                rgbx!(255, 255, 255, w)
+            ]
+        );
+        assert_eq!(palette.color_name(2), Some("White"));
+        // Version 1 only, without names
+        let v1 = Palette::from_aco(&aco[..4 + 3 * 10]).unwrap();
+        assert_eq!(v1.colors(), palette.colors());
+        assert_eq!(v1.color_name(0), None);
+    }
+}
@@ -0,0 +1,297 @@
+//! Looking at an image's colors without mapping it: channel histograms and class breakdowns, its
+//! dominant colors, and which of the built-in palettes suits it best.
+//!
+//! ```no_run
+//! use mapped::ProcOptions;
+//!
+//! let wallpaper = ProcOptions::default().load("wallpaper.png")?;
+//! if let Some(best) = wallpaper.suggest_palettes().first() {
+//!     println!("{} ({:.2})", best.palette, best.score());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use super::{
+    palette::{self, ColorClass, Palette, Rgbx},
+    quantize::{MedianCut, Quantizer},
+};
+use ahash::{AHashMap, AHashSet};
+use rayon::prelude::*;
+
+#[cfg(feature = "io")]
+use super::{Mapper, Processor};
+
+/// Share of the pixels a palette color has to be the closest match for to count as used.
+const USED: f32 = 0.01;
+
+/// Color statistics of an image, see [`ColorStats::of`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorStats {
+    /// Pixel counts for each value of the red, green, blue and alpha channels.
+    pub channels: [[usize; 256]; 4],
+    /// Number of distinct RGBA values.
+    pub unique_colors: usize,
+    /// Opaque pixels per [`classify`](palette::classify) class, most common first.
+    pub classes: Vec<(ColorClass, usize)>,
+}
+
+impl ColorStats {
+    /// Collects the statistics of `pixels`. Channels and unique colors count every pixel, while
+    /// mostly transparent pixels are left out of the class breakdown, which is classified per
+    /// 5-bit bin.
+    pub fn of(pixels: &[[u8; 4]]) -> Self {
+        let mut channels = [[0; 256]; 4];
+        let mut unique = AHashSet::new();
+        let mut bins: AHashMap<[u8; 3], usize> = AHashMap::new();
+        for &pixel in pixels {
+            for (channel, &value) in channels.iter_mut().zip(&pixel) {
+                channel[value as usize] += 1;
+            }
+            unique.insert(pixel);
+            if pixel[3] >= 128 {
+                *bins
+                    .entry([pixel[0], pixel[1], pixel[2]].map(|c| c >> 3))
+                    .or_default() += 1;
+            }
+        }
+
+        let bins: Vec<_> = bins.into_iter().collect();
+        let mut classes: AHashMap<ColorClass, usize> = AHashMap::new();
+        for (class, n) in bins
+            .par_iter()
+            .map(|&(bin, n)| {
+                let [r, g, b] = bin.map(|c| c << 3 | 4);
+                (palette::classify(&[r, g, b, 255]), n)
+            })
+            .collect::<Vec<_>>()
+        {
+            *classes.entry(class).or_default() += n;
+        }
+        let mut classes: Vec<_> = classes.into_iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        Self {
+            channels,
+            unique_colors: unique.len(),
+            classes,
+        }
+    }
+
+    /// Total number of pixels counted.
+    pub fn pixels(&self) -> usize {
+        self.channels[0].iter().sum()
+    }
+
+    /// Percentage of the opaque pixels that fall in `class`.
+    pub fn class_share(&self, class: ColorClass) -> f32 {
+        let total = self.classes.iter().map(|(_, n)| n).sum::<usize>().max(1);
+        self.classes
+            .iter()
+            .find(|(c, _)| *c == class)
+            .map_or(0., |(_, n)| *n as f32 / total as f32 * 100.)
+    }
+}
+
+/// A color standing for a group of similar pixels, see [`dominant_colors`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DominantColor {
+    /// The average of the group, with its class from [`classify`](palette::classify).
+    pub color: Rgbx,
+    /// Percentage of the opaque pixels in the group.
+    pub share: f32,
+}
+
+/// The `n` most common colors of an image, grouping similar colors with [`MedianCut`], most
+/// common first.
+#[cfg(feature = "io")]
+pub fn dominant_colors(image: &image::DynamicImage, n: usize) -> Vec<DominantColor> {
+    let pixels: Vec<[u8; 4]> = image.to_rgba8().pixels().map(|p| p.0).collect();
+    dominant_colors_in(&pixels, n)
+}
+
+/// [`dominant_colors`] of raw pixels. Mostly transparent pixels are ignored.
+pub fn dominant_colors_in(pixels: &[[u8; 4]], n: usize) -> Vec<DominantColor> {
+    let palette = MedianCut.quantize(pixels, n);
+    if palette.is_empty() {
+        return Vec::new();
+    }
+    let labs: Vec<[f32; 3]> = palette.iter().map(Rgbx::to_oklab).collect();
+    let mut counts = vec![0; palette.len()];
+    let bins = histogram(pixels);
+    for (lab, count) in &bins {
+        counts[nearest(&labs, lab).0] += count;
+    }
+    let total = bins.iter().map(|(_, n)| n).sum::<usize>().max(1) as f32;
+    let mut colors: Vec<DominantColor> = palette
+        .iter()
+        .zip(counts)
+        .map(|(&color, count)| DominantColor {
+            color,
+            share: count as f32 / total * 100.,
+        })
+        .collect();
+    colors.sort_by(|a, b| b.share.total_cmp(&a.share));
+    colors
+}
+
+/// How well a palette fits an image, see [`rank_palettes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteScore<'a> {
+    pub palette: &'a Palette,
+    /// Average OkLab distance from each pixel to its closest palette color; 0 is a perfect fit
+    /// and 1 is as far as black is from white.
+    pub distance: f32,
+    /// Share of the palette's colors that are the closest match for at least 1% of the pixels.
+    /// Low coverage means the image would collapse onto a few colors of the palette.
+    pub coverage: f32,
+}
+
+impl PaletteScore<'_> {
+    /// Coverage scaled by closeness, 0-1, higher is better.
+    pub fn score(&self) -> f32 {
+        self.coverage * (1. - self.distance).max(0.)
+    }
+}
+
+/// Scores every palette against the colors of `pixels`, best first. Colors are binned to 5 bits
+/// per channel, and mostly transparent pixels are ignored.
+pub fn rank_palettes<'a>(pixels: &[[u8; 4]], palettes: &'a [Palette]) -> Vec<PaletteScore<'a>> {
+    let bins = histogram(pixels);
+    let total = bins.iter().map(|(_, n)| n).sum::<usize>().max(1) as f32;
+    let mut scores: Vec<_> = palettes
+        .iter()
+        .filter(|p| !p.is_empty())
+        .map(|palette| {
+            let labs: Vec<[f32; 3]> = palette.iter().map(Rgbx::to_oklab).collect();
+            let mut hits = vec![0usize; labs.len()];
+            let mut distance = 0.;
+            for (lab, n) in &bins {
+                let (i, d) = nearest(&labs, lab);
+                hits[i] += n;
+                distance += d * *n as f32;
+            }
+            let used = hits.iter().filter(|&&n| n as f32 / total >= USED).count();
+            PaletteScore {
+                palette,
+                distance: distance / total,
+                coverage: used as f32 / labs.len() as f32,
+            }
+        })
+        .collect();
+    scores.sort_by(|a, b| b.score().total_cmp(&a.score()));
+    scores
+}
+
+/// [`rank_palettes`] over the built-in [`registry`](palette::registry).
+pub fn suggest_palettes(pixels: &[[u8; 4]]) -> Vec<PaletteScore<'static>> {
+    rank_palettes(pixels, palette::registry())
+}
+
+// Index of and distance to the closest of `labs`
+fn nearest(labs: &[[f32; 3]], lab: &[f32; 3]) -> (usize, f32) {
+    let (i, d) = labs
+        .iter()
+        .map(|p| (0..3).map(|c| (p[c] - lab[c]).powi(2)).sum::<f32>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+    (i, d.sqrt())
+}
+
+// OkLab coordinates of the center of each occupied 5-bit bin, with its pixel count
+fn histogram(pixels: &[[u8; 4]]) -> Vec<([f32; 3], usize)> {
+    let mut counts: AHashMap<[u8; 3], usize> = AHashMap::new();
+    for &[r, g, b, a] in pixels {
+        if a >= 128 {
+            *counts.entry([r, g, b].map(|c| c >> 3)).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(bin, n)| {
+            let [r, g, b] = bin.map(|c| c << 3 | 4);
+            (palette::oklab(&[r, g, b, 255]), n)
+        })
+        .collect()
+}
+
+#[cfg(feature = "io")]
+impl<'a, M: Mapper> Processor<'a, M> {
+    /// Ranks the built-in palettes by how well they fit the loaded image, see [`rank_palettes`].
+    pub fn suggest_palettes(&self) -> Vec<PaletteScore<'static>> {
+        let pixels: Vec<[u8; 4]> = self.data.to_rgba8().pixels().map(|p| p.0).collect();
+        suggest_palettes(&pixels)
+    }
+
+    /// Channel histograms, unique colors and class breakdown of the loaded image, see
+    /// [`ColorStats::of`].
+    pub fn color_stats(&self) -> ColorStats {
+        let pixels: Vec<[u8; 4]> = self.data.to_rgba8().pixels().map(|p| p.0).collect();
+        ColorStats::of(&pixels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pixels(palette: &Palette) -> Vec<[u8; 4]> {
+        palette.iter().flat_map(|c| [c.rgba_array(); 10]).collect()
+    }
+
+    #[test]
+    fn suggestions() {
+        for name in ["nord", "gruvbox-dark", "dracula"] {
+            let palette = palette::by_name(name).unwrap();
+            let ranked = suggest_palettes(&pixels(palette));
+            assert_eq!(ranked.len(), palette::registry().len());
+            assert_eq!(ranked[0].palette, palette, "{name}");
+            assert!(ranked[0].distance < 0.02);
+            assert!(ranked.windows(2).all(|w| w[0].score() >= w[1].score()));
+        }
+
+        let grey = [[128, 128, 128, 255]; 100];
+        let ranked = rank_palettes(&grey, palette::registry());
+        assert!(ranked.iter().all(|s| s.coverage < 0.1));
+        assert!(rank_palettes(&grey, &[Palette::default()]).is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let mut pixels = vec![[255, 0, 0, 255]; 30];
+        pixels.extend([[250, 2, 3, 255]; 10]);
+        pixels.extend([[0, 0, 0, 255]; 60]);
+        pixels.extend([[0, 0, 0, 0]; 20]);
+        let stats = ColorStats::of(&pixels);
+        assert_eq!(stats.pixels(), 120);
+        assert_eq!(stats.unique_colors, 4);
+        assert_eq!(stats.channels[0][255], 30);
+        assert_eq!(stats.channels[0][0], 80);
+        assert_eq!(stats.channels[3][255], 100);
+        assert_eq!(stats.classes.iter().map(|(_, n)| n).sum::<usize>(), 100);
+        assert_eq!(stats.classes[0].1, 60);
+        assert!(stats.classes.windows(2).all(|w| w[0].1 >= w[1].1));
+        let red = palette::classify(&[255, 0, 0, 255]);
+        assert_eq!(stats.class_share(red), 40.);
+
+        let empty = ColorStats::of(&[]);
+        assert_eq!((empty.pixels(), empty.unique_colors), (0, 0));
+        assert!(empty.classes.is_empty());
+        assert_eq!(empty.class_share(red), 0.);
+    }
+
+    #[test]
+    fn dominant() {
+        let mut pixels = vec![[46, 52, 64, 255]; 60];
+        pixels.extend([[191, 97, 106, 255]; 30]);
+        pixels.extend([[236, 239, 244, 255]; 10]);
+        pixels.extend([[0, 0, 0, 0]; 50]);
+        let colors = dominant_colors_in(&pixels, 3);
+        let shares: Vec<f32> = colors.iter().map(|c| c.share.round()).collect();
+        assert_eq!(shares, [60., 30., 10.]);
+        assert_eq!(colors[1].color.3, palette::ColorClass::Red);
+        assert_eq!(dominant_colors_in(&pixels, 1)[0].share, 100.);
+        assert!(dominant_colors_in(&[], 3).is_empty());
+        assert!(dominant_colors_in(&pixels, 0).is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use super::{palette::Palette, Mapper, ProcessedData, Processor};
+use image::{imageops, Rgba, RgbaImage};
+
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const FOREGROUND: Rgba<u8> = Rgba([236, 236, 236, 255]);
+const GAP: u32 = 8;
+const GLYPH_W: u32 = 5;
+const GLYPH_H: u32 = 7;
+
+/// Lays the given images out in a grid with `columns` cells per row, each one captioned with its
+/// label. Cells are sized after the largest image; labels are rendered in uppercase.
+pub fn grid(cells: &[(&str, &ProcessedData)], columns: usize) -> ProcessedData {
+    let columns = columns.clamp(1, cells.len().max(1)) as u32;
+    let rows = (cells.len() as u32).div_ceil(columns);
+    let cell_w = cells.iter().map(|(_, d)| d.width()).max().unwrap_or(0);
+    let cell_h = cells.iter().map(|(_, d)| d.height()).max().unwrap_or(0);
+    let scale = (cell_w / 160).clamp(1, 8);
+    let label_h = (GLYPH_H + 4) * scale;
+
+    let width = columns * (cell_w + GAP) + GAP;
+    let height = rows * (cell_h + label_h + GAP) + GAP;
+    let mut canvas = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+    for (i, (label, data)) in cells.iter().enumerate() {
+        let x = GAP + (i as u32 % columns) * (cell_w + GAP);
+        let y = GAP + (i as u32 / columns) * (cell_h + label_h + GAP);
+        draw_text(&mut canvas, label, (x, y + 2 * scale), cell_w, scale);
+        imageops::replace(
+            &mut canvas,
+            &data.to_image(),
+            x as i64,
+            (y + label_h) as i64,
+        );
+    }
+
+    canvas.into()
+}
+
+impl<'a, M: Mapper> Processor<'a, M> {
+    /// Maps the image against every palette and renders the results into a labeled grid.
+    pub fn compare_palettes(
+        &mut self,
+        palettes: &[(&str, &Palette)],
+        columns: usize,
+    ) -> ProcessedData {
+        let results = self.process_multi(&palettes.iter().map(|(_, p)| *p).collect::<Vec<_>>());
+        let cells: Vec<_> = palettes
+            .iter()
+            .zip(results.iter())
+            .map(|((label, _), data)| (*label, data))
+            .collect();
+        grid(&cells, columns)
+    }
+}
+
+fn draw_text(canvas: &mut RgbaImage, text: &str, (x, y): (u32, u32), max_w: u32, scale: u32) {
+    let advance = (GLYPH_W + 1) * scale;
+    let fits = (max_w + scale) / advance;
+    for (i, c) in text.chars().take(fits as usize).enumerate() {
+        let gx = x + i as u32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_W {
+                if bits & (1 << (GLYPH_W - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (px, py) = (gx + col * scale + dx, y + row as u32 * scale + dy);
+                        if px < canvas.width() && py < canvas.height() {
+                            canvas.put_pixel(px, py, FOREGROUND);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00; 7],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
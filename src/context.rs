@@ -0,0 +1,120 @@
+//! What mappers that look beyond a single pixel get to see.
+
+use std::any::Any;
+
+/// Per-image state computed by [`Mapper::prepare`](crate::Mapper::prepare) and handed back
+/// through [`PixelContext::prepared`]. Keeping it out of the mapper lets one mapper process
+/// several images at once.
+pub type Prepared = Box<dyn Any + Send + Sync>;
+
+/// A pixel together with its position and read access to the rest of the image, handed to
+/// [`Mapper::predict_context`](crate::Mapper::predict_context).
+#[derive(Debug, Clone, Copy)]
+pub struct PixelContext<'a> {
+    pixels: &'a [[u8; 4]],
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    pixel: Option<[u8; 4]>,
+    prepared: Option<&'a (dyn Any + Send + Sync)>,
+}
+
+impl<'a> PixelContext<'a> {
+    /// `pixels` holds the whole image in row-major order.
+    pub fn new(pixels: &'a [[u8; 4]], (width, height): (u32, u32), (x, y): (u32, u32)) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        assert!(x < width && y < height);
+        PixelContext {
+            pixels,
+            width,
+            height,
+            x,
+            y,
+            pixel: None,
+            prepared: None,
+        }
+    }
+
+    /// This context with `pixel` in place of the one at its position, for wrappers handing on a
+    /// pixel they changed. The rest of the image is left as is.
+    #[must_use]
+    pub fn with_pixel(&self, pixel: [u8; 4]) -> Self {
+        PixelContext {
+            pixel: Some(pixel),
+            ..*self
+        }
+    }
+
+    /// This context carrying `prepared` instead, for wrappers handing the pixel on to a mapper
+    /// with its own state.
+    #[must_use]
+    pub fn with_prepared(&self, prepared: Option<&'a (dyn Any + Send + Sync)>) -> Self {
+        PixelContext { prepared, ..*self }
+    }
+
+    /// What the mapper's [`prepare`](crate::Mapper::prepare) returned for this image, if it was
+    /// prepared and the state is a `T`.
+    pub fn prepared<T: Any>(&self) -> Option<&'a T> {
+        self.prepared?.downcast_ref()
+    }
+
+    pub fn pixel(&self) -> &[u8; 4] {
+        match &self.pixel {
+            Some(pixel) => pixel,
+            None => &self.pixels[self.y as usize * self.width as usize + self.x as usize],
+        }
+    }
+
+    pub const fn position(&self) -> (u32, u32) {
+        (self.x, self.y)
+    }
+
+    pub const fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn at(&self, x: u32, y: u32) -> Option<&'a [u8; 4]> {
+        (x < self.width && y < self.height)
+            .then(|| &self.pixels[y as usize * self.width as usize + x as usize])
+    }
+
+    /// The pixel offset by `(dx, dy)` from this one, or `None` past the edges of the image.
+    pub fn neighbor(&self, dx: i32, dy: i32) -> Option<&'a [u8; 4]> {
+        let x = self.x.checked_add_signed(dx)?;
+        let y = self.y.checked_add_signed(dy)?;
+        self.at(x, y)
+    }
+
+    /// Pixels within `radius` of this one in both directions, including itself, clipped to the
+    /// image.
+    pub fn neighborhood(&self, radius: u32) -> impl Iterator<Item = &'a [u8; 4]> + '_ {
+        let xs = self.x.saturating_sub(radius)..=(self.x + radius).min(self.width - 1);
+        let ys = self.y.saturating_sub(radius)..=(self.y + radius).min(self.height - 1);
+        ys.flat_map(move |y| xs.clone().filter_map(move |x| self.at(x, y)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn neighbors() {
+        let pixels: Vec<[u8; 4]> = (0..12).map(|i| [i, 0, 0, 255]).collect();
+        let ctx = PixelContext::new(&pixels, (4, 3), (0, 1));
+        assert_eq!(ctx.pixel()[0], 4);
+        assert_eq!(ctx.neighbor(1, 1).map(|p| p[0]), Some(9));
+        assert_eq!(ctx.neighbor(-1, 0), None);
+        assert_eq!(ctx.neighborhood(1).count(), 6);
+        let changed = ctx.with_pixel([99, 0, 0, 255]);
+        assert_eq!(changed.pixel()[0], 99);
+        assert_eq!(changed.at(0, 1).map(|p| p[0]), Some(4));
+
+        let state: Prepared = Box::new(7u8);
+        assert_eq!(ctx.prepared::<u8>(), None);
+        let ctx = ctx.with_prepared(Some(state.as_ref()));
+        assert_eq!(ctx.prepared::<u8>(), Some(&7));
+        assert_eq!(ctx.prepared::<u16>(), None);
+    }
+}
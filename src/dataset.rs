@@ -0,0 +1,539 @@
+//! Building labelled training sets for [`Knn`](crate::mappers::Knn) from gradients, CSV files and
+//! swatch images, as an alternative to the bundled [`SYN_DATA_SET`](crate::palette::SYN_DATA_SET).
+//!
+//! CSV rows hold a color and its [`ColorClass`], either as `r,g,b,class` or `#rrggbb,class`.
+//! Blank lines, lines starting with `//` and a leading `r,g,b,class` style header are skipped.
+//!
+//! ```text
+//! 255,209,220,red
+//! #c1e1c1,green
+//! ```
+
+use super::palette::{self, ColorClass, Rgbx};
+use std::{error::Error, fmt, path::Path, str::FromStr};
+
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(std::io::Error),
+    #[cfg(feature = "io")]
+    Image(image::ImageError),
+    /// A CSV row, by 1-based line number, that isn't a color followed by a class.
+    Malformed(usize),
+    UnknownClass(String),
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read dataset: {e}"),
+            #[cfg(feature = "io")]
+            Self::Image(e) => write!(f, "failed to read swatch: {e}"),
+            Self::Malformed(line) => write!(f, "line {line} is not a color followed by a class"),
+            Self::UnknownClass(name) => write!(f, "unknown color class `{name}`"),
+        }
+    }
+}
+
+impl Error for DatasetError {}
+
+impl From<std::io::Error> for DatasetError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "io")]
+impl From<image::ImageError> for DatasetError {
+    fn from(e: image::ImageError) -> Self {
+        Self::Image(e)
+    }
+}
+
+// Default bound on either side of a swatch image
+#[cfg(feature = "io")]
+const SWATCH_MAX_SIDE: u32 = 8192;
+
+/// Collects labelled colors from several sources into one deduplicated dataset.
+///
+/// ```no_run
+/// use mapped::{dataset::DatasetBuilder, mappers::Knn};
+///
+/// let dataset = DatasetBuilder::new()
+///     .csv_file("pastels.csv")?
+///     .swatch_dir("swatches")?
+///     .build();
+/// let knn = Knn::trained_on(&dataset);
+/// # Ok::<(), mapped::dataset::DatasetError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DatasetBuilder {
+    colors: Vec<Rgbx>,
+    samples: usize,
+    step: u8,
+    dedup: bool,
+    #[cfg(feature = "io")]
+    swatch_limits: crate::Limits,
+}
+
+impl Default for DatasetBuilder {
+    fn default() -> Self {
+        DatasetBuilder {
+            colors: Vec::new(),
+            samples: 64,
+            step: 5,
+            dedup: true,
+            #[cfg(feature = "io")]
+            swatch_limits: crate::Limits::default()
+                .max_width(SWATCH_MAX_SIDE)
+                .max_height(SWATCH_MAX_SIDE),
+        }
+    }
+}
+
+impl DatasetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of colors taken from each swatch image, 64 by default. Pixels are picked at
+    /// evenly spaced positions, so the sample is reproducible.
+    #[must_use]
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Channel step between the colors of each following [`DatasetBuilder::gradient`], 5 by
+    /// default.
+    #[must_use]
+    pub fn step(mut self, step: u8) -> Self {
+        self.step = step.max(1);
+        self
+    }
+
+    /// Adds the colors stepping from `start` to `end`, both included. Every color after `start`
+    /// takes the class of `end`.
+    #[must_use]
+    pub fn gradient(mut self, start: Rgbx, end: Rgbx) -> Self {
+        self.colors.extend(start.gradient(&end, self.step));
+        self
+    }
+
+    /// Whether [`DatasetBuilder::build`] drops repeated colors. On by default.
+    #[must_use]
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    #[must_use]
+    pub fn color(mut self, color: Rgbx) -> Self {
+        self.colors.push(color);
+        self
+    }
+
+    #[must_use]
+    pub fn colors(mut self, colors: impl IntoIterator<Item = Rgbx>) -> Self {
+        self.colors.extend(colors);
+        self
+    }
+
+    pub fn csv(mut self, csv: &str) -> Result<Self, DatasetError> {
+        for (i, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (rgb, class) = match fields.as_slice() {
+                [hex, class] if hex.starts_with('#') => (palette::parse_hex(hex), *class),
+                [r, g, b, class] => (
+                    r.parse()
+                        .ok()
+                        .zip(g.parse().ok())
+                        .zip(b.parse().ok())
+                        .map(|((r, g), b)| [r, g, b]),
+                    *class,
+                ),
+                _ => (None, ""),
+            };
+            let Some([r, g, b]) = rgb else {
+                if i == 0
+                    && fields
+                        .last()
+                        .is_some_and(|f| f.eq_ignore_ascii_case("class"))
+                {
+                    continue;
+                }
+                return Err(DatasetError::Malformed(i + 1));
+            };
+            let class = ColorClass::from_str(class)
+                .map_err(|_| DatasetError::UnknownClass(class.to_owned()))?;
+            self.colors.push(Rgbx(r, g, b, class));
+        }
+        Ok(self)
+    }
+
+    pub fn csv_file<P: AsRef<Path>>(self, path: P) -> Result<Self, DatasetError> {
+        let csv = std::fs::read_to_string(path)?;
+        self.csv(&csv)
+    }
+
+    /// Samples the opaque pixels of a swatch image, labelling all of them `class`.
+    #[cfg(feature = "io")]
+    #[must_use]
+    pub fn swatch(mut self, swatch: &image::RgbaImage, class: ColorClass) -> Self {
+        let opaque: Vec<&image::Rgba<u8>> = swatch.pixels().filter(|p| p[3] != 0).collect();
+        let step = opaque.len().div_ceil(self.samples).max(1);
+        self.colors.extend(
+            opaque
+                .into_iter()
+                .step_by(step)
+                .map(|p| Rgbx(p[0], p[1], p[2], class)),
+        );
+        self
+    }
+
+    /// Bounds on the images read by [`DatasetBuilder::swatch_file`], 8192 pixels on either side
+    /// by default. Larger images are rejected before being decoded.
+    #[cfg(feature = "io")]
+    #[must_use]
+    pub fn swatch_limits(mut self, limits: crate::Limits) -> Self {
+        self.swatch_limits = limits;
+        self
+    }
+
+    /// Like [`DatasetBuilder::swatch`], with the class taken from the leading letters of the file
+    /// name, e.g. `greens.png` or `red-dark.png`. Class names may be singular or plural.
+    #[cfg(feature = "io")]
+    pub fn swatch_file<P: AsRef<Path>>(self, path: P) -> Result<Self, DatasetError> {
+        use image::error::{ImageError, LimitError, LimitErrorKind};
+
+        let stem = path
+            .as_ref()
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let name = stem
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .next()
+            .unwrap_or_default();
+        let singular = name.strip_suffix(['s', 'S']).unwrap_or(name);
+        let class = [name, singular, &format!("{singular}s")]
+            .into_iter()
+            .find_map(|name| ColorClass::from_str(name).ok())
+            .ok_or_else(|| DatasetError::UnknownClass(stem.to_owned()))?;
+        let (w, h) = image::image_dimensions(path.as_ref())?;
+        if !self.swatch_limits.allows(w, h) {
+            let kind = LimitErrorKind::DimensionError;
+            return Err(ImageError::Limits(LimitError::from_kind(kind)).into());
+        }
+        let swatch = image::open(path.as_ref())?.into_rgba8();
+        Ok(self.swatch(&swatch, class))
+    }
+
+    /// Adds every file in `dir` with [`DatasetBuilder::swatch_file`], in file name order.
+    #[cfg(feature = "io")]
+    pub fn swatch_dir<P: AsRef<Path>>(mut self, dir: P) -> Result<Self, DatasetError> {
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        for path in paths.into_iter().filter(|p| p.is_file()) {
+            self = self.swatch_file(path)?;
+        }
+        Ok(self)
+    }
+
+    /// Adds augmented variants of the colors collected so far, see [`Augment`].
+    #[must_use]
+    pub fn augment(mut self, augment: &Augment) -> Self {
+        let variants = augment.apply(&self.colors);
+        self.colors.extend(variants);
+        self
+    }
+
+    /// Returns the collected colors, with duplicates removed unless turned off with
+    /// [`DatasetBuilder::dedup`]. When the same color was given more than one class, the first
+    /// one wins.
+    pub fn build(self) -> Vec<Rgbx> {
+        if !self.dedup {
+            return self.colors;
+        }
+        let mut seen = ahash::AHashSet::new();
+        self.colors
+            .into_iter()
+            .filter(|c| seen.insert([c.0, c.1, c.2]))
+            .collect()
+    }
+
+    /// Writes the built dataset to `path` as a Rust array expression, for embedding with
+    /// `include!` like [`SYN_DATA_SET`](crate::palette::SYN_DATA_SET).
+    pub fn write<P: AsRef<Path>>(self, path: P) -> Result<(), DatasetError> {
+        Ok(std::fs::write(path, format!("{:?}", self.build()))?)
+    }
+}
+
+/// Derives extra training colors from existing ones by scaling their HSV saturation and value and
+/// jittering their hue, so straight-line gradients also cover the duller and darker colors around
+/// them. Whites and greys are left alone, since their classes depend on exactly those channels.
+///
+/// ```
+/// use mapped::{dataset::{Augment, DatasetBuilder}, mappers::Knn, palette::SYN_DATA_SET};
+///
+/// let dataset = DatasetBuilder::new()
+///     .colors(SYN_DATA_SET)
+///     .augment(&Augment::new().value(&[0.6]).saturation(&[0.6]).hue_jitter(8., 1))
+///     .build();
+/// let knn = Knn::trained_on(&dataset);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Augment {
+    value: Vec<f32>,
+    saturation: Vec<f32>,
+    hue_jitter: f32,
+    copies: usize,
+    seed: u64,
+}
+
+impl Augment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one variant per factor with the HSV value scaled by it.
+    #[must_use]
+    pub fn value(mut self, factors: &[f32]) -> Self {
+        self.value = factors.to_vec();
+        self
+    }
+
+    /// Adds one variant per factor with the HSV saturation scaled by it.
+    #[must_use]
+    pub fn saturation(mut self, factors: &[f32]) -> Self {
+        self.saturation = factors.to_vec();
+        self
+    }
+
+    /// Adds `copies` variants with the hue shifted by up to `degrees` either way.
+    #[must_use]
+    pub fn hue_jitter(mut self, degrees: f32, copies: usize) -> Self {
+        self.hue_jitter = degrees.abs();
+        self.copies = copies;
+        self
+    }
+
+    /// Seed for the hue jitter, 0 by default.
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Variants of `colors`, without the originals.
+    pub fn apply(&self, colors: &[Rgbx]) -> Vec<Rgbx> {
+        let rng = fastrand::Rng::with_seed(self.seed);
+        let mut variants = Vec::new();
+        for c in colors {
+            if matches!(c.3, ColorClass::Whites | ColorClass::Greys) {
+                continue;
+            }
+            let [hue, sat, val] = palette::hsv(&c.rgba_array());
+            let value = self.value.iter().map(|f| [hue, sat, (val * f).min(1.)]);
+            let saturation = self
+                .saturation
+                .iter()
+                .map(|f| [hue, (sat * f).min(1.), val]);
+            let jitter =
+                (0..self.copies).map(|_| [hue + (rng.f32() * 2. - 1.) * self.hue_jitter, sat, val]);
+            variants.extend(value.chain(saturation).chain(jitter).map(|hsv| {
+                let [r, g, b, _] = palette::from_hsv(hsv);
+                Rgbx(r, g, b, c.3)
+            }));
+        }
+        variants
+    }
+}
+
+/// Renders a dataset as a grid of `cell`-sized swatches for reviewing labels: one block of rows
+/// per class, in class order, holding the class's colors in dataset order, 32 to a row. Blocks
+/// are separated by a transparent gap.
+#[cfg(feature = "io")]
+pub fn swatch_grid(dataset: &[Rgbx], cell: u32) -> image::RgbaImage {
+    const COLUMNS: usize = 32;
+
+    let mut classes: Vec<ColorClass> = dataset.iter().map(|c| c.3).collect();
+    classes.sort();
+    classes.dedup();
+    let groups: Vec<Vec<&Rgbx>> = classes
+        .iter()
+        .map(|class| dataset.iter().filter(|c| c.3 == *class).collect())
+        .collect();
+    let gap = cell / 2;
+    let rows: usize = groups.iter().map(|g| g.len().div_ceil(COLUMNS)).sum();
+    let width = dataset.len().min(COLUMNS) as u32 * cell;
+    let height = rows as u32 * cell + groups.len().saturating_sub(1) as u32 * gap;
+    let mut grid = image::RgbaImage::new(width, height);
+    let mut top = 0;
+    for group in groups {
+        for (i, c) in group.iter().enumerate() {
+            let (x, y) = (
+                (i % COLUMNS) as u32 * cell,
+                top + (i / COLUMNS) as u32 * cell,
+            );
+            for (dx, dy) in (0..cell).flat_map(|dx| (0..cell).map(move |dy| (dx, dy))) {
+                grid.put_pixel(x + dx, y + dy, image::Rgba(c.rgba_array()));
+            }
+        }
+        top += group.len().div_ceil(COLUMNS) as u32 * cell + gap;
+    }
+    grid
+}
+
+/// Saves [`swatch_grid`] with 16 pixel swatches, in the format given by the extension of `path`.
+///
+/// ```no_run
+/// mapped::dataset::save_swatch_grid(&mapped::palette::SYN_DATA_SET, "dataset.png")?;
+/// # Ok::<(), mapped::dataset::DatasetError>(())
+/// ```
+#[cfg(feature = "io")]
+pub fn save_swatch_grid<P: AsRef<Path>>(dataset: &[Rgbx], path: P) -> Result<(), DatasetError> {
+    Ok(swatch_grid(dataset, 16).save(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ColorClass::{Green, Red, Whites};
+
+    #[test]
+    fn csv_rows() {
+        let dataset = DatasetBuilder::new()
+            .csv("r,g,b,class\n255, 209, 220, red\n\n// pastel green\n#C1E1C1,Green\n255,209,220,green")
+            .unwrap()
+            .build();
+        assert_eq!(
+            dataset,
+            [Rgbx(255, 209, 220, Red), Rgbx(193, 225, 193, Green)]
+        );
+        assert!(matches!(
+            DatasetBuilder::new().csv("1,2,3,red\n1,2,red"),
+            Err(DatasetError::Malformed(2))
+        ));
+        assert!(matches!(
+            DatasetBuilder::new().csv("#ffffff,teal"),
+            Err(DatasetError::UnknownClass(_))
+        ));
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn swatch_sampling() {
+        let swatch = image::RgbaImage::from_fn(20, 10, |x, y| {
+            image::Rgba([x as u8 * 10, y as u8 * 10, 0, if x < 10 { 255 } else { 0 }])
+        });
+        let dataset = DatasetBuilder::new()
+            .samples(25)
+            .swatch(&swatch, Red)
+            .swatch(&swatch, Green)
+            .build();
+        assert_eq!(dataset.len(), 25);
+        assert!(dataset.iter().all(|c| c.0 < 100 && c.3 == Red));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn swatch_files() {
+        let dir = std::env::temp_dir().join(format!("mapped-swatches-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let swatch = image::RgbaImage::from_pixel(4, 4, image::Rgba([40, 200, 40, 255]));
+        for name in ["greens.png", "blue-light.png", "teals.png"] {
+            swatch.save(dir.join(name)).unwrap();
+        }
+        let builder = DatasetBuilder::new();
+        let greens = builder.clone().swatch_file(dir.join("greens.png")).unwrap();
+        assert!(greens.build().iter().all(|c| c.3 == Green));
+        let blues = builder
+            .clone()
+            .swatch_file(dir.join("blue-light.png"))
+            .unwrap();
+        assert!(blues.build().iter().all(|c| c.3 == ColorClass::Blues));
+        assert!(matches!(
+            builder.clone().swatch_file(dir.join("teals.png")),
+            Err(DatasetError::UnknownClass(_))
+        ));
+        let limited = builder.swatch_limits(crate::Limits::default().max_pixels(8));
+        assert!(matches!(
+            limited.swatch_file(dir.join("greens.png")),
+            Err(DatasetError::Image(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn grid_layout() {
+        let mut dataset = vec![Rgbx(0, 0, 255, ColorClass::Blues)];
+        dataset.extend((0..40).map(|i| Rgbx(200, i, 0, Red)));
+        dataset.push(Rgbx(250, 250, 250, Whites));
+        let grid = swatch_grid(&dataset, 4);
+        // Blues, a gap, whites, a gap, two rows of reds
+        assert_eq!(grid.dimensions(), (32 * 4, 4 * 4 + 2 * 2));
+        assert_eq!(grid.get_pixel(1, 1).0, [0, 0, 255, 255]);
+        assert_eq!(grid.get_pixel(1, 5).0, [0, 0, 0, 0]);
+        assert_eq!(grid.get_pixel(1, 7).0, [250, 250, 250, 255]);
+        assert_eq!(grid.get_pixel(4 + 1, 12 + 4 + 1).0, [200, 33, 0, 255]);
+    }
+
+    #[test]
+    fn gradients() {
+        let dataset = DatasetBuilder::new()
+            .step(100)
+            .gradient(Rgbx(0, 0, 0, Red), Rgbx(250, 0, 0, Red))
+            .step(250)
+            .gradient(Rgbx(0, 0, 0, Green), Rgbx(0, 250, 0, Green));
+        assert_eq!(
+            dataset.clone().dedup(false).build(),
+            [
+                Rgbx(0, 0, 0, Red),
+                Rgbx(100, 0, 0, Red),
+                Rgbx(200, 0, 0, Red),
+                Rgbx(250, 0, 0, Red),
+                Rgbx(0, 0, 0, Green),
+                Rgbx(0, 250, 0, Green),
+            ]
+        );
+        assert_eq!(dataset.build().len(), 5);
+    }
+
+    #[test]
+    fn augmentation() {
+        use crate::{
+            mappers::{evaluate, Knn},
+            palette::{DATA_SET, SYN_DATA_SET},
+        };
+
+        let augment = Augment::new()
+            .value(&[0.5])
+            .saturation(&[0.5, 0.8])
+            .hue_jitter(8., 2);
+        let variants = augment.apply(&[Rgbx(200, 40, 40, Red), Rgbx(250, 250, 250, Whites)]);
+        assert_eq!(variants.len(), 5);
+        assert_eq!(variants[0], Rgbx(100, 20, 20, Red));
+        assert_eq!(variants, augment.apply(&[Rgbx(200, 40, 40, Red)]));
+
+        let plain = Knn::builder().k(12).seed(1).build();
+        let augmented = Knn::builder()
+            .k(12)
+            .seed(1)
+            .dataset(
+                DatasetBuilder::new()
+                    .colors(SYN_DATA_SET)
+                    .augment(&augment)
+                    .build(),
+            )
+            .build();
+        let accuracy = |knn| evaluate(&knn, &DATA_SET).accuracy();
+        assert!(accuracy(augmented) > accuracy(plain) + 5.);
+    }
+}
@@ -0,0 +1,165 @@
+use super::palette;
+use std::fmt;
+
+/// Distance metric used by [`Nearest`](crate::mappers::Nearest) to rank palette colors.
+///
+/// Colors are first converted to [`ColorDistance::Coords`], which lets expensive conversions
+/// (e.g. to CIELAB) be done once per palette instead of once per comparison.
+pub trait ColorDistance: fmt::Debug + Clone + Send + Sync {
+    type Coords: Clone + Send + Sync;
+    /// Whether palette coordinates should be computed once and cached by the mapper. Worth it
+    /// only when [`ColorDistance::coords`] does more than copy the pixel.
+    const CACHED: bool = false;
+
+    fn coords(&self, pixel: &[u8; 4]) -> Self::Coords;
+    fn distance(&self, a: &Self::Coords, b: &Self::Coords) -> f32;
+}
+
+/// Sum of absolute RGB channel differences.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Manhattan;
+
+impl ColorDistance for Manhattan {
+    type Coords = [u8; 4];
+
+    fn coords(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        *pixel
+    }
+
+    fn distance(&self, a: &[u8; 4], b: &[u8; 4]) -> f32 {
+        (0..3).map(|i| a[i].abs_diff(b[i]) as u16).sum::<u16>() as f32
+    }
+}
+
+/// Straight-line distance in RGB space.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Euclidean;
+
+impl ColorDistance for Euclidean {
+    type Coords = [u8; 4];
+
+    fn coords(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        *pixel
+    }
+
+    fn distance(&self, a: &[u8; 4], b: &[u8; 4]) -> f32 {
+        (0..3)
+            .map(|i| (a[i].abs_diff(b[i]) as f32).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+/// Euclidean RGB distance with per-channel weights, e.g. [`Weighted::LUMA`] to bias matching
+/// towards luminance.
+#[derive(Debug, Clone, Copy)]
+pub struct Weighted {
+    weights: [f32; 3],
+}
+
+impl Weighted {
+    /// Rec. 601 luma coefficients.
+    pub const LUMA: Weighted = Weighted::new(0.299, 0.587, 0.114);
+
+    pub const fn new(red: f32, green: f32, blue: f32) -> Self {
+        Weighted {
+            weights: [red, green, blue],
+        }
+    }
+}
+
+impl Default for Weighted {
+    fn default() -> Self {
+        Weighted::new(1., 1., 1.)
+    }
+}
+
+impl ColorDistance for Weighted {
+    type Coords = [u8; 4];
+
+    fn coords(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        *pixel
+    }
+
+    // Squared, ranking is all that matters
+    fn distance(&self, a: &[u8; 4], b: &[u8; 4]) -> f32 {
+        (0..3)
+            .map(|i| self.weights[i] * (a[i].abs_diff(b[i]) as f32).powi(2))
+            .sum()
+    }
+}
+
+/// "Redmean" approximation of perceptual distance: Euclidean RGB distance with the red and blue
+/// weights shifted by the mean red level. Integer-only and nearly as cheap as [`Manhattan`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Redmean;
+
+impl ColorDistance for Redmean {
+    type Coords = [u8; 4];
+
+    fn coords(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        *pixel
+    }
+
+    // Squared, ranking is all that matters
+    fn distance(&self, a: &[u8; 4], b: &[u8; 4]) -> f32 {
+        let rmean = (a[0] as u32 + b[0] as u32) / 2;
+        let [dr, dg, db] = [0, 1, 2].map(|i| a[i].abs_diff(b[i]) as u32);
+        let d = (((512 + rmean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - rmean) * db * db) >> 8);
+        d as f32
+    }
+}
+
+/// Euclidean distance in CIELAB space.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cie76;
+
+impl ColorDistance for Cie76 {
+    type Coords = [f32; 3];
+    const CACHED: bool = true;
+
+    fn coords(&self, pixel: &[u8; 4]) -> [f32; 3] {
+        palette::lab(pixel)
+    }
+
+    fn distance(&self, a: &[f32; 3], b: &[f32; 3]) -> f32 {
+        palette::cie76(a, b)
+    }
+}
+
+/// CIEDE2000 difference in CIELAB space. Picks perceptually closer matches than RGB distances for
+/// skin tones and desaturated colors, but is considerably slower than [`Cie76`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ciede2000;
+
+impl ColorDistance for Ciede2000 {
+    type Coords = [f32; 3];
+    const CACHED: bool = true;
+
+    fn coords(&self, pixel: &[u8; 4]) -> [f32; 3] {
+        palette::lab(pixel)
+    }
+
+    fn distance(&self, a: &[f32; 3], b: &[f32; 3]) -> f32 {
+        palette::ciede2000(a, b)
+    }
+}
+
+/// Euclidean distance in OkLab space. Much cheaper than [`Ciede2000`], and still far more
+/// perceptually uniform than RGB distances.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Oklab;
+
+impl ColorDistance for Oklab {
+    type Coords = [f32; 3];
+    const CACHED: bool = true;
+
+    fn coords(&self, pixel: &[u8; 4]) -> [f32; 3] {
+        palette::oklab(pixel)
+    }
+
+    // Squared, ranking is all that matters
+    fn distance(&self, a: &[f32; 3], b: &[f32; 3]) -> f32 {
+        (0..3).map(|i| (a[i] - b[i]).powi(2)).sum()
+    }
+}
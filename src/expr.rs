@@ -0,0 +1,584 @@
+//! A small expression language for describing mapping rules, e.g.
+//! `if sat < 0.1 then nearest_in(Greys, Whites) else knn()`.
+//!
+//! Expressions are parsed and compiled into closures once, so evaluating a rule per pixel costs
+//! little more than the mapper calls it ends up making.
+//!
+//! Available variables are `r`, `g`, `b`, `a` (0-255), `hue` (0-360), `sat`, `val` and `luma`
+//! (0-1). Numbers support `+ - * /`, comparisons `< <= > >= == !=` and the logical operators
+//! `and`, `or`, `not`. Rules evaluate to one of the actions `nearest()`, `nearest_in(Class, ..)`,
+//! `knn()`/`knn(k)`, `creative()`, `double_pass()`, `palette(i)`, `rgb(r, g, b)` or `keep()`,
+//! optionally selected with `if .. then .. else ..`.
+
+use super::{
+    mappers::{Creative, Knn, Nearest, NearestDoublePass},
+    palette::{self, ColorClass, Rgbx},
+    Mapper,
+};
+use std::{error::Error, fmt, str::FromStr, sync::Arc};
+
+type Num = Box<dyn Fn(&Vars) -> f32 + Send + Sync>;
+type Cond = Box<dyn Fn(&Vars) -> bool + Send + Sync>;
+type Action = Box<dyn Fn(&[Rgbx], &Vars) -> [u8; 4] + Send + Sync>;
+
+#[derive(Clone)]
+pub struct ExprMapper {
+    source: String,
+    rule: Arc<Action>,
+}
+
+impl ExprMapper {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.len(),
+        };
+        let node = parser.expr()?;
+        if let Some((pos, tok)) = parser.tokens.get(parser.pos) {
+            return Err(ExprError::new(*pos, format!("unexpected `{tok}`")));
+        }
+
+        Ok(ExprMapper {
+            source: source.to_owned(),
+            rule: Arc::new(action(node)?),
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl FromStr for ExprMapper {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Debug for ExprMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ExprMapper").field(&self.source).finish()
+    }
+}
+
+impl Mapper for ExprMapper {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        (self.rule)(palette, &Vars::new(pixel))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    pub pos: usize,
+    pub msg: String,
+}
+
+impl ExprError {
+    fn new(pos: usize, msg: impl Into<String>) -> Self {
+        ExprError {
+            pos,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.msg, self.pos)
+    }
+}
+
+impl Error for ExprError {}
+
+struct Vars {
+    px: [u8; 4],
+    hsv: [f32; 3],
+}
+
+impl Vars {
+    fn new(px: &[u8; 4]) -> Self {
+        Vars {
+            px: *px,
+            hsv: palette::hsv(px),
+        }
+    }
+
+    fn luma(&self) -> f32 {
+        palette::luma(&self.px)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(f32),
+    Ident(String),
+    Sym(&'static str),
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Num(n) => write!(f, "{n}"),
+            Tok::Ident(s) => write!(f, "{s}"),
+            Tok::Sym(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 13] = [
+    "<=", ">=", "==", "!=", "<", ">", "(", ")", ",", "+", "-", "*", "/",
+];
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Tok)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(c) = src[i..].chars().next() {
+        if c.is_whitespace() {
+            i += c.len_utf8();
+        } else if c.is_ascii_digit() || c == '.' {
+            let len = src[i..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(src.len() - i);
+            let n = src[i..i + len]
+                .parse()
+                .map_err(|_| ExprError::new(i, "invalid number"))?;
+            tokens.push((i, Tok::Num(n)));
+            i += len;
+        } else if c.is_alphabetic() || c == '_' {
+            let len = src[i..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(src.len() - i);
+            tokens.push((i, Tok::Ident(src[i..i + len].to_owned())));
+            i += len;
+        } else if let Some(sym) = SYMBOLS.iter().find(|s| src[i..].starts_with(**s)) {
+            tokens.push((i, Tok::Sym(sym)));
+            i += sym.len();
+        } else {
+            return Err(ExprError::new(i, format!("unexpected character `{c}`")));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Node {
+    Num(f32),
+    Ident(usize, String),
+    Neg(Box<Node>),
+    Arith(&'static str, Box<Node>, Box<Node>),
+    Cmp(usize, &'static str, Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    If(Box<Node>, Box<Node>, Box<Node>),
+    Call(usize, String, Vec<Node>),
+}
+
+impl Node {
+    fn pos(&self) -> Option<usize> {
+        match self {
+            Node::Ident(pos, _) | Node::Cmp(pos, ..) | Node::Call(pos, ..) => Some(*pos),
+            _ => None,
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Tok)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Sym(s)) if *s == sym) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Ident(s)) if s == kw) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&self, what: &str, found: bool) -> Result<(), ExprError> {
+        if found {
+            Ok(())
+        } else {
+            Err(ExprError::new(self.offset(), format!("expected `{what}`")))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Node, ExprError> {
+        if self.eat_keyword("if") {
+            let cond = self.expr()?;
+            let found = self.eat_keyword("then");
+            self.expect("then", found)?;
+            let then = self.expr()?;
+            let found = self.eat_keyword("else");
+            self.expect("else", found)?;
+            let otherwise = self.expr()?;
+            return Ok(Node::If(
+                Box::new(cond),
+                Box::new(then),
+                Box::new(otherwise),
+            ));
+        }
+        self.or()
+    }
+
+    fn or(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.and()?;
+        while self.eat_keyword("or") {
+            lhs = Node::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.not()?;
+        while self.eat_keyword("and") {
+            lhs = Node::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Node, ExprError> {
+        if self.eat_keyword("not") {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Node, ExprError> {
+        let lhs = self.add()?;
+        let pos = self.offset();
+        for op in &SYMBOLS[..6] {
+            if self.eat_sym(op) {
+                return Ok(Node::Cmp(pos, op, Box::new(lhs), Box::new(self.add()?)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn add(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.mul()?;
+        loop {
+            if self.eat_sym("+") {
+                lhs = Node::Arith("+", Box::new(lhs), Box::new(self.mul()?));
+            } else if self.eat_sym("-") {
+                lhs = Node::Arith("-", Box::new(lhs), Box::new(self.mul()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn mul(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.unary()?;
+        loop {
+            if self.eat_sym("*") {
+                lhs = Node::Arith("*", Box::new(lhs), Box::new(self.unary()?));
+            } else if self.eat_sym("/") {
+                lhs = Node::Arith("/", Box::new(lhs), Box::new(self.unary()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        if self.eat_sym("-") {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        let pos = self.offset();
+        match self.tokens.get(self.pos).map(|(_, t)| t.clone()) {
+            Some(Tok::Num(n)) => {
+                self.pos += 1;
+                Ok(Node::Num(n))
+            }
+            Some(Tok::Sym("(")) => {
+                self.pos += 1;
+                let inner = self.expr()?;
+                let found = self.eat_sym(")");
+                self.expect(")", found)?;
+                Ok(inner)
+            }
+            Some(Tok::Ident(name)) => {
+                self.pos += 1;
+                if !self.eat_sym("(") {
+                    return Ok(Node::Ident(pos, name));
+                }
+                let mut args = Vec::new();
+                if !self.eat_sym(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat_sym(")") {
+                            break;
+                        }
+                        let found = self.eat_sym(",");
+                        self.expect(",", found)?;
+                    }
+                }
+                Ok(Node::Call(pos, name, args))
+            }
+            Some(tok) => Err(ExprError::new(pos, format!("unexpected `{tok}`"))),
+            None => Err(ExprError::new(pos, "unexpected end of expression")),
+        }
+    }
+}
+
+fn num(node: Node) -> Result<Num, ExprError> {
+    Ok(match node {
+        Node::Num(v) => Box::new(move |_| v),
+        Node::Ident(pos, name) => match name.as_str() {
+            "r" => Box::new(|v: &Vars| v.px[0] as f32),
+            "g" => Box::new(|v: &Vars| v.px[1] as f32),
+            "b" => Box::new(|v: &Vars| v.px[2] as f32),
+            "a" => Box::new(|v: &Vars| v.px[3] as f32),
+            "hue" => Box::new(|v: &Vars| v.hsv[0]),
+            "sat" => Box::new(|v: &Vars| v.hsv[1]),
+            "val" => Box::new(|v: &Vars| v.hsv[2]),
+            "luma" => Box::new(|v: &Vars| v.luma()),
+            _ => return Err(ExprError::new(pos, format!("unknown variable `{name}`"))),
+        },
+        Node::Neg(inner) => {
+            let inner = num(*inner)?;
+            Box::new(move |v| -inner(v))
+        }
+        Node::Arith(op, lhs, rhs) => {
+            let (lhs, rhs) = (num(*lhs)?, num(*rhs)?);
+            match op {
+                "+" => Box::new(move |v| lhs(v) + rhs(v)),
+                "-" => Box::new(move |v| lhs(v) - rhs(v)),
+                "*" => Box::new(move |v| lhs(v) * rhs(v)),
+                _ => Box::new(move |v| lhs(v) / rhs(v)),
+            }
+        }
+        Node::If(c, t, e) => {
+            let (c, t, e) = (cond(*c)?, num(*t)?, num(*e)?);
+            Box::new(move |v| if c(v) { t(v) } else { e(v) })
+        }
+        other => {
+            return Err(ExprError::new(
+                other.pos().unwrap_or(0),
+                "expected a number",
+            ))
+        }
+    })
+}
+
+fn cond(node: Node) -> Result<Cond, ExprError> {
+    Ok(match node {
+        Node::Cmp(_, op, lhs, rhs) => {
+            let (lhs, rhs) = (num(*lhs)?, num(*rhs)?);
+            match op {
+                "<" => Box::new(move |v| lhs(v) < rhs(v)),
+                "<=" => Box::new(move |v| lhs(v) <= rhs(v)),
+                ">" => Box::new(move |v| lhs(v) > rhs(v)),
+                ">=" => Box::new(move |v| lhs(v) >= rhs(v)),
+                "==" => Box::new(move |v| lhs(v) == rhs(v)),
+                _ => Box::new(move |v| lhs(v) != rhs(v)),
+            }
+        }
+        Node::And(lhs, rhs) => {
+            let (lhs, rhs) = (cond(*lhs)?, cond(*rhs)?);
+            Box::new(move |v| lhs(v) && rhs(v))
+        }
+        Node::Or(lhs, rhs) => {
+            let (lhs, rhs) = (cond(*lhs)?, cond(*rhs)?);
+            Box::new(move |v| lhs(v) || rhs(v))
+        }
+        Node::Not(inner) => {
+            let inner = cond(*inner)?;
+            Box::new(move |v| !inner(v))
+        }
+        other => {
+            return Err(ExprError::new(
+                other.pos().unwrap_or(0),
+                "expected a condition",
+            ))
+        }
+    })
+}
+
+fn literal(node: &Node, what: &str) -> Result<f32, ExprError> {
+    match node {
+        Node::Num(n) => Ok(*n),
+        other => Err(ExprError::new(
+            other.pos().unwrap_or(0),
+            format!("{what} must be a number literal"),
+        )),
+    }
+}
+
+fn action(node: Node) -> Result<Action, ExprError> {
+    Ok(match node {
+        Node::If(c, t, e) => {
+            let (c, t, e) = (cond(*c)?, action(*t)?, action(*e)?);
+            Box::new(move |p, v| if c(v) { t(p, v) } else { e(p, v) })
+        }
+        Node::Call(pos, name, args) => {
+            let arity = |n: usize| {
+                if args.len() == n {
+                    Ok(())
+                } else {
+                    Err(ExprError::new(
+                        pos,
+                        format!("`{name}` takes {n} argument(s)"),
+                    ))
+                }
+            };
+            match name.as_str() {
+                "nearest" => {
+                    arity(0)?;
+                    Box::new(|p, v| Nearest::new().predict(p, &v.px))
+                }
+                "creative" => {
+                    arity(0)?;
+                    Box::new(|p, v| Creative.predict(p, &v.px))
+                }
+                "double_pass" => {
+                    arity(0)?;
+                    Box::new(|p, v| NearestDoublePass.predict(p, &v.px))
+                }
+                "keep" => {
+                    arity(0)?;
+                    Box::new(|_, v| v.px)
+                }
+                "knn" => {
+                    if args.len() > 1 {
+                        return Err(ExprError::new(pos, "`knn` takes 0 or 1 argument(s)"));
+                    }
+                    // Knn caps k to the size of its dataset
+                    let knn = match args.first() {
+                        Some(k) => match literal(k, "k")? {
+                            n if n >= 1. && n.fract() == 0. => Knn::with(n as usize),
+                            _ => {
+                                return Err(ExprError::new(
+                                    k.pos().unwrap_or(pos),
+                                    "k must be a positive whole number",
+                                ))
+                            }
+                        },
+                        None => Knn::default(),
+                    };
+                    Box::new(move |p, v| knn.predict(p, &v.px))
+                }
+                "palette" => {
+                    arity(1)?;
+                    let i = match literal(&args[0], "palette index")? {
+                        n if n >= 0. && n.fract() == 0. => n as usize,
+                        _ => {
+                            return Err(ExprError::new(
+                                args[0].pos().unwrap_or(pos),
+                                "palette index must be a non-negative whole number",
+                            ))
+                        }
+                    };
+                    Box::new(move |p, v| p.get(i).map_or(v.px, |c| c.rgba_array()))
+                }
+                "rgb" => {
+                    arity(3)?;
+                    let mut c = [0, 0, 0, 255];
+                    for (i, arg) in args.iter().enumerate() {
+                        c[i] = literal(arg, "channel")?.clamp(0., 255.) as u8;
+                    }
+                    Box::new(move |_, _| c)
+                }
+                "nearest_in" => {
+                    let classes = args
+                        .into_iter()
+                        .map(|arg| match arg {
+                            Node::Ident(pos, name) => ColorClass::from_str(&name).map_err(|_| {
+                                ExprError::new(pos, format!("unknown color class `{name}`"))
+                            }),
+                            other => Err(ExprError::new(
+                                other.pos().unwrap_or(pos),
+                                "expected a color class name",
+                            )),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Box::new(move |p, v| {
+                        p.iter()
+                            .filter(|c| classes.contains(&c.group()))
+                            .min_by_key(|c| c.manhattan_dist(&v.px))
+                            .map_or_else(|| Nearest::new().predict(p, &v.px), |c| c.rgba_array())
+                    })
+                }
+                _ => return Err(ExprError::new(pos, format!("unknown action `{name}`"))),
+            }
+        }
+        other => {
+            return Err(ExprError::new(
+                other.pos().unwrap_or(0),
+                "expected a mapping action such as `nearest()`",
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::NORD;
+
+    #[test]
+    fn conditional_rule() {
+        let m =
+            ExprMapper::parse("if sat < 0.1 and luma < 0.5 then nearest_in(Greys) else palette(7)")
+                .unwrap();
+        assert_eq!(m.predict(&NORD, &[20, 20, 22, 255]), NORD[12].rgba_array());
+        assert_eq!(m.predict(&NORD, &[200, 30, 30, 255]), NORD[7].rgba_array());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let m =
+            ExprMapper::parse("if (r + g) / 2 > b * 2 - -1 then keep() else rgb(1, 2, 3)").unwrap();
+        assert_eq!(m.predict(&NORD, &[100, 100, 10, 255]), [100, 100, 10, 255]);
+        assert_eq!(m.predict(&NORD, &[0, 0, 200, 255]), [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(ExprMapper::parse("nearest_in(Teal)").unwrap_err().pos, 11);
+        assert!(ExprMapper::parse("if r < 3 then keep()").is_err());
+        assert!(ExprMapper::parse("r + 1").is_err());
+        assert!(ExprMapper::parse("keep() keep()").is_err());
+        assert!(ExprMapper::parse("knn(3, 4)").is_err());
+        assert!(ExprMapper::parse("knn(0)").is_err());
+        assert!(ExprMapper::parse("knn(2.5)").is_err());
+        assert!(ExprMapper::parse("knn(-3)").is_err());
+        let fraction = ExprMapper::parse("if r > 1 then palette(1.7) else keep()").unwrap_err();
+        assert_eq!(fraction.pos, 14);
+        assert!(ExprMapper::parse("palette(-1)").is_err());
+        assert!(ExprMapper::parse("palette(0)").is_ok());
+        // Larger than the dataset is fine, k is capped to it
+        let huge = ExprMapper::parse("knn(99999999999)").unwrap();
+        assert_ne!(huge.predict(&NORD, &[200, 40, 40, 255]), [200, 40, 40, 255]);
+    }
+}
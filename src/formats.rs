@@ -0,0 +1,67 @@
+//! Runtime queries for the image formats compiled into this build.
+//!
+//! Each format is behind a cargo feature of the same name (`png`, `jpeg`, `openexr`, ...), all
+//! enabled through `default-formats`. A build with `default-features = false` and
+//! `features = ["png", "jpeg"]` only decodes and encodes those two.
+
+pub use image::ImageFormat;
+
+const FORMATS: [(ImageFormat, bool); 13] = [
+    (ImageFormat::Bmp, cfg!(feature = "bmp")),
+    (ImageFormat::Dds, cfg!(feature = "dds")),
+    (ImageFormat::Farbfeld, cfg!(feature = "farbfeld")),
+    (ImageFormat::Gif, cfg!(feature = "gif")),
+    (ImageFormat::Hdr, cfg!(feature = "hdr")),
+    (ImageFormat::Ico, cfg!(feature = "ico")),
+    (ImageFormat::Jpeg, cfg!(feature = "jpeg")),
+    (ImageFormat::OpenExr, cfg!(feature = "openexr")),
+    (ImageFormat::Png, cfg!(feature = "png")),
+    (ImageFormat::Pnm, cfg!(feature = "pnm")),
+    (ImageFormat::Tga, cfg!(feature = "tga")),
+    (ImageFormat::Tiff, cfg!(feature = "tiff")),
+    (ImageFormat::WebP, cfg!(feature = "webp")),
+];
+
+pub fn can_decode(format: ImageFormat) -> bool {
+    FORMATS.iter().any(|&(f, enabled)| f == format && enabled)
+}
+
+/// WebP, HDR and DDS are decode-only.
+pub fn can_encode(format: ImageFormat) -> bool {
+    can_decode(format)
+        && !matches!(
+            format,
+            ImageFormat::WebP | ImageFormat::Hdr | ImageFormat::Dds
+        )
+}
+
+/// Formats that can be loaded by [`Processor`](crate::Processor).
+pub fn decodable() -> Vec<ImageFormat> {
+    FORMATS
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|&(f, _)| f)
+        .collect()
+}
+
+/// Formats that [`ProcessedData::save`](crate::ProcessedData::save) can write.
+pub fn encodable() -> Vec<ImageFormat> {
+    decodable().into_iter().filter(|&f| can_encode(f)).collect()
+}
+
+/// Whether a file extension maps to a format this build can decode.
+pub fn supports_extension<S: AsRef<std::ffi::OsStr>>(ext: S) -> bool {
+    ImageFormat::from_extension(ext).is_some_and(can_decode)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_only() {
+        assert!(!can_encode(ImageFormat::WebP));
+        assert_eq!(can_decode(ImageFormat::Png), cfg!(feature = "png"));
+        assert!(encodable().iter().all(|f| decodable().contains(f)));
+    }
+}
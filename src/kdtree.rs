@@ -0,0 +1,115 @@
+//! Exact nearest neighbour search over RGB colors, used by [`Knn`](crate::mappers::Knn) for
+//! large datasets.
+
+use std::collections::BinaryHeap;
+
+/// Static 3-d tree over the RGB channels of a set of colors. The tree is implicit: the median of
+/// each slice is its root, with the two subtrees on either side.
+pub(crate) struct KdTree {
+    // Color and its index in the original set
+    points: Vec<([u8; 3], u32)>,
+}
+
+impl KdTree {
+    pub(crate) fn new(colors: impl IntoIterator<Item = [u8; 3]>) -> Self {
+        let mut points: Vec<_> = colors
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| (c, i as u32))
+            .collect();
+        build(&mut points, 0);
+        KdTree { points }
+    }
+
+    /// Indices of the `n` colors closest to `rgb` by Euclidean distance, closest first. Equally
+    /// distant colors keep their original order, so the result matches a stable sort by distance.
+    pub(crate) fn nearest(&self, rgb: [u8; 3], n: usize) -> Vec<usize> {
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        if n > 0 {
+            search(&self.points, 0, rgb, n, &mut heap);
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|(_, i)| i as usize)
+            .collect()
+    }
+}
+
+fn build(points: &mut [([u8; 3], u32)], axis: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+    let mid = points.len() / 2;
+    points.select_nth_unstable_by_key(mid, |p| (p.0[axis], p.1));
+    let (left, right) = points.split_at_mut(mid);
+    build(left, (axis + 1) % 3);
+    build(&mut right[1..], (axis + 1) % 3);
+}
+
+// Keeps the `n` best (squared distance, index) pairs in a max-heap
+fn search(
+    points: &[([u8; 3], u32)],
+    axis: usize,
+    rgb: [u8; 3],
+    n: usize,
+    heap: &mut BinaryHeap<(u32, u32)>,
+) {
+    if points.is_empty() {
+        return;
+    }
+    let mid = points.len() / 2;
+    let (p, i) = points[mid];
+    let dist = (0..3).map(|a| (p[a].abs_diff(rgb[a]) as u32).pow(2)).sum();
+    if heap.len() < n {
+        heap.push((dist, i));
+    } else if heap.peek().is_some_and(|worst| (dist, i) < *worst) {
+        heap.pop();
+        heap.push((dist, i));
+    }
+    let diff = rgb[axis] as i32 - p[axis] as i32;
+    let (near, far) = if diff < 0 {
+        (&points[..mid], &points[mid + 1..])
+    } else {
+        (&points[mid + 1..], &points[..mid])
+    };
+    let next = (axis + 1) % 3;
+    search(near, next, rgb, n, heap);
+    // Ties may hide on the far side too, so only strictly farther planes are skipped
+    if heap.len() < n || heap.peek().is_some_and(|w| (diff * diff) as u32 <= w.0) {
+        search(far, next, rgb, n, heap);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_stable_sort() {
+        let rng = fastrand::Rng::with_seed(3);
+        // Coarse values, so there are plenty of duplicates and equal distances
+        let colors: Vec<[u8; 3]> = (0..500)
+            .map(|_| [0; 3].map(|_: u8| rng.u8(..16) * 16))
+            .collect();
+        let tree = KdTree::new(colors.iter().copied());
+        for _ in 0..200 {
+            let rgb = [0; 3].map(|_: u8| rng.u8(..));
+            let mut brute: Vec<_> = colors
+                .iter()
+                .map(|c| {
+                    (0..3)
+                        .map(|a| (c[a].abs_diff(rgb[a]) as u32).pow(2))
+                        .sum::<u32>()
+                })
+                .enumerate()
+                .collect();
+            brute.sort_by_key(|x| x.1);
+            for n in [1, 13, 31] {
+                let expected: Vec<_> = brute[..n].iter().map(|x| x.0).collect();
+                assert_eq!(tree.nearest(rgb, n), expected);
+            }
+        }
+        assert_eq!(tree.nearest([0; 3], 0), Vec::<usize>::new());
+        assert_eq!(KdTree::new([[1, 2, 3]]).nearest([0; 3], 5), [0]);
+    }
+}
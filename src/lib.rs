@@ -1,369 +1,99 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "adobe")]
+pub mod adobe;
+pub mod analysis;
+#[cfg(feature = "io")]
+pub mod compare;
+pub mod context;
+pub mod dataset;
+pub mod distance;
+pub mod expr;
+#[cfg(feature = "io")]
+pub mod formats;
+#[cfg(feature = "http")]
+pub mod lospec;
 pub mod mappers;
 pub mod memoize;
 pub mod palette;
-
-use image::{DynamicImage, GenericImageView, Rgba};
-use mappers::Nearest;
-use memoize::Memoized;
-use palette::Rgbx;
-
-use std::{
-    error::Error,
-    io::{Seek, Write},
-    num::NonZeroUsize,
-    ops::{Deref, DerefMut},
-    path::Path,
-    sync::mpsc::{self, Receiver, Sender},
-    thread,
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "preview")]
+pub mod preview;
+#[cfg(feature = "pywal")]
+pub mod pywal;
+pub mod quantize;
+#[cfg(feature = "rules")]
+pub mod rules;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "io")]
+pub mod service;
+pub mod theme;
+
+mod kdtree;
+#[cfg(feature = "io")]
+mod processor;
+
+#[cfg(feature = "io")]
+pub use processor::{
+    Encoding, Limits, ProcError, ProcOptions, ProcessedData, Processor, Strategy, ThreadCount,
+    Threads, Tracker,
 };
 
-use rayon::prelude::*;
-
-pub struct Processor<'a, M>
-where
-    M: Mapper,
-{
-    conf: ProcOptions<'a, M>,
-    data: DynamicImage,
-    prog: Progress,
-}
-
-impl<'a, M> Processor<'a, M>
-where
-    M: Mapper,
-{
-    pub fn configure() -> ProcOptions<'a> {
-        ProcOptions::default()
-    }
-
-    pub fn process(&self) -> ProcessedData {
-        let img_pixels: Vec<_> = self.data.pixels().map(|(_, _, rgb)| rgb).collect();
-
-        let ProcOptions {
-            mapper,
-            threads,
-            palette,
-            ..
-        } = &self.conf;
-
-        let raw: Vec<u8> = match threads {
-            Threads::Single => img_pixels
-                .iter()
-                .flat_map(|pixel| mapper.predict(palette, &pixel.0))
-                .collect(),
-            Threads::Auto => self.dispatch(
-                img_pixels
-                    .chunks(img_pixels.len() / ThreadCount::calculate().get())
-                    .collect(),
-            ),
-            Threads::Custom(n) => {
-                self.dispatch(img_pixels.chunks(img_pixels.len() / n.get()).collect())
-            }
-            Threads::Rayon => img_pixels
-                .par_iter()
-                .flat_map(|x| mapper.predict(palette, &x.0))
-                .collect(),
-            Threads::Extreme => self.dispatch(
-                img_pixels
-                    .chunks(img_pixels.len() / ThreadCount::extreme().get())
-                    .collect(),
-            ),
-        };
-
-        ProcessedData {
-            raw,
-            dimen: self.data.dimensions(),
-        }
-    }
-
-    pub fn gen_tracker(&mut self) -> Tracker {
-        let (x, y) = self.data.dimensions();
-        self.prog.init((x * y) as usize)
-    }
-
-    fn dispatch(&self, parts: Vec<&[Rgba<u8>]>) -> Vec<u8> {
-        let ProcOptions {
-            mapper, palette, ..
-        } = &self.conf;
-
-        thread::scope(|s| {
-            let mut handles: Vec<thread::ScopedJoinHandle<Vec<u8>>> = Vec::new();
-            let mut data: Vec<u8> = Vec::new();
-            for part in parts {
-                let sender = self.prog.get_sender();
-                let h = s.spawn(move || {
-                    part.iter()
-                        .flat_map(|rgb| {
-                            let r = mapper.predict(palette, &rgb.0);
-                            sender.notify();
-                            r
-                        })
-                        .collect::<Vec<u8>>()
-                });
-                handles.push(h);
-            }
-            for h in handles {
-                data.append(&mut h.join().unwrap());
-            }
-            data
-        })
-    }
-}
-
-pub struct ProcessedData {
-    raw: Vec<u8>,
-    dimen: (u32, u32),
-}
-
-impl ProcessedData {
-    pub fn raw_buffer(&self) -> &[u8] {
-        &self.raw
-    }
-
-    pub fn buffer_len(&self) -> usize {
-        self.raw.len()
-    }
-
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error + 'static>> {
-        let (w, h) = self.dimen;
-        image::save_buffer(path, &self.raw, w, h, image::ColorType::Rgba8)?;
-
-        Ok(())
-    }
-
-    pub fn encode<Buf: Write + Seek>(
-        &self,
-        buf: &mut Buf,
-        encoding: Encoding,
-    ) -> Result<(), Box<dyn Error>> {
-        let format = match encoding {
-            Encoding::Png => image::ImageOutputFormat::Png,
-            Encoding::Jpeg(q) => image::ImageOutputFormat::Jpeg(q),
-        };
-        let (height, width) = self.dimen;
-
-        image::write_buffer_with_format(
-            buf,
-            self.raw_buffer(),
-            height,
-            width,
-            image::ColorType::Rgba8,
-            format,
-        )?;
-        Ok(())
-    }
-}
-
-pub enum Encoding {
-    Png,
-    Jpeg(u8),
-}
-
-#[derive(Debug, Clone)]
-pub struct ProcOptions<'a, M: Mapper = Nearest> {
-    mapper: M,
-    threads: Threads,
-    palette: &'a [Rgbx],
-}
-
-impl Default for ProcOptions<'_> {
-    fn default() -> Self {
-        ProcOptions {
-            mapper: Nearest,
-            threads: Threads::default(),
-            palette: &palette::NORD,
-        }
-    }
-}
-
-impl<'a, M: Mapper> ProcOptions<'a, M> {
-    #[must_use]
-    pub fn new(mapper: M) -> Self {
-        ProcOptions {
-            mapper,
-            threads: Threads::default(),
-            palette: &palette::NORD,
-        }
-    }
-
-    #[must_use]
-    pub fn mapper<Map: Mapper>(self, mapper: Map) -> ProcOptions<'a, Map> {
-        ProcOptions {
-            mapper,
-            threads: self.threads,
-            palette: self.palette,
-        }
-    }
-
-    #[must_use]
-    pub fn copy_with_mapper<Map: Mapper>(&self, mapper: Map) -> ProcOptions<'a, Map> {
-        ProcOptions {
-            mapper,
-            threads: self.threads,
-            palette: self.palette,
-        }
-    }
-
-    #[must_use]
-    pub fn threads(mut self, threads: Threads) -> Self {
-        self.threads = threads;
-        self
-    }
-
-    #[must_use]
-    pub fn palette(mut self, palette: &'a [Rgbx]) -> Self {
-        self.palette = palette;
-        self
-    }
-
-    pub fn load<F: AsRef<Path>>(
-        self,
-        file: F,
-    ) -> Result<Processor<'a, M>, Box<dyn Error + 'static>> {
-        let data = image::open(file.as_ref())?;
-
-        Ok(Processor {
-            conf: self,
-            data,
-            prog: Progress::default(),
-        })
-    }
-
-    pub fn load_bytes(self, buffer: &[u8]) -> Result<Processor<'a, M>, Box<dyn Error + 'static>> {
-        let data = image::load_from_memory(buffer)?;
-
-        Ok(Processor {
-            conf: self,
-            data,
-            prog: Progress::default(),
-        })
-    }
-}
-
-#[derive(Clone, Default)]
-struct Progress(SignalSender);
-
-impl Progress {
-    fn init(&mut self, size: usize) -> Tracker {
-        let (s, r) = mpsc::channel::<Signal>();
-        self.0.replace(s);
-        Tracker {
-            current: 0,
-            total: size,
-            receiver: r,
-        }
-    }
-    fn get_sender(&self) -> SignalSender {
-        self.0.clone()
-    }
-}
-
-unsafe impl Sync for Progress {}
-
-#[derive(Clone, Default)]
-struct SignalSender(Option<Sender<Signal>>);
-
-impl SignalSender {
-    fn notify(&self) {
-        if let Some(s) = &self.0 {
-            s.send(Signal).unwrap();
-        }
-    }
-}
-
-impl Deref for SignalSender {
-    type Target = Option<Sender<Signal>>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl DerefMut for SignalSender {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
-pub struct Tracker {
-    current: usize,
-    total: usize,
-    receiver: Receiver<Signal>,
-}
-
-struct Signal;
-
-impl Tracker {
-    pub fn percentage(&mut self) -> f32 {
-        self.track();
-        (self.current as f32 / self.total as f32) * 100.0
-    }
-    pub fn current(&mut self) -> usize {
-        self.track();
-        self.current
-    }
-    pub const fn total(&self) -> usize {
-        self.total
-    }
-    fn track(&mut self) {
-        self.current += self.receiver.try_iter().count();
-    }
-}
-
-#[derive(Default, Debug, Clone, Copy)]
-pub enum Threads {
-    Single,
-    #[default]
-    Auto,
-    Rayon,
-    Custom(ThreadCount),
-    Extreme,
-}
-
-#[derive(Debug, Clone, Copy)]
-pub struct ThreadCount(NonZeroUsize);
-
-impl ThreadCount {
-    pub fn new(val: NonZeroUsize) -> Self {
-        ThreadCount(val)
-    }
-
-    pub fn calculate() -> Self {
-        if let Ok(c) = std::thread::available_parallelism() {
-            ThreadCount::new(c)
-        } else {
-            ThreadCount::default()
-        }
-    }
-
-    fn extreme() -> Self {
-        NonZeroUsize::new(2usize.pow((Self::calculate().get() / 2) as u32))
-            .unwrap()
-            .into()
-    }
-
-    fn get(&self) -> usize {
-        self.0.get()
-    }
-}
-
-impl From<NonZeroUsize> for ThreadCount {
-    fn from(value: NonZeroUsize) -> Self {
-        Self(value)
-    }
-}
-
-impl Default for ThreadCount {
-    fn default() -> Self {
-        Self(NonZeroUsize::new(2).unwrap())
-    }
-}
+use context::{PixelContext, Prepared};
+use mappers::Diffusion;
+use memoize::Memoized;
+use palette::{ColorClass, Rgbx};
 
 pub trait Mapper: Send + Sync + Clone {
     fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4];
     fn memoized(self) -> Memoized<Self> {
         self.into()
     }
+    /// Palette-independent stage of a prediction, if the mapper has one. The result is cached by
+    /// [`Processor::process_incremental`] and handed back to [`Mapper::predict_class`].
+    fn classify(&self, _pixel: &[u8; 4]) -> Option<ColorClass> {
+        None
+    }
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], _class: ColorClass) -> [u8; 4] {
+        self.predict(palette, pixel)
+    }
+    /// Error diffusion the processor should apply around this mapper's predictions, if any. See
+    /// [`mappers::Dither`].
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        None
+    }
+    /// Amount added to each color channel of the pixel at `(x, y)` before it is mapped, for
+    /// ordered dithering. See [`mappers::OrderedDither`].
+    fn dither_offset(&self, _x: u32, _y: u32) -> Option<f32> {
+        None
+    }
+    /// Whether the processor should map pixels through [`Mapper::predict_context`] instead of
+    /// [`Mapper::predict`]. The whole image is then kept in memory while processing. Combined with
+    /// [`Mapper::error_diffusion`], [`PixelContext::pixel`] carries the diffused error while the
+    /// rest of the context shows the image as is.
+    fn uses_context(&self) -> bool {
+        false
+    }
+    /// Prediction for mappers that need the position of the pixel or its surroundings, e.g. for
+    /// edge-aware mapping or positional noise.
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.predict(palette, ctx.pixel())
+    }
+    /// Called with the whole image before it is mapped through [`Mapper::predict_context`], for
+    /// mappers that depend on image-wide statistics such as the color histogram. The returned
+    /// state is available to every prediction for that image through
+    /// [`PixelContext::prepared`]; wrappers with state of their own pass the inner mapper's on
+    /// with [`PixelContext::with_prepared`].
+    fn prepare(
+        &self,
+        _palette: &[Rgbx],
+        _pixels: &[[u8; 4]],
+        _dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        None
+    }
 }
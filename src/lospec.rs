@@ -0,0 +1,98 @@
+//! Downloading palettes from the [Lospec palette list](https://lospec.com/palette-list).
+//!
+//! ```no_run
+//! use mapped::palette::Palette;
+//!
+//! let palette = Palette::from_lospec("endesga-32")?;
+//! println!("{palette}: {} colors", palette.len());
+//! # Ok::<(), mapped::palette::PaletteError>(())
+//! ```
+
+use super::palette::{self, Palette, PaletteError, Rgbx};
+use serde::Deserialize;
+
+const API: &str = "https://lospec.com/palette-list";
+
+#[derive(Deserialize)]
+struct LospecPalette {
+    name: String,
+    #[serde(default)]
+    author: String,
+    colors: Vec<String>,
+}
+
+impl Palette {
+    /// Downloads the palette with the given slug, the last part of its URL on Lospec. Names as
+    /// shown on the site work too: `"Endesga 32"` is looked up as `endesga-32`. Anything else
+    /// outside `[a-z0-9-]` is rejected with [`PaletteError::InvalidSlug`] before a request is made.
+    pub fn from_lospec(slug: &str) -> Result<Self, PaletteError> {
+        let slug = slug
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase();
+        if slug.is_empty()
+            || !slug
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        {
+            return Err(PaletteError::InvalidSlug(slug));
+        }
+        let json = ureq::get(&format!("{API}/{slug}.json"))
+            .call()?
+            .into_string()?;
+        Self::from_lospec_json(&json)
+    }
+
+    /// Parses a palette in the format served by the Lospec API, classifying each color with
+    /// [`classify`](palette::classify).
+    pub fn from_lospec_json(json: &str) -> Result<Self, PaletteError> {
+        let lospec: LospecPalette = serde_json::from_str(json)?;
+        let palette = lospec
+            .colors
+            .iter()
+            .map(|code| {
+                let [r, g, b] = palette::parse_hex(code)
+                    .ok_or_else(|| PaletteError::InvalidHex(code.into()))?;
+                Ok(Rgbx(r, g, b, palette::classify(&[r, g, b, 255])))
+            })
+            .collect::<Result<Palette, PaletteError>>()?
+            .named(lospec.name);
+        Ok(match lospec.author.trim() {
+            "" => palette,
+            author => palette.by(author),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{palette::ColorClass, rgbx};
+
+    #[test]
+    fn api_response() {
+        let json = r#"{"name":"Duo","author":"","colors":["bf616a","2e3440"],"slug":"duo"}"#;
+        let palette = Palette::from_lospec_json(json).unwrap();
+        assert_eq!(palette.to_string(), "Duo");
+        assert_eq!(
+            palette.colors(),
+            [rgbx!(191, 97, 106, r), rgbx!(46, 52, 64, gy)]
+        );
+        assert!(Palette::from_lospec_json(&json.replace("2e3440", "2e344")).is_err());
+        assert!(Palette::from_lospec_json("Not Found").is_err());
+    }
+
+    #[test]
+    fn invalid_slugs() {
+        for slug in ["", "../endesga-32", "endesga-32?x=1", "endesga_32", "café"] {
+            assert!(
+                matches!(
+                    Palette::from_lospec(slug),
+                    Err(PaletteError::InvalidSlug(_))
+                ),
+                "{slug}"
+            );
+        }
+    }
+}
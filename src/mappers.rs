@@ -1,21 +1,626 @@
 use crate::palette;
 
 use super::{
-    palette::{ColorClass, Rgbx},
+    context::{PixelContext, Prepared},
+    distance::{Ciede2000, ColorDistance, Manhattan, Oklab},
+    kdtree::KdTree,
+    palette::{ClassWeights, ColorClass, Rgbx, Rgbxa, Role, Roles},
     Mapper,
 };
 use ahash::AHashMap;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    ops::RangeInclusive,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+/// Nearest palette color under a [`ColorDistance`], Manhattan RGB distance by default.
+///
+/// With [`Nearest::max_distance`], pixels without a close enough palette color are left as is,
+/// for selective theming. [`Nearest::weight`] biases the matching towards or away from
+/// particular colors.
+///
+/// ```
+/// use mapped::{distance::Oklab, mappers::Nearest, palette::NORD};
+/// let perceptual = Nearest::with(Oklab);
+/// // Favor the background, play down a loud accent
+/// let biased = Nearest::with(Oklab).weight(NORD[12], 0.5).weight(NORD[7], 2.);
+/// ```
+#[derive(Clone)]
+pub struct Nearest<D: ColorDistance = Manhattan> {
+    distance: D,
+    max_distance: Option<f32>,
+    weights: Vec<([u8; 3], f32)>,
+    coords: PaletteCache<(D::Coords, f32)>,
+}
+
+impl Nearest {
+    pub const fn new() -> Self {
+        Nearest::with(Manhattan)
+    }
+}
+
+impl<D: ColorDistance> Nearest<D> {
+    pub const fn with(distance: D) -> Self {
+        Nearest {
+            distance,
+            max_distance: None,
+            weights: Vec::new(),
+            coords: PaletteCache::new(),
+        }
+    }
+
+    /// Leaves pixels unchanged when even the best match is further away than `max`, in the units
+    /// of the distance (some, like [`Oklab`], compare squared distances). Weights apply.
+    #[must_use]
+    pub const fn max_distance(mut self, max: f32) -> Self {
+        self.max_distance = Some(max);
+        self
+    }
+
+    /// Scales distances to palette entries of `color`'s RGB value by `weight`: below 1 draws
+    /// pixels to the color, above 1 keeps them away from it. Colors are weighted 1 by default,
+    /// negative weights count as 0, and colors missing from the palette are ignored, so the
+    /// same mapper can be used with any palette or variant.
+    #[must_use]
+    pub fn weight(mut self, color: Rgbx, weight: f32) -> Self {
+        let rgb = [color.0, color.1, color.2];
+        self.weights.retain(|(c, _)| *c != rgb);
+        self.weights.push((rgb, weight.max(0.)));
+        self
+    }
+
+    fn weight_of(&self, color: &Rgbx) -> f32 {
+        let rgb = [color.0, color.1, color.2];
+        self.weights
+            .iter()
+            .find_map(|(c, w)| (*c == rgb).then_some(*w))
+            .unwrap_or(1.)
+    }
+}
+
+impl<D: ColorDistance + Default> Default for Nearest<D> {
+    fn default() -> Self {
+        Nearest::with(D::default())
+    }
+}
+
+impl<D: ColorDistance> Mapper for Nearest<D> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let px = self.distance.coords(pixel);
+        let (i, dist) = if D::CACHED {
+            self.coords.with(
+                palette,
+                |c| (self.distance.coords(&c.rgba_array()), self.weight_of(c)),
+                |coords| {
+                    min_index(
+                        coords
+                            .iter()
+                            .map(|(c, w)| self.distance.distance(c, &px) * w),
+                    )
+                },
+            )
+        } else {
+            min_index(palette.iter().map(|c| {
+                let coords = self.distance.coords(&c.rgba_array());
+                self.distance.distance(&coords, &px) * self.weight_of(c)
+            }))
+        };
+        match self.max_distance {
+            Some(max) if dist > max => *pixel,
+            _ => palette[i].rgba_array(),
+        }
+    }
+}
+
+impl<D: ColorDistance> fmt::Debug for Nearest<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nearest")
+            .field("distance", &self.distance)
+            .field("max_distance", &self.max_distance)
+            .field("weights", &self.weights)
+            .finish()
+    }
+}
+
+/// [`Nearest`] in CIELAB space with CIEDE2000, which picks perceptually closer matches than RGB
+/// distances for skin tones and desaturated colors.
+pub type NearestLab = Nearest<Ciede2000>;
+
+/// [`Nearest`] in OkLab space, much cheaper than [`NearestLab`] and about as accurate.
+pub type NearestOklab = Nearest<Oklab>;
+
+// Index of the first smallest value
+fn argmin(values: impl Iterator<Item = f32>) -> usize {
+    min_index(values).0
+}
+
+fn min_index(values: impl Iterator<Item = f32>) -> (usize, f32) {
+    values
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+// Values derived from the palette, rebuilt whenever the mapper is used with a different one.
+// Palettes are told apart by a hash of their colors, taken outside the lock.
+struct PaletteCache<T>(RwLock<(Option<u64>, Vec<T>)>);
+
+impl<T> PaletteCache<T> {
+    const fn new() -> Self {
+        PaletteCache(RwLock::new((None, Vec::new())))
+    }
+
+    fn with<R>(
+        &self,
+        palette: &[Rgbx],
+        derive: impl Fn(&Rgbx) -> T,
+        f: impl FnOnce(&[T]) -> R,
+    ) -> R {
+        self.build(palette, |palette| palette.iter().map(derive).collect(), f)
+    }
+
+    fn build<R>(
+        &self,
+        palette: &[Rgbx],
+        derive: impl FnOnce(&[Rgbx]) -> Vec<T>,
+        f: impl FnOnce(&[T]) -> R,
+    ) -> R {
+        let key = Some(fxhash::hash64(palette));
+        {
+            let cache = self.0.read().unwrap();
+            if cache.0 == key {
+                return f(&cache.1);
+            }
+        }
+        let mut cache = self.0.write().unwrap();
+        if cache.0 != key {
+            *cache = (key, derive(palette));
+        }
+        f(&cache.1)
+    }
+}
+
+impl<T> Default for PaletteCache<T> {
+    fn default() -> Self {
+        PaletteCache::new()
+    }
+}
+
+// Cache contents don't affect results, so keep them out of fingerprints
+impl<T> fmt::Debug for PaletteCache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PaletteCache")
+    }
+}
+
+// Each clone rebuilds its own cache, which keeps worker threads off a shared lock
+impl<T> Clone for PaletteCache<T> {
+    fn clone(&self) -> Self {
+        PaletteCache::new()
+    }
+}
+
+/// Recolors pixels with the hue and saturation of the palette color closest in hue, keeping their
+/// original value (brightness) so shading survives the remap. Unsaturated pixels are matched on
+/// saturation instead, since their hue is meaningless.
+#[derive(Debug, Clone, Default)]
+pub struct NearestHue {
+    hsvs: PaletteCache<[f32; 3]>,
+}
+
+impl Mapper for NearestHue {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let [hue, sat, val] = palette::hsv(pixel);
+        let score = |c: &[f32; 3]| {
+            let dh = (c[0] - hue).abs();
+            let dh = dh.min(360. - dh) / 180.;
+            dh * sat + (c[1] - sat).abs() * (1. - sat)
+        };
+        let [h, s, _] = self.hsvs.with(
+            palette,
+            |c| palette::hsv(&c.rgba_array()),
+            |hsvs| hsvs[argmin(hsvs.iter().map(score))],
+        );
+        palette::from_hsv([h, s, val])
+    }
+}
+
+/// Gradient map: the palette is sorted by lightness into an evenly spaced ramp, and each pixel's
+/// luma picks the interpolated color at that point of the ramp. Gives clean duotone-like results
+/// with two-color palettes.
+#[derive(Debug, Clone, Default)]
+pub struct GradientMap {
+    ramp: PaletteCache<(f32, [u8; 4])>,
+}
+
+impl Mapper for GradientMap {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.ramp.build(
+            palette,
+            |palette| {
+                let mut colors: Vec<_> = palette.iter().map(Rgbx::rgba_array).collect();
+                colors.sort_by(|a, b| palette::luma(a).total_cmp(&palette::luma(b)));
+                let last = colors.len().saturating_sub(1).max(1) as f32;
+                colors
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, c)| (i as f32 / last, c))
+                    .collect()
+            },
+            |ramp| interpolate(ramp, palette::luma(pixel)).unwrap_or(*pixel),
+        )
+    }
+}
 
+/// Maps shadows and highlights, plus optionally midtones, to chosen palette colors (by index),
+/// interpolating smoothly in between. Pixels darker than the shadow pivot or lighter than the
+/// highlight pivot get the pure shadow or highlight color. With an empty palette, pixels are left
+/// as is.
 #[derive(Debug, Clone)]
-pub struct Nearest;
+pub struct Duotone {
+    shadows: (usize, f32),
+    midtones: Option<(usize, f32)>,
+    highlights: (usize, f32),
+    stops: PaletteCache<(f32, [u8; 4])>,
+}
+
+impl Duotone {
+    pub fn new(shadows: usize, highlights: usize) -> Self {
+        Duotone {
+            shadows: (shadows, 0.),
+            midtones: None,
+            highlights: (highlights, 1.),
+            stops: PaletteCache::new(),
+        }
+    }
+
+    /// Makes this a tritone, with the midtone color reached at luma `pivot`.
+    #[must_use]
+    pub fn midtones(mut self, index: usize, pivot: f32) -> Self {
+        self.midtones = Some((index, pivot));
+        self.stops = PaletteCache::new();
+        self
+    }
+
+    /// Luma levels (0-1) at which the pure shadow and highlight colors are reached.
+    #[must_use]
+    pub fn pivots(mut self, shadows: f32, highlights: f32) -> Self {
+        self.shadows.1 = shadows;
+        self.highlights.1 = highlights;
+        self.stops = PaletteCache::new();
+        self
+    }
+}
 
-impl Mapper for Nearest {
+impl Mapper for Duotone {
     fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
-        palette
+        self.stops.build(
+            palette,
+            |palette| {
+                // Out of range indices clamp to the last palette color
+                let stop = |(i, pivot): (usize, f32)| {
+                    Some((pivot, palette.get(i).or(palette.last())?.rgba_array()))
+                };
+                let mut stops: Vec<_> = [Some(self.shadows), self.midtones, Some(self.highlights)]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(stop)
+                    .collect();
+                stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+                stops
+            },
+            |stops| interpolate(stops, palette::luma(pixel)).unwrap_or(*pixel),
+        )
+    }
+}
+
+// Color at `t` on a ramp of stops sorted by position, `None` without any stops
+fn interpolate(stops: &[(f32, [u8; 4])], t: f32) -> Option<[u8; 4]> {
+    let i = stops.partition_point(|s| s.0 <= t);
+    if i == 0 {
+        return stops.first().map(|s| s.1);
+    }
+    if i == stops.len() {
+        return Some(stops[i - 1].1);
+    }
+    let ((p0, c0), (p1, c1)) = (stops[i - 1], stops[i]);
+    let f = (t - p0) / (p1 - p0);
+    Some([0, 1, 2, 3].map(|ch| (c0[ch] as f32 + (c1[ch] as f32 - c0[ch] as f32) * f).round() as u8))
+}
+
+/// Two-pass mapper that spreads the image over the whole palette instead of collapsing large
+/// areas onto a single entry, which greedy per-pixel mapping tends to do with small palettes.
+///
+/// [`Mapper::prepare`] clusters the image's color histogram into as many groups as there are
+/// palette colors (weighted k-means in OkLab), then assigns each group to a distinct palette color
+/// so that the total error over all pixels is minimal. Without a prepared image, pixels fall back
+/// to their nearest palette color in OkLab.
+#[derive(Debug, Clone)]
+pub struct GlobalMap {
+    iterations: usize,
+}
+
+impl GlobalMap {
+    /// Number of k-means refinement passes over the histogram, 10 by default.
+    #[must_use]
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+}
+
+impl Default for GlobalMap {
+    fn default() -> Self {
+        GlobalMap { iterations: 10 }
+    }
+}
+
+impl Mapper for GlobalMap {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        Nearest::with(Oklab).predict(palette, pixel)
+    }
+
+    fn uses_context(&self) -> bool {
+        true
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let [r, g, b, _] = *ctx.pixel();
+        let assignment = ctx.prepared::<AHashMap<[u8; 3], [u8; 4]>>();
+        match assignment.and_then(|a| a.get(&[r, g, b])) {
+            Some(&mapped) => mapped,
+            None => self.predict(palette, ctx.pixel()),
+        }
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        _dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        let mut histogram: AHashMap<[u8; 3], f32> = AHashMap::new();
+        for &[r, g, b, _] in pixels {
+            *histogram.entry([r, g, b]).or_default() += 1.;
+        }
+        let mut colors: Vec<_> = histogram
+            .into_iter()
+            .map(|(c, n)| (c, n, palette::oklab(&[c[0], c[1], c[2], 255])))
+            .collect();
+        // Hash map order is random, and the seeding below depends on the order among ties
+        colors.sort_unstable_by_key(|c| c.0);
+        let dist = |a: &[f32; 3], b: &[f32; 3]| Oklab.distance(a, b);
+
+        // Farthest-point seeding, favoring frequent colors
+        let k = palette.len().min(colors.len());
+        let mut centroids = Vec::with_capacity(k);
+        if let Some(first) = colors.iter().max_by(|a, b| a.1.total_cmp(&b.1)) {
+            centroids.push(first.2);
+        }
+        while centroids.len() < k {
+            let next = colors
+                .iter()
+                .max_by(|a, b| {
+                    let score = |c: &([u8; 3], f32, [f32; 3])| {
+                        c.1.sqrt()
+                            * centroids
+                                .iter()
+                                .map(|m| dist(m, &c.2))
+                                .fold(f32::MAX, f32::min)
+                    };
+                    score(a).total_cmp(&score(b))
+                })
+                .unwrap();
+            centroids.push(next.2);
+        }
+
+        let cluster =
+            |c: &[f32; 3], centroids: &[[f32; 3]]| argmin(centroids.iter().map(|m| dist(m, c)));
+        for _ in 0..self.iterations {
+            let mut sums = vec![([0f32; 3], 0f32); k];
+            for (_, n, lab) in &colors {
+                let sum = &mut sums[cluster(lab, &centroids)];
+                (0..3).for_each(|i| sum.0[i] += lab[i] * n);
+                sum.1 += n;
+            }
+            for (m, (sum, n)) in centroids.iter_mut().zip(sums) {
+                if n > 0. {
+                    *m = sum.map(|v| v / n);
+                }
+            }
+        }
+
+        let mut weights = vec![0f32; k];
+        for (_, n, lab) in &colors {
+            weights[cluster(lab, &centroids)] += n;
+        }
+        let labs: Vec<_> = palette.iter().map(Rgbx::to_oklab).collect();
+        let cost: Vec<Vec<f64>> = centroids
             .iter()
-            .min_by_key(|pal| pal.manhattan_dist(pixel))
-            .unwrap()
-            .rgba_array()
+            .zip(&weights)
+            .map(|(m, &n)| labs.iter().map(|l| (n * dist(m, l)) as f64).collect())
+            .collect();
+        let targets = min_cost_assignment(&cost);
+
+        let assignment: AHashMap<[u8; 3], [u8; 4]> = colors
+            .iter()
+            .map(|(c, _, lab)| (*c, palette[targets[cluster(lab, &centroids)]].rgba_array()))
+            .collect();
+        Some(Box::new(assignment))
+    }
+}
+
+// Hungarian algorithm: assigns every row to a distinct column (rows <= columns), minimizing the
+// total cost
+fn min_cost_assignment(cost: &[Vec<f64>]) -> Vec<usize> {
+    let (n, m) = (cost.len(), cost.first().map_or(0, Vec::len));
+    // 1-based, with row/column 0 as the sentinel
+    let (mut u, mut v) = (vec![0.; n + 1], vec![0.; m + 1]);
+    let mut owner = vec![0; m + 1];
+    let mut way = vec![0; m + 1];
+    for row in 1..=n {
+        owner[0] = row;
+        let mut col = 0;
+        let mut min = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[col] = true;
+            let (r, mut delta, mut next) = (owner[col], f64::INFINITY, 0);
+            for j in 1..=m {
+                if !used[j] {
+                    let reduced = cost[r - 1][j - 1] - u[r] - v[j];
+                    if reduced < min[j] {
+                        min[j] = reduced;
+                        way[j] = col;
+                    }
+                    if min[j] < delta {
+                        delta = min[j];
+                        next = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[owner[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min[j] -= delta;
+                }
+            }
+            col = next;
+            if owner[col] == 0 {
+                break;
+            }
+        }
+        while col != 0 {
+            let prev = way[col];
+            owner[col] = owner[prev];
+            col = prev;
+        }
+    }
+    let mut assignment = vec![0; n];
+    for j in 1..=m {
+        if owner[j] != 0 {
+            assignment[owner[j] - 1] = j - 1;
+        }
+    }
+    assignment
+}
+
+/// Caps the share of the image the wrapped mapper may send to chosen palette entries, e.g. at most
+/// 5% red, for balanced themed wallpapers. Pixels over a cap go to their nearest uncapped palette
+/// color instead, starting with those farthest from the capped color.
+///
+/// [`Mapper::prepare`] counts the image's colors and their predictions up front. Without a
+/// prepared image, pixels are mapped as the wrapped mapper would.
+///
+/// ```
+/// use mapped::mappers::{Nearest, UsageCap};
+/// let mapper = UsageCap::new(Nearest::new()).cap(7, 0.05);
+/// ```
+#[derive(Debug, Clone)]
+pub struct UsageCap<M: Mapper = Nearest> {
+    mapper: M,
+    caps: Vec<(usize, f32)>,
+}
+
+// Per-image state of UsageCap: the rerouted colors and whatever the wrapped mapper prepared
+struct CapState {
+    overflow: AHashMap<[u8; 4], [u8; 4]>,
+    inner: Option<Prepared>,
+}
+
+impl<M: Mapper> UsageCap<M> {
+    pub fn new(mapper: M) -> Self {
+        UsageCap {
+            mapper,
+            caps: Vec::new(),
+        }
+    }
+
+    /// Lets at most `share` (0-1) of the pixels map to `palette[index]`.
+    #[must_use]
+    pub fn cap(mut self, index: usize, share: f32) -> Self {
+        self.caps.retain(|c| c.0 != index);
+        self.caps.push((index, share.clamp(0., 1.)));
+        self
+    }
+}
+
+impl<M: Mapper> Mapper for UsageCap<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mapper.predict(palette, pixel)
+    }
+
+    fn uses_context(&self) -> bool {
+        true
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let Some(state) = ctx.prepared::<CapState>() else {
+            return self.mapper.predict_context(palette, ctx);
+        };
+        // Caps were worked out on the image as is, which dithering may have shifted the pixel from
+        let (x, y) = ctx.position();
+        match ctx.at(x, y).and_then(|px| state.overflow.get(px)) {
+            Some(&fallback) => fallback,
+            None => self
+                .mapper
+                .predict_context(palette, &ctx.with_prepared(state.inner.as_deref())),
+        }
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        let inner = self.mapper.prepare(palette, pixels, dimensions);
+        let mut histogram: AHashMap<[u8; 4], usize> = AHashMap::new();
+        for px in pixels {
+            *histogram.entry(*px).or_default() += 1;
+        }
+        let uncapped: Vec<Rgbx> = palette
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.caps.iter().all(|c| c.0 != *i))
+            .map(|(_, c)| *c)
+            .collect();
+        let mut overflow = AHashMap::new();
+        for &(index, share) in &self.caps {
+            let (Some(capped), false) = (palette.get(index), uncapped.is_empty()) else {
+                continue;
+            };
+            let mut mapped: Vec<_> = histogram
+                .iter()
+                .filter(|(px, _)| self.mapper.predict(palette, px) == capped.rgba_array())
+                .map(|(px, &n)| (capped.manhattan_dist(px), *px, n))
+                .collect();
+            mapped.sort_unstable();
+            let mut budget = (share * pixels.len() as f32) as usize;
+            for (_, px, n) in mapped {
+                if n <= budget {
+                    budget -= n;
+                } else {
+                    // Later colors may still fit, but keeping the cut clean avoids speckles
+                    budget = 0;
+                    overflow.insert(px, Nearest::new().predict(&uncapped, &px));
+                }
+            }
+        }
+        Some(Box::new(CapState { overflow, inner }))
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
     }
 }
 
@@ -64,165 +669,3628 @@ impl Mapper for Creative {
 #[derive(Debug, Clone)]
 pub struct Knn {
     k: usize,
+    weighted: bool,
+    weights: ClassWeights,
+    random: bool,
+    seed: Option<u64>,
+    dataset: Cow<'static, [Rgbx]>,
+    index: DatasetIndex,
 }
 
 impl Default for Knn {
     fn default() -> Self {
-        Knn { k: 12 }
+        Knn {
+            k: 12,
+            weighted: false,
+            weights: ClassWeights::new(),
+            random: true,
+            seed: None,
+            dataset: Cow::Borrowed(&super::palette::SYN_DATA_SET),
+            index: DatasetIndex::default(),
+        }
+    }
+}
+
+// Below this size a full scan is faster than building and walking a tree
+const INDEX_MIN_LEN: usize = 64;
+
+/// Lazily built search tree over a [`Knn`] dataset, shared between clones.
+#[derive(Clone, Default)]
+struct DatasetIndex(Arc<OnceLock<Option<KdTree>>>);
+
+impl DatasetIndex {
+    fn get(&self, dataset: &[Rgbx]) -> Option<&KdTree> {
+        self.0
+            .get_or_init(|| {
+                (dataset.len() >= INDEX_MIN_LEN)
+                    .then(|| KdTree::new(dataset.iter().map(|c| [c.0, c.1, c.2])))
+            })
+            .as_ref()
+    }
+}
+
+impl fmt::Debug for DatasetIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DatasetIndex")
     }
 }
 
 impl Knn {
     pub fn with(k: usize) -> Self {
-        Knn { k }
+        Knn::builder().k(k).build()
+    }
+
+    /// Classifies with a custom labelled dataset instead of the bundled one, e.g. one suited to a
+    /// pastel-only palette.
+    pub fn trained_on(dataset: &[Rgbx]) -> Self {
+        Knn::builder().dataset(dataset.to_vec()).build()
+    }
+
+    pub fn builder() -> KnnBuilder {
+        KnnBuilder {
+            knn: Knn::default(),
+        }
     }
 
     fn classify(
         c: &[u8; 4],
         k: usize,
         dataset: &[Rgbx],
+        index: Option<&KdTree>,
         random: bool,
-        weighted: bool,
+        weights: Option<&ClassWeights>,
+        seed: Option<u64>,
     ) -> ColorClass {
-        let mut ratings: Vec<_> = dataset
-            .iter()
-            .map(|pal| (pal.euclidian_dist(c), pal.group()))
-            .collect();
-        ratings.sort_by(|x, y| x.0.total_cmp(&y.0));
-        let mut vote_map = AHashMap::with_capacity(k);
-
-        for (_, g) in ratings[..=k].iter() {
+        let neighbours: Vec<ColorClass> = match index {
+            Some(tree) => tree
+                .nearest([c[0], c[1], c[2]], k + 1)
+                .into_iter()
+                .map(|i| dataset[i].group())
+                .collect(),
+            None => {
+                let mut ratings: Vec<_> = dataset
+                    .iter()
+                    .map(|pal| (pal.euclidian_dist(c), pal.group()))
+                    .collect();
+                ratings.sort_by(|x, y| x.0.total_cmp(&y.0));
+                ratings[..=k].iter().map(|x| x.1).collect()
+            }
+        };
+        // Ordered by class, so that ties are settled the same way on every run
+        let mut vote_map: BTreeMap<ColorClass, _> = BTreeMap::new();
+        for &g in neighbours.iter() {
             vote_map
                 .entry(g)
                 .and_modify(|entry| *entry += 1)
                 .or_insert(0);
         }
-        let (grp, count) = if weighted {
-            vote_map
-                .iter()
-                .map(|(k, v)| (k, v + k.weight()))
-                .max_by_key(|x| x.1)
-                .unwrap()
-        } else {
-            vote_map
-                .iter()
-                .map(|(k, v)| (k, *v))
-                .max_by_key(|x| x.1)
-                .unwrap()
-        };
+        let votes = vote_map.iter().map(|(&g, &v)| match weights {
+            Some(weights) => (g, v + weights.get(g)),
+            None => (g, v),
+        });
+        // The first of the classes with the most votes
+        let (grp, count) = votes
+            .clone()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+            .unwrap();
 
         if random {
-            let mut candidates: Vec<ColorClass> = Vec::new();
-            for (g, v) in vote_map.iter() {
-                if v == &count && g != grp {
-                    candidates.push(**g)
-                }
-            }
+            let candidates: Vec<ColorClass> = votes
+                .filter(|&(g, v)| v == count && g != grp)
+                .map(|(g, _)| g)
+                .collect();
             let l = candidates.len();
             if l == 0 {
-                **grp
+                grp
             } else {
-                candidates[fastrand::usize(..l)]
+                // Seeded per pixel, so results don't depend on the order pixels are mapped in
+                let i = match seed {
+                    Some(seed) => {
+                        let px = u32::from_le_bytes(*c) as u64;
+                        fastrand::Rng::with_seed(seed ^ px.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+                            .usize(..=l)
+                    }
+                    None => fastrand::usize(..=l),
+                };
+                if i == l {
+                    grp
+                } else {
+                    candidates[i]
+                }
             }
         } else {
-            **grp
+            grp
         }
     }
 }
 
 impl Mapper for Knn {
     fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
-        let grp = Knn::classify(pixel, self.k, &super::palette::SYN_DATA_SET, true, false);
-        let (i, _, _) = palette
-            .iter()
-            .enumerate()
-            .map(|(i, pal)| (i, pal.euclidian_dist(pixel), pal.group()))
-            .filter(|x| x.2 == grp)
-            .min_by(|x, y| x.1.total_cmp(&y.1))
-            .unwrap();
+        match Mapper::classify(self, pixel) {
+            Some(grp) => self.predict_class(palette, pixel, grp),
+            None => Nearest::new().predict(palette, pixel),
+        }
+    }
 
-        palette[i].rgba_array()
+    // An empty dataset has nothing to vote with
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        if self.dataset.is_empty() {
+            return None;
+        }
+        Some(Knn::classify(
+            pixel,
+            self.k,
+            &self.dataset,
+            self.index.get(&self.dataset),
+            self.random,
+            self.weighted.then_some(&self.weights),
+            self.seed,
+        ))
+    }
+
+    // Datasets may use classes the palette has no colors of
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], grp: ColorClass) -> [u8; 4] {
+        nearest_in_class(palette, pixel, grp)
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ManualMap;
+pub struct KnnBuilder {
+    knn: Knn,
+}
 
-impl Mapper for ManualMap {
-    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
-        match *pixel {
-            [100..=255, 0, 0, _] => palette[8].rgba_array(),
-            [185..=255, 0..=68, 0..=68, _] => palette[8].rgba_array(),
-            _ => *pixel,
-        }
+impl KnnBuilder {
+    /// Number of neighbours that vote on a pixel's class, 12 by default. Capped to the size of
+    /// the dataset.
+    #[must_use]
+    pub fn k(mut self, k: usize) -> Self {
+        self.knn.k = k;
+        self
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::palette::ColorClass::*;
-    use crate::palette::*;
-    use crate::rgbx;
+    /// Adds each class's weight to its votes, see [`KnnBuilder::weights`]. Off by default.
+    #[must_use]
+    pub fn weighted(mut self, weighted: bool) -> Self {
+        self.knn.weighted = weighted;
+        self
+    }
 
-    const BASIC_COLORS: [Rgbx; 14] = [
-        rgbx!(255, 0, 0, r),
-        rgbx!(255, 0, 127, r),
-        rgbx!(255, 128, 0, o),
-        rgbx!(255, 255, 0, y),
-        rgbx!(128, 255, 0, g),
-        rgbx!(0, 255, 0, g),
-        rgbx!(0, 255, 128, g),
-        rgbx!(0, 255, 255, b),
-        rgbx!(0, 128, 255, b),
-        rgbx!(0, 0, 255, b),
-        rgbx!(255, 0, 255, p),
-        rgbx!(128, 128, 128, g),
-        rgbx!(0, 0, 0, g),
-        rgbx!(255, 255, 255, w),
-    ];
+    /// Biases voting toward or away from classes. Turns on [`KnnBuilder::weighted`].
+    #[must_use]
+    pub fn weights(mut self, weights: ClassWeights) -> Self {
+        self.knn.weights = weights;
+        self.knn.weighted = true;
+        self
+    }
 
-    #[test]
-    fn basic_color_accuracy() {
-        let acc = prediction_accuracy(&BASIC_COLORS, &SYN_DATA_SET, 30, true);
-        println!("Basic color prediction accuracy: {}%", acc);
-        assert!(acc > 95.0)
+    /// Breaks ties between classes with equal votes randomly rather than by picking the first
+    /// of them in [`ColorClass`] order. On by default.
+    #[must_use]
+    pub fn random_ties(mut self, random: bool) -> Self {
+        self.knn.random = random;
+        self
     }
 
-    fn prediction_accuracy(sample: &[Rgbx], data_set: &[Rgbx], k: usize, print: bool) -> f32 {
-        let mut matches = 0;
-        for color in sample {
-            let grp = Knn::classify(&color.rgba_array(), k, data_set, true, false);
-            matches += if grp == color.3 {
-                1
-            } else {
-                if print {
-                    println!("Failed to predict: {:?}, prediction: {:?}", color, grp);
-                }
-                0
-            };
-        }
-        (matches as f32 / sample.len() as f32) * 100.0
+    /// Makes random tie-breaking reproducible: the same seed always breaks the tie for a given
+    /// color the same way, whatever the thread count.
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.knn.seed = Some(seed);
+        self
     }
 
-    #[test]
-    fn rgbx_equality() {
-        let x = Rgbx(255, 255, 255, ColorClass::Whites);
-        let y = x;
-        assert_eq!(x, y)
+    /// Labelled colors the classifier votes with, [`SYN_DATA_SET`](super::palette::SYN_DATA_SET)
+    /// by default.
+    #[must_use]
+    pub fn dataset(mut self, dataset: impl Into<Cow<'static, [Rgbx]>>) -> Self {
+        self.knn.dataset = dataset.into();
+        self
     }
 
-    #[test]
-    fn rgbx_inequality() {
-        let x = Rgbx(255, 255, 255, ColorClass::Whites);
-        let y = Rgbx(255, 200, 0, ColorClass::Orange);
-        assert_ne!(x, y)
+    pub fn build(mut self) -> Knn {
+        self.knn.k = self.knn.k.min(self.knn.dataset.len().saturating_sub(1));
+        self.knn.index = DatasetIndex::default();
+        self.knn
     }
+}
 
-    #[test]
+/// Classifies pixels with a classifier, [`Knn`] by default, and sends the classes pinned with
+/// [`ClassOverride::pin`] straight to a fixed palette entry. Pixels of other classes go through the
+/// wrapped mapper.
+///
+/// ```
+/// use mapped::{mappers::{ClassOverride, Knn, Nearest}, palette::ColorClass};
+/// let knn = Knn::builder().k(5).build();
+/// let mapper = ClassOverride::new(Nearest::new())
+///     .classifier(knn)
+///     .pin(ColorClass::Green, 10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClassOverride<M: Mapper = Nearest, C: Mapper = Knn> {
+    mapper: M,
+    classifier: C,
+    pins: Vec<(ColorClass, usize)>,
+}
+
+impl<M: Mapper> ClassOverride<M> {
+    pub fn new(mapper: M) -> Self {
+        ClassOverride {
+            mapper,
+            classifier: Knn::default(),
+            pins: Vec::new(),
+        }
+    }
+}
+
+impl<M: Mapper, C: Mapper> ClassOverride<M, C> {
+    /// Maps every pixel classified as `class` to `palette[index]`, or to the last entry if the
+    /// palette is shorter.
+    #[must_use]
+    pub fn pin(mut self, class: ColorClass, index: usize) -> Self {
+        self.pins.retain(|p| p.0 != class);
+        self.pins.push((class, index));
+        self
+    }
+
+    /// Mapper whose [`Mapper::classify`] picks the class of each pixel. Mappers that don't
+    /// classify leave every pixel to the wrapped mapper.
+    #[must_use]
+    pub fn classifier<C2: Mapper>(self, classifier: C2) -> ClassOverride<M, C2> {
+        ClassOverride {
+            mapper: self.mapper,
+            classifier,
+            pins: self.pins,
+        }
+    }
+
+    // The pinned color for `class`, the pixel itself if the palette is empty
+    fn pinned(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> Option<[u8; 4]> {
+        let &(_, i) = self.pins.iter().find(|p| p.0 == class)?;
+        Some(
+            palette
+                .get(i)
+                .or(palette.last())
+                .map_or(*pixel, Rgbx::rgba_array),
+        )
+    }
+}
+
+impl<M: Mapper, C: Mapper> Mapper for ClassOverride<M, C> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        match self.classify(pixel) {
+            Some(class) => self.predict_class(palette, pixel, class),
+            None => self.mapper.predict(palette, pixel),
+        }
+    }
+
+    // Classification is only worth its cost with pinned classes
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        if self.pins.is_empty() {
+            return None;
+        }
+        self.classifier.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.pinned(palette, pixel, class)
+            .unwrap_or_else(|| self.mapper.predict(palette, pixel))
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.classify(ctx.pixel())
+            .and_then(|class| self.pinned(palette, ctx.pixel(), class))
+            .unwrap_or_else(|| self.mapper.predict_context(palette, ctx))
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+/// Cheaper alternative to [`Knn`]: pixels are classified by the nearest per-class centroid of the
+/// bundled dataset, then mapped to the closest palette color of that class. Falls back to
+/// [`Nearest`] when the palette has no color of the class.
+#[derive(Debug, Clone, Default)]
+pub struct NearestCentroid;
+
+impl NearestCentroid {
+    fn centroids() -> &'static [(ColorClass, [f32; 3])] {
+        static CENTROIDS: OnceLock<Vec<(ColorClass, [f32; 3])>> = OnceLock::new();
+        CENTROIDS.get_or_init(|| {
+            let mut sums: Vec<(ColorClass, [f32; 3], f32)> = Vec::new();
+            for c in palette::SYN_DATA_SET.iter() {
+                let i = match sums.iter().position(|s| s.0 == c.3) {
+                    Some(i) => i,
+                    None => {
+                        sums.push((c.3, [0.; 3], 0.));
+                        sums.len() - 1
+                    }
+                };
+                let [r, g, b, _] = c.rgba_array();
+                let sum = &mut sums[i];
+                sum.1 = [
+                    sum.1[0] + r as f32,
+                    sum.1[1] + g as f32,
+                    sum.1[2] + b as f32,
+                ];
+                sum.2 += 1.;
+            }
+            sums.into_iter()
+                .map(|(class, sum, n)| (class, sum.map(|v| v / n)))
+                .collect()
+        })
+    }
+}
+
+impl Mapper for NearestCentroid {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let class = self.classify(pixel).unwrap();
+        self.predict_class(palette, pixel, class)
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        let centroids = Self::centroids();
+        let dist = |c: &[f32; 3]| (0..3).map(|i| (c[i] - pixel[i] as f32).powi(2)).sum();
+        Some(centroids[argmin(centroids.iter().map(|c| dist(&c.1)))].0)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        nearest_in_class(palette, pixel, class)
+    }
+}
+
+/// Classifies pixels with a Gaussian naive Bayes model over RGB and HSV features, then maps them
+/// to the closest palette color of that class like [`NearestCentroid`]. The model is fitted to the
+/// bundled dataset ahead of time by [`palette::generate_bayes_model`].
+#[derive(Debug, Clone, Default)]
+pub struct NaiveBayes;
+
+impl Mapper for NaiveBayes {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let class = self.classify(pixel).unwrap();
+        self.predict_class(palette, pixel, class)
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        let x = palette::bayes_features(pixel);
+        let log_likelihood = |(_, mean, inv, bias): &(ColorClass, [f32; 7], [f32; 7], f32)| {
+            bias - (0..7)
+                .map(|i| (x[i] - mean[i]).powi(2) * inv[i])
+                .sum::<f32>()
+        };
+        let model = &palette::BAYES_MODEL;
+        Some(model[argmin(model.iter().map(|m| -log_likelihood(m)))].0)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        nearest_in_class(palette, pixel, class)
+    }
+}
+
+// Closest palette color of a class, or the nearest overall if the palette has none
+fn nearest_in_class(palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+    palette
+        .iter()
+        .filter(|c| c.group() == class)
+        .min_by(|a, b| a.euclidian_dist(pixel).total_cmp(&b.euclidian_dist(pixel)))
+        .map_or_else(|| Nearest::new().predict(palette, pixel), Rgbx::rgba_array)
+}
+
+/// Classification quality of a mapper on a labelled sample, see [`evaluate`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Evaluation {
+    pub total: usize,
+    pub correct: usize,
+    /// Sample counts by expected and predicted class. The prediction is `None` for colors the
+    /// mapper did not classify.
+    pub confusion: BTreeMap<(ColorClass, Option<ColorClass>), usize>,
+}
+
+impl Evaluation {
+    /// Percentage of correctly classified colors.
+    pub fn accuracy(&self) -> f32 {
+        if self.total == 0 {
+            return 0.;
+        }
+        (self.correct as f32 / self.total as f32) * 100.0
+    }
+
+    /// Misclassified `(expected, predicted)` pairs with their counts.
+    pub fn errors(&self) -> impl Iterator<Item = (ColorClass, Option<ColorClass>, usize)> + '_ {
+        self.confusion
+            .iter()
+            .filter(|((expected, predicted), _)| Some(*expected) != *predicted)
+            .map(|(&(expected, predicted), &n)| (expected, predicted, n))
+    }
+
+    /// Classes present in the sample.
+    pub fn classes(&self) -> Vec<ColorClass> {
+        let mut classes: Vec<_> = self.confusion.keys().map(|k| k.0).collect();
+        classes.dedup();
+        classes
+    }
+
+    /// Percentage of the colors classified as `class` that really are of that class, or `None`
+    /// if nothing was.
+    pub fn precision(&self, class: ColorClass) -> Option<f32> {
+        let predicted: usize = self
+            .confusion
+            .iter()
+            .filter(|(k, _)| k.1 == Some(class))
+            .map(|(_, n)| n)
+            .sum();
+        (predicted > 0).then(|| self.hits(class) as f32 / predicted as f32 * 100.0)
+    }
+
+    /// Percentage of the colors of `class` that were classified as such, or `None` if the sample
+    /// has none.
+    pub fn recall(&self, class: ColorClass) -> Option<f32> {
+        let expected: usize = self
+            .confusion
+            .iter()
+            .filter(|(k, _)| k.0 == class)
+            .map(|(_, n)| n)
+            .sum();
+        (expected > 0).then(|| self.hits(class) as f32 / expected as f32 * 100.0)
+    }
+
+    fn hits(&self, class: ColorClass) -> usize {
+        self.confusion
+            .get(&(class, Some(class)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn merge(&mut self, other: Evaluation) {
+        self.total += other.total;
+        self.correct += other.correct;
+        for (k, n) in other.confusion {
+            *self.confusion.entry(k).or_default() += n;
+        }
+    }
+}
+
+/// Classifies every color of a labelled sample with [`Mapper::classify`] and tallies the results,
+/// for comparing values of `k` or custom datasets.
+///
+/// ```
+/// use mapped::{mappers::{evaluate, Knn}, palette::{DATA_SET, SYN_DATA_SET}};
+///
+/// let knn = Knn::builder().k(5).dataset(&SYN_DATA_SET[..]).seed(0).build();
+/// println!("{}%", evaluate(&knn, &DATA_SET).accuracy());
+/// ```
+pub fn evaluate<M: Mapper>(mapper: &M, sample: &[Rgbx]) -> Evaluation {
+    let mut eval = Evaluation {
+        total: sample.len(),
+        ..Evaluation::default()
+    };
+    for color in sample {
+        let predicted = mapper.classify(&color.rgba_array());
+        if predicted == Some(color.3) {
+            eval.correct += 1;
+        }
+        *eval.confusion.entry((color.3, predicted)).or_default() += 1;
+    }
+    eval
+}
+
+/// K-fold cross-validation: splits `dataset` into `folds` interleaved parts, trains a mapper on
+/// all but one part with `train` and [`evaluate`]s it on the held out part, once per part. The
+/// returned [`Evaluation`] sums all folds.
+///
+/// ```
+/// use mapped::{mappers::{cross_validate, Knn}, palette::{ColorClass, SYN_DATA_SET}};
+///
+/// let eval = cross_validate(&SYN_DATA_SET, 5, |train| Knn::builder().k(7).dataset(train).build());
+/// println!("red recall: {:?}%", eval.recall(ColorClass::Red));
+/// ```
+pub fn cross_validate<M, F>(dataset: &[Rgbx], folds: usize, train: F) -> Evaluation
+where
+    M: Mapper,
+    F: Fn(Vec<Rgbx>) -> M,
+{
+    let folds = folds.clamp(1, dataset.len().max(1));
+    let mut total = Evaluation::default();
+    for fold in 0..folds {
+        let (held_out, training): (Vec<_>, Vec<_>) = dataset
+            .iter()
+            .enumerate()
+            .partition(|(i, _)| i % folds == fold);
+        let strip = |part: Vec<(usize, &Rgbx)>| part.into_iter().map(|(_, c)| *c).collect();
+        let mapper = train(strip(training));
+        let held_out: Vec<_> = strip(held_out);
+        total.merge(evaluate(&mapper, &held_out));
+    }
+    total
+}
+
+/// Sends pixels within fixed RGB ranges to fixed targets and leaves everything else as is. The
+/// default map sends two ranges of reds to `palette[8]`; build other maps with
+/// [`ManualMap::builder`].
+#[derive(Debug, Clone)]
+pub struct ManualMap {
+    ranges: Vec<ManualRange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Palette entry by index. Out of range indices leave the pixel as is.
+    Palette(usize),
+    Color([u8; 4]),
+}
+
+#[derive(Debug, Clone)]
+struct ManualRange {
+    channels: [RangeInclusive<u8>; 3],
+    target: Target,
+    priority: i32,
+}
+
+impl ManualMap {
+    pub fn builder() -> ManualMapBuilder {
+        ManualMapBuilder::default()
+    }
+}
+
+impl Default for ManualMap {
+    fn default() -> Self {
+        ManualMap::builder()
+            .range(100..=255, 0..=0, 0..=0, Target::Palette(8))
+            .range(185..=255, 0..=68, 0..=68, Target::Palette(8))
+            .build()
+    }
+}
+
+impl Mapper for ManualMap {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let Some(range) = self
+            .ranges
+            .iter()
+            .find(|r| r.channels.iter().zip(pixel).all(|(r, c)| r.contains(c)))
+        else {
+            return *pixel;
+        };
+        match range.target {
+            Target::Palette(i) => palette.get(i).map_or(*pixel, Rgbx::rgba_array),
+            Target::Color(c) => c,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ManualMapBuilder {
+    ranges: Vec<ManualRange>,
+}
+
+impl ManualMapBuilder {
+    /// Registers a range with priority 0. Among ranges of equal priority, the first registered
+    /// one that matches wins.
+    #[must_use]
+    pub fn range(
+        self,
+        red: RangeInclusive<u8>,
+        green: RangeInclusive<u8>,
+        blue: RangeInclusive<u8>,
+        target: Target,
+    ) -> Self {
+        self.range_with_priority(red, green, blue, target, 0)
+    }
+
+    /// Registers a range that is checked before all ranges of lower priority.
+    #[must_use]
+    pub fn range_with_priority(
+        mut self,
+        red: RangeInclusive<u8>,
+        green: RangeInclusive<u8>,
+        blue: RangeInclusive<u8>,
+        target: Target,
+        priority: i32,
+    ) -> Self {
+        self.ranges.push(ManualRange {
+            channels: [red, green, blue],
+            target,
+            priority,
+        });
+        self
+    }
+
+    pub fn build(mut self) -> ManualMap {
+        // Stable, so registration order breaks ties
+        self.ranges.sort_by_key(|r| std::cmp::Reverse(r.priority));
+        ManualMap {
+            ranges: self.ranges,
+        }
+    }
+}
+
+/// Checks pixels against a table of source colors first, e.g. to swap an app's brand blue for a
+/// specific palette blue, and maps everything else through the wrapped mapper.
+#[derive(Debug, Clone)]
+pub struct Substitute<M: Mapper = Nearest> {
+    mapper: M,
+    table: Vec<([u8; 4], f32, Target)>,
+}
+
+impl<M: Mapper> Substitute<M> {
+    pub fn new(mapper: M) -> Self {
+        Substitute {
+            mapper,
+            table: Vec::new(),
+        }
+    }
+
+    /// Replaces exactly `source` (alpha is ignored) with `target`.
+    #[must_use]
+    pub fn replace(self, source: [u8; 3], target: Target) -> Self {
+        self.replace_near(source, 0., target)
+    }
+
+    /// Replaces colors within `tolerance` (Manhattan RGB distance) of `source` with `target`. When
+    /// several sources match, the closest one wins.
+    #[must_use]
+    pub fn replace_near(mut self, source: [u8; 3], tolerance: f32, target: Target) -> Self {
+        let [r, g, b] = source;
+        self.table.push(([r, g, b, 255], tolerance, target));
+        self
+    }
+
+    // The table's replacement for `pixel`, if it has one that fits the palette
+    fn lookup(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> Option<[u8; 4]> {
+        let target = self
+            .table
+            .iter()
+            .map(|(source, tolerance, target)| {
+                (Manhattan.distance(source, pixel), tolerance, target)
+            })
+            .filter(|(dist, tolerance, _)| dist <= tolerance)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, _, &target)| target)?;
+        match target {
+            Target::Palette(i) => palette.get(i).map(Rgbx::rgba_array),
+            Target::Color(c) => Some(c),
+        }
+    }
+}
+
+impl<M: Mapper> Mapper for Substitute<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.lookup(palette, pixel)
+            .unwrap_or_else(|| self.mapper.predict(palette, pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.lookup(palette, pixel)
+            .unwrap_or_else(|| self.mapper.predict_class(palette, pixel, class))
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.lookup(palette, ctx.pixel())
+            .unwrap_or_else(|| self.mapper.predict_context(palette, ctx))
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+/// Maps against the opaque colors of a translucent palette, then applies the alpha of the chosen
+/// entry according to `compositing`. The palette handed to the processor should be
+/// [`Rgbxa::opaque`] of the same entries; pixels that don't land on one of them are left as is.
+#[derive(Debug, Clone)]
+pub struct Translucent<M: Mapper> {
+    mapper: M,
+    entries: Vec<Rgbxa>,
+    compositing: palette::Compositing,
+}
+
+impl<M: Mapper> Translucent<M> {
+    pub fn new(mapper: M, palette: &[Rgbxa], compositing: palette::Compositing) -> Self {
+        Translucent {
+            mapper,
+            entries: palette.to_vec(),
+            compositing,
+        }
+    }
+
+    fn apply(&self, pixel: &[u8; 4], mapped: [u8; 4]) -> [u8; 4] {
+        let Some(entry) = self
+            .entries
+            .iter()
+            .find(|e| e.rgbx().rgba_array()[..3] == mapped[..3])
+        else {
+            return mapped;
+        };
+        match self.compositing {
+            palette::Compositing::Replace => entry.rgba_array(),
+            palette::Compositing::Over => entry.over(pixel),
+        }
+    }
+}
+
+impl<M: Mapper> Mapper for Translucent<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.apply(pixel, self.mapper.predict(palette, pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.apply(pixel, self.mapper.predict_class(palette, pixel, class))
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.apply(ctx.pixel(), self.mapper.predict_context(palette, ctx))
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+/// Interpolates between the original pixel and the wrapped mapper's result, for a "tinted toward
+/// the palette" look instead of a hard remap. A strength of 1 is the plain mapper, 0 leaves the
+/// image untouched.
+#[derive(Debug, Clone)]
+pub struct Blend<M: Mapper> {
+    mapper: M,
+    strength: f32,
+}
+
+impl<M: Mapper> Blend<M> {
+    pub fn new(mapper: M, strength: f32) -> Self {
+        Blend {
+            mapper,
+            strength: strength.clamp(0., 1.),
+        }
+    }
+
+    fn mix(&self, pixel: &[u8; 4], mapped: [u8; 4]) -> [u8; 4] {
+        [0, 1, 2, 3].map(|i| {
+            (pixel[i] as f32 + (mapped[i] as f32 - pixel[i] as f32) * self.strength).round() as u8
+        })
+    }
+}
+
+impl<M: Mapper> Mapper for Blend<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mix(pixel, self.mapper.predict(palette, pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.mix(pixel, self.mapper.predict_class(palette, pixel, class))
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.mix(ctx.pixel(), self.mapper.predict_context(palette, ctx))
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+/// Feeds the result of `A` into `B`.
+#[derive(Debug, Clone)]
+pub struct Chain<A: Mapper, B: Mapper> {
+    first: A,
+    then: B,
+}
+
+impl<A: Mapper, B: Mapper> Chain<A, B> {
+    pub fn new(first: A, then: B) -> Self {
+        Chain { first, then }
+    }
+}
+
+impl<A: Mapper, B: Mapper> Mapper for Chain<A, B> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.then
+            .predict(palette, &self.first.predict(palette, pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.first.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.then
+            .predict(palette, &self.first.predict_class(palette, pixel, class))
+    }
+
+    fn uses_context(&self) -> bool {
+        self.first.uses_context() || self.then.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let state = ctx.prepared::<ChainState>();
+        if let Some(ChainState { mapped, then, .. }) = state.filter(|s| !s.mapped.is_empty()) {
+            let ctx = PixelContext::new(mapped, ctx.dimensions(), ctx.position());
+            return self
+                .then
+                .predict_context(palette, &ctx.with_prepared(then.as_deref()));
+        }
+        let first = ctx.with_prepared(state.and_then(|s| s.first.as_deref()));
+        let mapped = self.first.predict_context(palette, &first);
+        self.then
+            .predict_context(palette, &ctx.with_pixel(mapped).with_prepared(None))
+    }
+
+    /// Prepares `B` on the image as mapped by `A` if `B` looks at the context, so that it sees
+    /// the pixels it is actually given.
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        let first = self.first.prepare(palette, pixels, dimensions);
+        let (mut mapped, mut then) = (Vec::new(), None);
+        if self.then.uses_context() {
+            let w = dimensions.0.max(1) as usize;
+            mapped = (0..pixels.len())
+                .map(|i| {
+                    let ctx =
+                        PixelContext::new(pixels, dimensions, ((i % w) as u32, (i / w) as u32));
+                    self.first
+                        .predict_context(palette, &ctx.with_prepared(first.as_deref()))
+                })
+                .collect();
+            then = self.then.prepare(palette, &mapped, dimensions);
+        }
+        Some(Box::new(ChainState {
+            first,
+            mapped,
+            then,
+        }))
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.first
+            .error_diffusion()
+            .or_else(|| self.then.error_diffusion())
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.first
+            .dither_offset(x, y)
+            .or_else(|| self.then.dither_offset(x, y))
+    }
+}
+
+// Per-image state of Chain: whatever `A` prepared, and if `B` looks at the context, the image as
+// mapped by `A` with whatever `B` prepared for it
+struct ChainState {
+    first: Option<Prepared>,
+    mapped: Vec<[u8; 4]>,
+    then: Option<Prepared>,
+}
+
+/// Uses the result of `A` unless it is further than `threshold` (Manhattan RGB distance) from the
+/// original pixel, in which case `B` maps the pixel instead.
+#[derive(Debug, Clone)]
+pub struct Fallback<A: Mapper, B: Mapper> {
+    primary: A,
+    fallback: B,
+    threshold: f32,
+}
+
+impl<A: Mapper, B: Mapper> Fallback<A, B> {
+    pub fn new(primary: A, fallback: B, threshold: f32) -> Self {
+        Fallback {
+            primary,
+            fallback,
+            threshold,
+        }
+    }
+
+    fn accept(&self, pixel: &[u8; 4], mapped: &[u8; 4]) -> bool {
+        Manhattan.distance(pixel, mapped) <= self.threshold
+    }
+}
+
+impl<A: Mapper, B: Mapper> Mapper for Fallback<A, B> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let mapped = self.primary.predict(palette, pixel);
+        if self.accept(pixel, &mapped) {
+            mapped
+        } else {
+            self.fallback.predict(palette, pixel)
+        }
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.primary.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        let mapped = self.primary.predict_class(palette, pixel, class);
+        if self.accept(pixel, &mapped) {
+            mapped
+        } else {
+            self.fallback.predict(palette, pixel)
+        }
+    }
+
+    fn uses_context(&self) -> bool {
+        self.primary.uses_context() || self.fallback.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let (primary, fallback) = split_prepared(ctx);
+        let mapped = self.primary.predict_context(palette, &primary);
+        if self.accept(ctx.pixel(), &mapped) {
+            mapped
+        } else {
+            self.fallback.predict_context(palette, &fallback)
+        }
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        prepare_pair(&self.primary, &self.fallback, palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.primary
+            .error_diffusion()
+            .or_else(|| self.fallback.error_diffusion())
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.primary
+            .dither_offset(x, y)
+            .or_else(|| self.fallback.dither_offset(x, y))
+    }
+}
+
+/// Sends pixels matching `predicate` through `A` and everything else through `B`. Error diffusion
+/// and ordered dithering can't be applied per pixel, so those of `A` are used for the whole
+/// image, or else those of `B`.
+#[derive(Clone)]
+pub struct Split<A: Mapper, B: Mapper, P = fn(&[u8; 4]) -> bool> {
+    predicate: P,
+    matched: A,
+    rest: B,
+}
+
+impl<A: Mapper, B: Mapper, P> Split<A, B, P>
+where
+    P: Fn(&[u8; 4]) -> bool + Send + Sync + Clone,
+{
+    pub fn new(predicate: P, matched: A, rest: B) -> Self {
+        Split {
+            predicate,
+            matched,
+            rest,
+        }
+    }
+}
+
+impl<A: Mapper + fmt::Debug, B: Mapper + fmt::Debug, P> fmt::Debug for Split<A, B, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Split")
+            .field("matched", &self.matched)
+            .field("rest", &self.rest)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<A: Mapper, B: Mapper, P> Mapper for Split<A, B, P>
+where
+    P: Fn(&[u8; 4]) -> bool + Send + Sync + Clone,
+{
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        if (self.predicate)(pixel) {
+            self.matched.predict(palette, pixel)
+        } else {
+            self.rest.predict(palette, pixel)
+        }
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        if (self.predicate)(pixel) {
+            self.matched.classify(pixel)
+        } else {
+            self.rest.classify(pixel)
+        }
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        if (self.predicate)(pixel) {
+            self.matched.predict_class(palette, pixel, class)
+        } else {
+            self.rest.predict_class(palette, pixel, class)
+        }
+    }
+
+    fn uses_context(&self) -> bool {
+        self.matched.uses_context() || self.rest.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let (matched, rest) = split_prepared(ctx);
+        if (self.predicate)(ctx.pixel()) {
+            self.matched.predict_context(palette, &matched)
+        } else {
+            self.rest.predict_context(palette, &rest)
+        }
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        prepare_pair(&self.matched, &self.rest, palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.matched
+            .error_diffusion()
+            .or_else(|| self.rest.error_diffusion())
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.matched
+            .dither_offset(x, y)
+            .or_else(|| self.rest.dither_offset(x, y))
+    }
+}
+
+// Per-image state of a mapper built from two others, one entry each
+struct PairState(Option<Prepared>, Option<Prepared>);
+
+fn prepare_pair(
+    a: &impl Mapper,
+    b: &impl Mapper,
+    palette: &[Rgbx],
+    pixels: &[[u8; 4]],
+    dimensions: (u32, u32),
+) -> Option<Prepared> {
+    let a = a.prepare(palette, pixels, dimensions);
+    let b = b.prepare(palette, pixels, dimensions);
+    Some(Box::new(PairState(a, b)))
+}
+
+// The contexts to hand the two mappers of a pair, each carrying its own state
+fn split_prepared<'a>(ctx: &PixelContext<'a>) -> (PixelContext<'a>, PixelContext<'a>) {
+    match ctx.prepared::<PairState>() {
+        Some(PairState(a, b)) => (
+            ctx.with_prepared(a.as_deref()),
+            ctx.with_prepared(b.as_deref()),
+        ),
+        None => (ctx.with_prepared(None), ctx.with_prepared(None)),
+    }
+}
+
+/// Sends pixels within a luma band straight to the color assigned to a role, and everything else
+/// through the wrapped mapper. Bands are checked in the order they were added.
+///
+/// To map the darkest 10% of an image to the background color, use
+/// [`Processor::luma_quantile`](crate::Processor::luma_quantile) for the threshold:
+/// `RoleAware::new(Nearest::new(), roles).darkest(proc.luma_quantile(0.1), Role::Background)`,
+/// or let [`RoleAware::themed`] pick bands from the palette's own background and foreground.
+///
+/// Role colors missing from the palette being mapped to, e.g. excluded ones, are left to the
+/// wrapped mapper.
+#[derive(Debug, Clone)]
+pub struct RoleAware<M: Mapper> {
+    mapper: M,
+    // Roles and bands of each linked variant, used when mapping to that variant
+    themes: Vec<(Roles, Vec<Band>)>,
+}
+
+// Luma range and the role its pixels map to
+type Band = (f32, f32, Role);
+
+impl<M: Mapper> RoleAware<M> {
+    pub fn new(mapper: M, roles: Roles) -> Self {
+        RoleAware {
+            mapper,
+            themes: vec![(roles, Vec::new())],
+        }
+    }
+
+    /// Uses the roles of `palette`, sending the pixels closest in luma to its background and
+    /// foreground to them, so that an image takes on the theme's base colors instead of its
+    /// accents. Each band reaches 15% of the way from its role's luma towards the other's, or
+    /// towards the far end of the range when the palette only has one of the two.
+    ///
+    /// The [variants](palette::Palette::variants) linked to `palette` get their own roles and
+    /// bands, used whenever the palette being mapped to is that variant.
+    ///
+    /// ```
+    /// use mapped::{mappers::{Nearest, RoleAware}, palette::Palette};
+    /// let mapper = RoleAware::themed(Nearest::new(), &Palette::nord());
+    /// ```
+    pub fn themed(mapper: M, palette: &palette::Palette) -> Self {
+        const REACH: f32 = 0.15;
+        let theme = |palette: &palette::Palette| {
+            let roles = palette.roles().clone();
+            let luma = |role| roles.get(role).map(|c: Rgbx| c.luma());
+            let (bg, fg) = (luma(Role::Background), luma(Role::Foreground));
+            let mut bands = Vec::new();
+            for (role, this, other) in [(Role::Background, bg, fg), (Role::Foreground, fg, bg)] {
+                let Some(this) = this else { continue };
+                let other = other.unwrap_or(if this < 0.5 { 1. } else { 0. });
+                let edge = this + (other - this) * REACH;
+                bands.push(if this <= other {
+                    (0., edge, role)
+                } else {
+                    (edge, 1., role)
+                });
+            }
+            (roles, bands)
+        };
+        RoleAware {
+            mapper,
+            themes: std::iter::once(palette)
+                .chain(palette.variants())
+                .map(theme)
+                .collect(),
+        }
+    }
+
+    /// Pixels with a luma (0-1) of at most `luma` map to `role`.
+    #[must_use]
+    pub fn darkest(self, luma: f32, role: Role) -> Self {
+        self.band(0., luma, role)
+    }
+
+    /// Pixels with a luma (0-1) of at least `luma` map to `role`.
+    #[must_use]
+    pub fn lightest(self, luma: f32, role: Role) -> Self {
+        self.band(luma, 1., role)
+    }
+
+    #[must_use]
+    pub fn band(mut self, min: f32, max: f32, role: Role) -> Self {
+        for (_, bands) in self.themes.iter_mut() {
+            bands.push((min, max, role));
+        }
+        self
+    }
+
+    fn role_color(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> Option<[u8; 4]> {
+        let rgb = |c: &Rgbx| [c.0, c.1, c.2];
+        let present = |c: &Rgbx| palette.iter().any(|p| rgb(p) == rgb(c));
+        // The variant whose role colors are all in the palette, the first one otherwise
+        let (roles, bands) = self
+            .themes
+            .iter()
+            .find(|(roles, _)| roles.iter().all(|(_, c)| present(c)))
+            .unwrap_or(&self.themes[0]);
+        let luma = palette::luma(pixel);
+        bands
+            .iter()
+            .filter(|(min, max, _)| (*min..=*max).contains(&luma))
+            .find_map(|(_, _, role)| roles.get(*role).filter(present))
+            .map(|c| c.rgba_array())
+    }
+}
+
+impl<M: Mapper> Mapper for RoleAware<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.role_color(palette, pixel)
+            .unwrap_or_else(|| self.mapper.predict(palette, pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.role_color(palette, pixel)
+            .unwrap_or_else(|| self.mapper.predict_class(palette, pixel, class))
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.role_color(palette, ctx.pixel())
+            .unwrap_or_else(|| self.mapper.predict_context(palette, ctx))
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+/// Keeps every mapped pixel at a WCAG [`contrast`](palette::contrast) of at least `ratio`
+/// against a background, e.g. so that desktop icons and text stay legible on a wallpaper.
+/// Predictions of the wrapped mapper that fall short snap to the closest palette color that
+/// doesn't, or to the highest contrast one if none does.
+///
+/// ```
+/// use mapped::{mappers::{MinContrast, Nearest}, palette::NORD};
+/// let mapper = MinContrast::new(Nearest::new(), NORD[4], 3.);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MinContrast<M: Mapper = Nearest> {
+    mapper: M,
+    background: Rgbx,
+    ratio: f32,
+    allowed: PaletteCache<Rgbx>,
+}
+
+impl<M: Mapper> MinContrast<M> {
+    pub fn new(mapper: M, background: Rgbx, ratio: f32) -> Self {
+        MinContrast {
+            mapper,
+            background,
+            ratio,
+            allowed: PaletteCache::new(),
+        }
+    }
+
+    fn passes(&self, color: &[u8; 4]) -> bool {
+        palette::contrast(color, &self.background.rgba_array()) >= self.ratio
+    }
+
+    fn constrain(&self, palette: &[Rgbx], pixel: &[u8; 4], mapped: [u8; 4]) -> [u8; 4] {
+        if palette.is_empty() || self.passes(&mapped) {
+            return mapped;
+        }
+        self.allowed.build(
+            palette,
+            |palette| {
+                let allowed: Vec<Rgbx> = palette
+                    .iter()
+                    .filter(|c| self.passes(&c.rgba_array()))
+                    .copied()
+                    .collect();
+                if !allowed.is_empty() {
+                    return allowed;
+                }
+                let bg = self.background.rgba_array();
+                let best = palette
+                    .iter()
+                    .max_by(|a, b| {
+                        let contrast = |c: &Rgbx| palette::contrast(&c.rgba_array(), &bg);
+                        contrast(a).total_cmp(&contrast(b))
+                    })
+                    .unwrap();
+                vec![*best]
+            },
+            |allowed| Nearest::new().predict(allowed, pixel),
+        )
+    }
+}
+
+impl<M: Mapper> Mapper for MinContrast<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.constrain(palette, pixel, self.mapper.predict(palette, pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        let mapped = self.mapper.predict_class(palette, pixel, class);
+        self.constrain(palette, pixel, mapped)
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let mapped = self.mapper.predict_context(palette, ctx);
+        self.constrain(palette, ctx.pixel(), mapped)
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+/// Shows how pixels look with a color vision [`Deficiency`](palette::Deficiency), ignoring the
+/// palette. Chain it after another mapper to preview a mapped image through the deficiency:
+///
+/// ```
+/// use mapped::{mappers::{Chain, Nearest, Simulate}, palette::Deficiency};
+/// let preview = Chain::new(Nearest::new(), Simulate::new(Deficiency::Deuteranopia));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Simulate {
+    deficiency: palette::Deficiency,
+}
+
+impl Simulate {
+    pub const fn new(deficiency: palette::Deficiency) -> Self {
+        Simulate { deficiency }
+    }
+}
+
+impl Mapper for Simulate {
+    fn predict(&self, _palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.deficiency.simulate(pixel)
+    }
+}
+
+/// Maps for viewers with a color vision [`Deficiency`](palette::Deficiency). Pixels are
+/// [daltonized](palette::Deficiency::daltonize) first, then handed to the wrapped mapper with
+/// only the palette colors that stay distinguishable under the deficiency: of colors that look
+/// alike, the first one is kept.
+///
+/// ```
+/// use mapped::{mappers::{Daltonize, Nearest}, palette::Deficiency};
+/// let mapper = Daltonize::new(Nearest::new(), Deficiency::Protanopia);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Daltonize<M: Mapper = Nearest> {
+    mapper: M,
+    deficiency: palette::Deficiency,
+    distinct: PaletteCache<Rgbx>,
+}
+
+impl<M: Mapper> Daltonize<M> {
+    pub fn new(mapper: M, deficiency: palette::Deficiency) -> Self {
+        Daltonize {
+            mapper,
+            deficiency,
+            distinct: PaletteCache::new(),
+        }
+    }
+
+    fn distinct<R>(&self, palette: &[Rgbx], f: impl FnOnce(&[Rgbx]) -> R) -> R {
+        // OkLab distance under which simulated colors are taken as the same
+        const ALIKE: f32 = 0.04;
+        self.distinct.build(
+            palette,
+            |palette| {
+                let mut kept: Vec<(Rgbx, [f32; 3])> = Vec::new();
+                for color in palette {
+                    let seen = palette::oklab(&self.deficiency.simulate(&color.rgba_array()));
+                    let alike = |(_, other): &(Rgbx, [f32; 3])| {
+                        (0..3).map(|c| (other[c] - seen[c]).powi(2)).sum::<f32>() < ALIKE.powi(2)
+                    };
+                    if !kept.iter().any(alike) {
+                        kept.push((*color, seen));
+                    }
+                }
+                kept.into_iter().map(|(c, _)| c).collect()
+            },
+            f,
+        )
+    }
+}
+
+impl<M: Mapper> Mapper for Daltonize<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let pixel = self.deficiency.daltonize(pixel);
+        self.distinct(palette, |palette| self.mapper.predict(palette, &pixel))
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(&self.deficiency.daltonize(pixel))
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        let pixel = self.deficiency.daltonize(pixel);
+        self.distinct(palette, |palette| {
+            self.mapper.predict_class(palette, &pixel, class)
+        })
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    /// The wrapped mapper sees the daltonized image, surroundings included, once prepared.
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let ctx = match ctx.prepared::<Daltonized>() {
+            Some(Daltonized { pixels, inner }) => {
+                PixelContext::new(pixels, ctx.dimensions(), ctx.position())
+                    .with_prepared(inner.as_deref())
+            }
+            None => ctx.with_pixel(self.deficiency.daltonize(ctx.pixel())),
+        };
+        self.distinct(palette, |palette| {
+            self.mapper.predict_context(palette, &ctx)
+        })
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        let pixels: Vec<[u8; 4]> = pixels
+            .iter()
+            .map(|p| self.deficiency.daltonize(p))
+            .collect();
+        let inner = self.distinct(palette, |palette| {
+            self.mapper.prepare(palette, &pixels, dimensions)
+        });
+        Some(Box::new(Daltonized { pixels, inner }))
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+// Per-image state of Daltonize: the daltonized image and whatever the wrapped mapper prepared for it
+struct Daltonized {
+    pixels: Vec<[u8; 4]>,
+    inner: Option<Prepared>,
+}
+
+/// Error diffusion kernels for [`Dither`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum DiffusionKernel {
+    #[default]
+    FloydSteinberg,
+    JarvisJudiceNinke,
+    Stucki,
+    Burkes,
+    Sierra3,
+    Sierra2,
+    SierraLite,
+    /// Rows of weights starting at the current row, with the current pixel at column `origin` of
+    /// the first row. Weights at or left of the origin in the first row are ignored, and every
+    /// weight is divided by `divisor`.
+    Custom {
+        weights: Vec<Vec<f32>>,
+        origin: usize,
+        divisor: f32,
+    },
+    /// Riemersma dithering: instead of going row by row, the image is walked along a Hilbert
+    /// curve and the errors of the last `history` pixels (16 is typical) are carried forward with
+    /// decaying weights. This avoids the directional artifacts of raster order diffusion.
+    Riemersma {
+        history: usize,
+    },
+}
+
+impl DiffusionKernel {
+    /// Offsets `(dx, dy)` of the neighbours receiving error, with the share each one gets. Empty
+    /// for [`DiffusionKernel::Riemersma`], which doesn't diffuse to fixed neighbours.
+    pub fn taps(&self) -> Vec<(isize, usize, f32)> {
+        let (weights, origin, divisor): (&[&[f32]], usize, f32) = match self {
+            Self::FloydSteinberg => (&[&[0., 0., 7.], &[3., 5., 1.]], 1, 16.),
+            Self::JarvisJudiceNinke => (
+                &[
+                    &[0., 0., 0., 7., 5.],
+                    &[3., 5., 7., 5., 3.],
+                    &[1., 3., 5., 3., 1.],
+                ],
+                2,
+                48.,
+            ),
+            Self::Stucki => (
+                &[
+                    &[0., 0., 0., 8., 4.],
+                    &[2., 4., 8., 4., 2.],
+                    &[1., 2., 4., 2., 1.],
+                ],
+                2,
+                42.,
+            ),
+            Self::Burkes => (&[&[0., 0., 0., 8., 4.], &[2., 4., 8., 4., 2.]], 2, 32.),
+            Self::Sierra3 => (
+                &[
+                    &[0., 0., 0., 5., 3.],
+                    &[2., 4., 5., 4., 2.],
+                    &[0., 2., 3., 2., 0.],
+                ],
+                2,
+                32.,
+            ),
+            Self::Sierra2 => (&[&[0., 0., 0., 4., 3.], &[1., 2., 3., 2., 1.]], 2, 16.),
+            Self::SierraLite => (&[&[0., 0., 2.], &[1., 1., 0.]], 1, 4.),
+            Self::Custom {
+                weights,
+                origin,
+                divisor,
+            } => {
+                let rows: Vec<&[f32]> = weights.iter().map(Vec::as_slice).collect();
+                return Self::collect_taps(&rows, *origin, *divisor);
+            }
+            Self::Riemersma { .. } => return Vec::new(),
+        };
+        Self::collect_taps(weights, origin, divisor)
+    }
+
+    fn collect_taps(weights: &[&[f32]], origin: usize, divisor: f32) -> Vec<(isize, usize, f32)> {
+        let mut taps = Vec::new();
+        for (dy, row) in weights.iter().enumerate() {
+            for (col, &w) in row.iter().enumerate() {
+                let share = w / divisor;
+                if (dy == 0 && col <= origin) || share == 0. || !share.is_finite() {
+                    continue;
+                }
+                taps.push((col as isize - origin as isize, dy, share));
+            }
+        }
+        taps
+    }
+}
+
+/// How a dithering mapper wants its error diffused.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diffusion {
+    pub kernel: DiffusionKernel,
+    /// Fraction of the error that is diffused, from 0 (no dithering) to 1.
+    pub strength: f32,
+    /// Scan every other row right to left, which avoids the diagonal "worm" patterns of plain
+    /// left to right diffusion. Has no effect on [`DiffusionKernel::Riemersma`].
+    pub serpentine: bool,
+    /// Diffuse bands of this many rows in parallel, unless the processor is set to
+    /// [`Threads::Single`](crate::Threads::Single). Each band starts diffusing `overlap` rows
+    /// early, and the overlapping rows are blended with the band above so no seams show. Has no
+    /// effect on [`DiffusionKernel::Riemersma`].
+    pub tiles: Option<(u32, u32)>,
+}
+
+impl Default for Diffusion {
+    fn default() -> Self {
+        Diffusion {
+            kernel: DiffusionKernel::default(),
+            strength: 1.,
+            serpentine: false,
+            tiles: None,
+        }
+    }
+}
+
+/// Error diffusion dithering on top of another mapper, Floyd–Steinberg unless another
+/// [`DiffusionKernel`] is picked. The difference between each pixel and the color it was mapped
+/// to is spread over its unmapped neighbours, so gradients come out as a mix of palette colors
+/// instead of flat bands.
+///
+/// Since every pixel depends on the ones before it, the processor maps images sequentially when a
+/// dithering mapper is used, unless [`Dither::tiled`] is enabled. Called directly,
+/// [`Mapper::predict`] simply defers to the wrapped mapper.
+#[derive(Debug, Clone)]
+pub struct Dither<M: Mapper = Nearest> {
+    mapper: M,
+    diffusion: Diffusion,
+}
+
+impl<M: Mapper> Dither<M> {
+    pub fn new(mapper: M) -> Self {
+        Dither {
+            mapper,
+            diffusion: Diffusion::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn kernel(mut self, kernel: DiffusionKernel) -> Self {
+        self.diffusion.kernel = kernel;
+        self
+    }
+
+    /// Fraction of the error that is diffused, from 0 (no dithering) to 1 (the default).
+    #[must_use]
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.diffusion.strength = strength.clamp(0., 1.);
+        self
+    }
+
+    /// See [`Diffusion::serpentine`].
+    #[must_use]
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.diffusion.serpentine = serpentine;
+        self
+    }
+
+    /// See [`Diffusion::tiles`].
+    #[must_use]
+    pub fn tiled(mut self, rows: u32, overlap: u32) -> Self {
+        self.diffusion.tiles = Some((rows.max(1), overlap));
+        self
+    }
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Dither::new(Nearest::new())
+    }
+}
+
+impl<M: Mapper> Mapper for Dither<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mapper.predict(palette, pixel)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        Some(&self.diffusion)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bayer {
+    X2,
+    X4,
+    X8,
+}
+
+impl Bayer {
+    pub const fn size(&self) -> usize {
+        match self {
+            Self::X2 => 2,
+            Self::X4 => 4,
+            Self::X8 => 8,
+        }
+    }
+
+    /// Threshold rank of the cell at `(x, y)`, from 0 to `size² - 1`.
+    pub fn rank(&self, x: usize, y: usize) -> usize {
+        let bits = self.size().trailing_zeros();
+        (0..bits).fold(0, |v, bit| {
+            let (xb, yb) = ((x >> bit) & 1, (y >> bit) & 1);
+            (v << 2) | ((xb ^ yb) << 1 | yb)
+        })
+    }
+}
+
+/// Ordered dithering with a Bayer threshold matrix on top of another mapper. Each pixel is
+/// shifted by up to half of `spread` in either direction depending on its position in the tiled
+/// matrix, then mapped as usual. Pixels don't depend on each other, so this works with every
+/// [`Threads`](crate::Threads) mode and with memoization.
+#[derive(Debug, Clone)]
+pub struct OrderedDither<M: Mapper = Nearest> {
+    mapper: M,
+    matrix: Bayer,
+    thresholds: [f32; 64],
+    spread: f32,
+    strength: f32,
+}
+
+impl<M: Mapper> OrderedDither<M> {
+    pub fn new(mapper: M, matrix: Bayer) -> Self {
+        let n = matrix.size();
+        let mut thresholds = [0.; 64];
+        for (i, t) in thresholds.iter_mut().take(n * n).enumerate() {
+            *t = (matrix.rank(i % n, i / n) as f32 + 0.5) / (n * n) as f32 - 0.5;
+        }
+        OrderedDither {
+            mapper,
+            matrix,
+            thresholds,
+            spread: 64.,
+            strength: 1.,
+        }
+    }
+
+    /// Range of the offsets added to color channels, 64 by default. Palettes with colors far
+    /// apart need a larger spread.
+    #[must_use]
+    pub fn spread(mut self, spread: f32) -> Self {
+        self.spread = spread.max(0.);
+        self
+    }
+
+    /// Scales the offsets, from 0 (flat colors) to 1 (the default).
+    #[must_use]
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength.clamp(0., 1.);
+        self
+    }
+}
+
+impl<M: Mapper> Mapper for OrderedDither<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mapper.predict(palette, pixel)
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        let n = self.matrix.size();
+        let i = (y as usize % n) * n + x as usize % n;
+        Some(self.thresholds[i] * self.spread * self.strength)
+    }
+}
+
+// Void-and-cluster threshold textures, one 0-255 threshold per cell in row-major order
+const BLUE_NOISE_16: [u8; 256] = include!("bluenoise16");
+const BLUE_NOISE_32: [u8; 1024] = include!("bluenoise32");
+pub(crate) const BLUE_NOISE_64: [u8; 4096] = include!("bluenoise64");
+
+/// Side length of the tiled blue-noise texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseSize {
+    X16,
+    X32,
+    #[default]
+    X64,
+}
+
+impl NoiseSize {
+    pub const fn size(&self) -> usize {
+        match self {
+            Self::X16 => 16,
+            Self::X32 => 32,
+            Self::X64 => 64,
+        }
+    }
+
+    fn texture(&self) -> &'static [u8] {
+        match self {
+            Self::X16 => &BLUE_NOISE_16,
+            Self::X32 => &BLUE_NOISE_32,
+            Self::X64 => &BLUE_NOISE_64,
+        }
+    }
+}
+
+/// Ordered dithering with a tiled blue-noise texture instead of a Bayer matrix. The pattern has no
+/// visible structure, which suits photographs better; larger textures repeat less noticeably.
+/// Like [`OrderedDither`], it works with every threading mode and with memoization.
+#[derive(Debug, Clone)]
+pub struct BlueNoiseDither<M: Mapper = Nearest> {
+    mapper: M,
+    size: NoiseSize,
+    spread: f32,
+    strength: f32,
+}
+
+impl<M: Mapper> BlueNoiseDither<M> {
+    pub fn new(mapper: M, size: NoiseSize) -> Self {
+        BlueNoiseDither {
+            mapper,
+            size,
+            spread: 64.,
+            strength: 1.,
+        }
+    }
+
+    /// Range of the offsets added to color channels, 64 by default.
+    #[must_use]
+    pub fn spread(mut self, spread: f32) -> Self {
+        self.spread = spread.max(0.);
+        self
+    }
+
+    /// Scales the offsets, from 0 (flat colors) to 1 (the default).
+    #[must_use]
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength.clamp(0., 1.);
+        self
+    }
+}
+
+impl<M: Mapper> Mapper for BlueNoiseDither<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mapper.predict(palette, pixel)
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        let n = self.size.size();
+        let t = self.size.texture()[(y as usize % n) * n + x as usize % n];
+        Some(((t as f32 + 0.5) / 256. - 0.5) * self.spread * self.strength)
+    }
+}
+
+/// Dithers smooth regions and maps detailed ones without dithering: the image is split into
+/// square tiles, and tiles whose luma standard deviation stays below the threshold go through the
+/// `smooth` mapper, with its [`Mapper::dither_offset`] applied. Everything else goes through
+/// `detailed`. Error diffusion can't be applied per region, so use an ordered or blue-noise
+/// dither for `smooth`.
+#[derive(Debug, Clone)]
+pub struct Adaptive<S: Mapper = BlueNoiseDither, D: Mapper = Nearest> {
+    smooth: S,
+    detailed: D,
+    tile: u32,
+    threshold: f32,
+}
+
+// Per-image state of Adaptive: the number of tile columns, which tiles are smooth, and whatever
+// the detailed mapper prepared
+struct TileState {
+    columns: u32,
+    smooth: Vec<bool>,
+    detailed: Option<Prepared>,
+}
+
+impl<S: Mapper, D: Mapper> Adaptive<S, D> {
+    pub fn new(smooth: S, detailed: D) -> Self {
+        Adaptive {
+            smooth,
+            detailed,
+            tile: 16,
+            threshold: 0.05,
+        }
+    }
+
+    /// Side length of the analyzed tiles in pixels, 16 by default.
+    #[must_use]
+    pub fn tile(mut self, size: u32) -> Self {
+        self.tile = size.max(1);
+        self
+    }
+
+    /// Luma standard deviation (0-1) below which a tile counts as smooth, 0.05 by default.
+    #[must_use]
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive::new(
+            BlueNoiseDither::new(Nearest::new(), NoiseSize::default()),
+            Nearest::new(),
+        )
+    }
+}
+
+impl<S: Mapper, D: Mapper> Mapper for Adaptive<S, D> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.detailed.predict(palette, pixel)
+    }
+
+    fn uses_context(&self) -> bool {
+        true
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let (x, y) = ctx.position();
+        let Some(tiles) = ctx.prepared::<TileState>() else {
+            return self.detailed.predict_context(palette, ctx);
+        };
+        let i = (y / self.tile * tiles.columns + x / self.tile) as usize;
+        if !tiles.smooth.get(i).copied().unwrap_or(false) {
+            return self
+                .detailed
+                .predict_context(palette, &ctx.with_prepared(tiles.detailed.as_deref()));
+        }
+        let pixel = ctx.pixel();
+        match self.smooth.dither_offset(x, y) {
+            Some(offset) => {
+                let shift = |c: u8| (c as f32 + offset).round().clamp(0., 255.) as u8;
+                let px = [shift(pixel[0]), shift(pixel[1]), shift(pixel[2]), pixel[3]];
+                self.smooth.predict(palette, &px)
+            }
+            None => self.smooth.predict(palette, pixel),
+        }
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        (w, h): (u32, u32),
+    ) -> Option<Prepared> {
+        // The smooth mapper only ever predicts single pixels, so it needs no state
+        let detailed = self.detailed.prepare(palette, pixels, (w, h));
+        let columns = w.div_ceil(self.tile);
+        let rows = h.div_ceil(self.tile);
+        let mut stats = vec![(0f32, 0f32, 0f32); (columns * rows) as usize];
+        for (i, px) in pixels.iter().enumerate() {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            let l = palette::luma(px);
+            let s = &mut stats[(y / self.tile * columns + x / self.tile) as usize];
+            *s = (s.0 + l, s.1 + l * l, s.2 + 1.);
+        }
+        let smooth = stats
+            .into_iter()
+            .map(|(sum, sq, n)| {
+                let mean = sum / n;
+                (sq / n - mean * mean).max(0.).sqrt() < self.threshold
+            })
+            .collect();
+        Some(Box::new(TileState {
+            columns,
+            smooth,
+            detailed,
+        }))
+    }
+}
+
+/// Poster-like mapping: [`Mapper::prepare`] segments the image into superpixels with SLIC, and
+/// every segment is mapped as a whole, through its average color, to a single palette color.
+#[derive(Debug, Clone)]
+pub struct Segmented<M: Mapper = Nearest> {
+    mapper: M,
+    size: u32,
+    compactness: f32,
+    iterations: usize,
+}
+
+impl<M: Mapper> Segmented<M> {
+    pub fn new(mapper: M) -> Self {
+        Segmented {
+            mapper,
+            size: 24,
+            compactness: 10.,
+            iterations: 10,
+        }
+    }
+
+    /// Approximate side length of a segment in pixels, 24 by default.
+    #[must_use]
+    pub fn size(mut self, size: u32) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// How strongly segments are kept square rather than following color edges, 10 by default.
+    #[must_use]
+    pub fn compactness(mut self, compactness: f32) -> Self {
+        self.compactness = compactness.max(0.);
+        self
+    }
+
+    /// Number of SLIC refinement passes, 10 by default.
+    #[must_use]
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    // SLIC superpixels, without the connectivity post-processing: returns the segment of every
+    // pixel and the number of segments
+    fn segment(&self, pixels: &[[u8; 4]], (w, h): (u32, u32)) -> (Vec<usize>, usize) {
+        let labs: Vec<_> = pixels.iter().map(palette::lab).collect();
+        let s = self.size.min(w).min(h).max(1);
+        // Centers hold the Lab color followed by the position
+        let mut centers: Vec<[f32; 5]> = Vec::new();
+        for y in (s / 2..h).step_by(s as usize) {
+            for x in (s / 2..w).step_by(s as usize) {
+                let [l, a, b] = labs[(y * w + x) as usize];
+                centers.push([l, a, b, x as f32, y as f32]);
+            }
+        }
+        let weight = (self.compactness / s as f32).powi(2);
+        let mut labels = vec![0; pixels.len()];
+        for _ in 0..self.iterations.max(1) {
+            let mut best = vec![f32::INFINITY; pixels.len()];
+            for (k, c) in centers.iter().enumerate() {
+                let (cx, cy) = (c[3] as i64, c[4] as i64);
+                let (x0, x1) = ((cx - s as i64).max(0), (cx + s as i64).min(w as i64 - 1));
+                let (y0, y1) = ((cy - s as i64).max(0), (cy + s as i64).min(h as i64 - 1));
+                for y in y0..=y1 {
+                    for x in x0..=x1 {
+                        let i = (y * w as i64 + x) as usize;
+                        let lab = &labs[i];
+                        let dc = (0..3).map(|j| (lab[j] - c[j]).powi(2)).sum::<f32>();
+                        let ds = (x as f32 - c[3]).powi(2) + (y as f32 - c[4]).powi(2);
+                        let d = dc + ds * weight;
+                        if d < best[i] {
+                            best[i] = d;
+                            labels[i] = k;
+                        }
+                    }
+                }
+            }
+            let mut sums = vec![([0f32; 5], 0f32); centers.len()];
+            for (i, &k) in labels.iter().enumerate() {
+                let lab = &labs[i];
+                let pos = [(i as u32 % w) as f32, (i as u32 / w) as f32];
+                let sum = &mut sums[k];
+                for (j, v) in lab.iter().chain(&pos).enumerate() {
+                    sum.0[j] += v;
+                }
+                sum.1 += 1.;
+            }
+            for (c, (sum, n)) in centers.iter_mut().zip(sums) {
+                if n > 0. {
+                    *c = sum.map(|v| v / n);
+                }
+            }
+        }
+        (labels, centers.len())
+    }
+}
+
+impl Default for Segmented {
+    fn default() -> Self {
+        Segmented::new(Nearest::new())
+    }
+}
+
+impl<M: Mapper> Mapper for Segmented<M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mapper.predict(palette, pixel)
+    }
+
+    fn uses_context(&self) -> bool {
+        true
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        let (x, y) = ctx.position();
+        let i = (y * ctx.dimensions().0 + x) as usize;
+        match ctx.prepared::<Vec<[u8; 4]>>().and_then(|c| c.get(i)) {
+            Some(&mapped) => mapped,
+            None => self.predict(palette, ctx.pixel()),
+        }
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        if pixels.is_empty() {
+            return None;
+        }
+        let (labels, segments) = self.segment(pixels, dimensions);
+        let mut sums = vec![([0u64; 4], 0u64); segments];
+        for (px, &k) in pixels.iter().zip(&labels) {
+            let sum = &mut sums[k];
+            (0..4).for_each(|j| sum.0[j] += px[j] as u64);
+            sum.1 += 1;
+        }
+        let mapped: Vec<_> = sums
+            .into_iter()
+            .map(|(sum, n)| {
+                let avg = sum.map(|v| (v / n.max(1)) as u8);
+                self.mapper.predict(palette, &avg)
+            })
+            .collect();
+        let colors: Vec<[u8; 4]> = labels.into_iter().map(|k| mapped[k]).collect();
+        Some(Box::new(colors))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::distance::*;
+    use crate::palette::ColorClass::*;
+    use crate::palette::*;
+    use crate::rgbx;
+    use std::collections::HashSet;
+
+    const BASIC_COLORS: [Rgbx; 14] = [
+        rgbx!(255, 0, 0, r),
+        rgbx!(255, 0, 127, r),
+        rgbx!(255, 128, 0, or),
+        rgbx!(255, 255, 0, y),
+        rgbx!(128, 255, 0, gn),
+        rgbx!(0, 255, 0, gn),
+        rgbx!(0, 255, 128, gn),
+        rgbx!(0, 255, 255, b),
+        rgbx!(0, 128, 255, b),
+        rgbx!(0, 0, 255, b),
+        rgbx!(255, 0, 255, p),
+        rgbx!(128, 128, 128, gy),
+        rgbx!(0, 0, 0, gy),
+        rgbx!(255, 255, 255, w),
+    ];
+
+    #[test]
+    fn basic_color_accuracy() {
+        let eval = evaluate(&Knn::with(30), &BASIC_COLORS);
+        for (expected, predicted, n) in eval.errors() {
+            println!("Failed to predict {n} {expected:?}, prediction: {predicted:?}");
+        }
+        println!("Basic color prediction accuracy: {}%", eval.accuracy());
+        assert!(eval.accuracy() > 95.0)
+    }
+
+    #[test]
+    fn standalone_classify() {
+        for c in BASIC_COLORS {
+            assert_eq!(palette::classify(&c.rgba_array()), c.3);
+        }
+    }
+
+    #[test]
+    fn themed_palettes() {
+        assert_eq!(registry().len(), 14);
+        assert_eq!(by_name("NORD"), Some(&Palette::nord()));
+        assert_eq!(by_name("rose_pine").unwrap().colors(), ROSE_PINE);
+        assert_eq!(
+            by_name("Catppuccin Frappé").unwrap().colors(),
+            CATPPUCCIN_FRAPPE
+        );
+        assert_eq!(by_name("solarized dark"), None);
+        let knn = Knn::builder().random_ties(false).build();
+        for theme in registry() {
+            assert!(theme.role(Role::Background).is_some() && theme.color_name(0).is_some());
+            let unique: HashSet<_> = theme.iter().map(|c| c.rgba_array()).collect();
+            assert_eq!(unique.len(), theme.len());
+            for c in BASIC_COLORS
+                .iter()
+                .filter(|c| theme.iter().any(|t| t.3 == c.3))
+            {
+                let mapped = knn.predict(theme, &c.rgba_array());
+                let entry = theme.iter().find(|t| t.rgba_array() == mapped).unwrap();
+                assert_eq!(entry.3, c.3, "{c:?} mapped to {entry:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn hex_palettes() {
+        let palette = Palette::from_hex_str("#2e3440\n\n  #D8DEE9\nbf616a, #fff\n").unwrap();
+        assert_eq!(
+            palette.colors(),
+            [
+                rgbx!(46, 52, 64, gy),
+                rgbx!(216, 222, 233, w),
+                rgbx!(191, 97, 106, r),
+                rgbx!(255, 255, 255, w),
+            ]
+        );
+        assert_eq!(
+            Palette::from_hex(&["#2e3440", "#d8dee9", "#bf616a"]).unwrap()[..],
+            palette[..3]
+        );
+        assert!(Palette::from_hex(&["#2e344"]).is_err());
+        assert!(Palette::from_hex_str("#2e3440 #+12345").is_err());
+    }
+
+    #[test]
+    fn gimp_palettes() {
+        let gpl = "GIMP Palette\r\n\
+            Name: Lospec\r\n\
+            Columns: 4\r\n\
+            # https://lospec.com\r\n\
+            \r\n\
+            191  97 106\tbf616a\r\n\
+              0   0   0\tUntitled\r\n\
+            163 190 140\r\n";
+        let palette = Palette::from_gpl(gpl).unwrap();
+        assert_eq!(palette.name(), Some("Lospec"));
+        assert_eq!(
+            palette.colors(),
+            [
+                rgbx!(191, 97, 106, r),
+                rgbx!(0, 0, 0, gy),
+                rgbx!(163, 190, 140, gn)
+            ]
+        );
+        assert_eq!(palette.color_name(0), Some("bf616a"));
+        assert_eq!(palette.color_name(1), None);
+        assert!(matches!(
+            Palette::from_gpl("GIMP Palette\n0 0 0\n0 0 x\n"),
+            Err(PaletteError::Malformed(3))
+        ));
+        assert!(matches!(
+            Palette::from_gpl("0 0 0\n"),
+            Err(PaletteError::MissingHeader("GIMP Palette"))
+        ));
+    }
+
+    #[test]
+    fn jasc_and_paint_net_palettes() {
+        let expected = [rgbx!(191, 97, 106, r), rgbx!(46, 52, 64, gy)];
+        let pal =
+            Palette::from_jasc("JASC-PAL\r\n0100\r\n2\r\n191 97 106\r\n46 52 64\r\n").unwrap();
+        assert_eq!(pal.colors(), expected);
+        assert!(matches!(
+            Palette::from_jasc("JASC-PAL\n0100\n2\n191 97\n"),
+            Err(PaletteError::Malformed(4))
+        ));
+        assert!(matches!(
+            Palette::from_jasc("GIMP Palette\n"),
+            Err(PaletteError::MissingHeader("JASC-PAL"))
+        ));
+        let txt = "; paint.net Palette File\n;Colors: 2\nFFBF616A\n802E3440\n";
+        assert_eq!(Palette::from_paint_net(txt).unwrap().colors(), expected);
+        assert!(matches!(
+            Palette::from_paint_net("FFBF616A\n#2E3440\n"),
+            Err(PaletteError::Malformed(2))
+        ));
+        for line in ["€12345", "+fBF616A", "FFBF616A€"] {
+            assert!(matches!(
+                Palette::from_paint_net(line),
+                Err(PaletteError::Malformed(1))
+            ));
+        }
+    }
+
+    #[test]
+    fn base16_schemes() {
+        let nord = r##"
+system: "base16"
+name: "Nord"
+author: "arcticicestudio"
+variant: "dark"
+palette:
+  base00: "#2E3440" # background
+  base01: "#3B4252"
+  base02: "#434C5E"
+  base03: "#4C566A"
+  base04: "#D8DEE9"
+  base05: "#E5E9F0"
+  base06: "#ECEFF4"
+  base07: "#8FBCBB"
+  base08: "#BF616A"
+  base09: "#D08770"
+  base0A: "#EBCB8B"
+  base0B: "#A3BE8C"
+  base0C: "#88C0D0"
+  base0D: "#81A1C1"
+  base0E: "#B48EAD"
+  base0F: "#5E81AC"
+"##;
+        let scheme = Palette::from_base16(nord).unwrap();
+        assert_eq!(scheme.to_string(), "Nord by arcticicestudio");
+        assert_eq!(scheme.len(), 16);
+        assert_eq!(scheme[0], rgbx!(46, 52, 64, gy));
+        assert_eq!(scheme[5], rgbx!(229, 233, 240, w));
+        assert_eq!(scheme[8], rgbx!(191, 97, 106, r));
+        assert_eq!(scheme[12], rgbx!(136, 192, 208, c));
+        assert_eq!(scheme[15], rgbx!(94, 129, 172, br));
+        assert_eq!(scheme.role(Role::Accent(0)), Some(scheme[13]));
+        // Legacy layout, with the base24 extension
+        let legacy = (0..24).fold(String::from("scheme: 'Ramp''s'\n"), |yaml, i| {
+            yaml + format!("base{i:02X}: {:02x}{0:02x}{0:02x}\n", i * 10).as_str()
+        });
+        let scheme = Palette::from_base16(&legacy).unwrap();
+        assert_eq!(scheme.name(), Some("Ramp's"));
+        assert_eq!(scheme.author(), None);
+        assert_eq!(scheme.len(), 24);
+        assert_eq!(scheme.color_name(23), Some("base17"));
+        assert!(matches!(
+            Palette::from_base16(&legacy.replace("base0B", "baseXX")),
+            Err(PaletteError::MissingColor("base0B"))
+        ));
+    }
+
+    #[test]
+    fn rgbx_strings() {
+        for s in [
+            "#BF616A",
+            "bf616a",
+            "#bf616a80",
+            " rgb(191, 97,106) ",
+            "RGB(191,97,106)",
+        ] {
+            assert_eq!(s.parse().ok(), Some(rgbx!(191, 97, 106, r)), "{s}");
+        }
+        assert_eq!(
+            "#fff".parse::<Rgbx>().map(|c| c.to_hex()).ok(),
+            Some("#ffffff".into())
+        );
+        for s in [
+            "#bf616",
+            "#bf616a8",
+            "rgb(191, 97)",
+            "rgb(191, 97, 106, 1)",
+            "rgb(256, 0, 0)",
+        ] {
+            assert!(
+                matches!(s.parse::<Rgbx>(), Err(PaletteError::InvalidHex(e)) if e == s),
+                "{s}"
+            );
+        }
+        assert_eq!(NORD[12].to_string(), "#2e3440");
+    }
+
+    #[test]
+    fn palette_macro() {
+        const THEME: [Rgbx; 4] = crate::palette![
+            "#2E3440" gy,
+            "bf616a" r,
+            "#fff" w,
+            "#88c0d0" b,
+        ];
+        assert_eq!(THEME, [NORD[12], NORD[7], rgbx!(255, 255, 255, w), NORD[4]]);
+        let classified = crate::palette!["#bf616a", "#000" pk];
+        assert_eq!(classified, [rgbx!(191, 97, 106, r), rgbx!(0, 0, 0, pk)]);
+
+        // The original suffixes keep their meaning
+        let classes = [rgbx!(0, 0, 0, g), rgbx!(0, 0, 0, o)].map(|c| c.3);
+        assert_eq!(classes, [Greys, Yellow]);
+        let classes = [rgbx!(0, 0, 0, gn), rgbx!(0, 0, 0, or)].map(|c| c.3);
+        assert_eq!(classes, [Green, Orange]);
+        assert_eq!(crate::palette!["#000" g, "#000" gn][0].3, Greys);
+    }
+
+    #[test]
+    fn palette_validation() {
+        assert!(matches!(
+            Palette::nord().validate()[..],
+            [PaletteIssue::NearDuplicate(1, 2, _)]
+        ));
+        let palette = Palette::from_hex(&["#000", "#fff", "#f00", "#010101", "#fff"]).unwrap();
+        let issues = palette.validate();
+        assert!(matches!(issues[0], PaletteIssue::NearDuplicate(0, 3, d) if d < 1.));
+        assert_eq!(issues[1], PaletteIssue::Duplicate(1, 4));
+        assert_eq!(issues[2], PaletteIssue::MissingClass(Blues));
+        assert_eq!(issues.last(), Some(&PaletteIssue::HueGap(0., 0.)));
+        assert_eq!(
+            Palette::from_hex(&["#000", "#f00", "#0f0"])
+                .unwrap()
+                .validate()
+                .last(),
+            Some(&PaletteIssue::HueGap(120., 0.))
+        );
+        assert_eq!(
+            Palette::default().validate().last(),
+            Some(&PaletteIssue::HueGap(0., 360.))
+        );
+    }
+
+    #[test]
+    fn contrast_report() {
+        let palette = Palette::from_hex(&["#000", "#fff", "#777", "#666"]).unwrap();
+        let report = palette.contrast_report();
+        assert_eq!(report.len(), 6);
+        assert!(report.windows(2).all(|w| w[0].ratio <= w[1].ratio));
+        let last = report.last().unwrap();
+        assert_eq!(
+            (last.first, last.second, last.level()),
+            (0, Some(1), ContrastLevel::Aaa)
+        );
+        assert!((last.ratio - 21.).abs() < 1e-3);
+        assert_eq!(report[0].level(), ContrastLevel::Fail);
+
+        let on_white = palette.contrast_against(&rgbx!(255, 255, 255, w));
+        let levels: Vec<_> = on_white.iter().map(|p| (p.first, p.level())).collect();
+        assert_eq!(
+            levels,
+            [
+                (1, ContrastLevel::Fail),
+                (2, ContrastLevel::AaLarge),
+                (3, ContrastLevel::Aa),
+                (0, ContrastLevel::Aaa)
+            ]
+        );
+        assert!(on_white.iter().all(|p| p.second.is_none()));
+        assert_eq!(ContrastLevel::Aa.to_string(), "AA");
+        assert!(Palette::default().contrast_report().is_empty());
+    }
+
+    #[test]
+    fn palette_merge_and_dedup() {
+        let extracted = Palette::new([rgbx!(192, 97, 106, r), rgbx!(10, 10, 10, gy)])
+            .name_color(0, "lips")
+            .assign(Role::Accent(0), 0)
+            .assign(Role::Background, 1);
+        let merged = Palette::nord().merge(&extracted);
+        assert_eq!(merged.name(), Some("Nord"));
+        assert_eq!(merged.len(), 18);
+        assert_eq!(merged.color_name(16), Some("lips"));
+        assert_eq!(merged.role(Role::Background), Some(NORD[12]));
+        assert_eq!(merged.role(Role::Accent(0)), Some(NORD[4]));
+
+        let merged = extracted.merge(&Palette::nord());
+        let exact = merged.clone().dedup(0.);
+        assert_eq!(exact.len(), 18);
+        let deduped = merged.dedup(1.);
+        assert_eq!(deduped.len(), 17);
+        assert_eq!(deduped[0], rgbx!(192, 97, 106, r));
+        assert_eq!(deduped.color_name(0), Some("lips"));
+        assert_eq!(deduped.by_name("nord11"), None);
+        assert_eq!(deduped.role(Role::Foreground), Some(NORD[0]));
+        assert_eq!(Palette::new([NORD[0], NORD[0]]).dedup(0.).len(), 1);
+    }
+
+    #[test]
+    fn palette_export() {
+        let nord = Palette::nord();
+        let rgb = |p: &Palette| p.iter().map(|c| c.rgba_array()).collect::<Vec<_>>();
+        let gpl = Palette::from_gpl(&nord.export(PaletteFormat::Gpl)).unwrap();
+        assert_eq!(gpl.name(), Some("Nord"));
+        assert_eq!(rgb(&gpl), rgb(&nord));
+        assert_eq!(gpl.color_name(4), Some("nord8"));
+        let hex = Palette::from_hex_str(&nord.export(PaletteFormat::Hex)).unwrap();
+        assert_eq!(rgb(&hex), rgb(&nord));
+
+        let duo = Palette::new([rgbx!(191, 97, 106, r), rgbx!(0, 0, 0, gy)]).named("\"Duo\"");
+        assert_eq!(
+            duo.export(PaletteFormat::Json),
+            "{\"name\": \"\\\"Duo\\\"\", \"author\": \"\", \"colors\": [\"bf616a\", \"000000\"]}\n"
+        );
+        assert!(duo
+            .export(PaletteFormat::Gpl)
+            .ends_with("  0   0   0\tUntitled\n"));
+        assert_eq!("GPL".parse(), Ok(PaletteFormat::Gpl));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn palette_serde() {
+        let nord = Palette::nord();
+        let json = serde_json::to_string(&nord).unwrap();
+        assert_eq!(serde_json::from_str::<Palette>(&json).unwrap(), nord);
+        assert!(!json.contains("variants"));
+        let linked = nord.clone().with_variant(Palette::new([NORD[0]]));
+        let json = serde_json::to_string(&linked).unwrap();
+        assert_eq!(serde_json::from_str::<Palette>(&json).unwrap(), linked);
+
+        let color: Rgbx = serde_json::from_str(r#"[191, 97, 106, "red"]"#).unwrap();
+        assert_eq!(color, rgbx!(191, 97, 106, r));
+        assert_eq!(
+            serde_json::to_string(&Custom(3)).unwrap(),
+            r#"{"custom":3}"#
+        );
+        let palette: Palette = serde_json::from_str(
+            r#"{"colors": [[0, 0, 0, "greys"]], "color_names": ["black", "extra"]}"#,
+        )
+        .unwrap();
+        assert_eq!(palette.name(), None);
+        assert_eq!(palette.by_name("extra"), None);
+        assert_eq!(palette.by_name("black"), Some(rgbx!(0, 0, 0, gy)));
+    }
+
+    #[test]
+    fn cross_validation() {
+        let eval = cross_validate(&SYN_DATA_SET, 4, |train| {
+            Knn::builder().k(5).dataset(train).seed(0).build()
+        });
+        assert_eq!(eval.total, SYN_DATA_SET.len());
+        assert!(eval.accuracy() > 90.0);
+        for class in eval.classes() {
+            assert!(eval.precision(class).unwrap() > 80.0);
+            assert!(eval.recall(class).unwrap() > 80.0);
+        }
+        assert_eq!(eval.recall(Pink), None);
+    }
+
+    #[test]
+    fn evaluation_confusion() {
+        let sample = [
+            rgbx!(255, 0, 0, r),
+            rgbx!(0, 0, 255, r),
+            rgbx!(0, 255, 0, gn),
+        ];
+        let eval = evaluate(&NearestCentroid, &sample);
+        assert_eq!((eval.total, eval.correct), (3, 2));
+        assert_eq!(eval.confusion[&(Red, Some(Blues))], 1);
+        assert_eq!(eval.errors().collect::<Vec<_>>(), [(Red, Some(Blues), 1)]);
+        assert_eq!(
+            evaluate(&Nearest::new(), &sample).confusion[&(Green, None)],
+            1
+        );
+    }
+
+    #[test]
+    fn rgbx_equality() {
+        let x = Rgbx(255, 255, 255, ColorClass::Whites);
+        let y = x;
+        assert_eq!(x, y)
+    }
+
+    #[test]
+    fn rgbx_inequality() {
+        let x = Rgbx(255, 255, 255, ColorClass::Whites);
+        let y = Rgbx(255, 200, 0, ColorClass::Orange);
+        assert_ne!(x, y)
+    }
+
+    #[test]
     fn gradient() {
         let start = Rgbx(255, 204, 204, Blues);
         let end = Rgbx(102, 0, 0, Blues);
         let _g = start.gradient(&end, 10);
     }
+
+    #[test]
+    fn harmonies() {
+        let red = NORD[7];
+        let rgb = |c: Rgbx| [c.0, c.1, c.2];
+        assert_eq!(red.rotate_hue(360.), red);
+        assert_eq!(rgb(red.complementary()), [97, 191, 182]);
+        assert_eq!(red.complementary().complementary(), red);
+        assert_eq!(red.analogous().map(rgb), [[191, 97, 153], [191, 135, 97]]);
+        assert_eq!(red.triadic().map(rgb), [[106, 191, 97], [97, 106, 191]]);
+        assert_eq!(
+            red.split_complementary().map(rgb),
+            [[97, 191, 135], [97, 153, 191]]
+        );
+        for c in red.triadic() {
+            assert_eq!(c.3, palette::classify(&c.rgba_array()));
+        }
+        let grey = rgbx!(128, 128, 128, gy);
+        assert_eq!(grey.complementary(), grey);
+    }
+
+    #[test]
+    fn color_spaces() {
+        let red = rgbx!(255, 0, 0, r);
+        assert_eq!(red.to_hsl(), [0., 1., 0.5]);
+        assert_eq!(rgbx!(128, 128, 128, gy).to_hsl()[..2], [0., 0.]);
+        assert_eq!(rgbx!(255, 255, 255, w).to_hsl(), [0., 0., 1.]);
+        for c in NORD {
+            assert_eq!(from_hsl(c.to_hsl()), c.rgba_array(), "{c}");
+            assert_eq!(c.to_oklab(), oklab(&c.rgba_array()));
+        }
+        assert!((red.luma() - 0.299).abs() < 1e-6);
+
+        let dark = red.lighten(-0.25);
+        assert_eq!(dark.rgba_array(), [128, 0, 0, 255]);
+        assert_eq!(red.lighten(1.).rgba_array(), [255; 4]);
+        assert_eq!(red.lighten(1.).3, Whites);
+        let muted = red.saturate(-0.5);
+        assert_eq!(muted.to_hsl()[0], 0.);
+        assert!((muted.to_hsl()[1] - 0.5).abs() < 0.01);
+        assert_eq!(red.saturate(0.5), red);
+    }
+
+    #[test]
+    fn packed_colors() {
+        for c in NORD {
+            assert_eq!(Rgbx::from_u32(c.to_u32()).rgba_array(), c.rgba_array());
+        }
+        assert_eq!(rgbx!(1, 2, 3, gy).to_u32(), 0x010203);
+        assert_eq!(Rgbx::from_u32(0xff_2e3440), NORD[12]);
+
+        let pixels = [[191, 97, 106, 255], [46, 52, 64, 0], [191, 97, 106, 9]];
+        let colors = Rgbx::from_pixels(&pixels);
+        assert_eq!(colors, [NORD[7], NORD[12], NORD[7]]);
+        assert_eq!(colors[0].3, classify(&pixels[0]));
+        assert_eq!(
+            Rgbx::to_pixels(&colors),
+            [[191, 97, 106, 255], [46, 52, 64, 255], [191, 97, 106, 255]]
+        );
+        assert!(Rgbx::from_pixels(&[]).is_empty());
+    }
+
+    #[test]
+    fn gradient_steps() {
+        let (black, white) = (rgbx!(0, 0, 0, gy), rgbx!(255, 255, 255, w));
+        let ramp = black.gradient_steps(&white, 5);
+        assert_eq!(ramp.len(), 5);
+        assert_eq!((ramp[0], ramp[4]), (black, white));
+        for c in &ramp[1..4] {
+            assert_eq!(c.3, classify(&c.rgba_array()), "{c:?}");
+        }
+        // Classified by their own color rather than taking either end's class
+        let orange = rgbx!(255, 0, 0, r).lerp(&rgbx!(255, 255, 0, y), 0.5);
+        assert_eq!(orange.3, Orange, "{orange:?}");
+        // Even steps in lightness rather than in RGB
+        let l: Vec<f32> = ramp.iter().map(|c| oklab(&c.rgba_array())[0]).collect();
+        for w in l.windows(2) {
+            assert!((w[1] - w[0] - 0.25).abs() < 0.01, "{l:?}");
+        }
+        assert!(black.gradient_steps(&white, 0).is_empty());
+        assert_eq!(black.gradient_steps(&white, 1), [black]);
+        for c in NORD {
+            assert_eq!(from_oklab(oklab(&c.rgba_array())), c.rgba_array());
+        }
+    }
+
+    #[test]
+    fn palette_lerp() {
+        let (black, white) = (rgbx!(0, 0, 0, gy), rgbx!(255, 255, 255, w));
+        let red = rgbx!(191, 97, 106, r);
+        let day = Palette::new([white, red]).named("Day");
+        let night = Palette::new([rgbx!(200, 200, 200, gy), black, rgbx!(120, 20, 30, r)])
+            .assign(Role::Background, 1);
+        assert_eq!(day.lerp(&night, 0.), day);
+        assert_eq!(day.lerp(&night, -1.), day);
+        assert_eq!(day.lerp(&Palette::default(), 0.5), day);
+
+        // White has no match in its class and goes to the closest grey, red to red
+        let dusk = day.lerp(&night, 1.);
+        assert_eq!(dusk.name(), Some("Day"));
+        assert_eq!(&dusk[..], [rgbx!(200, 200, 200, gy), rgbx!(120, 20, 30, r)]);
+        assert_eq!(dusk.roles().get(Role::Background), None);
+
+        let half = day.lerp(&night, 0.5);
+        assert_eq!(half[0], white.lerp(&night[0], 0.5));
+        assert_eq!(half[0].3, Whites);
+        assert!(half[1] != day[1] && half[1] != night[2]);
+
+        // Roles stay on their entry instead of heading for the other palette's role
+        let day = day.assign(Role::Background, 0);
+        let quarter = day.lerp(&night, 0.25);
+        let bg = quarter.roles().get(Role::Background);
+        assert_eq!(bg, Some(quarter[0]));
+        assert_eq!(bg, Some(white.lerp(&night[0], 0.25)));
+    }
+
+    #[test]
+    fn translucent_entries() {
+        let palette = [
+            Rgbxa::new(0, 0, 0, 204, Greys),
+            Rgbxa::new(255, 255, 255, 255, Whites),
+        ];
+        let opaque = Rgbxa::opaque(&palette);
+        let px = [20, 20, 20, 255];
+
+        let replace = Translucent::new(Nearest::new(), &palette, palette::Compositing::Replace);
+        assert_eq!(replace.predict(&opaque, &px), [0, 0, 0, 204]);
+        let over = Translucent::new(Nearest::new(), &palette, palette::Compositing::Over);
+        assert_eq!(over.predict(&opaque, &px), [4, 4, 4, 255]);
+        assert_eq!(
+            over.predict(&opaque, &[250, 250, 250, 255]),
+            [255, 255, 255, 255]
+        );
+        let parsed: Vec<Rgbxa> = ["#000000cc", "rgb(255, 255, 255)"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert_eq!(parsed, palette);
+        assert_eq!(parsed[0].to_string(), "#000000cc");
+        assert_eq!(parsed[1].to_string(), "#ffffff");
+        assert!(matches!(
+            "#000000c".parse::<Rgbxa>(),
+            Err(PaletteError::InvalidHex(_))
+        ));
+    }
+
+    #[test]
+    fn blend_strength() {
+        let px = [200, 40, 40, 255];
+        let mapped = Nearest::new().predict(&NORD, &px);
+        assert_eq!(Blend::new(Nearest::new(), 1.).predict(&NORD, &px), mapped);
+        assert_eq!(Blend::new(Nearest::new(), 0.).predict(&NORD, &px), px);
+        let half = Blend::new(Nearest::new(), 0.5).predict(&NORD, &px);
+        assert_eq!(
+            half[0],
+            ((px[0] as f32 + mapped[0] as f32) / 2.).round() as u8
+        );
+    }
+
+    #[test]
+    fn combinators() {
+        let px = [200, 40, 40, 255];
+        let nearest = Nearest::new().predict(&NORD, &px);
+        let chained = Chain::new(Blend::new(Nearest::new(), 0.5), Nearest::new());
+        assert_eq!(chained.predict(&NORD, &px), nearest);
+
+        // Nord's nearest red is too far for a tight threshold, so the pixel is kept as is
+        let keep = Blend::new(Nearest::new(), 0.);
+        let fallback = Fallback::new(Nearest::new(), keep.clone(), 10.);
+        assert_eq!(fallback.predict(&NORD, &px), px);
+        let fallback = Fallback::new(Nearest::new(), keep, 765.);
+        assert_eq!(fallback.predict(&NORD, &px), nearest);
+
+        let threshold = 128;
+        let split = Split::new(
+            move |p: &[u8; 4]| p[0] > threshold,
+            Nearest::new(),
+            Creative,
+        );
+        assert_eq!(split.predict(&NORD, &px), nearest);
+        let dark = [20, 40, 40, 255];
+        assert_eq!(split.predict(&NORD, &dark), Creative.predict(&NORD, &dark));
+
+        // The hooks of either mapper are kept
+        let dithered = Chain::new(Dither::new(Nearest::new()), Nearest::new());
+        assert!(dithered.error_diffusion().is_some());
+        assert!(
+            Fallback::new(Nearest::new(), Dither::new(Nearest::new()), 10.)
+                .error_diffusion()
+                .is_some()
+        );
+        let global = Chain::new(Blend::new(Nearest::new(), 0.), GlobalMap::default());
+        assert!(global.uses_context());
+
+        // A context mapper after another one is prepared on, and sees, the mapped image
+        let pixels: Vec<[u8; 4]> = (0..64).map(|i| [0, i * 2, 128 + i * 2, 255]).collect();
+        let palette = [
+            rgbx!(0, 80, 200, b),
+            rgbx!(255, 0, 0, r),
+            rgbx!(0, 0, 0, gy),
+        ];
+        let map = |mapper: &dyn Fn(&PixelContext<'_>) -> [u8; 4]| {
+            (0..64)
+                .map(|x| mapper(&PixelContext::new(&pixels, (64, 1), (x, 0))))
+                .collect::<Vec<_>>()
+        };
+        let prepared = global.prepare(&palette, &pixels, (64, 1));
+        let chained =
+            map(&|ctx| global.predict_context(&palette, &ctx.with_prepared(prepared.as_deref())));
+        let prepared = GlobalMap::default().prepare(&palette, &pixels, (64, 1));
+        let direct = map(&|ctx| {
+            GlobalMap::default().predict_context(&palette, &ctx.with_prepared(prepared.as_deref()))
+        });
+        assert_eq!(chained, direct);
+    }
+
+    #[test]
+    fn class_override() {
+        let mapper = ClassOverride::new(Nearest::new()).pin(Green, 10);
+        assert_eq!(
+            mapper.predict(&NORD, &[20, 200, 20, 255]),
+            NORD[10].rgba_array()
+        );
+        let red = [200, 40, 40, 255];
+        assert_eq!(
+            mapper.predict(&NORD, &red),
+            Nearest::new().predict(&NORD, &red)
+        );
+        // Pins past the end of the palette land on its last entry, and nothing panics without one
+        let short = &NORD[..4];
+        assert_eq!(
+            mapper.predict(short, &[20, 200, 20, 255]),
+            short[3].rgba_array()
+        );
+        assert_eq!(
+            mapper.predict_class(&[], &[20, 200, 20, 255], Green),
+            [20, 200, 20, 255]
+        );
+
+        let bayes = ClassOverride::new(Nearest::new())
+            .classifier(NaiveBayes)
+            .pin(Green, 10);
+        assert_eq!(
+            bayes.predict(&NORD, &[20, 200, 20, 255]),
+            NORD[10].rgba_array()
+        );
+        // A classifier that doesn't classify pins nothing
+        let none = ClassOverride::new(Nearest::new())
+            .classifier(Nearest::new())
+            .pin(Green, 10);
+        assert_eq!(none.classify(&[20, 200, 20, 255]), None);
+        let dithered = ClassOverride::new(Dither::new(Nearest::new())).pin(Green, 10);
+        assert!(dithered.error_diffusion().is_some());
+    }
+
+    #[test]
+    fn manual_map_builder() {
+        let red = [200, 40, 40, 255];
+        assert_eq!(
+            ManualMap::default().predict(&NORD, &red),
+            NORD[8].rgba_array()
+        );
+        let map = ManualMap::builder()
+            .range(150..=255, 0..=255, 0..=255, Target::Palette(11))
+            .range_with_priority(150..=255, 0..=50, 0..=50, Target::Color([1, 2, 3, 255]), 1)
+            .range(0..=255, 0..=255, 0..=255, Target::Palette(99))
+            .build();
+        assert_eq!(map.predict(&NORD, &red), [1, 2, 3, 255]);
+        assert_eq!(
+            map.predict(&NORD, &[200, 90, 40, 255]),
+            NORD[11].rgba_array()
+        );
+        assert_eq!(map.predict(&NORD, &[20, 90, 40, 255]), [20, 90, 40, 255]);
+    }
+
+    #[test]
+    fn usage_cap() {
+        // Mostly reds, with a few greys
+        let pixels: Vec<[u8; 4]> = (0..100)
+            .map(|i| match i {
+                0..=89 => [180 + (i % 30) as u8, 90, 100, 255],
+                _ => [60, 65, 80, 255],
+            })
+            .collect();
+        let mapper = UsageCap::new(Nearest::new()).cap(7, 0.2);
+        let map = |mapper: &UsageCap| {
+            let prepared = mapper.prepare(&NORD, &pixels, (100, 1));
+            (0..100)
+                .map(|x| {
+                    let ctx = PixelContext::new(&pixels, (100, 1), (x, 0));
+                    mapper.predict_context(&NORD, &ctx.with_prepared(prepared.as_deref()))
+                })
+                .collect::<Vec<_>>()
+        };
+        let red = NORD[7].rgba_array();
+        let plain = map(&UsageCap::new(Nearest::new()));
+        assert_eq!(plain.iter().filter(|&&c| c == red).count(), 90);
+        let capped = map(&mapper);
+        let reds: Vec<_> = (0..100).filter(|&i| capped[i] == red).collect();
+        assert!(!reds.is_empty() && reds.len() <= 20);
+        // The closest reds keep the capped color
+        assert!(reds.iter().all(|&i| pixels[i][0].abs_diff(191) <= 3));
+        assert_eq!(capped[95], plain[95]);
+    }
+
+    #[test]
+    fn global_assignment() {
+        let cost = [vec![4., 1., 3.], vec![2., 0., 5.], vec![3., 2., 2.]];
+        assert_eq!(min_cost_assignment(&cost), [1, 0, 2]);
+
+        // A gradient of blues against a palette with a single blue
+        let pixels: Vec<[u8; 4]> = (0..64).map(|i| [0, i * 2, 128 + i * 2, 255]).collect();
+        let palette = [
+            rgbx!(0, 80, 200, b),
+            rgbx!(255, 0, 0, r),
+            rgbx!(0, 0, 0, gy),
+            rgbx!(255, 255, 255, w),
+        ];
+        let nearest = Nearest::with(Oklab);
+        let greedy: HashSet<_> = pixels
+            .iter()
+            .map(|p| nearest.predict(&palette, p))
+            .collect();
+        assert_eq!(greedy.len(), 1);
+
+        let mapper = GlobalMap::default();
+        let prepared = mapper.prepare(&palette, &pixels, (64, 1));
+        let map = |pixels: &[[u8; 4]], prepared: &Option<Prepared>| {
+            (0..64)
+                .map(|x| {
+                    let ctx = PixelContext::new(pixels, (64, 1), (x, 0));
+                    mapper.predict_context(&palette, &ctx.with_prepared(prepared.as_deref()))
+                })
+                .collect::<Vec<_>>()
+        };
+        let global = map(&pixels, &prepared);
+        assert_eq!(global.iter().collect::<HashSet<_>>().len(), palette.len());
+
+        // Preparing another image, e.g. on a clone in another thread, leaves this one's mapping be
+        let reds: Vec<[u8; 4]> = (0..64).map(|i| [128 + i * 2, 0, i, 255]).collect();
+        let other = mapper.clone().prepare(&palette, &reds, (64, 1));
+        assert_ne!(map(&reds, &other), global);
+        assert_eq!(map(&pixels, &prepared), global);
+        // Preparing the same image again gives the same mapping
+        for _ in 0..10 {
+            let again = mapper.prepare(&palette, &pixels, (64, 1));
+            assert_eq!(map(&pixels, &again), global);
+        }
+    }
+
+    #[test]
+    fn centroid_accuracy() {
+        let accuracy = |sample: &[Rgbx]| evaluate(&NearestCentroid, sample).accuracy();
+        println!("Centroid prediction accuracy: {}%", accuracy(&DATA_SET));
+        assert!(accuracy(&BASIC_COLORS) > 95.0);
+        assert!(accuracy(&DATA_SET) > 60.0);
+    }
+
+    #[test]
+    fn bayes_accuracy() {
+        let accuracy = |sample: &[Rgbx]| evaluate(&NaiveBayes, sample).accuracy();
+        println!("Naive Bayes prediction accuracy: {}%", accuracy(&DATA_SET));
+        assert!(accuracy(&BASIC_COLORS) > 95.0);
+        assert!(accuracy(&DATA_SET) > 70.0);
+    }
+
+    #[test]
+    fn adaptive_regions() {
+        // Left half a flat mid grey, right half a checkerboard
+        let (w, h) = (32, 16);
+        let pixels: Vec<[u8; 4]> = (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                match (x < 16, (x + y) % 2 == 0) {
+                    (true, _) => [110, 110, 110, 255],
+                    (false, true) => [0, 0, 0, 255],
+                    (false, false) => [255, 255, 255, 255],
+                }
+            })
+            .collect();
+        let palette = [rgbx!(0, 0, 0, gy), rgbx!(255, 255, 255, w)];
+        let mapper = Adaptive::default();
+        let prepared = mapper.prepare(&palette, &pixels, (w, h));
+        let mapped: Vec<_> = (0..w * h)
+            .map(|i| {
+                let ctx = PixelContext::new(&pixels, (w, h), (i % w, i / w));
+                mapper.predict_context(&palette, &ctx.with_prepared(prepared.as_deref()))
+            })
+            .collect();
+        let nearest = Nearest::new();
+        for i in 0..(w * h) as usize {
+            if i as u32 % w >= 16 {
+                assert_eq!(mapped[i], nearest.predict(&palette, &pixels[i]));
+            }
+        }
+        // The flat half is dithered rather than collapsing to a single color
+        let left: HashSet<_> = (0..(w * h) as usize)
+            .filter(|i| (*i as u32 % w) < 16)
+            .map(|i| mapped[i])
+            .collect();
+        assert_eq!(left.len(), 2);
+    }
+
+    #[test]
+    fn segmented_flat_regions() {
+        // Two noisy halves, each of which should come out as a single flat color
+        let (w, h) = (48, 24);
+        let pixels: Vec<[u8; 4]> = (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                let noise = ((x * 7 + y * 13) % 5) as i16 * 20 - 40;
+                let base = if x < 24 { NORD[11] } else { NORD[10] };
+                let c = |v: u8| (v as i16 + noise) as u8;
+                [c(base.0), c(base.1), base.2, 255]
+            })
+            .collect();
+        let mapper = Segmented::default().size(12).compactness(40.);
+        let prepared = mapper.prepare(&NORD, &pixels, (w, h));
+        let mapped: Vec<_> = (0..w * h)
+            .map(|i| {
+                let ctx = PixelContext::new(&pixels, (w, h), (i % w, i / w));
+                mapper.predict_context(&NORD, &ctx.with_prepared(prepared.as_deref()))
+            })
+            .collect();
+        for half in [0..24, 24..48] {
+            let colors: HashSet<_> = (0..(w * h) as usize)
+                .filter(|i| half.contains(&(*i as u32 % w)))
+                .map(|i| mapped[i])
+                .collect();
+            assert_eq!(colors.len(), 1);
+        }
+        let per_pixel: HashSet<_> = pixels
+            .iter()
+            .map(|p| Nearest::new().predict(&NORD, p))
+            .collect();
+        assert!(per_pixel.len() > 2);
+    }
+
+    #[test]
+    fn role_bands() {
+        let bg = Rgbx(46, 52, 64, Greys);
+        let roles = Roles::new()
+            .assign(Role::Background, bg)
+            .assign("accent1".parse().unwrap(), NORD[7]);
+        let mapper = RoleAware::new(Nearest::new(), roles).darkest(0.2, Role::Background);
+        assert_eq!(mapper.predict(&NORD, &[10, 10, 10, 255]), bg.rgba_array());
+        assert_eq!(
+            mapper.predict(&NORD, &[200, 40, 40, 255]),
+            Nearest::new().predict(&NORD, &[200, 40, 40, 255])
+        );
+        assert_eq!("BG".parse().ok(), Some(Role::Background));
+        assert!(matches!(
+            "accent".parse::<Role>(),
+            Err(PaletteError::UnknownRole(role)) if role == "accent"
+        ));
+    }
+
+    #[test]
+    fn themed_roles() {
+        let nord = Palette::nord();
+        let mapper = RoleAware::themed(Nearest::new(), &nord);
+        assert_eq!(
+            mapper.predict(&nord, &[10, 10, 10, 255]),
+            NORD[12].rgba_array()
+        );
+        assert_eq!(
+            mapper.predict(&nord, &[250, 250, 250, 255]),
+            NORD[0].rgba_array()
+        );
+        let red = [200, 40, 40, 255];
+        assert_eq!(
+            mapper.predict(&nord, &red),
+            Nearest::new().predict(&nord, &red)
+        );
+
+        // A light theme swaps the ends, and a lone background reaches towards the far end
+        let light = Palette::new([NORD[0], NORD[12]])
+            .assign(Role::Background, 0)
+            .assign(Role::Foreground, 1);
+        let mapper = RoleAware::themed(Nearest::new(), &light);
+        assert_eq!(
+            mapper.predict(&light, &[250, 250, 250, 255]),
+            NORD[0].rgba_array()
+        );
+        assert_eq!(
+            mapper.predict(&light, &[10, 10, 10, 255]),
+            NORD[12].rgba_array()
+        );
+        let lone = Palette::new(NORD).assign(Role::Background, 12);
+        let mapper = RoleAware::themed(Nearest::new(), &lone);
+        assert_eq!(
+            mapper.predict(&lone, &[40, 40, 40, 255]),
+            NORD[12].rgba_array()
+        );
+        assert_eq!(
+            mapper.predict(&lone, &[250, 250, 250, 255]),
+            Nearest::new().predict(&lone, &[250, 250, 250, 255])
+        );
+        let mapper = RoleAware::themed(Nearest::new(), &Palette::new(NORD));
+        assert_eq!(
+            mapper.predict(&NORD, &[10, 10, 10, 255]),
+            NORD[12].rgba_array()
+        );
+    }
+
+    #[test]
+    fn min_contrast() {
+        let bg = NORD[12];
+        let mapper = MinContrast::new(Nearest::new(), bg, 4.5);
+        for px in [
+            [10, 10, 10, 255],
+            [60, 66, 80, 255],
+            [200, 40, 40, 255],
+            [250; 4],
+        ] {
+            let mapped = mapper.predict(&NORD, &px);
+            assert!(contrast(&mapped, &bg.rgba_array()) >= 4.5, "{px:?}");
+            assert!(NORD.iter().any(|c| c.rgba_array() == mapped));
+        }
+        // Already legible predictions are kept
+        let light = [236, 239, 244, 255];
+        assert_eq!(
+            mapper.predict(&NORD, &light),
+            Nearest::new().predict(&NORD, &light)
+        );
+
+        // Without any legible color, the highest contrast one
+        let dim = [rgbx!(50, 50, 50, gy), rgbx!(90, 90, 90, gy)];
+        let mapper = MinContrast::new(Nearest::new(), rgbx!(0, 0, 0, gy), 21.);
+        assert_eq!(mapper.predict(&dim, &[0, 0, 0, 255]), [90, 90, 90, 255]);
+    }
+
+    #[test]
+    fn color_deficiencies() {
+        let dist = |a: &[u8; 4], b: &[u8; 4]| {
+            let (a, b) = (oklab(a), oklab(b));
+            (0..3).map(|c| (a[c] - b[c]).powi(2)).sum::<f32>().sqrt()
+        };
+        let (red, green) = ([140, 100, 60, 255], [60, 120, 60, 255]);
+        for d in [Deficiency::Protanopia, Deficiency::Deuteranopia] {
+            let (r, g) = (d.simulate(&red), d.simulate(&green));
+            assert!(dist(&r, &g) < dist(&red, &green) / 2., "{d:?}");
+            assert!(dist(&d.daltonize(&red), &d.daltonize(&green)) > dist(&r, &g));
+            assert_eq!(Simulate::new(d).predict(&NORD, &red), r);
+        }
+        for d in [
+            Deficiency::Protanopia,
+            Deficiency::Deuteranopia,
+            Deficiency::Tritanopia,
+        ] {
+            for grey in [[0, 0, 0, 255], [128, 128, 128, 40], [255; 4]] {
+                let sim = d.simulate(&grey);
+                assert!(
+                    (0..3).all(|c| sim[c].abs_diff(grey[c]) <= 2),
+                    "{d:?} {sim:?}"
+                );
+                assert_eq!(sim[3], grey[3]);
+            }
+        }
+        assert_eq!("tritanopia".parse(), Ok(Deficiency::Tritanopia));
+
+        // The green entry looks like the brown one and is left out
+        let palette = [
+            rgbx!(140, 100, 60, or),
+            rgbx!(30, 60, 200, b),
+            rgbx!(60, 120, 60, gn),
+        ];
+        let mapper = Daltonize::new(Nearest::new(), Deficiency::Deuteranopia);
+        for px in [[60, 120, 60, 255], [70, 130, 50, 255], [140, 100, 60, 255]] {
+            assert_ne!(mapper.predict(&palette, &px), palette[2].rgba_array());
+        }
+        // Context mappers are kept as such and see the daltonized image
+        let mapper = Daltonize::new(GlobalMap::default(), Deficiency::Deuteranopia);
+        assert!(mapper.uses_context());
+        let pixels = [[60, 120, 60, 255], [70, 130, 50, 255], [30, 60, 200, 255]];
+        let prepared = mapper.prepare(&palette, &pixels, (3, 1));
+        for x in 0..3 {
+            let ctx = PixelContext::new(&pixels, (3, 1), (x, 0));
+            let mapped = mapper.predict_context(&palette, &ctx.with_prepared(prepared.as_deref()));
+            assert_ne!(mapped, palette[2].rgba_array());
+            let unprepared = mapper.predict_context(&palette, &ctx);
+            assert_ne!(unprepared, palette[2].rgba_array());
+        }
+        let mapper = Daltonize::new(Nearest::new(), Deficiency::Tritanopia);
+        assert_eq!(
+            mapper.predict(&palette, &[60, 120, 60, 255]),
+            palette[2].rgba_array()
+        );
+    }
+
+    #[test]
+    fn bayer_matrices() {
+        assert_eq!(
+            [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, y)| Bayer::X2.rank(x, y)),
+            [0, 2, 3, 1]
+        );
+        for matrix in [Bayer::X2, Bayer::X4, Bayer::X8] {
+            let n = matrix.size();
+            let mut ranks: Vec<_> = (0..n * n).map(|i| matrix.rank(i % n, i / n)).collect();
+            ranks.sort_unstable();
+            assert_eq!(ranks, (0..n * n).collect::<Vec<_>>());
+        }
+        assert_eq!(Bayer::X4.rank(1, 1), 4);
+    }
+
+    #[test]
+    fn blue_noise_textures() {
+        for size in [NoiseSize::X16, NoiseSize::X32, NoiseSize::X64] {
+            let texture = size.texture();
+            assert_eq!(texture.len(), size.size() * size.size());
+            let mean = texture.iter().map(|&t| t as f32).sum::<f32>() / texture.len() as f32;
+            assert!((mean - 127.5).abs() < 1.);
+        }
+    }
+
+    #[test]
+    fn diffusion_kernels() {
+        let fs = DiffusionKernel::FloydSteinberg.taps();
+        assert_eq!(
+            fs,
+            vec![
+                (1, 0, 7. / 16.),
+                (-1, 1, 3. / 16.),
+                (0, 1, 5. / 16.),
+                (1, 1, 1. / 16.)
+            ]
+        );
+        let custom = DiffusionKernel::Custom {
+            weights: vec![vec![0., 0., 7.], vec![3., 5., 1.]],
+            origin: 1,
+            divisor: 16.,
+        };
+        assert_eq!(custom.taps(), fs);
+        for kernel in [
+            DiffusionKernel::JarvisJudiceNinke,
+            DiffusionKernel::Stucki,
+            DiffusionKernel::Burkes,
+            DiffusionKernel::Sierra3,
+            DiffusionKernel::Sierra2,
+            DiffusionKernel::SierraLite,
+        ] {
+            let total: f32 = kernel.taps().iter().map(|t| t.2).sum();
+            assert!((total - 1.).abs() < 1e-6, "{kernel:?}");
+        }
+    }
+
+    #[test]
+    fn ciede2000_reference() {
+        // Pairs from Sharma, Wu and Dalal's CIEDE2000 test data
+        let pairs = [
+            ([50., 2.6772, -79.7751], [50., 0., -82.7485], 2.0425),
+            ([50., -1.3802, -84.2814], [50., 0., -82.7485], 1.0),
+            ([50., 2.5, 0.], [73., 25., -18.], 27.1492),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+        ];
+        for (a, b, expected) in pairs {
+            assert!((ciede2000(&a, &b) - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn distance_strategies() {
+        let palette = [rgbx!(90, 0, 0, r), rgbx!(40, 40, 40, gy)];
+        let black = [0, 0, 0, 255];
+        assert_eq!(Nearest::new().predict(&palette, &black), [90, 0, 0, 255]);
+        assert_eq!(
+            Nearest::with(Euclidean).predict(&palette, &black),
+            [40, 40, 40, 255]
+        );
+        assert_eq!(palette[1].manhattan_dist(&black), 120);
+
+        // Redmean weighs green differences heavier than Manhattan does
+        let palette = [rgbx!(60, 0, 0, r), rgbx!(0, 45, 0, gn)];
+        assert_eq!(Nearest::new().predict(&palette, &black), [0, 45, 0, 255]);
+        assert_eq!(
+            Nearest::with(Redmean).predict(&palette, &black),
+            [60, 0, 0, 255]
+        );
+
+        // Green-heavy weights prefer the red candidate, red-heavy ones the green one
+        assert_eq!(
+            Nearest::with(Weighted::LUMA).predict(&palette, &black),
+            [60, 0, 0, 255]
+        );
+        assert_eq!(
+            Nearest::with(Weighted::new(1., 0.1, 0.1)).predict(&palette, &black),
+            [0, 45, 0, 255]
+        );
+    }
+
+    #[test]
+    fn hue_preserving() {
+        for px in [[200, 40, 40, 255], [12, 80, 200, 255], [90, 90, 90, 255]] {
+            assert_eq!(palette::from_hsv(palette::hsv(&px)), px);
+        }
+        let palette = [
+            rgbx!(255, 0, 0, r),
+            rgbx!(0, 0, 255, b),
+            rgbx!(128, 128, 128, gy),
+        ];
+        let mapper = NearestHue::default();
+        // Dark orange takes the red hue but keeps its brightness
+        assert_eq!(
+            mapper.predict(&palette, &[100, 40, 0, 255]),
+            [100, 0, 0, 255]
+        );
+        assert_eq!(
+            mapper.predict(&palette, &[60, 60, 60, 255]),
+            [60, 60, 60, 255]
+        );
+    }
+
+    #[test]
+    fn gradient_map() {
+        let palette = [rgbx!(255, 200, 0, y), rgbx!(40, 0, 80, p)];
+        let mapper = GradientMap::default();
+        assert_eq!(mapper.predict(&palette, &[0, 0, 0, 255]), [40, 0, 80, 255]);
+        assert_eq!(
+            mapper.predict(&palette, &[255, 255, 255, 255]),
+            [255, 200, 0, 255]
+        );
+        let mid = mapper.predict(&palette, &[128, 128, 128, 255]);
+        assert!(mid[0] > 40 && mid[0] < 255 && mid[2] < 80);
+    }
+
+    #[test]
+    fn duotone() {
+        let palette = [
+            rgbx!(40, 0, 80, p),
+            rgbx!(255, 200, 0, y),
+            rgbx!(0, 160, 160, b),
+        ];
+        let mapper = Duotone::new(0, 1).pivots(0.2, 0.8);
+        assert_eq!(
+            mapper.predict(&palette, &[30, 30, 30, 255]),
+            [40, 0, 80, 255]
+        );
+        assert_eq!(
+            mapper.predict(&palette, &[220, 220, 220, 255]),
+            [255, 200, 0, 255]
+        );
+        let tritone = mapper.midtones(2, 128. / 255.);
+        assert_eq!(
+            tritone.predict(&palette, &[128, 128, 128, 255]),
+            [0, 160, 160, 255]
+        );
+        // The cached stops follow the palette, and an empty one leaves pixels be
+        let swapped = [palette[1], palette[0]];
+        assert_eq!(
+            tritone.predict(&swapped, &[30, 30, 30, 255]),
+            [255, 200, 0, 255]
+        );
+        let px = [30, 30, 30, 255];
+        assert_eq!(tritone.predict(&[], &px), px);
+        assert_eq!(GradientMap::default().predict(&[], &px), px);
+    }
+
+    #[test]
+    fn max_distance_pass_through() {
+        let mapper = Nearest::new().max_distance(30.);
+        let close = [NORD[11].0 + 10, NORD[11].1, NORD[11].2, 255];
+        assert_eq!(mapper.predict(&NORD, &close), NORD[11].rgba_array());
+        let far = [255, 0, 255, 255];
+        assert_eq!(mapper.predict(&NORD, &far), far);
+    }
+
+    #[test]
+    fn nearest_aliases() {
+        let px = [200, 40, 40, 255];
+        assert_eq!(
+            NearestLab::default().predict(&NORD, &px),
+            Nearest::with(Ciede2000).predict(&NORD, &px)
+        );
+        assert_eq!(
+            NearestOklab::default().predict(&NORD, &px),
+            Nearest::with(Oklab).predict(&NORD, &px)
+        );
+    }
+
+    #[test]
+    fn weighted_entries() {
+        let palette = [rgbx!(0, 0, 0, gy), rgbx!(255, 255, 255, w)];
+        let (black, white) = ([0, 0, 0, 255], [255, 255, 255, 255]);
+        let grey = [120, 120, 120, 255];
+        assert_eq!(Nearest::new().predict(&palette, &grey), black);
+        assert_eq!(
+            Nearest::new()
+                .weight(palette[0], 2.)
+                .predict(&palette, &grey),
+            white
+        );
+        assert_eq!(
+            Nearest::new()
+                .weight(palette[1], 0.5)
+                .predict(&palette, &grey),
+            white
+        );
+        // Colors missing from the palette are harmless, and weights follow their color
+        let red = rgbx!(255, 0, 0, r);
+        assert_eq!(
+            Nearest::new().weight(red, 0.).predict(&palette, &grey),
+            black
+        );
+        let mapper = Nearest::new()
+            .weight(palette[1], 0.5)
+            .weight(palette[0], 2.);
+        assert_eq!(mapper.predict(&palette, &grey), white);
+        assert_eq!(mapper.predict(&[palette[1], palette[0]], &grey), white);
+        assert_eq!(
+            mapper.weight(palette[0], 1.).predict(&palette, &grey),
+            white
+        );
+
+        let light = [200, 200, 200, 255];
+        let mapper = Nearest::with(Oklab).weight(palette[0], 0.);
+        assert_eq!(mapper.predict(&palette, &light), black);
+        assert_eq!(mapper.predict(&palette, &white), black);
+        assert_eq!(
+            Nearest::with(Oklab)
+                .weight(palette[0], -1.)
+                .predict(&palette, &light),
+            black
+        );
+
+        let mapper = Nearest::new().max_distance(100.).weight(palette[1], 2.);
+        assert_eq!(mapper.predict(&palette, &[240, 240, 240, 255]), white);
+        assert_eq!(
+            mapper.predict(&palette, &[220, 220, 220, 255]),
+            [220, 220, 220, 255]
+        );
+    }
+
+    #[test]
+    fn substitution_table() {
+        let mapper = Substitute::new(Nearest::new())
+            .replace([0, 120, 215], Target::Palette(10))
+            .replace_near([230, 30, 30], 20., Target::Color([1, 2, 3, 255]));
+        assert_eq!(
+            mapper.predict(&NORD, &[0, 120, 215, 255]),
+            NORD[10].rgba_array()
+        );
+        assert_eq!(mapper.predict(&NORD, &[240, 30, 25, 255]), [1, 2, 3, 255]);
+        let other = [0, 121, 215, 255];
+        assert_eq!(
+            mapper.predict(&NORD, &other),
+            Nearest::new().predict(&NORD, &other)
+        );
+
+        // The table applies on every path, and the wrapped mapper's hooks are kept
+        let brand = [0, 120, 215, 255];
+        let mapper = Substitute::new(NaiveBayes).replace([0, 120, 215], Target::Palette(10));
+        let class = mapper.classify(&brand).unwrap();
+        assert_eq!(
+            mapper.predict_class(&NORD, &brand, class),
+            NORD[10].rgba_array()
+        );
+        let mapper =
+            Substitute::new(GlobalMap::default()).replace([0, 120, 215], Target::Palette(10));
+        assert!(mapper.uses_context());
+        let pixels = [brand, [200, 40, 40, 255]];
+        let prepared = mapper.prepare(&NORD, &pixels, (2, 1));
+        let ctx = PixelContext::new(&pixels, (2, 1), (0, 0)).with_prepared(prepared.as_deref());
+        assert_eq!(mapper.predict_context(&NORD, &ctx), NORD[10].rgba_array());
+        let mapper = Substitute::new(Dither::new(Nearest::new()));
+        assert!(mapper.error_diffusion().is_some());
+    }
+
+    #[test]
+    fn knn_custom_dataset() {
+        let pastels = [
+            rgbx!(255, 209, 220, r),
+            rgbx!(250, 200, 210, r),
+            rgbx!(245, 195, 215, r),
+            rgbx!(193, 225, 193, gn),
+            rgbx!(200, 230, 200, gn),
+        ];
+        let knn = Knn::trained_on(&pastels);
+        assert_eq!(knn.classify(&[252, 205, 215, 255]), Some(Red));
+        // Without a pastel red in the palette, the nearest color is used instead
+        let palette = [rgbx!(190, 220, 190, gn), rgbx!(40, 40, 40, gy)];
+        assert_eq!(
+            knn.predict(&palette, &[252, 205, 215, 255]),
+            Nearest::new().predict(&palette, &[252, 205, 215, 255])
+        );
+        assert_eq!(Knn::trained_on(&[]).classify(&[0, 0, 0, 255]), None);
+    }
+
+    #[test]
+    fn custom_classes() {
+        assert_eq!("Grays".parse().ok(), Some(Greys));
+        assert_eq!("pink".parse().ok(), Some(Pink));
+        assert_eq!("custom7".parse().ok(), Some(Custom(7)));
+        assert!(matches!(
+            "custom".parse::<ColorClass>(),
+            Err(PaletteError::UnknownClass(name)) if name == "custom"
+        ));
+        let dataset = vec![
+            rgbx!(0, 200, 200, c),
+            rgbx!(20, 220, 210, c),
+            Rgbx(120, 80, 40, Custom(1)),
+            Rgbx(130, 90, 50, Custom(1)),
+        ];
+        let knn = Knn::builder().k(1).dataset(dataset).seed(0).build();
+        assert_eq!(knn.classify(&[125, 85, 45, 255]), Some(Custom(1)));
+        let palette = [rgbx!(10, 190, 190, c), Rgbx(140, 100, 60, Custom(1))];
+        assert_eq!(
+            knn.predict(&palette, &[5, 230, 200, 255]),
+            [10, 190, 190, 255]
+        );
+    }
+
+    #[test]
+    fn class_weights() {
+        // Two reds against one blue, with ties broken by vote order
+        let dataset = vec![
+            rgbx!(250, 10, 10, r),
+            rgbx!(200, 0, 40, r),
+            rgbx!(10, 10, 250, b),
+        ];
+        let knn = |weights| {
+            Knn::builder()
+                .k(2)
+                .random_ties(false)
+                .dataset(dataset.clone())
+                .weights(weights)
+                .build()
+        };
+        let px = [120, 10, 120, 255];
+        assert_eq!(knn(ClassWeights::new()).classify(&px), Some(Red));
+        assert_eq!(
+            knn(ClassWeights::new().set(Blues, 2)).classify(&px),
+            Some(Blues)
+        );
+        assert_eq!(
+            knn(ClassWeights::new().set(Red, -2)).classify(&px),
+            Some(Blues)
+        );
+        assert_eq!(ClassWeights::new().set(Red, 3).get(Green), 0);
+    }
+
+    #[test]
+    fn knn_builder() {
+        let dataset = vec![
+            rgbx!(250, 10, 10, r),
+            rgbx!(200, 0, 40, r),
+            rgbx!(10, 10, 250, b),
+        ];
+        let knn = Knn::builder()
+            .k(100)
+            .random_ties(false)
+            .weighted(true)
+            .dataset(dataset)
+            .build();
+        assert_eq!(knn.classify(&[240, 20, 20, 255]), Some(Red));
+        let px = [200, 40, 40, 255];
+        let deterministic = Knn::builder().random_ties(false).build();
+        assert_eq!(
+            deterministic.predict(&NORD, &px),
+            deterministic.predict(&NORD, &px)
+        );
+        let seeded = || Knn::builder().k(40).seed(7).build();
+        for c in SYN_DATA_SET.iter().step_by(7) {
+            let c = c.rgba_array();
+            assert_eq!(seeded().classify(&c), seeded().classify(&c));
+        }
+
+        // Two votes each for Red and Blues
+        let tied = vec![
+            rgbx!(55, 0, 50, r),
+            rgbx!(45, 0, 50, b),
+            rgbx!(50, 0, 58, r),
+            rgbx!(50, 0, 42, b),
+            rgbx!(250, 250, 250, w),
+        ];
+        let build = || Knn::builder().k(3).dataset(tied.clone());
+        let px = [50, 0, 50, 255];
+        let seeded = build().seed(7).build().classify(&px);
+        for _ in 0..50 {
+            let first = build().random_ties(false).build().classify(&px);
+            assert_eq!(first, Some(Blues));
+            assert_eq!(build().seed(7).build().classify(&px), seeded);
+        }
+    }
+
+    #[test]
+    fn lab_nearest() {
+        let white = lab(&[255, 255, 255, 255]);
+        assert!((white[0] - 100.).abs() < 0.01 && white[1].abs() < 0.01);
+        let mapper = Nearest::with(Cie76);
+        assert_eq!(
+            mapper.predict(&NORD, &[200, 40, 40, 255]),
+            NORD[7].rgba_array()
+        );
+        assert_eq!(
+            Nearest::<Ciede2000>::default().predict(&NORD, &[200, 40, 40, 255]),
+            NORD[7].rgba_array()
+        );
+    }
+
+    #[test]
+    fn oklab_nearest() {
+        let white = oklab(&[255, 255, 255, 255]);
+        assert!((white[0] - 1.).abs() < 1e-3 && white[1].abs() < 1e-3 && white[2].abs() < 1e-3);
+        let mapper = Nearest::with(Oklab);
+        assert_eq!(
+            mapper.predict(&NORD, &[200, 40, 40, 255]),
+            NORD[7].rgba_array()
+        );
+        // Palette changes between calls are picked up
+        let bw = [rgbx!(0, 0, 0, gy), rgbx!(255, 255, 255, w)];
+        assert_eq!(
+            mapper.predict(&bw, &[200, 200, 200, 255]),
+            [255, 255, 255, 255]
+        );
+    }
 }
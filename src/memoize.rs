@@ -1,11 +1,39 @@
-use super::{palette::Rgbx, Mapper};
+use super::{
+    context::{PixelContext, Prepared},
+    mappers::Diffusion,
+    palette::{ColorClass, Rgbx},
+    Mapper,
+};
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::{cell::RefCell, fmt, sync::Arc};
 
+// A hash of the palette along with the pixel
+type Key = (u64, [u8; 4]);
+
+thread_local! {
+    // The palette this thread last predicted with and its hash, so that runs over one palette
+    // only compare it with the last one instead of hashing it for every pixel
+    static LAST_PALETTE: RefCell<(Vec<Rgbx>, u64)> =
+        RefCell::new((Vec::new(), fxhash::hash64::<[Rgbx]>(&[])));
+}
+
+fn palette_hash(palette: &[Rgbx]) -> u64 {
+    LAST_PALETTE.with_borrow_mut(|(last, hash)| {
+        if last.as_slice() != palette {
+            last.clear();
+            last.extend_from_slice(palette);
+            *hash = fxhash::hash64(palette);
+        }
+        *hash
+    })
+}
+
+/// Caches the predictions of the wrapped mapper by pixel and palette, so a memoized mapper can be
+/// reused with other palettes.
 #[derive(Clone)]
 pub struct Memoized<M: Mapper> {
     mapper: M,
-    mem: Arc<DashMap<[u8; 4], [u8; 4], ahash::RandomState>>,
+    mem: Arc<DashMap<Key, [u8; 4], ahash::RandomState>>,
 }
 
 impl<M: Mapper> Memoized<M> {
@@ -22,14 +50,55 @@ impl<M: Mapper> Memoized<M> {
 
 impl<M: Mapper> Mapper for Memoized<M> {
     fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
-        if let Some(v) = self.mem.get(pixel) {
+        let key = (palette_hash(palette), *pixel);
+        if let Some(v) = self.mem.get(&key) {
             *v
         } else {
             let pred = self.mapper.predict(palette, pixel);
-            self.mem.insert(*pixel, pred);
+            self.mem.insert(key, pred);
             pred
         }
     }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.mapper.predict_class(palette, pixel, class)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    // Not memoized, the result depends on more than the pixel
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.mapper.predict_context(palette, ctx)
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper.prepare(palette, pixels, dimensions)
+    }
+}
+
+impl<M: Mapper + fmt::Debug> fmt::Debug for Memoized<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Memoized").field(&self.mapper).finish()
+    }
 }
 
 impl<M: Mapper> From<M> for Memoized<M> {
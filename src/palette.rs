@@ -1,5 +1,18 @@
-use std::cmp::Ordering;
+use crate::distance::{ColorDistance, Euclidean, Manhattan};
+use rayon::prelude::*;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    error::Error,
+    fmt::{self, Write},
+    ops::Deref,
+    str::FromStr,
+    sync::OnceLock,
+};
+use strum_macros::EnumString;
 
+/// Builds an [`Rgbx`] with a class suffix: `w`, `gy`, `b`, `r`, `p`, `gn`, `y`, `or`, `c`, `m`,
+/// `br` or `pk`, Whites without one. `g` and `o` stand for Greys and Yellow, as they always have.
 #[macro_export]
 macro_rules! rgbx {
     ($r:expr, $g:expr, $b:expr) => {
@@ -14,6 +27,10 @@ macro_rules! rgbx {
         Rgbx::new($r, $g, $b, ColorClass::Greys)
     };
 
+    ($r:expr, $g:expr, $b:expr, gy) => {
+        Rgbx::new($r, $g, $b, ColorClass::Greys)
+    };
+
     ($r:expr, $g:expr, $b:expr, b) => {
         Rgbx::new($r, $g, $b, ColorClass::Blues)
     };
@@ -26,7 +43,7 @@ macro_rules! rgbx {
         Rgbx::new($r, $g, $b, ColorClass::Purple)
     };
 
-    ($r:expr, $g:expr, $b:expr, g) => {
+    ($r:expr, $g:expr, $b:expr, gn) => {
         Rgbx::new($r, $g, $b, ColorClass::Green)
     };
 
@@ -37,9 +54,80 @@ macro_rules! rgbx {
     ($r:expr, $g:expr, $b:expr, o) => {
         Rgbx::new($r, $g, $b, ColorClass::Yellow)
     };
+
+    ($r:expr, $g:expr, $b:expr, or) => {
+        Rgbx::new($r, $g, $b, ColorClass::Orange)
+    };
+
+    ($r:expr, $g:expr, $b:expr, c) => {
+        Rgbx::new($r, $g, $b, ColorClass::Cyan)
+    };
+
+    ($r:expr, $g:expr, $b:expr, m) => {
+        Rgbx::new($r, $g, $b, ColorClass::Magenta)
+    };
+
+    ($r:expr, $g:expr, $b:expr, br) => {
+        Rgbx::new($r, $g, $b, ColorClass::Brown)
+    };
+
+    ($r:expr, $g:expr, $b:expr, pk) => {
+        Rgbx::new($r, $g, $b, ColorClass::Pink)
+    };
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy)]
+/// Builds a `[Rgbx; N]` from `#rrggbb` or `#rgb` literals, which are parsed at compile time. Each
+/// code may be followed by a class suffix, the same ones [`rgbx!`] takes; colors without one are
+/// classified with [`classify`](crate::palette::classify) when the array is built, so only fully
+/// suffixed palettes can be used in a `const`.
+///
+/// ```
+/// use mapped::palette::{Rgbx, NORD};
+///
+/// const DUO: [Rgbx; 2] = mapped::palette!["#2e3440" gy, "#bf616a" r];
+/// assert_eq!(DUO, [NORD[12], NORD[7]]);
+/// let classified = mapped::palette!["#2e3440", "fff"];
+/// assert_eq!(classified[1].3, mapped::palette::ColorClass::Whites);
+/// ```
+#[macro_export]
+macro_rules! palette {
+    (@class w) => { $crate::palette::ColorClass::Whites };
+    (@class g) => { $crate::palette::ColorClass::Greys };
+    (@class gy) => { $crate::palette::ColorClass::Greys };
+    (@class b) => { $crate::palette::ColorClass::Blues };
+    (@class r) => { $crate::palette::ColorClass::Red };
+    (@class p) => { $crate::palette::ColorClass::Purple };
+    (@class gn) => { $crate::palette::ColorClass::Green };
+    (@class y) => { $crate::palette::ColorClass::Yellow };
+    (@class o) => { $crate::palette::ColorClass::Yellow };
+    (@class or) => { $crate::palette::ColorClass::Orange };
+    (@class c) => { $crate::palette::ColorClass::Cyan };
+    (@class m) => { $crate::palette::ColorClass::Magenta };
+    (@class br) => { $crate::palette::ColorClass::Brown };
+    (@class pk) => { $crate::palette::ColorClass::Pink };
+
+    (@color $code:literal) => {{
+        const RGB: [u8; 3] = $crate::palette::hex($code);
+        $crate::palette::Rgbx(
+            RGB[0],
+            RGB[1],
+            RGB[2],
+            $crate::palette::classify(&[RGB[0], RGB[1], RGB[2], 255]),
+        )
+    }};
+
+    (@color $code:literal $class:ident) => {{
+        const RGB: [u8; 3] = $crate::palette::hex($code);
+        $crate::palette::Rgbx::new(RGB[0], RGB[1], RGB[2], $crate::palette!(@class $class))
+    }};
+
+    ($($code:literal $($class:ident)?),* $(,)?) => {
+        [$($crate::palette!(@color $code $($class)?)),*]
+    };
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rgbx(pub u8, pub u8, pub u8, pub ColorClass);
 
 impl Rgbx {
@@ -76,16 +164,11 @@ impl Rgbx {
     }
 
     pub fn manhattan_dist(&self, rgb_val: &[u8; 4]) -> u16 {
-        (self.0 as u16).abs_diff(rgb_val[0] as u16)
-            + (self.1 as u16).abs_diff(rgb_val[1] as u16)
-            + (self.2 as u16).abs_diff(rgb_val[2] as u16)
+        Manhattan.distance(&self.rgba_array(), rgb_val) as u16
     }
 
     pub fn euclidian_dist(&self, rgb_val: &[u8; 4]) -> f32 {
-        ((self.0.abs_diff(rgb_val[0]) as f32).powi(2)
-            + (self.1.abs_diff(rgb_val[1]) as f32).powi(2)
-            + (self.2.abs_diff(rgb_val[2]) as f32).powi(2))
-        .sqrt()
+        Euclidean.distance(&self.rgba_array(), rgb_val)
     }
 
     pub fn rgba_array(&self) -> [u8; 4] {
@@ -93,6 +176,53 @@ impl Rgbx {
         [r, g, b, 255]
     }
 
+    /// The color as `#rrggbb`, which is also how it's displayed.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+
+    /// The color packed as `0xRRGGBB`, ignoring the top byte, classified with [`classify`].
+    ///
+    /// ```
+    /// use mapped::palette::{Rgbx, NORD};
+    ///
+    /// assert_eq!(Rgbx::from_u32(0xbf616a), NORD[7]);
+    /// assert_eq!(NORD[7].to_u32(), 0xbf616a);
+    /// ```
+    pub fn from_u32(rgb: u32) -> Rgbx {
+        let [_, r, g, b] = rgb.to_be_bytes();
+        Rgbx(r, g, b, classify(&[r, g, b, 255]))
+    }
+
+    /// The color packed as `0xRRGGBB`.
+    pub const fn to_u32(&self) -> u32 {
+        u32::from_be_bytes([0, self.0, self.1, self.2])
+    }
+
+    /// Colors of raw pixels, classified with [`classify`]. Each distinct color is classified
+    /// once, in parallel, so whole framebuffers convert quickly. Alpha is dropped.
+    pub fn from_pixels(pixels: &[[u8; 4]]) -> Vec<Rgbx> {
+        let mut unique: Vec<[u8; 3]> = pixels.iter().map(|p| [p[0], p[1], p[2]]).collect();
+        unique.sort_unstable();
+        unique.dedup();
+        let classes: Vec<ColorClass> = unique
+            .par_iter()
+            .map(|&[r, g, b]| classify(&[r, g, b, 255]))
+            .collect();
+        pixels
+            .iter()
+            .map(|&[r, g, b, _]| {
+                let i = unique.binary_search(&[r, g, b]).unwrap();
+                Rgbx(r, g, b, classes[i])
+            })
+            .collect()
+    }
+
+    /// Opaque pixels of `colors`, the inverse of [`Rgbx::from_pixels`].
+    pub fn to_pixels(colors: &[Rgbx]) -> Vec<[u8; 4]> {
+        colors.iter().map(Rgbx::rgba_array).collect()
+    }
+
     pub fn rgb_float_array(&self) -> [f32; 3] {
         let max: f32 = 255.;
         [
@@ -106,6 +236,53 @@ impl Rgbx {
         self.3
     }
 
+    /// Rec. 601 luma, 0-1.
+    pub fn luma(&self) -> f32 {
+        luma(&self.rgba_array())
+    }
+
+    /// Hue in degrees, saturation and lightness, both 0-1.
+    pub fn to_hsl(&self) -> [f32; 3] {
+        hsl(&self.rgba_array())
+    }
+
+    /// See [`oklab`].
+    pub fn to_oklab(&self) -> [f32; 3] {
+        oklab(&self.rgba_array())
+    }
+
+    /// See [`lab`].
+    pub fn to_lab(&self) -> [f32; 3] {
+        lab(&self.rgba_array())
+    }
+
+    /// This color with `amount` added to its HSL saturation, clamped to 0-1; negative amounts
+    /// desaturate. The result is classified with [`classify`].
+    ///
+    /// ```
+    /// use mapped::palette::NORD;
+    ///
+    /// let vivid = NORD[7].saturate(0.2);
+    /// assert!(vivid.to_hsl()[1] > NORD[7].to_hsl()[1]);
+    /// assert_eq!(NORD[7].saturate(-1.).to_hsl()[1], 0.);
+    /// ```
+    pub fn saturate(&self, amount: f32) -> Rgbx {
+        let [hue, sat, light] = self.to_hsl();
+        Rgbx::classified(from_hsl([hue, (sat + amount).clamp(0., 1.), light]))
+    }
+
+    /// This color with `amount` added to its HSL lightness, clamped to 0-1; negative amounts
+    /// darken. The result is classified with [`classify`].
+    pub fn lighten(&self, amount: f32) -> Rgbx {
+        let [hue, sat, light] = self.to_hsl();
+        Rgbx::classified(from_hsl([hue, sat, (light + amount).clamp(0., 1.)]))
+    }
+
+    fn classified(pixel: [u8; 4]) -> Rgbx {
+        let [r, g, b, _] = pixel;
+        Rgbx(r, g, b, classify(&pixel))
+    }
+
     pub fn step_towards(&self, other: &Rgbx, step: u8) -> Rgbx {
         let r = Self::step_towards_val(self.0, other.0, step);
         let g = Self::step_towards_val(self.1, other.1, step);
@@ -113,6 +290,79 @@ impl Rgbx {
         Rgbx(r, g, b, other.group())
     }
 
+    /// This color with its hue turned by `degrees`, keeping its saturation and lightness. The
+    /// result is classified with [`classify`], except for greys which have no hue to turn.
+    pub fn rotate_hue(&self, degrees: f32) -> Rgbx {
+        let [hue, sat, val] = hsv(&self.rgba_array());
+        if sat == 0. {
+            return *self;
+        }
+        let rotated = from_hsv([hue + degrees, sat, val]);
+        let [r, g, b, _] = rotated;
+        Rgbx(r, g, b, classify(&rotated))
+    }
+
+    /// The color opposite on the color wheel.
+    pub fn complementary(&self) -> Rgbx {
+        self.rotate_hue(180.)
+    }
+
+    /// The neighbours 30° to either side.
+    pub fn analogous(&self) -> [Rgbx; 2] {
+        [-30., 30.].map(|d| self.rotate_hue(d))
+    }
+
+    /// The colors that divide the color wheel into thirds with this one.
+    ///
+    /// ```
+    /// use mapped::palette::{ColorClass, Rgbx};
+    ///
+    /// let [green, blue] = Rgbx::new(255, 0, 0, ColorClass::Red).triadic();
+    /// assert_eq!(green, Rgbx::new(0, 255, 0, ColorClass::Green));
+    /// assert_eq!(blue.3, ColorClass::Blues);
+    /// ```
+    pub fn triadic(&self) -> [Rgbx; 2] {
+        [120., 240.].map(|d| self.rotate_hue(d))
+    }
+
+    /// The two neighbours of the complementary color, 150° to either side.
+    pub fn split_complementary(&self) -> [Rgbx; 2] {
+        [150., 210.].map(|d| self.rotate_hue(d))
+    }
+
+    /// The color a fraction `t` of the way to `other` in [`oklab`], clamped to 0-1. Colors in
+    /// between are classified with [`classify`]; the ends are the two colors as given.
+    pub fn lerp(&self, other: &Rgbx, t: f32) -> Rgbx {
+        let t = t.clamp(0., 1.);
+        if t == 0. || t == 1. {
+            return if t == 0. { *self } else { *other };
+        }
+        let (from, to) = (self.to_oklab(), other.to_oklab());
+        Rgbx::classified(from_oklab(
+            [0, 1, 2].map(|c| from[c] + (to[c] - from[c]) * t),
+        ))
+    }
+
+    /// Exactly `n` colors evenly spaced in [`oklab`] from this color to `end`, both included.
+    /// The colors in between are classified with [`classify`], as with [`Rgbx::lerp`].
+    ///
+    /// ```
+    /// use mapped::palette::NORD;
+    ///
+    /// let ramp = NORD[12].gradient_steps(&NORD[6], 5);
+    /// assert_eq!(ramp.len(), 5);
+    /// assert_eq!((ramp[0], ramp[4]), (NORD[12], NORD[6]));
+    /// ```
+    pub fn gradient_steps(&self, end: &Rgbx, n: usize) -> Vec<Rgbx> {
+        (0..n)
+            .map(|i| match i {
+                0 => *self,
+                i if i + 1 == n => *end,
+                i => self.lerp(end, i as f32 / (n - 1) as f32),
+            })
+            .collect()
+    }
+
     pub fn gradient(&self, end_point: &Rgbx, distance: u8) -> Vec<Rgbx> {
         let mut vals: Vec<Rgbx> = Vec::new();
         loop {
@@ -130,12 +380,128 @@ impl Rgbx {
     }
 }
 
+impl fmt::Display for Rgbx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// Parses `#rrggbb`, `#rgb`, `#rrggbbaa` or `rgb(r, g, b)`, classifying the color with
+/// [`classify`]. The alpha of `#rrggbbaa` is dropped.
+///
+/// ```
+/// use mapped::palette::{Rgbx, NORD};
+///
+/// let red: Rgbx = "rgb(191, 97, 106)".parse()?;
+/// assert_eq!(red, NORD[7]);
+/// assert_eq!(red.to_string(), "#bf616a");
+/// # Ok::<(), mapped::palette::PaletteError>(())
+/// ```
+impl FromStr for Rgbx {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [r, g, b, _] = parse_color(s).ok_or_else(|| PaletteError::InvalidHex(s.to_owned()))?;
+        Ok(Rgbx(r, g, b, classify(&[r, g, b, 255])))
+    }
+}
+
 impl From<[u8; 4]> for Rgbx {
     fn from(value: [u8; 4]) -> Self {
         rgbx!(value[0], value[1], value[2])
     }
 }
 
+/// A palette entry with an alpha component, for palettes with translucent surfaces.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rgbxa(pub Rgbx, pub u8);
+
+impl Rgbxa {
+    pub const fn new(red: u8, green: u8, blue: u8, alpha: u8, class: ColorClass) -> Rgbxa {
+        Rgbxa(Rgbx(red, green, blue, class), alpha)
+    }
+
+    pub fn rgbx(&self) -> Rgbx {
+        self.0
+    }
+
+    pub fn alpha(&self) -> u8 {
+        self.1
+    }
+
+    pub fn rgba_array(&self) -> [u8; 4] {
+        [self.0 .0, self.0 .1, self.0 .2, self.1]
+    }
+
+    /// The entry as `#rrggbbaa`, or `#rrggbb` when it's opaque.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+
+    /// Composites this entry over `pixel` with the usual "over" operator, keeping the pixel's
+    /// own alpha.
+    pub fn over(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        let a = self.1 as u16;
+        let mix = |c: u8, p: u8| ((c as u16 * a + p as u16 * (255 - a) + 127) / 255) as u8;
+        [
+            mix(self.0 .0, pixel[0]),
+            mix(self.0 .1, pixel[1]),
+            mix(self.0 .2, pixel[2]),
+            pixel[3],
+        ]
+    }
+
+    /// The opaque colors of a translucent palette, which is what mappers choose from.
+    pub fn opaque(palette: &[Rgbxa]) -> Vec<Rgbx> {
+        palette.iter().map(Rgbxa::rgbx).collect()
+    }
+}
+
+impl fmt::Display for Rgbxa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            255 => write!(f, "{}", self.0),
+            a => write!(f, "{}{a:02x}", self.0),
+        }
+    }
+}
+
+/// Parses the same forms as [`Rgbx`], keeping the alpha of `#rrggbbaa`.
+///
+/// ```
+/// use mapped::palette::Rgbxa;
+///
+/// let shadow: Rgbxa = "#2e3440cc".parse()?;
+/// assert_eq!(shadow.alpha(), 204);
+/// assert_eq!(shadow.to_hex(), "#2e3440cc");
+/// # Ok::<(), mapped::palette::PaletteError>(())
+/// ```
+impl FromStr for Rgbxa {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [r, g, b, a] = parse_color(s).ok_or_else(|| PaletteError::InvalidHex(s.to_owned()))?;
+        Ok(Rgbxa::new(r, g, b, a, classify(&[r, g, b, 255])))
+    }
+}
+
+impl From<Rgbx> for Rgbxa {
+    fn from(value: Rgbx) -> Self {
+        Rgbxa(value, 255)
+    }
+}
+
+/// How the alpha of a translucent palette entry is applied to a mapped pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compositing {
+    /// Emit the entry as is, alpha included.
+    #[default]
+    Replace,
+    /// Composite the entry over the original pixel.
+    Over,
+}
+
 pub fn find_closest(clrs: &[[u8; 4]], clr: &[u8; 4]) -> [u8; 4] {
     let (_, clrtyp) = clrs
         .iter()
@@ -145,7 +511,1649 @@ pub fn find_closest(clrs: &[[u8; 4]], clr: &[u8; 4]) -> [u8; 4] {
     *clrtyp
 }
 
+/// Classifies a color with the bundled dataset, the way the default [`Knn`](crate::mappers::Knn)
+/// does but with ties always broken the same way. The alpha channel is ignored.
+///
+/// ```
+/// use mapped::palette::{classify, ColorClass};
+/// assert_eq!(classify(&[230, 20, 30, 255]), ColorClass::Red);
+/// ```
+pub fn classify(pixel: &[u8; 4]) -> ColorClass {
+    use crate::{mappers::Knn, Mapper};
+
+    static KNN: OnceLock<Knn> = OnceLock::new();
+    KNN.get_or_init(|| Knn::builder().random_ties(false).build())
+        .classify(pixel)
+        .expect("the bundled dataset is not empty")
+}
+
+/// Parses `rrggbb` or `rgb` hex digits, optionally prefixed with `#`.
+pub(crate) const fn parse_hex(code: &str) -> Option<[u8; 3]> {
+    let digits = match code.as_bytes() {
+        [b'#', rest @ ..] => rest,
+        digits => digits,
+    };
+    let (mut v, mut i) = (0u32, 0);
+    while i < digits.len() {
+        let d = match digits[i] {
+            d @ b'0'..=b'9' => d - b'0',
+            d @ b'a'..=b'f' => d - b'a' + 10,
+            d @ b'A'..=b'F' => d - b'A' + 10,
+            _ => return None,
+        };
+        v = v << 4 | d as u32;
+        i += 1;
+    }
+    match digits.len() {
+        6 => Some([(v >> 16) as u8, (v >> 8) as u8, v as u8]),
+        3 => Some([
+            ((v >> 8) as u8 & 0xf) * 17,
+            ((v >> 4) as u8 & 0xf) * 17,
+            (v as u8 & 0xf) * 17,
+        ]),
+        _ => None,
+    }
+}
+
+/// Parses a hex code, with an optional alpha byte, or CSS style `rgb(r, g, b)`.
+pub(crate) fn parse_color(s: &str) -> Option<[u8; 4]> {
+    let s = s.trim().to_ascii_lowercase();
+    if let Some(args) = s.strip_prefix("rgb(").and_then(|s| s.strip_suffix(')')) {
+        let mut fields = args.split(',');
+        let mut rgba = [255; 4];
+        for c in &mut rgba[..3] {
+            *c = fields.next()?.trim().parse().ok()?;
+        }
+        return fields.next().is_none().then_some(rgba);
+    }
+    let digits = s.strip_prefix('#').unwrap_or(&s);
+    match (digits.len(), digits.bytes().all(|b| b.is_ascii_hexdigit())) {
+        (8, true) => {
+            let [r, g, b] = parse_hex(&digits[..6])?;
+            Some([r, g, b, u8::from_str_radix(&digits[6..], 16).ok()?])
+        }
+        _ => parse_hex(digits).map(|[r, g, b]| [r, g, b, 255]),
+    }
+}
+
+/// [`parse_hex`] for [`palette!`](crate::palette!), where an invalid code fails the build.
+#[doc(hidden)]
+pub const fn hex(code: &str) -> [u8; 3] {
+    match parse_hex(code) {
+        Some(rgb) => rgb,
+        None => panic!("invalid hex color, expected #rrggbb or #rgb"),
+    }
+}
+
+pub(crate) fn hsv(pixel: &[u8; 4]) -> [f32; 3] {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 255.);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / delta).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / delta + 2.)
+    } else {
+        60. * ((r - g) / delta + 4.)
+    };
+    let sat = if max == 0. { 0. } else { delta / max };
+    [hue, sat, max]
+}
+
+/// Inverse of [`hsv`], with an opaque alpha.
+pub(crate) fn from_hsv([hue, sat, val]: [f32; 3]) -> [u8; 4] {
+    let c = val * sat;
+    let h = hue.rem_euclid(360.) / 60.;
+    let x = c * (1. - (h % 2. - 1.).abs());
+    let [r, g, b] = match h as u8 {
+        0 => [c, x, 0.],
+        1 => [x, c, 0.],
+        2 => [0., c, x],
+        3 => [0., x, c],
+        4 => [x, 0., c],
+        _ => [c, 0., x],
+    };
+    let m = val - c;
+    let [r, g, b] = [r, g, b].map(|v| ((v + m) * 255.).round().clamp(0., 255.) as u8);
+    [r, g, b, 255]
+}
+
+/// Hue in degrees, saturation and lightness, both 0-1.
+pub(crate) fn hsl(pixel: &[u8; 4]) -> [f32; 3] {
+    let [hue, sat, val] = hsv(pixel);
+    let light = val * (1. - sat / 2.);
+    let sat = if light == 0. || light == 1. {
+        0.
+    } else {
+        (val - light) / light.min(1. - light)
+    };
+    [hue, sat, light]
+}
+
+/// Inverse of [`hsl`], with an opaque alpha.
+pub(crate) fn from_hsl([hue, sat, light]: [f32; 3]) -> [u8; 4] {
+    let val = light + sat * light.min(1. - light);
+    let sat = if val == 0. {
+        0.
+    } else {
+        2. * (1. - light / val)
+    };
+    from_hsv([hue, sat, val])
+}
+
+/// Rec. 601 luma, 0-1.
+pub(crate) fn luma(pixel: &[u8; 4]) -> f32 {
+    (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) / 255.
+}
+
+/// WCAG contrast ratio between two colors, from 1 for the same luminance to 21 for black on
+/// white. Text needs at least 4.5 to be comfortably readable.
+pub fn contrast(a: &[u8; 4], b: &[u8; 4]) -> f32 {
+    let luminance = |c: &[u8; 4]| {
+        let [r, g, b] = [c[0], c[1], c[2]].map(linear);
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    };
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Dichromatic color vision deficiencies, for [`Deficiency::simulate`] and the
+/// [`Simulate`](crate::mappers::Simulate) and [`Daltonize`](crate::mappers::Daltonize) mappers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum Deficiency {
+    /// No red cones
+    Protanopia,
+    /// No green cones, the most common
+    Deuteranopia,
+    /// No blue cones
+    Tritanopia,
+}
+
+impl Deficiency {
+    /// How `pixel` looks with the deficiency, using the full severity matrices of Machado et al.
+    /// (2009) in linear RGB. Alpha is kept.
+    pub fn simulate(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        let m = match self {
+            Self::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Self::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.01182, 0.04294, 0.968881],
+            ],
+            Self::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.3039],
+            ],
+        };
+        let rgb = [pixel[0], pixel[1], pixel[2]].map(linear);
+        let [r, g, b] = m.map(|row| encode((0..3).map(|c| row[c] * rgb[c]).sum()));
+        [r, g, b, pixel[3]]
+    }
+
+    /// Shifts the difference between `pixel` and its [simulation](Deficiency::simulate) into the
+    /// channels the deficiency leaves intact, so that colors it confuses look different again.
+    pub fn daltonize(&self, pixel: &[u8; 4]) -> [u8; 4] {
+        let sim = self.simulate(pixel);
+        let [r, g, b] = [0, 1, 2].map(|c| pixel[c] as f32 - sim[c] as f32);
+        let shift = match self {
+            Self::Protanopia | Self::Deuteranopia => [0., 0.7 * r + g, 0.7 * r + b],
+            Self::Tritanopia => [r + 0.7 * b, g + 0.7 * b, 0.],
+        };
+        let [r, g, b] =
+            [0, 1, 2].map(|c| (pixel[c] as f32 + shift[c]).round().clamp(0., 255.) as u8);
+        [r, g, b, pixel[3]]
+    }
+}
+
+fn linear(c: u8) -> f32 {
+    let c = c as f32 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Inverse of `linear`, clamping to the gamut
+fn encode(c: f32) -> u8 {
+    let c = c.clamp(0., 1.);
+    let c = if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    };
+    (c * 255.).round() as u8
+}
+
+/// CIELAB coordinates (D65 white point) of an sRGB pixel.
+pub fn lab(pixel: &[u8; 4]) -> [f32; 3] {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(linear);
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 216. / 24389. {
+            t.cbrt()
+        } else {
+            (24389. / 27. * t + 16.) / 116.
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+/// The sRGB pixel, clamped to the gamut, at CIELAB coordinates as given by [`lab`].
+pub fn from_lab([l, a, b]: [f32; 3]) -> [u8; 4] {
+    let fy = (l + 16.) / 116.;
+    let (fx, fz) = (fy + a / 500., fy - b / 200.);
+    let f_inv = |t: f32| {
+        if t > 6. / 29. {
+            t.powi(3)
+        } else {
+            (116. * t - 16.) * 27. / 24389.
+        }
+    };
+    let [x, y, z] = [f_inv(fx) * 0.95047, f_inv(fy), f_inv(fz) * 1.08883];
+    let rgb = [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ];
+    let [r, g, b] = rgb.map(encode);
+    [r, g, b, 255]
+}
+
+/// OkLab coordinates of an sRGB pixel.
+pub fn oklab(pixel: &[u8; 4]) -> [f32; 3] {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(linear);
+    let l = (0.4122215 * r + 0.5363325 * g + 0.051446 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.107397 * b).cbrt();
+    let s = (0.0883025 * r + 0.2817188 * g + 0.6299787 * b).cbrt();
+    [
+        0.2104543 * l + 0.7936178 * m - 0.004072 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904 * l + 0.7827718 * m - 0.8086758 * s,
+    ]
+}
+
+/// The sRGB pixel, clamped to the gamut, at OkLab coordinates as given by [`oklab`].
+pub fn from_oklab([l, a, b]: [f32; 3]) -> [u8; 4] {
+    let l_ = (l + 0.3963377 * a + 0.2158037 * b).powi(3);
+    let m_ = (l - 0.1055613 * a - 0.0638541 * b).powi(3);
+    let s_ = (l - 0.0894841 * a - 1.2914855 * b).powi(3);
+    let rgb = [
+        4.0767416 * l_ - 3.3077115 * m_ + 0.2309699 * s_,
+        -1.268438 * l_ + 2.6097574 * m_ - 0.3413193 * s_,
+        -0.004196 * l_ - 0.7034186 * m_ + 1.7076147 * s_,
+    ];
+    let [r, g, b] = rgb.map(encode);
+    [r, g, b, 255]
+}
+
+/// Euclidean distance between two CIELAB colors.
+pub fn cie76(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// CIEDE2000 color difference between two CIELAB colors.
+pub fn ciede2000(lab1: &[f32; 3], lab2: &[f32; 3]) -> f32 {
+    use std::f32::consts::PI;
+    let [l1, a1, b1] = *lab1;
+    let [l2, a2, b2] = *lab2;
+
+    let c_avg = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.;
+    let g = 0.5 * (1. - (c_avg.powi(7) / (c_avg.powi(7) + 25f32.powi(7))).sqrt());
+    let (a1, a2) = (a1 * (1. + g), a2 * (1. + g));
+    let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    let hue = |b: f32, a: f32| {
+        if a == 0. && b == 0. {
+            0.
+        } else {
+            b.atan2(a).rem_euclid(2. * PI)
+        }
+    };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0. {
+        0.
+    } else if (h2 - h1).abs() <= PI {
+        h2 - h1
+    } else if h2 - h1 > PI {
+        h2 - h1 - 2. * PI
+    } else {
+        h2 - h1 + 2. * PI
+    };
+    let dh = 2. * (c1 * c2).sqrt() * (dh / 2.).sin();
+
+    let l_avg = (l1 + l2) / 2.;
+    let c_avg = (c1 + c2) / 2.;
+    let h_avg = if c1 * c2 == 0. {
+        h1 + h2
+    } else if (h1 - h2).abs() <= PI {
+        (h1 + h2) / 2.
+    } else if h1 + h2 < 2. * PI {
+        (h1 + h2 + 2. * PI) / 2.
+    } else {
+        (h1 + h2 - 2. * PI) / 2.
+    };
+
+    let t = 1. - 0.17 * (h_avg - PI / 6.).cos()
+        + 0.24 * (2. * h_avg).cos()
+        + 0.32 * (3. * h_avg + PI / 30.).cos()
+        - 0.20 * (4. * h_avg - 63f32.to_radians()).cos();
+    let d_theta = 30f32.to_radians() * (-((h_avg.to_degrees() - 275.) / 25.).powi(2)).exp();
+    let rc = 2. * (c_avg.powi(7) / (c_avg.powi(7) + 25f32.powi(7))).sqrt();
+    let sl = 1. + 0.015 * (l_avg - 50.).powi(2) / (20. + (l_avg - 50.).powi(2)).sqrt();
+    let sc = 1. + 0.045 * c_avg;
+    let sh = 1. + 0.015 * c_avg * t;
+    let rt = -(2. * d_theta).sin() * rc;
+
+    ((dl / sl).powi(2) + (dc / sc).powi(2) + (dh / sh).powi(2) + rt * (dc / sc) * (dh / sh)).sqrt()
+}
+
+/// Semantic role of a palette color, for theme palettes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Role {
+    Background,
+    Foreground,
+    Accent(u8),
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Background => write!(f, "background"),
+            Self::Foreground => write!(f, "foreground"),
+            Self::Accent(n) => write!(f, "accent{n}"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "background" | "bg" => Ok(Self::Background),
+            "foreground" | "fg" => Ok(Self::Foreground),
+            other => other
+                .strip_prefix("accent")
+                .and_then(|n| n.parse().ok())
+                .map(Self::Accent)
+                .ok_or_else(|| PaletteError::UnknownRole(s.into())),
+        }
+    }
+}
+
+/// Palette colors by role.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Roles(Vec<(Role, Rgbx)>);
+
+impl Roles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assigns `color` to `role`, replacing any previous assignment.
+    #[must_use]
+    pub fn assign(mut self, role: Role, color: Rgbx) -> Self {
+        self.0.retain(|(r, _)| *r != role);
+        self.0.push((role, color));
+        self
+    }
+
+    pub fn get(&self, role: Role) -> Option<Rgbx> {
+        self.0.iter().find(|(r, _)| *r == role).map(|(_, c)| *c)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Role, Rgbx)> {
+        self.0.iter()
+    }
+}
+
+/// Light or dark flavor of a palette, see [`Palette::variant`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Variant {
+    /// Whichever suits the image being processed: dark for images with an average luma below
+    /// 0.5, light otherwise.
+    Auto,
+    Light,
+    Dark,
+}
+
+/// Text formats palettes can be exported to with [`Palette::export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum PaletteFormat {
+    /// A GIMP palette, also read by Aseprite, Inkscape and Krita
+    Gpl,
+    /// `#rrggbb` codes, one per line
+    Hex,
+    /// The Lospec layout, `{"name": "...", "author": "...", "colors": ["rrggbb", ...]}`
+    Json,
+}
+
+/// WCAG 2 conformance of a [`contrast`] ratio, from worst to best.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ContrastLevel {
+    /// Below 3, not enough for any text.
+    Fail,
+    /// At least 3, AA for large text only.
+    AaLarge,
+    /// At least 4.5, AA for all text and AAA for large text.
+    Aa,
+    /// At least 7, AAA for all text.
+    Aaa,
+}
+
+impl ContrastLevel {
+    pub fn of(ratio: f32) -> Self {
+        match ratio {
+            r if r >= 7. => Self::Aaa,
+            r if r >= 4.5 => Self::Aa,
+            r if r >= 3. => Self::AaLarge,
+            _ => Self::Fail,
+        }
+    }
+}
+
+impl fmt::Display for ContrastLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fail => write!(f, "fail"),
+            Self::AaLarge => write!(f, "AA large"),
+            Self::Aa => write!(f, "AA"),
+            Self::Aaa => write!(f, "AAA"),
+        }
+    }
+}
+
+/// The contrast between two entries of a palette, or an entry and a background, see
+/// [`Palette::contrast_report`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContrastPair {
+    pub first: usize,
+    /// `None` when checked against a background with [`Palette::contrast_against`].
+    pub second: Option<usize>,
+    pub ratio: f32,
+}
+
+impl ContrastPair {
+    pub fn level(&self) -> ContrastLevel {
+        ContrastLevel::of(self.ratio)
+    }
+}
+
+/// A problem with a palette found by [`Palette::validate`]. Entries are referred to by index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaletteIssue {
+    /// Two entries with the same RGB value; only the first is ever chosen.
+    Duplicate(usize, usize),
+    /// Two entries with a [`ciede2000`] distance below 3, which are hard to tell apart.
+    NearDuplicate(usize, usize, f32),
+    /// One of the classes of the bundled datasets without any entry, so classifying mappers fall
+    /// back to the nearest color for pixels of that class.
+    MissingClass(ColorClass),
+    /// A span of hues, in degrees from the first to the second, wider than 90 with no saturated
+    /// entry. Colors in it map to greys or to neighbouring hues. Equal ends mean the palette has
+    /// a single saturated hue.
+    HueGap(f32, f32),
+}
+
+impl fmt::Display for PaletteIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate(a, b) => write!(f, "colors {a} and {b} are the same"),
+            Self::NearDuplicate(a, b, d) => {
+                write!(
+                    f,
+                    "colors {a} and {b} are nearly the same (\u{394}E {d:.2})"
+                )
+            }
+            Self::MissingClass(class) => write!(f, "no color of class {class:?}"),
+            Self::HueGap(from, to) if from == to => {
+                write!(f, "no saturated color for hues other than {from:.0}")
+            }
+            Self::HueGap(from, to) => write!(f, "no saturated color for hues {from:.0}-{to:.0}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PaletteError {
+    Io(std::io::Error),
+    #[cfg(any(feature = "pywal", feature = "http"))]
+    Json(serde_json::Error),
+    #[cfg(feature = "http")]
+    Http(Box<ureq::Error>),
+    InvalidHex(String),
+    /// A binary palette file that is truncated or uses features that aren't supported.
+    InvalidFile(&'static str),
+    /// A text palette without the header line that identifies its format.
+    MissingHeader(&'static str),
+    /// A text palette line, by 1-based line number, that isn't a color.
+    Malformed(usize),
+    /// A scheme without one of the colors its format requires.
+    MissingColor(&'static str),
+    UnknownClass(String),
+    UnknownRole(String),
+    /// A Lospec slug with characters other than lowercase letters, digits and dashes.
+    #[cfg(feature = "http")]
+    InvalidSlug(String),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read palette: {e}"),
+            #[cfg(any(feature = "pywal", feature = "http"))]
+            Self::Json(e) => write!(f, "failed to parse palette: {e}"),
+            #[cfg(feature = "http")]
+            Self::Http(e) => write!(f, "failed to download palette: {e}"),
+            Self::InvalidHex(code) => write!(f, "`{code}` is not a valid color"),
+            Self::InvalidFile(reason) => write!(f, "invalid palette file: {reason}"),
+            Self::MissingHeader(header) => write!(f, "missing `{header}` header"),
+            Self::Malformed(line) => write!(f, "line {line} is not a color"),
+            Self::MissingColor(slot) => write!(f, "missing color `{slot}`"),
+            Self::UnknownClass(name) => write!(f, "unknown color class `{name}`"),
+            Self::UnknownRole(name) => write!(f, "unknown role `{name}`"),
+            #[cfg(feature = "http")]
+            Self::InvalidSlug(slug) => write!(f, "`{slug}` is not a valid Lospec slug"),
+        }
+    }
+}
+
+impl Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(any(feature = "pywal", feature = "http"))]
+impl From<serde_json::Error> for PaletteError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<ureq::Error> for PaletteError {
+    fn from(e: ureq::Error) -> Self {
+        Self::Http(Box::new(e))
+    }
+}
+
+/// A palette with its metadata: a name, an author, names for individual colors and their roles.
+/// Dereferences to its colors, so it can be passed anywhere a `&[Rgbx]` is expected.
+///
+/// ```
+/// use mapped::{palette::{Palette, Role, NORD}, ProcOptions};
+///
+/// let nord = Palette::nord();
+/// assert_eq!(nord.role(Role::Background), Some(NORD[12]));
+/// let opts = ProcOptions::default().palette(&nord);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "PaletteFields"))]
+pub struct Palette {
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    name: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    author: Option<String>,
+    colors: Vec<Rgbx>,
+    color_names: Vec<Option<String>>,
+    roles: Roles,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    variants: Vec<Palette>,
+}
+
+impl Palette {
+    pub fn new(colors: impl Into<Vec<Rgbx>>) -> Self {
+        let colors = colors.into();
+        Palette {
+            color_names: vec![None; colors.len()],
+            colors,
+            ..Palette::default()
+        }
+    }
+
+    /// Parses `#rrggbb` or `#rgb` codes, with or without the `#`, classifying each color with
+    /// [`classify`].
+    ///
+    /// ```
+    /// use mapped::palette::{ColorClass, Palette};
+    ///
+    /// let palette = Palette::from_hex(&["#2e3440", "#d8dee9", "bf616a"])?;
+    /// assert_eq!(palette[2].3, ColorClass::Red);
+    /// # Ok::<(), mapped::palette::PaletteError>(())
+    /// ```
+    pub fn from_hex<S: AsRef<str>>(codes: &[S]) -> Result<Self, PaletteError> {
+        codes
+            .iter()
+            .map(|code| {
+                let code = code.as_ref().trim();
+                let [r, g, b] =
+                    parse_hex(code).ok_or_else(|| PaletteError::InvalidHex(code.into()))?;
+                Ok(Rgbx(r, g, b, classify(&[r, g, b, 255])))
+            })
+            .collect()
+    }
+
+    /// [`Palette::from_hex`] for codes pasted as text, one per line. Commas and other whitespace
+    /// also separate codes, and blank lines are skipped.
+    pub fn from_hex_str(text: &str) -> Result<Self, PaletteError> {
+        let codes: Vec<&str> = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|code| !code.is_empty())
+            .collect();
+        Self::from_hex(&codes)
+    }
+
+    /// Parses a GIMP palette (`.gpl`), classifying each color with [`classify`]. The `Name:`
+    /// header and the names after each color are kept, except GIMP's `Untitled` placeholder.
+    ///
+    /// ```
+    /// use mapped::palette::Palette;
+    ///
+    /// let gpl = "GIMP Palette\nName: Duo\nColumns: 2\n#\n 46  52  64\tPolar Night\n216 222 233\n";
+    /// let palette = Palette::from_gpl(gpl)?;
+    /// assert_eq!(palette.name(), Some("Duo"));
+    /// assert_eq!(palette.color_name(0), Some("Polar Night"));
+    /// # Ok::<(), mapped::palette::PaletteError>(())
+    /// ```
+    pub fn from_gpl(gpl: &str) -> Result<Self, PaletteError> {
+        let lines = text_lines(gpl, "GIMP Palette")?;
+        let mut name = None;
+        let mut colors = Vec::new();
+        let mut names = Vec::new();
+        for (i, line) in lines {
+            if line.starts_with('#') || line.starts_with("Columns:") {
+                continue;
+            }
+            if let Some(n) = line.strip_prefix("Name:") {
+                name = Some(n.trim().to_string());
+                continue;
+            }
+            let ([r, g, b], color_name) = rgb_fields(line).ok_or(PaletteError::Malformed(i))?;
+            colors.push(Rgbx(r, g, b, classify(&[r, g, b, 255])));
+            names.push(
+                (!color_name.is_empty() && color_name != "Untitled").then(|| color_name.into()),
+            );
+        }
+        let mut palette = Palette::new(colors);
+        palette.name = name.filter(|n| !n.is_empty());
+        palette.color_names = names;
+        Ok(palette)
+    }
+
+    pub fn load_gpl<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_gpl(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a JASC-PAL palette (`.pal`), as written by Paint Shop Pro and Aseprite, classifying
+    /// each color with [`classify`]. The color count on the third line isn't checked against the
+    /// colors that follow.
+    pub fn from_jasc(pal: &str) -> Result<Self, PaletteError> {
+        let mut lines = text_lines(pal, "JASC-PAL")?;
+        for _ in 0..2 {
+            match lines.next() {
+                Some((i, field)) if field.parse::<u32>().is_err() => {
+                    return Err(PaletteError::Malformed(i))
+                }
+                _ => {}
+            }
+        }
+        lines
+            .map(|(i, line)| match rgb_fields(line) {
+                Some(([r, g, b], "")) => Ok(Rgbx(r, g, b, classify(&[r, g, b, 255]))),
+                _ => Err(PaletteError::Malformed(i)),
+            })
+            .collect()
+    }
+
+    pub fn load_jasc<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_jasc(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a paint.net palette (`.txt`): one `AARRGGBB` hex color per line, with `;` starting
+    /// comments. Colors are classified with [`classify`] and their alpha is dropped.
+    pub fn from_paint_net(txt: &str) -> Result<Self, PaletteError> {
+        txt.lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+            .map(|(i, line)| {
+                let rgb = match line.split_at_checked(2) {
+                    Some((alpha, rgb)) if alpha.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                        parse_hex(rgb).filter(|_| rgb.len() == 6)
+                    }
+                    _ => None,
+                };
+                let [r, g, b] = rgb.ok_or(PaletteError::Malformed(i))?;
+                Ok(Rgbx(r, g, b, classify(&[r, g, b, 255])))
+            })
+            .collect()
+    }
+
+    pub fn load_paint_net<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_paint_net(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a [base16](https://github.com/tinted-theming/home) or base24 scheme, in either the
+    /// flat legacy layout or the newer one with a `palette:` map. The colors keep their slot order
+    /// and names (`base00`, `base01`, ...) and get classes from their standard meaning: the
+    /// `base00`-`base07` shades are [`Whites`] or [`Greys`] by brightness, `base08` is red,
+    /// `base09` orange and so on.
+    /// `base00` is the background, `base05` the foreground and `base0D` the first accent.
+    ///
+    /// ```
+    /// use mapped::palette::{ColorClass, Palette, Role};
+    ///
+    /// let yaml = (0..16).fold(String::from("scheme: \"Gray\"\n"), |yaml, i| {
+    ///     yaml + format!("base0{i:X}: \"{:02x}{0:02x}{0:02x}\"\n", i * 16).as_str()
+    /// });
+    /// let scheme = Palette::from_base16(&yaml)?;
+    /// assert_eq!(scheme.name(), Some("Gray"));
+    /// assert_eq!(scheme.by_name("base08").map(|c| c.3), Some(ColorClass::Red));
+    /// assert_eq!(scheme.role(Role::Foreground), scheme.by_name("base05"));
+    /// # Ok::<(), mapped::palette::PaletteError>(())
+    /// ```
+    pub fn from_base16(yaml: &str) -> Result<Self, PaletteError> {
+        let fields: Vec<(&str, String)> = yaml
+            .lines()
+            .filter_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                Some((key.trim(), yaml_scalar(value.trim())))
+            })
+            .collect();
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        let mut colors = Vec::new();
+        for (i, (slot, _)) in BASE16_SLOTS.into_iter().enumerate() {
+            let Some(code) = field(slot) else {
+                match i {
+                    0..=15 => return Err(PaletteError::MissingColor(slot)),
+                    _ => break,
+                }
+            };
+            colors.push(parse_hex(code).ok_or_else(|| PaletteError::InvalidHex(code.into()))?);
+        }
+        let mut palette = Self::from_base16_colors(colors);
+        let text = |key| field(key).filter(|v| !v.is_empty()).map(String::from);
+        palette.name = text("scheme").or_else(|| text("name"));
+        palette.author = text("author");
+        Ok(palette)
+    }
+
+    /// The colors of a base16 or base24 scheme in slot order, named, classified and with roles as
+    /// [`Palette::from_base16`] does.
+    pub(crate) fn from_base16_colors(colors: impl IntoIterator<Item = [u8; 3]>) -> Self {
+        let colors: Vec<Rgbx> = colors
+            .into_iter()
+            .zip(BASE16_SLOTS)
+            .enumerate()
+            .map(|(i, ([r, g, b], (_, class)))| {
+                let pixel = [r, g, b, 255];
+                let class = class.unwrap_or_else(|| match i {
+                    0..=7 | 16 | 17 if luma(&pixel) >= 0.5 => ColorClass::Whites,
+                    0..=7 | 16 | 17 => ColorClass::Greys,
+                    _ => classify(&pixel),
+                });
+                Rgbx(r, g, b, class)
+            })
+            .collect();
+        BASE16_SLOTS
+            .iter()
+            .enumerate()
+            .fold(Palette::new(colors), |palette, (i, (slot, _))| {
+                palette.name_color(i, *slot)
+            })
+            .assign(Role::Background, 0)
+            .assign(Role::Foreground, 5)
+            .assign(Role::Accent(0), 13)
+    }
+
+    pub fn load_base16<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_base16(&std::fs::read_to_string(path)?)
+    }
+
+    /// Renders the palette in the given format. GIMP palettes keep the palette and color names,
+    /// so they round-trip through [`Palette::from_gpl`]; classes and roles aren't kept by any of
+    /// the formats.
+    ///
+    /// ```
+    /// use mapped::palette::{Palette, PaletteFormat};
+    ///
+    /// let hex = Palette::nord().export(PaletteFormat::Hex);
+    /// assert!(hex.starts_with("#d8dee9\n"));
+    /// ```
+    pub fn export(&self, format: PaletteFormat) -> String {
+        let mut out = String::new();
+        match format {
+            PaletteFormat::Gpl => {
+                out.push_str("GIMP Palette\n");
+                if let Some(name) = &self.name {
+                    writeln!(out, "Name: {name}").unwrap();
+                }
+                out.push_str("#\n");
+                for (i, c) in self.colors.iter().enumerate() {
+                    let name = self.color_name(i).unwrap_or("Untitled");
+                    writeln!(out, "{:3} {:3} {:3}\t{name}", c.0, c.1, c.2).unwrap();
+                }
+            }
+            PaletteFormat::Hex => {
+                for c in &self.colors {
+                    writeln!(out, "{c}").unwrap();
+                }
+            }
+            PaletteFormat::Json => {
+                let colors: Vec<String> = self
+                    .colors
+                    .iter()
+                    .map(|c| format!("\"{}\"", &c.to_hex()[1..]))
+                    .collect();
+                writeln!(
+                    out,
+                    "{{\"name\": {}, \"author\": {}, \"colors\": [{}]}}",
+                    json_string(self.name().unwrap_or_default()),
+                    json_string(self.author().unwrap_or_default()),
+                    colors.join(", ")
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    /// Writes [`Palette::export`] to `path`.
+    pub fn save<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        format: PaletteFormat,
+    ) -> Result<(), PaletteError> {
+        Ok(std::fs::write(path, self.export(format))?)
+    }
+
+    /// [`NORD`] with its metadata.
+    pub fn nord() -> Self {
+        let names = [4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3];
+        names.iter().enumerate().fold(
+            Palette::new(NORD)
+                .named("Nord")
+                .by("Arctic Ice Studio")
+                .assign(Role::Background, 12)
+                .assign(Role::Foreground, 0)
+                .assign(Role::Accent(0), 4),
+            |palette, (i, n)| palette.name_color(i, format!("nord{n}")),
+        )
+    }
+
+    #[must_use]
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn by(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Names the color at `index`. Out of range indices are ignored.
+    #[must_use]
+    pub fn name_color(mut self, index: usize, name: impl Into<String>) -> Self {
+        if let Some(slot) = self.color_names.get_mut(index) {
+            *slot = Some(name.into());
+        }
+        self
+    }
+
+    /// Appends the colors of `other` with their names. Its roles are kept where this palette
+    /// doesn't fill them already; the name and author are this palette's.
+    ///
+    /// ```
+    /// use mapped::palette::{Palette, NORD};
+    ///
+    /// let extracted = Palette::new([NORD[7], NORD[12]]);
+    /// let merged = [Palette::nord(), extracted]
+    ///     .iter()
+    ///     .fold(Palette::default(), Palette::merge)
+    ///     .dedup(1.);
+    /// assert_eq!(merged.len(), NORD.len());
+    /// ```
+    #[must_use]
+    pub fn merge(mut self, other: &Palette) -> Self {
+        self.colors.extend_from_slice(&other.colors);
+        self.color_names.extend(other.color_names.iter().cloned());
+        for &(role, color) in other.roles.iter() {
+            if self.roles.get(role).is_none() {
+                self.roles = self.roles.assign(role, color);
+            }
+        }
+        self
+    }
+
+    /// Drops colors within a [`ciede2000`] distance of `tolerance` from an earlier color, 0 only
+    /// dropping exact duplicates. Names of dropped colors go to the color they were merged into
+    /// if it has none, and so do their roles.
+    #[must_use]
+    pub fn dedup(self, tolerance: f32) -> Self {
+        let labs: Vec<[f32; 3]> = self.colors.iter().map(|c| lab(&c.rgba_array())).collect();
+        // Indices of the colors kept, and the position among them each color merged into
+        let (mut kept, mut merged_into) = (Vec::<usize>::new(), Vec::new());
+        for (i, color) in labs.iter().enumerate() {
+            match kept
+                .iter()
+                .position(|&k| ciede2000(&labs[k], color) <= tolerance)
+            {
+                Some(p) => merged_into.push(p),
+                None => {
+                    merged_into.push(kept.len());
+                    kept.push(i);
+                }
+            }
+        }
+        let mut names: Vec<Option<String>> = vec![None; kept.len()];
+        for (name, &p) in self.color_names.into_iter().zip(&merged_into) {
+            if names[p].is_none() {
+                names[p] = name;
+            }
+        }
+        let colors: Vec<Rgbx> = kept.iter().map(|&k| self.colors[k]).collect();
+        let roles = self
+            .roles
+            .iter()
+            .fold(Roles::new(), |roles, &(role, color)| {
+                let color = match self.colors.iter().position(|c| *c == color) {
+                    Some(i) => colors[merged_into[i]],
+                    None => color,
+                };
+                roles.assign(role, color)
+            });
+        Palette {
+            name: self.name,
+            author: self.author,
+            colors,
+            color_names: names,
+            roles,
+            variants: self.variants,
+        }
+    }
+
+    /// An intermediate palette a fraction `t` of the way to `other`, clamped to 0-1. Each color
+    /// moves towards the closest color of `other` in its class, or the closest overall when the
+    /// class is missing there, interpolating in [`oklab`] with [`Rgbx::lerp`]. Roles follow the
+    /// entries they were given to, and the length, names, name and author are this palette's.
+    ///
+    /// Processing with the palettes of `t` stepping from 0 to 1 morphs an image from one
+    /// palette to the other, e.g. for a day to night wallpaper.
+    ///
+    /// ```
+    /// use mapped::palette;
+    ///
+    /// let day = palette::by_name("gruvbox-light").unwrap();
+    /// let night = palette::by_name("gruvbox-dark").unwrap();
+    /// let dusk = day.lerp(night, 0.5);
+    /// assert_eq!(dusk.len(), day.len());
+    /// assert_eq!(&day.lerp(night, 0.), day);
+    /// ```
+    #[must_use]
+    pub fn lerp(&self, other: &Palette, t: f32) -> Self {
+        let mut palette = self.clone();
+        if other.is_empty() {
+            return palette;
+        }
+        let labs: Vec<[f32; 3]> = other.iter().map(Rgbx::to_oklab).collect();
+        let target = |color: &Rgbx| {
+            let lab = color.to_oklab();
+            let dist = |i: &usize| (0..3).map(|c| (labs[*i][c] - lab[c]).powi(2)).sum::<f32>();
+            let closest = |same_class: bool| {
+                (0..labs.len())
+                    .filter(|&i| !same_class || other.colors[i].3 == color.3)
+                    .min_by(|a, b| dist(a).total_cmp(&dist(b)))
+            };
+            other.colors[closest(true).or_else(|| closest(false)).unwrap()]
+        };
+        for color in palette.colors.iter_mut() {
+            *color = color.lerp(&target(color), t);
+        }
+        // Roles stay on the entry they were given to, wherever that entry moved
+        palette.roles = self
+            .roles
+            .iter()
+            .fold(Roles::new(), |roles, &(role, color)| {
+                let moved = match self.colors.iter().position(|c| *c == color) {
+                    Some(i) => palette.colors[i],
+                    None => color.lerp(&target(&color), t),
+                };
+                roles.assign(role, moved)
+            });
+        palette
+    }
+
+    /// Gives the color at `index` a role. Out of range indices are ignored.
+    #[must_use]
+    pub fn assign(mut self, role: Role, index: usize) -> Self {
+        if let Some(&color) = self.colors.get(index) {
+            self.roles = self.roles.assign(role, color);
+        }
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn colors(&self) -> &[Rgbx] {
+        &self.colors
+    }
+
+    pub fn color_name(&self, index: usize) -> Option<&str> {
+        self.color_names.get(index)?.as_deref()
+    }
+
+    /// Looks a color up by name, ignoring case.
+    pub fn by_name(&self, name: &str) -> Option<Rgbx> {
+        self.color_names
+            .iter()
+            .position(|n| n.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+            .map(|i| self.colors[i])
+    }
+
+    pub fn role(&self, role: Role) -> Option<Rgbx> {
+        self.roles.get(role)
+    }
+
+    /// Links `palette` as the light or dark counterpart of this one, going by
+    /// [`Palette::is_dark`]. A linked variant of the same flavor is replaced.
+    ///
+    /// ```
+    /// use mapped::palette::{Palette, Variant, NORD};
+    ///
+    /// let light = Palette::new([NORD[6], NORD[3], NORD[8]]);
+    /// let nord = Palette::nord().with_variant(light.clone());
+    /// assert_eq!(nord.variant(Variant::Light), &light);
+    /// assert_eq!(nord.variant(Variant::Dark), &nord);
+    /// ```
+    #[must_use]
+    pub fn with_variant(mut self, mut palette: Palette) -> Self {
+        palette.variants.clear();
+        self.variants.retain(|v| v.is_dark() != palette.is_dark());
+        self.variants.push(palette);
+        self
+    }
+
+    /// This palette if it already is of the `variant`'s flavor or no linked variant is,
+    /// otherwise the linked one. [`Variant::Auto`] depends on an image, see
+    /// [`ProcOptions::variant`](crate::ProcOptions::variant), so it gives this palette.
+    pub fn variant(&self, variant: Variant) -> &Palette {
+        let dark = match variant {
+            Variant::Auto => return self,
+            Variant::Light => false,
+            Variant::Dark => true,
+        };
+        if self.is_dark() == dark {
+            return self;
+        }
+        self.variants
+            .iter()
+            .find(|v| v.is_dark() == dark)
+            .unwrap_or(self)
+    }
+
+    /// Whether the background, or the average of the colors if no background is assigned, has a
+    /// luma below 0.5.
+    pub fn is_dark(&self) -> bool {
+        let luma = match self.role(Role::Background) {
+            Some(bg) => bg.luma(),
+            None if self.is_empty() => return false,
+            None => self.iter().map(Rgbx::luma).sum::<f32>() / self.len() as f32,
+        };
+        luma < 0.5
+    }
+
+    pub fn roles(&self) -> &Roles {
+        &self.roles
+    }
+
+    /// The light or dark counterparts linked with [`Palette::with_variant`].
+    pub fn variants(&self) -> &[Palette] {
+        &self.variants
+    }
+
+    /// Looks for what makes mappings with this palette muddy: duplicate and nearly duplicate
+    /// colors, classes of the bundled datasets that no color belongs to, and wide ranges of hue
+    /// without a saturated color.
+    ///
+    /// ```
+    /// use mapped::palette::{ColorClass, Palette, PaletteIssue};
+    ///
+    /// let issues = Palette::from_hex(&["#2e3440", "#2e3440", "#bf616a"])?.validate();
+    /// assert_eq!(issues[0], PaletteIssue::Duplicate(0, 1));
+    /// assert!(issues.contains(&PaletteIssue::MissingClass(ColorClass::Blues)));
+    /// # Ok::<(), mapped::palette::PaletteError>(())
+    /// ```
+    pub fn validate(&self) -> Vec<PaletteIssue> {
+        const NEAR: f32 = 3.;
+        const GAP: f32 = 90.;
+        const BUNDLED: [ColorClass; 8] = [
+            ColorClass::Blues,
+            ColorClass::Whites,
+            ColorClass::Greys,
+            ColorClass::Red,
+            ColorClass::Purple,
+            ColorClass::Green,
+            ColorClass::Yellow,
+            ColorClass::Orange,
+        ];
+
+        let mut issues = Vec::new();
+        let labs: Vec<[f32; 3]> = self.colors.iter().map(|c| lab(&c.rgba_array())).collect();
+        for i in 0..self.colors.len() {
+            for j in i + 1..self.colors.len() {
+                let (a, b) = (&self.colors[i], &self.colors[j]);
+                if a.rgba_array() == b.rgba_array() {
+                    issues.push(PaletteIssue::Duplicate(i, j));
+                    continue;
+                }
+                let d = ciede2000(&labs[i], &labs[j]);
+                if d < NEAR {
+                    issues.push(PaletteIssue::NearDuplicate(i, j, d));
+                }
+            }
+        }
+        issues.extend(
+            BUNDLED
+                .into_iter()
+                .filter(|class| self.colors.iter().all(|c| c.3 != *class))
+                .map(PaletteIssue::MissingClass),
+        );
+
+        let mut hues: Vec<f32> = self
+            .colors
+            .iter()
+            .map(|c| hsv(&c.rgba_array()))
+            .filter(|&[_, sat, val]| sat >= 0.2 && val >= 0.2)
+            .map(|[hue, ..]| hue)
+            .collect();
+        hues.sort_by(f32::total_cmp);
+        match (hues.first(), hues.last()) {
+            (Some(&first), Some(&last)) => {
+                let wrap = [(last, first + 360.)];
+                let gaps = hues.windows(2).map(|w| (w[0], w[1])).chain(wrap);
+                issues.extend(
+                    gaps.filter(|(from, to)| to - from > GAP)
+                        .map(|(from, to)| PaletteIssue::HueGap(from, to % 360.)),
+                );
+            }
+            _ => issues.push(PaletteIssue::HueGap(0., 360.)),
+        }
+        issues
+    }
+
+    /// The WCAG [`contrast`] of every pair of entries, lowest first, to check which
+    /// combinations are readable as text on each other.
+    ///
+    /// ```
+    /// use mapped::palette::{ContrastLevel, Palette};
+    ///
+    /// let report = Palette::nord().contrast_report();
+    /// let unreadable = report.iter().filter(|p| p.level() < ContrastLevel::Aa).count();
+    /// assert!(unreadable > 0 && unreadable < report.len());
+    /// ```
+    pub fn contrast_report(&self) -> Vec<ContrastPair> {
+        let mut pairs: Vec<ContrastPair> = (0..self.colors.len())
+            .flat_map(|i| (i + 1..self.colors.len()).map(move |j| (i, j)))
+            .map(|(i, j)| ContrastPair {
+                first: i,
+                second: Some(j),
+                ratio: contrast(&self.colors[i].rgba_array(), &self.colors[j].rgba_array()),
+            })
+            .collect();
+        pairs.sort_by(|a, b| a.ratio.total_cmp(&b.ratio));
+        pairs
+    }
+
+    /// The WCAG [`contrast`] of every entry against `background`, lowest first. Pass the
+    /// palette's own [`Role::Background`] to check its text colors.
+    pub fn contrast_against(&self, background: &Rgbx) -> Vec<ContrastPair> {
+        let mut pairs: Vec<ContrastPair> = self
+            .colors
+            .iter()
+            .enumerate()
+            .map(|(i, c)| ContrastPair {
+                first: i,
+                second: None,
+                ratio: contrast(&c.rgba_array(), &background.rgba_array()),
+            })
+            .collect();
+        pairs.sort_by(|a, b| a.ratio.total_cmp(&b.ratio));
+        pairs
+    }
+}
+
+// Deserialized palette before its color names are matched up with its colors
+#[cfg(feature = "serde")]
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PaletteFields {
+    name: Option<String>,
+    author: Option<String>,
+    colors: Vec<Rgbx>,
+    color_names: Vec<Option<String>>,
+    roles: Roles,
+    variants: Vec<Palette>,
+}
+
+#[cfg(feature = "serde")]
+impl From<PaletteFields> for Palette {
+    fn from(mut fields: PaletteFields) -> Self {
+        fields.color_names.resize(fields.colors.len(), None);
+        Palette {
+            name: fields.name,
+            author: fields.author,
+            colors: fields.colors,
+            color_names: fields.color_names,
+            roles: fields.roles,
+            variants: fields.variants,
+        }
+    }
+}
+
+impl Deref for Palette {
+    type Target = [Rgbx];
+
+    fn deref(&self) -> &[Rgbx] {
+        &self.colors
+    }
+}
+
+impl AsRef<[Rgbx]> for Palette {
+    fn as_ref(&self) -> &[Rgbx] {
+        &self.colors
+    }
+}
+
+impl From<Vec<Rgbx>> for Palette {
+    fn from(colors: Vec<Rgbx>) -> Self {
+        Palette::new(colors)
+    }
+}
+
+impl From<&[Rgbx]> for Palette {
+    fn from(colors: &[Rgbx]) -> Self {
+        Palette::new(colors)
+    }
+}
+
+impl<const N: usize> From<[Rgbx; N]> for Palette {
+    fn from(colors: [Rgbx; N]) -> Self {
+        Palette::new(colors)
+    }
+}
+
+impl<const N: usize> From<&[Rgbx; N]> for Palette {
+    fn from(colors: &[Rgbx; N]) -> Self {
+        Palette::new(colors.as_slice())
+    }
+}
+
+impl<'a> From<&'a Palette> for Cow<'a, Palette> {
+    fn from(palette: &'a Palette) -> Self {
+        Cow::Borrowed(palette)
+    }
+}
+
+impl From<Palette> for Cow<'_, Palette> {
+    fn from(palette: Palette) -> Self {
+        Cow::Owned(palette)
+    }
+}
+
+impl FromIterator<Rgbx> for Palette {
+    fn from_iter<I: IntoIterator<Item = Rgbx>>(iter: I) -> Self {
+        Palette::new(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+/// Shows the name and author, e.g. `Nord by Arctic Ice Studio`, or the number of colors for
+/// unnamed palettes.
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, &self.author) {
+            (Some(name), Some(author)) => write!(f, "{name} by {author}"),
+            (Some(name), None) => write!(f, "{name}"),
+            _ => write!(f, "unnamed palette of {} colors", self.colors.len()),
+        }
+    }
+}
+
+// The trimmed, non-empty lines of a text palette after its header, with 1-based line numbers
+fn text_lines<'a>(
+    text: &'a str,
+    header: &'static str,
+) -> Result<impl Iterator<Item = (usize, &'a str)>, PaletteError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()));
+    match lines.next() {
+        Some((_, first)) if first.trim_start_matches('\u{feff}') == header => {
+            Ok(lines.filter(|(_, line)| !line.is_empty()))
+        }
+        _ => Err(PaletteError::MissingHeader(header)),
+    }
+}
+
+// Three whitespace separated channel values and the trimmed rest of the line
+fn rgb_fields(line: &str) -> Option<([u8; 3], &str)> {
+    let (mut rgb, mut rest) = ([0; 3], line);
+    for c in &mut rgb {
+        let field = rest.trim_start();
+        let end = field.find(char::is_whitespace).unwrap_or(field.len());
+        *c = field[..end].parse().ok()?;
+        rest = &field[end..];
+    }
+    Some((rgb, rest.trim()))
+}
+
+// Slot names of base16 and base24 schemes, with the class of the slots that have a standard meaning
+const BASE16_SLOTS: [(&str, Option<ColorClass>); 24] = [
+    ("base00", None),
+    ("base01", None),
+    ("base02", None),
+    ("base03", None),
+    ("base04", None),
+    ("base05", None),
+    ("base06", None),
+    ("base07", None),
+    ("base08", Some(ColorClass::Red)),
+    ("base09", Some(ColorClass::Orange)),
+    ("base0A", Some(ColorClass::Yellow)),
+    ("base0B", Some(ColorClass::Green)),
+    ("base0C", Some(ColorClass::Cyan)),
+    ("base0D", Some(ColorClass::Blues)),
+    ("base0E", Some(ColorClass::Purple)),
+    ("base0F", Some(ColorClass::Brown)),
+    // Base24 only
+    ("base10", None),
+    ("base11", None),
+    ("base12", Some(ColorClass::Red)),
+    ("base13", Some(ColorClass::Yellow)),
+    ("base14", Some(ColorClass::Green)),
+    ("base15", Some(ColorClass::Cyan)),
+    ("base16", Some(ColorClass::Blues)),
+    ("base17", Some(ColorClass::Purple)),
+];
+
+// The value of a YAML scalar: double-quoted ones may hold backslash escapes, single-quoted ones
+// doubled quotes, and plain ones end at a comment
+fn yaml_scalar(value: &str) -> String {
+    let mut chars = value.chars();
+    let mut out = String::new();
+    match chars.next() {
+        Some('"') => {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('n') => out.push('\n'),
+                        Some('t') => out.push('\t'),
+                        Some('u') => {
+                            let code: String = chars.by_ref().take(4).collect();
+                            out.extend(
+                                u32::from_str_radix(&code, 16).ok().and_then(char::from_u32),
+                            );
+                        }
+                        Some(c) => out.push(c),
+                        None => break,
+                    },
+                    c => out.push(c),
+                }
+            }
+        }
+        Some('\'') => {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\'' if chars.as_str().starts_with('\'') => {
+                        out.push(c);
+                        chars.next();
+                    }
+                    '\'' => break,
+                    c => out.push(c),
+                }
+            }
+        }
+        _ => out.push_str(value.split(" #").next().unwrap_or_default().trim()),
+    }
+    out
+}
+
+// A JSON string literal, escaping quotes, backslashes and control characters. Also a valid
+// double-quoted YAML scalar
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Every built-in palette with its metadata, [`Palette::nord`] first. The light and dark
+/// flavors of Gruvbox and Catppuccin are linked as [variants](Palette::variants) of each other,
+/// with Latte as the light variant of the dark Catppuccin flavors and Mocha as its dark one.
+pub fn registry() -> &'static [Palette] {
+    const CATPPUCCIN: &str =
+        "rosewater flamingo pink mauve red maroon peach yellow green teal sky sapphire blue \
+        lavender text subtext1 subtext0 overlay2 overlay1 overlay0 surface2 surface1 surface0 \
+        base mantle crust";
+    static REGISTRY: OnceLock<Vec<Palette>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        // Colors, name, author, color names and the background, foreground and accent indices
+        type Theme = (
+            &'static [Rgbx],
+            &'static str,
+            &'static str,
+            &'static str,
+            [usize; 3],
+        );
+        let themes: [Theme; 13] = [
+            (
+                &GRUVBOX_DARK,
+                "Gruvbox Dark",
+                "Pavel Pertsev",
+                "bg0 bg1 bg2 bg3 bg4 gray fg4 fg3 fg2 fg1 red green yellow blue purple aqua \
+                 orange bright_red bright_green bright_yellow bright_blue bright_purple \
+                 bright_aqua bright_orange",
+                [0, 9, 16],
+            ),
+            (
+                &GRUVBOX_LIGHT,
+                "Gruvbox Light",
+                "Pavel Pertsev",
+                "bg0 bg1 bg2 bg3 bg4 gray fg4 fg3 fg2 fg1 red green yellow blue purple aqua \
+                 orange faded_red faded_green faded_yellow faded_blue faded_purple faded_aqua \
+                 faded_orange",
+                [0, 9, 16],
+            ),
+            (
+                &DRACULA,
+                "Dracula",
+                "Zeno Rocha",
+                "background current_line foreground comment cyan green orange pink purple red \
+                 yellow",
+                [0, 2, 8],
+            ),
+            (
+                &CATPPUCCIN_LATTE,
+                "Catppuccin Latte",
+                "Catppuccin",
+                CATPPUCCIN,
+                [23, 14, 3],
+            ),
+            (
+                &CATPPUCCIN_FRAPPE,
+                "Catppuccin Frappé",
+                "Catppuccin",
+                CATPPUCCIN,
+                [23, 14, 3],
+            ),
+            (
+                &CATPPUCCIN_MACCHIATO,
+                "Catppuccin Macchiato",
+                "Catppuccin",
+                CATPPUCCIN,
+                [23, 14, 3],
+            ),
+            (
+                &CATPPUCCIN_MOCHA,
+                "Catppuccin Mocha",
+                "Catppuccin",
+                CATPPUCCIN,
+                [23, 14, 3],
+            ),
+            (
+                &SOLARIZED,
+                "Solarized",
+                "Ethan Schoonover",
+                "base03 base02 base01 base00 base0 base1 base2 base3 yellow orange red magenta \
+                 violet blue cyan green",
+                [0, 4, 13],
+            ),
+            (
+                &TOKYO_NIGHT,
+                "Tokyo Night",
+                "enkia",
+                "bg_dark bg bg_highlight terminal_black comment fg_dark fg blue cyan blue1 \
+                 magenta purple orange yellow green teal red red1",
+                [1, 6, 7],
+            ),
+            (
+                &EVERFOREST,
+                "Everforest",
+                "sainnhe",
+                "bg0 bg1 bg2 bg3 bg4 grey0 grey1 grey2 fg red orange yellow green aqua blue \
+                 purple",
+                [0, 8, 12],
+            ),
+            (
+                &ROSE_PINE,
+                "Rosé Pine",
+                "Rosé Pine",
+                "base surface overlay highlight_med highlight_high muted subtle text love gold \
+                 rose pine foam iris",
+                [0, 7, 10],
+            ),
+            (
+                &ONE_DARK,
+                "One Dark",
+                "Atom",
+                "background gutter_grey comment_grey foreground red dark_red green yellow \
+                 dark_yellow blue magenta cyan",
+                [0, 3, 9],
+            ),
+            (
+                &MONOKAI,
+                "Monokai",
+                "Wimer Hazenberg",
+                "background line_highlight selection comment foreground pink orange yellow green \
+                 blue purple",
+                [0, 4, 5],
+            ),
+        ];
+        let mut palettes: Vec<Palette> = std::iter::once(Palette::nord())
+            .chain(
+                themes
+                    .into_iter()
+                    .map(|(colors, name, author, names, [bg, fg, accent])| {
+                        names.split_whitespace().enumerate().fold(
+                            Palette::new(colors)
+                                .named(name)
+                                .by(author)
+                                .assign(Role::Background, bg)
+                                .assign(Role::Foreground, fg)
+                                .assign(Role::Accent(0), accent),
+                            |palette, (i, n)| palette.name_color(i, n),
+                        )
+                    }),
+            )
+            .collect();
+        // Gruvbox Dark and Light, then Catppuccin Latte and its dark flavors
+        for (palette, variant) in [(1, 2), (2, 1), (4, 7), (5, 4), (6, 4), (7, 4)] {
+            let variant = palettes[variant].clone();
+            palettes[palette] = palettes[palette].clone().with_variant(variant);
+        }
+        palettes
+    })
+}
+
+/// Looks a built-in palette up by name. Case, accents, spaces and punctuation are ignored, so
+/// `"rose-pine"`, `"rose_pine"` and `"Rosé Pine"` all find the same palette.
+pub fn by_name(name: &str) -> Option<&'static Palette> {
+    fn key(name: &str) -> String {
+        name.chars()
+            .map(|c| if c == 'é' || c == 'É' { 'e' } else { c })
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+    let name = key(name);
+    registry()
+        .iter()
+        .find(|p| p.name().is_some_and(|n| key(n) == name))
+}
+
+/// Color bucket used by the classifying mappers. The bundled datasets only use the first eight
+/// classes; the rest, and [`ColorClass::Custom`] IDs, are for user-defined palettes and datasets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum ColorClass {
     Blues,
     Whites,
@@ -155,6 +2163,38 @@ pub enum ColorClass {
     Green,
     Yellow,
     Orange,
+    Cyan,
+    Magenta,
+    Brown,
+    Pink,
+    /// A user-defined class, parsed from `custom<id>`, e.g. `custom3`.
+    Custom(u8),
+}
+
+impl FromStr for ColorClass {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "blues" => Ok(Self::Blues),
+            "whites" => Ok(Self::Whites),
+            "greys" | "grays" => Ok(Self::Greys),
+            "red" => Ok(Self::Red),
+            "purple" => Ok(Self::Purple),
+            "green" => Ok(Self::Green),
+            "yellow" => Ok(Self::Yellow),
+            "orange" => Ok(Self::Orange),
+            "cyan" => Ok(Self::Cyan),
+            "magenta" => Ok(Self::Magenta),
+            "brown" => Ok(Self::Brown),
+            "pink" => Ok(Self::Pink),
+            other => other
+                .strip_prefix("custom")
+                .and_then(|n| n.parse().ok())
+                .map(Self::Custom)
+                .ok_or_else(|| PaletteError::UnknownClass(s.to_owned())),
+        }
+    }
 }
 
 impl ColorClass {
@@ -168,16 +2208,48 @@ impl ColorClass {
             Self::Green => 0,
             Self::Yellow => 0,
             Self::Orange => 0,
+            Self::Cyan => 0,
+            Self::Magenta => 0,
+            Self::Brown => 0,
+            Self::Pink => 0,
+            Self::Custom(_) => 0,
         }
     }
 }
 
+/// Per-class vote bonuses for weighted [`Knn`](crate::mappers::Knn) classification. Negative
+/// weights bias classification away from a class. Classes without an explicit weight use
+/// [`ColorClass::weight`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassWeights(Vec<(ColorClass, i32)>);
+
+impl ClassWeights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the weight of `class`, replacing any previous one.
+    #[must_use]
+    pub fn set(mut self, class: ColorClass, weight: i32) -> Self {
+        self.0.retain(|(c, _)| *c != class);
+        self.0.push((class, weight));
+        self
+    }
+
+    pub fn get(&self, class: ColorClass) -> i32 {
+        self.0
+            .iter()
+            .find(|(c, _)| *c == class)
+            .map_or(class.weight() as i32, |(_, w)| *w)
+    }
+}
+
 use ColorClass::{Blues, Green, Greys, Orange, Purple, Red, Whites, Yellow};
 
+/// Regenerates [`SYN_DATA_SET`] into `src/generated_data`. See
+/// [`DatasetBuilder`](crate::dataset::DatasetBuilder) for generating datasets of your own.
 pub fn generate_data() {
     // Start and end points for all classes
-    use std::fmt::Write as FmtWrite;
-
     let red1: (Rgbx, Rgbx) = (Rgbx(153, 0, 0, Red), Rgbx(255, 0, 0, Red));
     let red2: (Rgbx, Rgbx) = (Rgbx(255, 0, 0, Red), Rgbx(255, 153, 153, Red));
     let red3: (Rgbx, Rgbx) = (Rgbx(255, 0, 127, Red), Rgbx(153, 0, 76, Red));
@@ -205,19 +2277,78 @@ pub fn generate_data() {
     let whites3: (Rgbx, Rgbx) = (Rgbx(255, 255, 255, Whites), Rgbx(229, 255, 204, Whites));
     let whites4: (Rgbx, Rgbx) = (Rgbx(255, 255, 255, Whites), Rgbx(255, 204, 204, Whites));
     let blacks: (Rgbx, Rgbx) = (Rgbx(0, 0, 0, Greys), Rgbx(128, 128, 128, Greys));
-    let gradients: Vec<Rgbx> = [
+    [
         red1, red2, red3, red4, blue1, blue2, blue3, blue4, blue5, blue6, purple1, purple2, green1,
         green2, green3, green4, green5, green6, yellow1, yellow2, orange1, orange2, whites,
         whites2, whites3, whites4, blacks,
     ]
     .into_iter()
-    .flat_map(|(start, end)| start.gradient(&end, 5))
-    .collect();
-    let mut data = String::new();
-    write!(&mut data, "{:?}", gradients).expect("Failed to write to string");
-    std::fs::write("src/generated_data", data).expect("Failed to write data to file");
+    .fold(
+        crate::dataset::DatasetBuilder::new().step(5).dedup(false),
+        |builder, (start, end)| builder.gradient(start, end),
+    )
+    .write("src/generated_data")
+    .expect("Failed to write data to file");
 }
 
+/// Features used by the naive Bayes classifier: RGB, the hue as a unit vector, saturation and
+/// value, all in 0-1.
+pub(crate) fn bayes_features(pixel: &[u8; 4]) -> [f32; 7] {
+    let [hue, sat, val] = hsv(pixel);
+    let (sin, cos) = hue.to_radians().sin_cos();
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 255.);
+    [r, g, b, sin * sat, cos * sat, sat, val]
+}
+
+/// Fits a Gaussian naive Bayes model to [`SYN_DATA_SET`] and writes it to `src/bayes_model`.
+/// Each class is stored as its feature means, `1 / 2σ²` per feature and the log prior minus the
+/// log normalization terms, so classification needs no divisions or logarithms.
+pub fn generate_bayes_model() {
+    use std::fmt::Write as FmtWrite;
+
+    let mut classes: Vec<(ColorClass, Vec<[f32; 7]>)> = Vec::new();
+    for c in SYN_DATA_SET.iter() {
+        let features = bayes_features(&c.rgba_array());
+        match classes.iter_mut().find(|e| e.0 == c.3) {
+            Some(entry) => entry.1.push(features),
+            None => classes.push((c.3, vec![features])),
+        }
+    }
+    let mut model = String::from("[\n");
+    for (class, samples) in classes {
+        let n = samples.len() as f32;
+        let mean: [f32; 7] = std::array::from_fn(|i| samples.iter().map(|s| s[i]).sum::<f32>() / n);
+        // Smoothed so channels that never vary within a class don't produce infinities
+        let var: [f32; 7] = std::array::from_fn(|i| {
+            samples
+                .iter()
+                .map(|s| (s[i] - mean[i]).powi(2))
+                .sum::<f32>()
+                / n
+                + 1e-3
+        });
+        let bias = (n / SYN_DATA_SET.len() as f32).ln()
+            - var
+                .iter()
+                .map(|v| 0.5 * (2. * std::f32::consts::PI * v).ln())
+                .sum::<f32>();
+        writeln!(
+            &mut model,
+            "    ({:?}, {:?}, {:?}, {:?}),",
+            class,
+            mean,
+            var.map(|v| 1. / (2. * v)),
+            bias
+        )
+        .expect("Failed to write to string");
+    }
+    model.push(']');
+    std::fs::write("src/bayes_model", model).expect("Failed to write model to file");
+}
+
+/// Per-class Gaussian naive Bayes parameters, see [`generate_bayes_model`].
+pub(crate) const BAYES_MODEL: [(ColorClass, [f32; 7], [f32; 7], f32); 8] = include!("bayes_model");
+
 pub const NORD: [Rgbx; 16] = [
     Rgbx(216, 222, 233, Whites),
     Rgbx(229, 233, 240, Whites),
@@ -237,6 +2368,308 @@ pub const NORD: [Rgbx; 16] = [
     Rgbx(76, 86, 106, Greys),
 ];
 
+/// Dark variant of [Gruvbox](https://github.com/morhetz/gruvbox).
+pub const GRUVBOX_DARK: [Rgbx; 24] = [
+    Rgbx(40, 40, 40, Greys),
+    Rgbx(60, 56, 54, Greys),
+    Rgbx(80, 73, 69, Greys),
+    Rgbx(102, 92, 84, Greys),
+    Rgbx(124, 111, 100, Greys),
+    Rgbx(146, 131, 116, Greys),
+    Rgbx(168, 153, 132, Greys),
+    Rgbx(189, 174, 147, Greys),
+    Rgbx(213, 196, 161, Whites),
+    Rgbx(235, 219, 178, Whites),
+    Rgbx(204, 36, 29, Red),
+    Rgbx(152, 151, 26, Green),
+    Rgbx(215, 153, 33, Yellow),
+    Rgbx(69, 133, 136, Blues),
+    Rgbx(177, 98, 134, Purple),
+    Rgbx(104, 157, 106, Green),
+    Rgbx(214, 93, 14, Orange),
+    Rgbx(251, 73, 52, Red),
+    Rgbx(184, 187, 38, Green),
+    Rgbx(250, 189, 47, Yellow),
+    Rgbx(131, 165, 152, Blues),
+    Rgbx(211, 134, 155, Purple),
+    Rgbx(142, 192, 124, Green),
+    Rgbx(254, 128, 25, Orange),
+];
+
+/// Light variant of [Gruvbox](https://github.com/morhetz/gruvbox).
+pub const GRUVBOX_LIGHT: [Rgbx; 24] = [
+    Rgbx(251, 241, 199, Whites),
+    Rgbx(235, 219, 178, Whites),
+    Rgbx(213, 196, 161, Whites),
+    Rgbx(189, 174, 147, Greys),
+    Rgbx(168, 153, 132, Greys),
+    Rgbx(146, 131, 116, Greys),
+    Rgbx(124, 111, 100, Greys),
+    Rgbx(102, 92, 84, Greys),
+    Rgbx(80, 73, 69, Greys),
+    Rgbx(60, 56, 54, Greys),
+    Rgbx(204, 36, 29, Red),
+    Rgbx(152, 151, 26, Green),
+    Rgbx(215, 153, 33, Yellow),
+    Rgbx(69, 133, 136, Blues),
+    Rgbx(177, 98, 134, Purple),
+    Rgbx(104, 157, 106, Green),
+    Rgbx(214, 93, 14, Orange),
+    Rgbx(157, 0, 6, Red),
+    Rgbx(121, 116, 14, Green),
+    Rgbx(181, 118, 20, Yellow),
+    Rgbx(7, 102, 120, Blues),
+    Rgbx(143, 63, 113, Purple),
+    Rgbx(66, 123, 88, Green),
+    Rgbx(175, 58, 3, Orange),
+];
+
+/// [Dracula](https://draculatheme.com).
+pub const DRACULA: [Rgbx; 11] = [
+    Rgbx(40, 42, 54, Greys),
+    Rgbx(68, 71, 90, Greys),
+    Rgbx(248, 248, 242, Whites),
+    Rgbx(98, 114, 164, Greys),
+    Rgbx(139, 233, 253, Blues),
+    Rgbx(80, 250, 123, Green),
+    Rgbx(255, 184, 108, Orange),
+    Rgbx(255, 121, 198, Red),
+    Rgbx(189, 147, 249, Purple),
+    Rgbx(255, 85, 85, Red),
+    Rgbx(241, 250, 140, Yellow),
+];
+
+/// Latte flavor of [Catppuccin](https://catppuccin.com), the only light one.
+pub const CATPPUCCIN_LATTE: [Rgbx; 26] = [
+    Rgbx(220, 138, 120, Red),
+    Rgbx(221, 120, 120, Red),
+    Rgbx(234, 118, 203, Red),
+    Rgbx(136, 57, 239, Purple),
+    Rgbx(210, 15, 57, Red),
+    Rgbx(230, 69, 83, Red),
+    Rgbx(254, 100, 11, Orange),
+    Rgbx(223, 142, 29, Yellow),
+    Rgbx(64, 160, 43, Green),
+    Rgbx(23, 146, 153, Blues),
+    Rgbx(4, 165, 229, Blues),
+    Rgbx(32, 159, 181, Blues),
+    Rgbx(30, 102, 245, Blues),
+    Rgbx(114, 135, 253, Blues),
+    Rgbx(76, 79, 105, Greys),
+    Rgbx(92, 95, 119, Greys),
+    Rgbx(108, 111, 133, Greys),
+    Rgbx(124, 127, 147, Greys),
+    Rgbx(140, 143, 161, Greys),
+    Rgbx(156, 160, 176, Greys),
+    Rgbx(172, 176, 190, Whites),
+    Rgbx(188, 192, 204, Whites),
+    Rgbx(204, 208, 218, Whites),
+    Rgbx(239, 241, 245, Whites),
+    Rgbx(230, 233, 239, Whites),
+    Rgbx(220, 224, 232, Whites),
+];
+
+/// Frappé flavor of [Catppuccin](https://catppuccin.com).
+pub const CATPPUCCIN_FRAPPE: [Rgbx; 26] = [
+    Rgbx(242, 213, 207, Red),
+    Rgbx(238, 190, 190, Red),
+    Rgbx(244, 184, 228, Red),
+    Rgbx(202, 158, 230, Purple),
+    Rgbx(231, 130, 132, Red),
+    Rgbx(234, 153, 156, Red),
+    Rgbx(239, 159, 118, Orange),
+    Rgbx(229, 200, 144, Yellow),
+    Rgbx(166, 209, 137, Green),
+    Rgbx(129, 200, 190, Blues),
+    Rgbx(153, 209, 219, Blues),
+    Rgbx(133, 193, 220, Blues),
+    Rgbx(140, 170, 238, Blues),
+    Rgbx(186, 187, 241, Blues),
+    Rgbx(198, 208, 245, Whites),
+    Rgbx(181, 191, 226, Whites),
+    Rgbx(165, 173, 206, Whites),
+    Rgbx(148, 156, 187, Greys),
+    Rgbx(131, 139, 167, Greys),
+    Rgbx(115, 121, 148, Greys),
+    Rgbx(98, 104, 128, Greys),
+    Rgbx(81, 87, 109, Greys),
+    Rgbx(65, 69, 89, Greys),
+    Rgbx(48, 52, 70, Greys),
+    Rgbx(41, 44, 60, Greys),
+    Rgbx(35, 38, 52, Greys),
+];
+
+/// Macchiato flavor of [Catppuccin](https://catppuccin.com).
+pub const CATPPUCCIN_MACCHIATO: [Rgbx; 26] = [
+    Rgbx(244, 219, 214, Red),
+    Rgbx(240, 198, 198, Red),
+    Rgbx(245, 189, 230, Red),
+    Rgbx(198, 160, 246, Purple),
+    Rgbx(237, 135, 150, Red),
+    Rgbx(238, 153, 160, Red),
+    Rgbx(245, 169, 127, Orange),
+    Rgbx(238, 212, 159, Yellow),
+    Rgbx(166, 218, 149, Green),
+    Rgbx(139, 213, 202, Blues),
+    Rgbx(145, 215, 227, Blues),
+    Rgbx(125, 196, 228, Blues),
+    Rgbx(138, 173, 244, Blues),
+    Rgbx(183, 189, 248, Blues),
+    Rgbx(202, 211, 245, Whites),
+    Rgbx(184, 192, 224, Whites),
+    Rgbx(165, 173, 203, Whites),
+    Rgbx(147, 154, 183, Greys),
+    Rgbx(128, 135, 162, Greys),
+    Rgbx(110, 115, 141, Greys),
+    Rgbx(91, 96, 120, Greys),
+    Rgbx(73, 77, 100, Greys),
+    Rgbx(54, 58, 79, Greys),
+    Rgbx(36, 39, 58, Greys),
+    Rgbx(30, 32, 48, Greys),
+    Rgbx(24, 25, 38, Greys),
+];
+
+/// Mocha flavor of [Catppuccin](https://catppuccin.com), the darkest one.
+pub const CATPPUCCIN_MOCHA: [Rgbx; 26] = [
+    Rgbx(245, 224, 220, Red),
+    Rgbx(242, 205, 205, Red),
+    Rgbx(245, 194, 231, Red),
+    Rgbx(203, 166, 247, Purple),
+    Rgbx(243, 139, 168, Red),
+    Rgbx(235, 160, 172, Red),
+    Rgbx(250, 179, 135, Orange),
+    Rgbx(249, 226, 175, Yellow),
+    Rgbx(166, 227, 161, Green),
+    Rgbx(148, 226, 213, Blues),
+    Rgbx(137, 220, 235, Blues),
+    Rgbx(116, 199, 236, Blues),
+    Rgbx(137, 180, 250, Blues),
+    Rgbx(180, 190, 254, Blues),
+    Rgbx(205, 214, 244, Whites),
+    Rgbx(186, 194, 222, Whites),
+    Rgbx(166, 173, 200, Whites),
+    Rgbx(147, 153, 178, Greys),
+    Rgbx(127, 132, 156, Greys),
+    Rgbx(108, 112, 134, Greys),
+    Rgbx(88, 91, 112, Greys),
+    Rgbx(69, 71, 90, Greys),
+    Rgbx(49, 50, 68, Greys),
+    Rgbx(30, 30, 46, Greys),
+    Rgbx(24, 24, 37, Greys),
+    Rgbx(17, 17, 27, Greys),
+];
+
+/// [Solarized](https://ethanschoonover.com/solarized), base tones from dark to light then accents.
+pub const SOLARIZED: [Rgbx; 16] = [
+    Rgbx(0, 43, 54, Greys),
+    Rgbx(7, 54, 66, Greys),
+    Rgbx(88, 110, 117, Greys),
+    Rgbx(101, 123, 131, Greys),
+    Rgbx(131, 148, 150, Greys),
+    Rgbx(147, 161, 161, Greys),
+    Rgbx(238, 232, 213, Whites),
+    Rgbx(253, 246, 227, Whites),
+    Rgbx(181, 137, 0, Yellow),
+    Rgbx(203, 75, 22, Orange),
+    Rgbx(220, 50, 47, Red),
+    Rgbx(211, 54, 130, Purple),
+    Rgbx(108, 113, 196, Purple),
+    Rgbx(38, 139, 210, Blues),
+    Rgbx(42, 161, 152, Blues),
+    Rgbx(133, 153, 0, Green),
+];
+
+/// Night style of [Tokyo Night](https://github.com/enkia/tokyo-night-vscode-theme).
+pub const TOKYO_NIGHT: [Rgbx; 18] = [
+    Rgbx(22, 22, 30, Greys),
+    Rgbx(26, 27, 38, Greys),
+    Rgbx(41, 46, 66, Greys),
+    Rgbx(65, 72, 104, Greys),
+    Rgbx(86, 95, 137, Greys),
+    Rgbx(169, 177, 214, Whites),
+    Rgbx(192, 202, 245, Whites),
+    Rgbx(122, 162, 247, Blues),
+    Rgbx(125, 207, 255, Blues),
+    Rgbx(42, 195, 222, Blues),
+    Rgbx(187, 154, 247, Purple),
+    Rgbx(157, 124, 216, Purple),
+    Rgbx(255, 158, 100, Orange),
+    Rgbx(224, 175, 104, Yellow),
+    Rgbx(158, 206, 106, Green),
+    Rgbx(26, 188, 156, Blues),
+    Rgbx(247, 118, 142, Red),
+    Rgbx(219, 75, 75, Red),
+];
+
+/// Medium dark variant of [Everforest](https://github.com/sainnhe/everforest).
+pub const EVERFOREST: [Rgbx; 16] = [
+    Rgbx(45, 53, 59, Greys),
+    Rgbx(52, 63, 68, Greys),
+    Rgbx(61, 72, 77, Greys),
+    Rgbx(71, 82, 88, Greys),
+    Rgbx(79, 88, 94, Greys),
+    Rgbx(122, 132, 120, Greys),
+    Rgbx(133, 146, 137, Greys),
+    Rgbx(157, 169, 160, Greys),
+    Rgbx(211, 198, 170, Whites),
+    Rgbx(230, 126, 128, Red),
+    Rgbx(230, 152, 117, Orange),
+    Rgbx(219, 188, 127, Yellow),
+    Rgbx(167, 192, 128, Green),
+    Rgbx(131, 192, 146, Green),
+    Rgbx(127, 187, 179, Blues),
+    Rgbx(214, 153, 182, Purple),
+];
+
+/// Main variant of [Rosé Pine](https://rosepinetheme.com).
+pub const ROSE_PINE: [Rgbx; 14] = [
+    Rgbx(25, 23, 36, Greys),
+    Rgbx(31, 29, 46, Greys),
+    Rgbx(38, 35, 58, Greys),
+    Rgbx(64, 61, 82, Greys),
+    Rgbx(82, 79, 103, Greys),
+    Rgbx(110, 106, 134, Greys),
+    Rgbx(144, 140, 170, Greys),
+    Rgbx(224, 222, 244, Whites),
+    Rgbx(235, 111, 146, Red),
+    Rgbx(246, 193, 119, Yellow),
+    Rgbx(235, 188, 186, Red),
+    Rgbx(49, 116, 143, Blues),
+    Rgbx(156, 207, 216, Blues),
+    Rgbx(196, 167, 231, Purple),
+];
+
+/// [One Dark](https://github.com/atom/atom/tree/master/packages/one-dark-syntax), Atom's default syntax theme.
+pub const ONE_DARK: [Rgbx; 12] = [
+    Rgbx(40, 44, 52, Greys),
+    Rgbx(75, 82, 99, Greys),
+    Rgbx(92, 99, 112, Greys),
+    Rgbx(171, 178, 191, Whites),
+    Rgbx(224, 108, 117, Red),
+    Rgbx(190, 80, 70, Red),
+    Rgbx(152, 195, 121, Green),
+    Rgbx(229, 192, 123, Yellow),
+    Rgbx(209, 154, 102, Orange),
+    Rgbx(97, 175, 239, Blues),
+    Rgbx(198, 120, 221, Purple),
+    Rgbx(86, 182, 194, Blues),
+];
+
+/// [Monokai](https://monokai.pro), the classic Sublime Text colors.
+pub const MONOKAI: [Rgbx; 11] = [
+    Rgbx(39, 40, 34, Greys),
+    Rgbx(62, 61, 50, Greys),
+    Rgbx(73, 72, 62, Greys),
+    Rgbx(117, 113, 94, Greys),
+    Rgbx(248, 248, 242, Whites),
+    Rgbx(249, 38, 114, Red),
+    Rgbx(253, 151, 31, Orange),
+    Rgbx(230, 219, 116, Yellow),
+    Rgbx(166, 226, 46, Green),
+    Rgbx(102, 217, 239, Blues),
+    Rgbx(174, 129, 255, Purple),
+];
+
 pub const BASECOLORS: [[u8; 4]; 139] = include!("basecolors");
 
 pub const SYN_DATA_SET: [Rgbx; 671] = include!("generated_data");
@@ -0,0 +1,492 @@
+//! Processing jobs described as data.
+//!
+//! A [`Pipeline`] is deserialized from JSON or TOML and runs the image through optional
+//! preprocessing steps, a mapper, optional postprocessing steps and finally an encoder:
+//!
+//! ```toml
+//! input = "wallpaper.jpg"
+//! output = "wallpaper-nord.png"
+//! palette = "nord"
+//! threads = "rayon"
+//! preprocess = [{ op = "resize", width = 1920, height = 1080 }]
+//!
+//! [mapper]
+//! kind = "knn"
+//! k = 12
+//! ```
+//!
+//! Named palettes are looked up with [`palette::by_name`].
+
+use super::{
+    expr::ExprMapper,
+    mappers::{Creative, Dither, Knn, ManualMap, Nearest, NearestDoublePass},
+    palette::{self, ClassWeights, ColorClass, Palette, Rgbx},
+    rules::{Rule, RuleMapper},
+    Mapper, ProcError, ProcOptions, ProcessedData, ThreadCount, Threads,
+};
+use image::{imageops::FilterType, DynamicImage, ImageOutputFormat};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    io::{Cursor, Seek, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    #[serde(default)]
+    pub input: Option<PathBuf>,
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub preprocess: Vec<Step>,
+    #[serde(default)]
+    pub mapper: MapperSpec,
+    #[serde(default)]
+    pub memoize: bool,
+    /// Floyd–Steinberg dithering strength, 0-1.
+    #[serde(default)]
+    pub dither: Option<f32>,
+    #[serde(default)]
+    pub palette: PaletteSpec,
+    #[serde(default)]
+    pub threads: ThreadsSpec,
+    /// Memory cap in bytes, checked before decoding the input and again before mapping it, see
+    /// [`ProcOptions::memory_limit`].
+    #[serde(default)]
+    pub memory_limit: Option<usize>,
+    #[serde(default)]
+    pub postprocess: Vec<Step>,
+    #[serde(default)]
+    pub encoding: EncodingSpec,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    Resize {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        exact: bool,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Blur {
+        sigma: f32,
+    },
+    Unsharpen {
+        sigma: f32,
+        threshold: i32,
+    },
+    Brighten {
+        value: i32,
+    },
+    Contrast {
+        value: f32,
+    },
+    HueRotate {
+        degrees: i32,
+    },
+    Grayscale,
+    Invert,
+    FlipHorizontal,
+    FlipVertical,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Step {
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        match *self {
+            Step::Resize {
+                width,
+                height,
+                exact: false,
+            } => img.resize(width, height, FilterType::Lanczos3),
+            Step::Resize { width, height, .. } => {
+                img.resize_exact(width, height, FilterType::Lanczos3)
+            }
+            Step::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => img.crop_imm(x, y, width, height),
+            Step::Blur { sigma } => img.blur(sigma),
+            Step::Unsharpen { sigma, threshold } => img.unsharpen(sigma, threshold),
+            Step::Brighten { value } => img.brighten(value),
+            Step::Contrast { value } => img.adjust_contrast(value),
+            Step::HueRotate { degrees } => img.huerotate(degrees),
+            Step::Grayscale => DynamicImage::ImageRgba8(img.grayscale().to_rgba8()),
+            Step::Invert => {
+                let mut img = img;
+                img.invert();
+                img
+            }
+            Step::FlipHorizontal => img.fliph(),
+            Step::FlipVertical => img.flipv(),
+            Step::Rotate90 => img.rotate90(),
+            Step::Rotate180 => img.rotate180(),
+            Step::Rotate270 => img.rotate270(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MapperSpec {
+    #[default]
+    Nearest,
+    DoublePass,
+    Creative,
+    Knn {
+        #[serde(default)]
+        k: Option<usize>,
+        /// Makes tie-breaking reproducible, see [`KnnBuilder::seed`](crate::mappers::KnnBuilder::seed).
+        #[serde(default)]
+        seed: Option<u64>,
+        /// Labelled colors to classify with instead of the bundled dataset.
+        #[serde(default)]
+        dataset: Option<PaletteSpec>,
+        /// Vote bonuses by class name, see [`KnnBuilder::weights`](crate::mappers::KnnBuilder::weights).
+        #[serde(default)]
+        weights: BTreeMap<String, i32>,
+    },
+    Manual,
+    Expr {
+        rule: String,
+    },
+    Rules {
+        rules: Vec<Rule>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteSpec {
+    Named(String),
+    Colors(Vec<ColorSpec>),
+}
+
+impl Default for PaletteSpec {
+    fn default() -> Self {
+        PaletteSpec::Named("nord".into())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorSpec {
+    pub rgb: [u8; 3],
+    pub class: String,
+}
+
+impl PaletteSpec {
+    /// The palette to map to: registry palettes are borrowed with their names, roles and
+    /// variants, inline ones are built from their colors.
+    pub fn resolve(&self) -> Result<Cow<'static, Palette>, PipelineError> {
+        match self {
+            PaletteSpec::Named(name) => palette::by_name(name)
+                .map(Cow::Borrowed)
+                .ok_or_else(|| PipelineError::UnknownPalette(name.clone())),
+            PaletteSpec::Colors(colors) if colors.is_empty() => Err(PipelineError::EmptyPalette),
+            PaletteSpec::Colors(colors) => colors
+                .iter()
+                .map(|c| {
+                    let class = ColorClass::from_str(&c.class)
+                        .map_err(|_| PipelineError::UnknownClass(c.class.clone()))?;
+                    Ok(Rgbx(c.rgb[0], c.rgb[1], c.rgb[2], class))
+                })
+                .collect::<Result<Palette, _>>()
+                .map(Cow::Owned),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadsSpec {
+    Single,
+    #[default]
+    Auto,
+    Rayon,
+    Extreme,
+    Custom(NonZeroUsize),
+}
+
+impl From<ThreadsSpec> for Threads {
+    fn from(value: ThreadsSpec) -> Self {
+        match value {
+            ThreadsSpec::Single => Threads::Single,
+            ThreadsSpec::Auto => Threads::Auto,
+            ThreadsSpec::Rayon => Threads::Rayon,
+            ThreadsSpec::Extreme => Threads::Extreme,
+            ThreadsSpec::Custom(n) => Threads::Custom(ThreadCount::new(n)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EncodingSpec {
+    #[default]
+    Png,
+    #[cfg(feature = "jpeg")]
+    Jpeg { quality: u8 },
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    UnknownPalette(String),
+    UnknownClass(String),
+    EmptyPalette,
+    MissingInput,
+    MissingOutput,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownPalette(name) => write!(f, "unknown palette `{name}`"),
+            Self::UnknownClass(name) => write!(f, "unknown color class `{name}`"),
+            Self::EmptyPalette => write!(f, "palette has no colors"),
+            Self::MissingInput => write!(f, "pipeline does not specify an input"),
+            Self::MissingOutput => write!(f, "pipeline does not specify an output"),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+impl Pipeline {
+    pub fn from_json(spec: &str) -> Result<Self, Box<dyn Error + 'static>> {
+        Ok(serde_json::from_str(spec)?)
+    }
+
+    pub fn from_toml(spec: &str) -> Result<Self, Box<dyn Error + 'static>> {
+        Ok(toml::from_str(spec)?)
+    }
+
+    /// Picks the parser from the file extension, defaulting to JSON.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'static>> {
+        let spec = std::fs::read_to_string(path.as_ref())?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&spec),
+            _ => Self::from_json(&spec),
+        }
+    }
+
+    /// Runs the pipeline from its `input` path and writes the encoded result to its `output` path.
+    pub fn execute(&self) -> Result<(), Box<dyn Error + 'static>> {
+        let input = self.input.as_ref().ok_or(PipelineError::MissingInput)?;
+        let output = self.output.as_ref().ok_or(PipelineError::MissingOutput)?;
+        let data = self.process(self.options().decode_file(input)?)?;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(output)?);
+        self.encode(&data, &mut file)
+    }
+
+    /// Runs the pipeline on an encoded image held in memory, returning the encoded result.
+    pub fn run_bytes(&self, input: &[u8]) -> Result<Vec<u8>, Box<dyn Error + 'static>> {
+        let data = self.process(self.options().decode_bytes(input)?)?;
+        let mut buf = Cursor::new(Vec::new());
+        self.encode(&data, &mut buf)?;
+        Ok(buf.into_inner())
+    }
+
+    pub fn process(&self, img: DynamicImage) -> Result<ProcessedData, Box<dyn Error + 'static>> {
+        let palette = self.palette.resolve()?;
+        let img = self
+            .preprocess
+            .iter()
+            .fold(img, |img, step| step.apply(img));
+        let opts = self.options().threads(self.threads.into()).palette(palette);
+
+        let data = match &self.mapper {
+            MapperSpec::Nearest => self.run(opts, Nearest::new(), img),
+            MapperSpec::DoublePass => self.run(opts, NearestDoublePass, img),
+            MapperSpec::Creative => self.run(opts, Creative, img),
+            MapperSpec::Knn {
+                k,
+                seed,
+                dataset,
+                weights,
+            } => {
+                let mut knn = Knn::builder();
+                if !weights.is_empty() {
+                    let mut class_weights = ClassWeights::new();
+                    for (name, weight) in weights {
+                        let class = ColorClass::from_str(name)
+                            .map_err(|_| PipelineError::UnknownClass(name.clone()))?;
+                        class_weights = class_weights.set(class, *weight);
+                    }
+                    knn = knn.weights(class_weights);
+                }
+                if let Some(dataset) = dataset {
+                    knn = knn.dataset(dataset.resolve()?.to_vec());
+                }
+                if let Some(k) = k {
+                    knn = knn.k(*k);
+                }
+                if let Some(seed) = seed {
+                    knn = knn.seed(*seed);
+                }
+                self.run(opts, knn.build(), img)
+            }
+            MapperSpec::Manual => self.run(opts, ManualMap::default(), img),
+            MapperSpec::Expr { rule } => self.run(opts, ExprMapper::parse(rule)?, img),
+            MapperSpec::Rules { rules } => self.run(opts, RuleMapper::new(rules.clone())?, img),
+        }?;
+        if self.postprocess.is_empty() {
+            return Ok(data);
+        }
+        let img = DynamicImage::ImageRgba8(data.to_image());
+        let img = self
+            .postprocess
+            .iter()
+            .fold(img, |img, step| step.apply(img));
+        Ok(img.to_rgba8().into())
+    }
+
+    fn options<'a>(&self) -> ProcOptions<'a> {
+        let opts = ProcOptions::default();
+        match self.memory_limit {
+            Some(bytes) => opts.memory_limit(bytes),
+            None => opts,
+        }
+    }
+
+    fn run<M: Mapper>(
+        &self,
+        opts: ProcOptions<'_>,
+        mapper: M,
+        img: DynamicImage,
+    ) -> Result<ProcessedData, ProcError> {
+        match self.dither {
+            Some(strength) => self.run_mapper(opts, Dither::new(mapper).strength(strength), img),
+            None => self.run_mapper(opts, mapper, img),
+        }
+    }
+
+    fn run_mapper<M: Mapper>(
+        &self,
+        opts: ProcOptions<'_>,
+        mapper: M,
+        img: DynamicImage,
+    ) -> Result<ProcessedData, ProcError> {
+        Ok(if self.memoize {
+            opts.mapper(mapper.memoized())
+                .try_load_image(img)?
+                .process()
+        } else {
+            opts.mapper(mapper).try_load_image(img)?.process()
+        })
+    }
+
+    fn encode<W: Write + Seek>(
+        &self,
+        data: &ProcessedData,
+        buf: &mut W,
+    ) -> Result<(), Box<dyn Error + 'static>> {
+        match self.encoding {
+            EncodingSpec::Png => data.to_image().write_to(buf, ImageOutputFormat::Png)?,
+            // Jpeg has no alpha channel, so encode through an RGB conversion
+            #[cfg(feature = "jpeg")]
+            EncodingSpec::Jpeg { quality } => DynamicImage::ImageRgba8(data.to_image())
+                .to_rgb8()
+                .write_to(buf, ImageOutputFormat::Jpeg(quality))?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "png"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn toml_and_json_agree() {
+        let toml = Pipeline::from_toml(
+            r#"
+            palette = [{ rgb = [0, 0, 0], class = "greys" }, { rgb = [255, 255, 255], class = "whites" }]
+            threads = "single"
+            preprocess = [{ op = "resize", width = 8, height = 8, exact = true }]
+            mapper = { kind = "knn", k = 5 }
+            "#,
+        )
+        .unwrap();
+        let json = Pipeline::from_json(
+            r#"{
+                "palette": [{ "rgb": [0, 0, 0], "class": "greys" }, { "rgb": [255, 255, 255], "class": "whites" }],
+                "threads": "single",
+                "preprocess": [{ "op": "resize", "width": 8, "height": 8, "exact": true }],
+                "mapper": { "kind": "knn", "k": 5 }
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(toml.mapper, MapperSpec::Knn { k: Some(5), .. }));
+        assert_eq!(
+            toml.palette.resolve().unwrap(),
+            json.palette.resolve().unwrap()
+        );
+    }
+
+    #[test]
+    fn runs_on_bytes() {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(16, 16, image::Rgba([250, 10, 10, 255]))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        let pipeline = Pipeline::from_json(
+            r#"{ "postprocess": [{ "op": "rotate90" }], "mapper": { "kind": "expr", "rule": "palette(7)" } }"#,
+        )
+        .unwrap();
+        let out = image::load_from_memory(&pipeline.run_bytes(png.get_ref()).unwrap()).unwrap();
+        assert_eq!(
+            out.to_rgba8().get_pixel(0, 0).0,
+            palette::NORD[7].rgba_array()
+        );
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(64, 64, image::Rgba([250, 10, 10, 255]))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        let pipeline = Pipeline::from_json(r#"{ "memory_limit": 1024 }"#).unwrap();
+        let err = pipeline.run_bytes(png.get_ref()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProcError>(),
+            Some(ProcError::MemoryLimit { limit: 1024, .. })
+        ));
+        // Upscaling past the limit is caught before mapping
+        let pipeline = Pipeline::from_json(
+            r#"{ "memory_limit": 65536, "preprocess": [{ "op": "resize", "width": 512, "height": 512, "exact": true }] }"#,
+        )
+        .unwrap();
+        assert!(pipeline.run_bytes(png.get_ref()).is_err());
+    }
+
+    #[test]
+    fn unknown_palette() {
+        let pipeline = Pipeline::from_json(r#"{ "palette": "nope" }"#).unwrap();
+        assert!(pipeline.palette.resolve().is_err());
+        let pipeline = Pipeline::from_json(r#"{ "palette": "catppuccin-mocha" }"#).unwrap();
+        assert_eq!(
+            pipeline.palette.resolve().unwrap().as_ref(),
+            palette::by_name("catppuccin-mocha").unwrap()
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Loading of mappers compiled into external dynamic libraries.
+//!
+//! A plugin is a `cdylib` exporting a function named `mapped_plugin_info` with the signature
+//! `extern "C" fn() -> *const PluginInfo`. The returned pointer must stay valid for as long as
+//! the library is loaded. Palette entries are handed to the plugin as packed
+//! `[r, g, b, class, id]` quintuplets, where `class` is the position of the entry's
+//! [`ColorClass`] among its variants, counting from 0, and `id` the id of a
+//! [`ColorClass::Custom`] class, 0 for the others.
+
+use super::{
+    palette::{ColorClass, Rgbx},
+    Mapper,
+};
+use libloading::Library;
+use std::{
+    error::Error,
+    ffi::{c_char, CStr},
+    fmt,
+    path::Path,
+    sync::Arc,
+};
+
+pub const ABI_VERSION: u32 = 2;
+pub const ENTRY_SYMBOL: &[u8] = b"mapped_plugin_info";
+
+pub type PredictFn =
+    extern "C" fn(palette: *const u8, palette_len: usize, pixel: *const u8, out: *mut u8);
+
+#[repr(C)]
+pub struct PluginInfo {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub version: *const c_char,
+    pub predict: PredictFn,
+}
+
+#[derive(Debug)]
+pub enum PluginError {
+    AbiMismatch { expected: u32, found: u32 },
+    NullInfo,
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AbiMismatch { expected, found } => write!(
+                f,
+                "plugin was built for ABI version {found}, expected {expected}"
+            ),
+            Self::NullInfo => write!(f, "plugin returned a null info pointer"),
+        }
+    }
+}
+
+impl Error for PluginError {}
+
+#[derive(Clone)]
+pub struct PluginMapper {
+    name: String,
+    version: String,
+    predict: PredictFn,
+    // Keeps the library mapped for as long as any clone of the mapper is alive
+    _lib: Arc<Library>,
+}
+
+impl PluginMapper {
+    /// Loads a mapper plugin from the dynamic library at `path`.
+    ///
+    /// # Safety
+    /// Loading a library runs its initialisation routines, and the exported entry point is
+    /// trusted to conform to the ABI described in the module documentation.
+    pub unsafe fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'static>> {
+        let lib = Library::new(path.as_ref())?;
+        let entry = lib.get::<extern "C" fn() -> *const PluginInfo>(ENTRY_SYMBOL)?;
+        let info = entry().as_ref().ok_or(PluginError::NullInfo)?;
+        if info.abi_version != ABI_VERSION {
+            return Err(PluginError::AbiMismatch {
+                expected: ABI_VERSION,
+                found: info.abi_version,
+            }
+            .into());
+        }
+        let text = |ptr: *const c_char| {
+            if ptr.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
+            }
+        };
+
+        Ok(PluginMapper {
+            name: text(info.name),
+            version: text(info.version),
+            predict: info.predict,
+            _lib: Arc::new(lib),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+}
+
+impl fmt::Debug for PluginMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PluginMapper")
+            .field("name", &self.name)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+// Bytes per palette entry handed to the plugin
+const ENTRY_LEN: usize = 5;
+
+impl Mapper for PluginMapper {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        let mut out = *pixel;
+        let mut stack = [0u8; 64 * ENTRY_LEN];
+        let mut heap = Vec::new();
+        let packed: &mut [u8] = if palette.len() <= 64 {
+            &mut stack[..palette.len() * ENTRY_LEN]
+        } else {
+            heap.resize(palette.len() * ENTRY_LEN, 0);
+            &mut heap
+        };
+        for (dst, c) in packed.chunks_exact_mut(ENTRY_LEN).zip(palette) {
+            let [class, id] = class_id(c.3);
+            dst.copy_from_slice(&[c.0, c.1, c.2, class, id]);
+        }
+        (self.predict)(
+            packed.as_ptr(),
+            palette.len(),
+            pixel.as_ptr(),
+            out.as_mut_ptr(),
+        );
+        out
+    }
+}
+
+// Position of the class among the variants and the custom id
+fn class_id(class: ColorClass) -> [u8; 2] {
+    use ColorClass::*;
+    match class {
+        Blues => [0, 0],
+        Whites => [1, 0],
+        Greys => [2, 0],
+        Red => [3, 0],
+        Purple => [4, 0],
+        Green => [5, 0],
+        Yellow => [6, 0],
+        Orange => [7, 0],
+        Cyan => [8, 0],
+        Magenta => [9, 0],
+        Brown => [10, 0],
+        Pink => [11, 0],
+        Custom(id) => [12, id],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distinct_class_ids() {
+        let classes = [ColorClass::Pink, ColorClass::Custom(0)]
+            .into_iter()
+            .chain((0..=255).map(ColorClass::Custom));
+        let mut ids: Vec<_> = classes.map(class_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 257);
+    }
+}
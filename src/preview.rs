@@ -0,0 +1,114 @@
+//! Showing results in a window while tuning mappers and palettes, behind the `preview` feature.
+//!
+//! ```no_run
+//! use mapped::{palette, preview::Key, ProcOptions};
+//!
+//! let mut processor = ProcOptions::default().load("wallpaper.png")?;
+//! let mut palettes = palette::registry().iter().cycle();
+//! processor.watch("mapped", |opts, preview| {
+//!     if !preview.key_pressed(Key::Space) {
+//!         return false;
+//!     }
+//!     *opts = opts.clone().palette(palettes.next().unwrap());
+//!     true
+//! })?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use super::{Mapper, ProcOptions, ProcessedData, Processor};
+use image::GenericImageView;
+use minifb::{KeyRepeat, Window, WindowOptions};
+use std::{error::Error, time::Duration};
+
+pub use minifb::Key;
+
+pub struct Preview {
+    window: Window,
+    buffer: Vec<u32>,
+    dimen: (usize, usize),
+}
+
+impl Preview {
+    pub fn new(title: &str, (width, height): (u32, u32)) -> Result<Self, Box<dyn Error>> {
+        let dimen = (width as usize, height as usize);
+        let mut window = Window::new(
+            title,
+            dimen.0,
+            dimen.1,
+            WindowOptions {
+                resize: true,
+                ..WindowOptions::default()
+            },
+        )?;
+        window.limit_update_rate(Some(Duration::from_micros(16600)));
+
+        Ok(Preview {
+            window,
+            buffer: vec![0; dimen.0 * dimen.1],
+            dimen,
+        })
+    }
+
+    pub fn render(&mut self, data: &ProcessedData) -> Result<(), Box<dyn Error>> {
+        let (w, h) = data.dimensions();
+        self.dimen = (w as usize, h as usize);
+        // minifb expects 0RGB pixels, so alpha is applied against a black backdrop
+        self.buffer = data
+            .to_rgba_premultiplied()
+            .chunks_exact(4)
+            .map(|px| (px[0] as u32) << 16 | (px[1] as u32) << 8 | px[2] as u32)
+            .collect();
+        self.refresh()
+    }
+
+    pub fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+        let (w, h) = self.dimen;
+        self.window.update_with_buffer(&self.buffer, w, h)?;
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    pub fn key_pressed(&self, key: Key) -> bool {
+        self.window.is_key_pressed(key, KeyRepeat::No)
+    }
+
+    pub fn wait(mut self) -> Result<(), Box<dyn Error>> {
+        while self.is_open() {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+}
+
+impl ProcessedData {
+    pub fn show(&self, title: &str) -> Result<(), Box<dyn Error>> {
+        let mut preview = Preview::new(title, self.dimensions())?;
+        preview.render(self)?;
+        preview.wait()
+    }
+}
+
+impl<'a, M: Mapper> Processor<'a, M> {
+    /// Opens a preview window and calls `update` once per frame. Whenever `update` reports that
+    /// it changed the options, the cache of [`Processor::process_incremental`] is cleared, since
+    /// the mapper may have changed too, and the image is processed again and re-rendered.
+    pub fn watch<F>(&mut self, title: &str, mut update: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&mut ProcOptions<'a, M>, &Preview) -> bool,
+    {
+        let mut preview = Preview::new(title, self.data.dimensions())?;
+        preview.render(&self.process())?;
+        while preview.is_open() {
+            if update(&mut self.conf, &preview) {
+                self.clear_cache();
+                preview.render(&self.process())?;
+            } else {
+                preview.refresh()?;
+            }
+        }
+        Ok(())
+    }
+}
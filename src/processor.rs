@@ -0,0 +1,1461 @@
+use super::{
+    context::{PixelContext, Prepared},
+    mappers::{Diffusion, DiffusionKernel, Nearest, BLUE_NOISE_64},
+    palette::{self, ColorClass, Palette, Rgbx, Variant},
+    Mapper,
+};
+use ahash::AHashMap;
+use image::{ColorType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use rayon::prelude::*;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    io::{BufRead, Cursor, Seek, Write},
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut, Range},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+pub struct Processor<'a, M>
+where
+    M: Mapper,
+{
+    pub(crate) conf: ProcOptions<'a, M>,
+    pub(crate) data: DynamicImage,
+    prog: Progress,
+    cache: Option<RemapCache>,
+}
+
+// Palette-independent intermediate state kept between incremental runs
+struct RemapCache {
+    colors: Vec<[u8; 4]>,
+    classes: Vec<Option<ColorClass>>,
+    indices: Vec<u32>,
+}
+
+impl RemapCache {
+    fn build<M: Mapper>(data: &DynamicImage, mapper: &M, threads: Threads) -> Self {
+        let mut lookup: AHashMap<[u8; 4], u32> = AHashMap::new();
+        let mut colors = Vec::new();
+        let indices = data
+            .pixels()
+            .map(|(_, _, px)| {
+                *lookup.entry(px.0).or_insert_with(|| {
+                    colors.push(px.0);
+                    (colors.len() - 1) as u32
+                })
+            })
+            .collect();
+        let classes = map_threaded(threads, &colors, |_, c| mapper.classify(c));
+
+        RemapCache {
+            colors,
+            classes,
+            indices,
+        }
+    }
+
+    fn render<M: Mapper>(
+        &self,
+        mapper: &M,
+        palette: &[Rgbx],
+        dimen: (u32, u32),
+        threads: Threads,
+    ) -> ProcessedData {
+        let parallel = !matches!(threads, Threads::Single);
+        if let Some(diffusion) = mapper.error_diffusion() {
+            let width = dimen.0 as usize;
+            let raw = diffuse(mapper, palette, diffusion, dimen, parallel, |x, y| {
+                self.colors[self.indices[y as usize * width + x as usize] as usize]
+            });
+            return ProcessedData { raw, dimen };
+        }
+        if mapper.uses_context() {
+            let pixels: Vec<[u8; 4]> = self
+                .indices
+                .iter()
+                .map(|&i| self.colors[i as usize])
+                .collect();
+            let raw = map_context(mapper, palette, &pixels, dimen, parallel);
+            return ProcessedData { raw, dimen };
+        }
+        if mapper.dither_offset(0, 0).is_some() {
+            let width = dimen.0 as usize;
+            let raw = map_threaded(threads, &self.indices, |i, &c| {
+                predict_at(mapper, palette, &self.colors[c as usize], i, width)
+            });
+            let raw = raw.into_iter().flatten().collect();
+            return ProcessedData { raw, dimen };
+        }
+        let mapped = map_threaded(threads, &self.colors, |i, px| match self.classes[i] {
+            Some(class) => mapper.predict_class(palette, px, class),
+            None => mapper.predict(palette, px),
+        });
+        let raw = self
+            .indices
+            .iter()
+            .flat_map(|&i| mapped[i as usize])
+            .collect();
+
+        ProcessedData { raw, dimen }
+    }
+}
+
+// Maps `items` in order, with their indices, on the threads asked for: equal parts on scoped
+// threads like `Processor::dispatch` does, rayon's pool, or the calling thread alone
+fn map_threaded<T: Sync, R: Send>(
+    threads: Threads,
+    items: &[T],
+    f: impl Fn(usize, &T) -> R + Sync,
+) -> Vec<R> {
+    let f = &f;
+    let parts = |n: usize| {
+        let size = items.len().div_ceil(n).max(1);
+        thread::scope(|s| {
+            let handles: Vec<_> = items
+                .chunks(size)
+                .enumerate()
+                .map(|(p, part)| {
+                    s.spawn(move || {
+                        let first = p * size;
+                        part.iter()
+                            .enumerate()
+                            .map(|(i, item)| f(first + i, item))
+                            .collect::<Vec<R>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        })
+    };
+    match threads {
+        Threads::Single => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| f(i, item))
+            .collect(),
+        Threads::Auto => parts(ThreadCount::calculate().get()),
+        Threads::Custom(n) => parts(n.get()),
+        Threads::Rayon => items
+            .par_iter()
+            .enumerate()
+            .map(|(i, item)| f(i, item))
+            .collect(),
+        Threads::Extreme => parts(ThreadCount::extreme().get()),
+    }
+}
+
+fn map_context<M: Mapper>(
+    mapper: &M,
+    palette: &[Rgbx],
+    pixels: &[[u8; 4]],
+    (w, h): (u32, u32),
+    parallel: bool,
+) -> Vec<u8> {
+    let prepared = mapper.prepare(palette, pixels, (w, h));
+    let at = |i: usize| {
+        let pos = ((i % w as usize) as u32, (i / w as usize) as u32);
+        let ctx = PixelContext::new(pixels, (w, h), pos).with_prepared(prepared.as_deref());
+        mapper.predict_context(palette, &ctx)
+    };
+    if parallel {
+        (0..pixels.len())
+            .into_par_iter()
+            .flat_map_iter(at)
+            .collect()
+    } else {
+        (0..pixels.len()).flat_map(at).collect()
+    }
+}
+
+fn diffuse<M: Mapper>(
+    mapper: &M,
+    palette: &[Rgbx],
+    diffusion: &Diffusion,
+    (w, h): (u32, u32),
+    parallel: bool,
+    pixel: impl Fn(u32, u32) -> [u8; 4] + Sync,
+) -> Vec<u8> {
+    // Context mappers are prepared on, and see, the image as it is, with the pixel carrying the
+    // diffused error in place of the current one
+    let image: Option<Vec<[u8; 4]>> = mapper.uses_context().then(|| {
+        let pixel = &pixel;
+        (0..h)
+            .flat_map(|y| (0..w).map(move |x| pixel(x, y)))
+            .collect()
+    });
+    let prepared = image
+        .as_ref()
+        .and_then(|image| mapper.prepare(palette, image, (w, h)));
+    let predict = |px: &[u8; 4], x: u32, y: u32| match &image {
+        Some(image) => {
+            let ctx = PixelContext::new(image, (w, h), (x, y));
+            let ctx = ctx.with_pixel(*px).with_prepared(prepared.as_deref());
+            mapper.predict_context(palette, &ctx)
+        }
+        None => mapper.predict(palette, px),
+    };
+
+    if let DiffusionKernel::Riemersma { history } = diffusion.kernel {
+        return riemersma(&predict, history, diffusion.strength, (w, h), pixel);
+    }
+    let Some((rows, overlap)) = diffusion.tiles.filter(|_| parallel) else {
+        return diffuse_rows(&predict, diffusion, w, 0..h, &pixel);
+    };
+    let row_len = w as usize * 4;
+    let bands: Vec<(u32, Vec<u8>)> = (0..h)
+        .step_by(rows as usize)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|top| {
+            let start = top.saturating_sub(overlap);
+            let end = (top + rows).min(h);
+            (
+                start,
+                diffuse_rows(&predict, diffusion, w, start..end, &pixel),
+            )
+        })
+        .collect();
+
+    let mut raw = vec![0; row_len * h as usize];
+    for (band, (start, data)) in bands.iter().enumerate() {
+        let top = band as u32 * rows;
+        for (y, row) in (*start..).zip(data.chunks_exact(row_len)) {
+            let out = &mut raw[y as usize * row_len..][..row_len];
+            if y >= top {
+                out.copy_from_slice(row);
+                continue;
+            }
+            // Hand over from the band above to this one gradually, picking pixels by blue noise
+            let weight = (y - start + 1) as f32 / (top - start + 1) as f32;
+            for (x, (o, px)) in out.chunks_exact_mut(4).zip(row.chunks_exact(4)).enumerate() {
+                let noise = BLUE_NOISE_64[(y as usize % 64) * 64 + x % 64] as f32 / 256.;
+                if noise < weight {
+                    o.copy_from_slice(px);
+                }
+            }
+        }
+    }
+    raw
+}
+
+fn diffuse_rows(
+    predict: &impl Fn(&[u8; 4], u32, u32) -> [u8; 4],
+    diffusion: &Diffusion,
+    w: u32,
+    rows: Range<u32>,
+    pixel: &impl Fn(u32, u32) -> [u8; 4],
+) -> Vec<u8> {
+    let mut diffuser = Diffuser::new(w as usize, &diffusion.kernel, diffusion.strength);
+    let mut raw = vec![0; w as usize * rows.len() * 4];
+    for (i, y) in rows.enumerate() {
+        let reverse = diffusion.serpentine && y % 2 == 1;
+        for n in 0..w {
+            let x = if reverse { w - 1 - n } else { n };
+            let out = diffuser.map(&pixel(x, y), x as usize, reverse, |px| predict(px, x, y));
+            let at = (i * w as usize + x as usize) * 4;
+            raw[at..at + 4].copy_from_slice(&out);
+        }
+        diffuser.next_row();
+    }
+    raw
+}
+
+// Walks the image along Hilbert curves, adding a decaying history of the errors of the last
+// `history` pixels visited to the current one
+fn riemersma(
+    predict: &impl Fn(&[u8; 4], u32, u32) -> [u8; 4],
+    history: usize,
+    strength: f32,
+    (w, h): (u32, u32),
+    pixel: impl Fn(u32, u32) -> [u8; 4],
+) -> Vec<u8> {
+    const RATIO: f32 = 16.;
+    let history = history.max(1);
+    let weights: Vec<f32> = (0..history)
+        .map(|i| RATIO.powf(i as f32 / (history - 1).max(1) as f32) / RATIO)
+        .collect();
+    let mut errors = VecDeque::from(vec![[0f32; 3]; history]);
+    let mut raw = vec![0; w as usize * h as usize * 4];
+
+    for (x, y) in hilbert_walk(w, h) {
+        let px = pixel(x, y);
+        let carried = [0, 1, 2].map(|c| {
+            let e: f32 = errors.iter().zip(&weights).map(|(e, w)| e[c] * w).sum();
+            (px[c] as f32 + e).round().clamp(0., 255.) as u8
+        });
+        let out = predict(&[carried[0], carried[1], carried[2], px[3]], x, y);
+
+        errors.pop_front();
+        errors.push_back([0, 1, 2].map(|c| (px[c] as f32 - out[c] as f32) * strength));
+        let i = (y as usize * w as usize + x as usize) * 4;
+        raw[i..i + 4].copy_from_slice(&out);
+    }
+    raw
+}
+
+// Covers the image with square Hilbert curves laid out along its longer side, each one ending
+// next to where the following one starts. Unless the shorter side is a power of two the curves are
+// clipped, and the walk occasionally jumps a few pixels where they leave and re-enter the image.
+fn hilbert_walk(w: u32, h: u32) -> impl Iterator<Item = (u32, u32)> {
+    let transpose = h > w;
+    let (long, short) = if transpose { (h, w) } else { (w, h) };
+    let side = short.max(1).next_power_of_two();
+    (0..long.div_ceil(side))
+        .flat_map(move |block| {
+            (0..side as u64 * side as u64).map(move |d| {
+                let (x, y) = hilbert_point(side, d);
+                (block * side + x, y)
+            })
+        })
+        .filter(move |&(x, y)| x < long && y < short)
+        .map(move |(x, y)| if transpose { (y, x) } else { (x, y) })
+}
+
+// Position of the `d`th point of a Hilbert curve filling an `n`x`n` square, going from (0, 0) to
+// (n - 1, 0)
+fn hilbert_point(n: u32, d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = (1 & (t / 2)) as u32;
+        let ry = (1 & (t ^ rx as u64)) as u32;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+// Quantization error carried over to the rest of the current row and to the rows below
+struct Diffuser {
+    taps: Vec<(isize, usize, f32)>,
+    strength: f32,
+    pad: usize,
+    rows: Vec<Vec<[f32; 3]>>,
+}
+
+impl Diffuser {
+    fn new(width: usize, kernel: &DiffusionKernel, strength: f32) -> Self {
+        let taps = kernel.taps();
+        // Padding on each side saves bounds checks at the edges
+        let pad = taps.iter().map(|t| t.0.unsigned_abs()).max().unwrap_or(0);
+        let depth = taps.iter().map(|t| t.1).max().unwrap_or(0) + 1;
+        Diffuser {
+            taps,
+            strength,
+            pad,
+            rows: vec![vec![[0.; 3]; width + 2 * pad]; depth],
+        }
+    }
+
+    // `reverse` mirrors the kernel for rows scanned right to left
+    fn map(
+        &mut self,
+        pixel: &[u8; 4],
+        x: usize,
+        reverse: bool,
+        predict: impl FnOnce(&[u8; 4]) -> [u8; 4],
+    ) -> [u8; 4] {
+        let x = x + self.pad;
+        let wanted = [0, 1, 2].map(|c| (pixel[c] as f32 + self.rows[0][x][c]).clamp(0., 255.));
+        let adjusted = wanted.map(|c| c.round() as u8);
+        let out = predict(&[adjusted[0], adjusted[1], adjusted[2], pixel[3]]);
+
+        for &(dx, dy, share) in &self.taps {
+            let dx = if reverse { -dx } else { dx };
+            let cell = &mut self.rows[dy][x.wrapping_add_signed(dx)];
+            for c in 0..3 {
+                cell[c] += (wanted[c] - out[c] as f32) * self.strength * share;
+            }
+        }
+        out
+    }
+
+    fn next_row(&mut self) {
+        self.rows.rotate_left(1);
+        self.rows.last_mut().unwrap().fill([0.; 3]);
+    }
+}
+
+impl<'a, M> Processor<'a, M>
+where
+    M: Mapper,
+{
+    pub fn configure() -> ProcOptions<'a> {
+        ProcOptions::default()
+    }
+
+    pub fn process(&self) -> ProcessedData {
+        let (w, h) = self.data.dimensions();
+        if let Some(diffusion) = self.conf.mapper.error_diffusion() {
+            let ProcOptions {
+                palette, threads, ..
+            } = &self.conf;
+            let parallel = !matches!(threads, Threads::Single);
+            let mapper = &self.conf.excluding(palette);
+            let raw = diffuse(mapper, palette, diffusion, (w, h), parallel, |x, y| {
+                self.data.get_pixel(x, y).0
+            });
+            return ProcessedData { raw, dimen: (w, h) };
+        }
+        if self.conf.mapper.uses_context() {
+            let pixels: Vec<[u8; 4]> = self.data.pixels().map(|(_, _, px)| px.0).collect();
+            let parallel = !matches!(self.conf.threads, Threads::Single);
+            let palette = &self.conf.palette;
+            let raw = map_context(
+                &self.conf.excluding(palette),
+                palette,
+                &pixels,
+                (w, h),
+                parallel,
+            );
+            return ProcessedData { raw, dimen: (w, h) };
+        }
+        let raw = match self.strategy() {
+            Strategy::InMemory => {
+                let img_pixels: Vec<_> = self.data.pixels().map(|(_, _, rgb)| rgb).collect();
+                self.map_pixels(&img_pixels, 0)
+            }
+            Strategy::Streaming { rows } => {
+                let mut raw = Vec::with_capacity(w as usize * h as usize * 4);
+                for y in (0..h).step_by(rows as usize) {
+                    let band: Vec<_> = self
+                        .data
+                        .view(0, y, w, rows.min(h - y))
+                        .pixels()
+                        .map(|(_, _, rgb)| rgb)
+                        .collect();
+                    raw.extend(self.map_pixels(&band, y as usize * w as usize));
+                }
+                raw
+            }
+        };
+
+        ProcessedData { raw, dimen: (w, h) }
+    }
+
+    /// How [`Processor::process`] will go about mapping the image, given the configured
+    /// memory limit. Mappers with error diffusion or context always map the whole image in
+    /// memory, which loading has already made sure fits.
+    pub fn strategy(&self) -> Strategy {
+        let Some(limit) = self.conf.memory_limit else {
+            return Strategy::InMemory;
+        };
+        if self.conf.whole_image() {
+            return Strategy::InMemory;
+        }
+        let (w, h) = self.data.dimensions();
+        let decoded = self.data.as_bytes().len();
+        let pixels = w as usize * h as usize;
+        if decoded + pixels * IN_MEMORY_COST <= limit {
+            return Strategy::InMemory;
+        }
+        let spare = limit.saturating_sub(decoded + pixels * 4);
+        let rows = (spare / (w as usize * STREAMING_COST).max(1)).clamp(1, h.max(1) as usize);
+        Strategy::Streaming { rows: rows as u32 }
+    }
+
+    // `first` is the index of the first pixel of `img_pixels` within the image
+    fn map_pixels(&self, img_pixels: &[Rgba<u8>], first: usize) -> Vec<u8> {
+        let ProcOptions {
+            threads, palette, ..
+        } = &self.conf;
+        let mapper = &self.conf.excluding(palette);
+        let width = self.data.width() as usize;
+        let at = |i: usize, px: &Rgba<u8>| predict_at(mapper, palette, &px.0, first + i, width);
+        let parts = |n: usize| {
+            let size = (img_pixels.len() / n).max(1);
+            img_pixels
+                .chunks(size)
+                .enumerate()
+                .map(|(i, part)| (first + i * size, part))
+                .collect()
+        };
+
+        match threads {
+            Threads::Single => img_pixels
+                .iter()
+                .enumerate()
+                .flat_map(|(i, px)| at(i, px))
+                .collect(),
+            Threads::Auto => self.dispatch(parts(ThreadCount::calculate().get())),
+            Threads::Custom(n) => self.dispatch(parts(n.get())),
+            Threads::Rayon => img_pixels
+                .par_iter()
+                .enumerate()
+                .flat_map(|(i, px)| at(i, px))
+                .collect(),
+            Threads::Extreme => self.dispatch(parts(ThreadCount::extreme().get())),
+        }
+    }
+
+    /// Maps the image like [`Processor::process`], but keeps the unique colors of the image and the
+    /// mapper's palette-independent classifications around, so that subsequent calls after
+    /// [`Processor::set_palette`] only need to re-run the palette lookup for each unique color.
+    pub fn process_incremental(&mut self) -> ProcessedData {
+        let ProcOptions {
+            mapper,
+            palette,
+            threads,
+            ..
+        } = &self.conf;
+        let cache = self
+            .cache
+            .get_or_insert_with(|| RemapCache::build(&self.data, mapper, *threads));
+        cache.render(
+            &self.conf.excluding(palette),
+            palette,
+            self.data.dimensions(),
+            *threads,
+        )
+    }
+
+    /// Maps the image against several palettes, returning one result per palette in the same
+    /// order. Decoding and the mapper's classification stage are shared between all of them, and
+    /// each palette is mapped in turn on the configured [`threads`](ProcOptions::threads).
+    pub fn process_multi(&mut self, palettes: &[&Palette]) -> Vec<ProcessedData> {
+        let ProcOptions {
+            mapper, threads, ..
+        } = &self.conf;
+        let cache = self
+            .cache
+            .get_or_insert_with(|| RemapCache::build(&self.data, mapper, *threads));
+        let dimen = self.data.dimensions();
+        palettes
+            .iter()
+            .map(|palette| cache.render(&self.conf.excluding(palette), palette, dimen, *threads))
+            .collect()
+    }
+
+    /// Switches to another palette, either borrowed or owned. Slices of colors can be
+    /// converted with [`Palette::from`].
+    pub fn set_palette(&mut self, palette: impl Into<Cow<'a, Palette>>) {
+        self.conf.palette = palette.into();
+    }
+
+    /// Drops the intermediate state used by [`Processor::process_incremental`]. Needed if the
+    /// mapper's classification behaviour changed since the last run.
+    pub fn clear_cache(&mut self) {
+        self.cache = None;
+    }
+
+    /// Luma (0-1) below which the given fraction of the image's pixels fall, e.g. `0.1` for the
+    /// threshold of the darkest 10%.
+    pub fn luma_quantile(&self, q: f32) -> f32 {
+        let mut hist = [0usize; 256];
+        for (_, _, px) in self.data.pixels() {
+            hist[(palette::luma(&px.0) * 255.).round() as usize] += 1;
+        }
+        let (w, h) = self.data.dimensions();
+        let target = (q.clamp(0., 1.) * (w as usize * h as usize) as f32).ceil() as usize;
+        let mut seen = 0;
+        for (i, n) in hist.iter().enumerate() {
+            seen += n;
+            if seen >= target.max(1) {
+                return i as f32 / 255.;
+            }
+        }
+        1.
+    }
+
+    pub fn gen_tracker(&mut self) -> Tracker {
+        let (x, y) = self.data.dimensions();
+        self.prog.init((x * y) as usize)
+    }
+
+    fn dispatch(&self, parts: Vec<(usize, &[Rgba<u8>])>) -> Vec<u8> {
+        let palette = &self.conf.palette;
+        let mapper = &self.conf.excluding(palette);
+        let width = self.data.width() as usize;
+
+        thread::scope(|s| {
+            let mut handles: Vec<thread::ScopedJoinHandle<Vec<u8>>> = Vec::new();
+            let mut data: Vec<u8> = Vec::new();
+            for (first, part) in parts {
+                let sender = self.prog.get_sender();
+                let h = s.spawn(move || {
+                    part.iter()
+                        .enumerate()
+                        .flat_map(|(i, rgb)| {
+                            let r = predict_at(mapper, palette, &rgb.0, first + i, width);
+                            sender.notify();
+                            r
+                        })
+                        .collect::<Vec<u8>>()
+                });
+                handles.push(h);
+            }
+            for h in handles {
+                data.append(&mut h.join().unwrap());
+            }
+            data
+        })
+    }
+}
+
+// Applies the mapper's ordered dither offset for the pixel at linear index `i`, if it has one
+fn predict_at<M: Mapper>(
+    mapper: &M,
+    palette: &[Rgbx],
+    pixel: &[u8; 4],
+    i: usize,
+    width: usize,
+) -> [u8; 4] {
+    match mapper.dither_offset((i % width) as u32, (i / width) as u32) {
+        Some(offset) => {
+            let shift = |c: u8| (c as f32 + offset).round().clamp(0., 255.) as u8;
+            let px = [shift(pixel[0]), shift(pixel[1]), shift(pixel[2]), pixel[3]];
+            mapper.predict(palette, &px)
+        }
+        None => mapper.predict(palette, pixel),
+    }
+}
+
+pub(crate) fn fnv1a(parts: &[&[u8]]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|p| p.iter())
+        .fold(0xcbf29ce484222325, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+// Dimensions and color type of an image from its header, for the formats whose decoders are
+// cheap to set up, and 8-bit RGBA for the rest
+fn header<R: BufRead + Seek>(
+    reader: image::io::Reader<R>,
+) -> image::ImageResult<((u32, u32), ColorType)> {
+    #[allow(unused_imports)]
+    use image::{codecs, ImageDecoder, ImageFormat};
+    #[allow(dead_code)]
+    fn info<'a>(decoder: impl ImageDecoder<'a>) -> ((u32, u32), ColorType) {
+        (decoder.dimensions(), decoder.color_type())
+    }
+    let reader = reader.with_guessed_format()?;
+    match reader.format() {
+        #[cfg(feature = "png")]
+        Some(ImageFormat::Png) => Ok(info(codecs::png::PngDecoder::new(reader.into_inner())?)),
+        #[cfg(feature = "jpeg")]
+        Some(ImageFormat::Jpeg) => Ok(info(codecs::jpeg::JpegDecoder::new(reader.into_inner())?)),
+        #[cfg(feature = "gif")]
+        Some(ImageFormat::Gif) => Ok(info(codecs::gif::GifDecoder::new(reader.into_inner())?)),
+        #[cfg(feature = "bmp")]
+        Some(ImageFormat::Bmp) => Ok(info(codecs::bmp::BmpDecoder::new(reader.into_inner())?)),
+        #[cfg(feature = "webp")]
+        Some(ImageFormat::WebP) => Ok(info(codecs::webp::WebPDecoder::new(reader.into_inner())?)),
+        #[cfg(feature = "tiff")]
+        Some(ImageFormat::Tiff) => Ok(info(codecs::tiff::TiffDecoder::new(reader.into_inner())?)),
+        _ => Ok((reader.into_dimensions()?, ColorType::Rgba8)),
+    }
+}
+
+// Average luma (0-1) of the image's pixels
+fn mean_luma(data: &DynamicImage) -> f32 {
+    let (w, h) = data.dimensions();
+    let total: f64 = data
+        .pixels()
+        .map(|(_, _, px)| palette::luma(&px.0) as f64)
+        .sum();
+    (total / (w as f64 * h as f64).max(1.)) as f32
+}
+
+pub struct ProcessedData {
+    raw: Vec<u8>,
+    dimen: (u32, u32),
+}
+
+impl ProcessedData {
+    pub fn raw_buffer(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn to_image(&self) -> RgbaImage {
+        RgbaImage::from_raw(self.dimen.0, self.dimen.1, self.raw.clone())
+            .expect("buffer length should match dimensions")
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub const fn dimensions(&self) -> (u32, u32) {
+        self.dimen
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.dimen.0
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.dimen.1
+    }
+
+    /// Number of bytes per row of the raw buffer. Rows are tightly packed, with no padding.
+    pub const fn stride(&self) -> usize {
+        self.dimen.0 as usize * 4
+    }
+
+    /// Returns a copy of the raw RGBA8 buffer with color channels multiplied by alpha,
+    /// which is the layout GPU texture uploads (egui, iced, wgpu) generally expect.
+    pub fn to_rgba_premultiplied(&self) -> Vec<u8> {
+        self.raw
+            .chunks_exact(4)
+            .flat_map(|px| {
+                let a = px[3] as u16;
+                let mul = |c: u8| ((c as u16 * a + 127) / 255) as u8;
+                [mul(px[0]), mul(px[1]), mul(px[2]), px[3]]
+            })
+            .collect()
+    }
+
+    /// Replaces every pixel matching the RGB of `from[i]` with `to[i]`, keeping its alpha. Meant
+    /// for switching the palette of an already mapped image without running the mapper again.
+    /// Entries past the end of the shorter palette are ignored.
+    pub fn remap(&mut self, from: &[Rgbx], to: &[Rgbx]) {
+        let mut table: AHashMap<[u8; 3], [u8; 3]> = AHashMap::new();
+        for (f, t) in from.iter().zip(to) {
+            table.entry([f.0, f.1, f.2]).or_insert([t.0, t.1, t.2]);
+        }
+        self.raw.par_chunks_exact_mut(4).for_each(|px| {
+            if let Some(c) = table.get(&[px[0], px[1], px[2]]) {
+                px[..3].copy_from_slice(c);
+            }
+        });
+    }
+
+    /// A 64-bit FNV-1a hash of the dimensions and raw buffer. Stable across runs, platforms and
+    /// crate versions, so it can be stored and compared later.
+    pub fn digest(&self) -> u64 {
+        let (w, h) = self.dimen;
+        fnv1a(&[&w.to_le_bytes(), &h.to_le_bytes(), &self.raw])
+    }
+
+    /// Distinct colors in the buffer with their pixel counts, most used first.
+    pub fn used_colors(&self) -> Vec<([u8; 4], usize)> {
+        let mut counts: AHashMap<[u8; 4], usize> = AHashMap::new();
+        for px in self.raw.chunks_exact(4) {
+            *counts.entry([px[0], px[1], px[2], px[3]]).or_default() += 1;
+        }
+        let mut colors: Vec<_> = counts.into_iter().collect();
+        colors.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        colors
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error + 'static>> {
+        let (w, h) = self.dimen;
+        image::save_buffer(path, &self.raw, w, h, image::ColorType::Rgba8)?;
+
+        Ok(())
+    }
+
+    // Without an encoding feature `Encoding` has no variants and everything past the match is
+    // unreachable
+    #[cfg_attr(
+        not(any(feature = "png", feature = "jpeg")),
+        allow(unreachable_code, unused_variables)
+    )]
+    pub fn encode<Buf: Write + Seek>(
+        &self,
+        buf: &mut Buf,
+        encoding: Encoding,
+    ) -> Result<(), Box<dyn Error>> {
+        let format: image::ImageOutputFormat = match encoding {
+            #[cfg(feature = "png")]
+            Encoding::Png => image::ImageOutputFormat::Png,
+            #[cfg(feature = "jpeg")]
+            Encoding::Jpeg(q) => image::ImageOutputFormat::Jpeg(q),
+        };
+        let (height, width) = self.dimen;
+
+        image::write_buffer_with_format(
+            buf,
+            self.raw_buffer(),
+            height,
+            width,
+            image::ColorType::Rgba8,
+            format,
+        )?;
+        Ok(())
+    }
+}
+
+impl From<RgbaImage> for ProcessedData {
+    fn from(value: RgbaImage) -> Self {
+        ProcessedData {
+            dimen: value.dimensions(),
+            raw: value.into_raw(),
+        }
+    }
+}
+
+/// Output formats for [`ProcessedData::encode`], each available with its format feature.
+pub enum Encoding {
+    #[cfg(feature = "png")]
+    Png,
+    #[cfg(feature = "jpeg")]
+    Jpeg(u8),
+}
+
+// Approximate peak bytes per pixel needed on top of the decoded image: the pixel copy, the
+// per-thread results and the concatenated output buffer
+const IN_MEMORY_COST: usize = 12;
+// Bytes per pixel of a band processed while streaming, excluding the output buffer itself
+const STREAMING_COST: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    InMemory,
+    Streaming { rows: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcError {
+    MemoryLimit { required: usize, limit: usize },
+    DimensionLimit { width: u32, height: u32 },
+}
+
+impl fmt::Display for ProcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryLimit { required, limit } => write!(
+                f,
+                "processing needs at least {required} bytes, but the memory limit is {limit} bytes"
+            ),
+            Self::DimensionLimit { width, height } => {
+                write!(
+                    f,
+                    "image dimensions {width}x{height} exceed the configured limits"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ProcError {}
+
+/// Bounds on the size of input images, for guarding against decompression bombs and other
+/// pathological inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_pixels: Option<u64>,
+    /// Shrink oversized images to fit instead of rejecting them.
+    pub downscale: bool,
+    /// With [`Limits::downscale`], the largest image in pixels that is still decoded to be
+    /// shrunk, [`Limits::DEFAULT_DECODED_PIXELS`] if unset. Oversized images have to be decoded in
+    /// full first, so anything larger is rejected up front.
+    pub max_decoded_pixels: Option<u64>,
+}
+
+impl Limits {
+    /// 64 megapixels, a 256 MiB RGBA image.
+    pub const DEFAULT_DECODED_PIXELS: u64 = 1 << 26;
+
+    #[must_use]
+    pub fn max_width(mut self, width: u32) -> Self {
+        self.max_width = Some(width);
+        self
+    }
+
+    #[must_use]
+    pub fn max_height(mut self, height: u32) -> Self {
+        self.max_height = Some(height);
+        self
+    }
+
+    #[must_use]
+    pub fn max_pixels(mut self, pixels: u64) -> Self {
+        self.max_pixels = Some(pixels);
+        self
+    }
+
+    #[must_use]
+    pub fn downscale(mut self, downscale: bool) -> Self {
+        self.downscale = downscale;
+        self
+    }
+
+    #[must_use]
+    pub fn max_decoded_pixels(mut self, pixels: u64) -> Self {
+        self.max_decoded_pixels = Some(pixels);
+        self
+    }
+
+    pub fn allows(&self, width: u32, height: u32) -> bool {
+        self.max_width.is_none_or(|max| width <= max)
+            && self.max_height.is_none_or(|max| height <= max)
+            && self
+                .max_pixels
+                .is_none_or(|max| width as u64 * height as u64 <= max)
+    }
+
+    // Whether an image of these dimensions may be decoded at all
+    fn decodable(&self, width: u32, height: u32) -> bool {
+        if self.downscale {
+            let max = self
+                .max_decoded_pixels
+                .unwrap_or(Self::DEFAULT_DECODED_PIXELS);
+            width as u64 * height as u64 <= max
+        } else {
+            self.allows(width, height)
+        }
+    }
+
+    fn fit(&self, img: DynamicImage) -> DynamicImage {
+        let (w, h) = img.dimensions();
+        let (nw, nh) = self.fitted(w, h);
+        if (nw, nh) == (w, h) {
+            return img;
+        }
+        img.resize_exact(nw, nh, image::imageops::FilterType::Triangle)
+    }
+
+    // Dimensions an image ends up with after `fit`
+    fn fitted(&self, w: u32, h: u32) -> (u32, u32) {
+        if !self.downscale || self.allows(w, h) {
+            return (w, h);
+        }
+        let mut scale = 1f64;
+        if let Some(max) = self.max_width {
+            scale = scale.min(max as f64 / w as f64);
+        }
+        if let Some(max) = self.max_height {
+            scale = scale.min(max as f64 / h as f64);
+        }
+        if let Some(max) = self.max_pixels {
+            scale = scale.min((max as f64 / (w as f64 * h as f64)).sqrt());
+        }
+        let nw = ((w as f64 * scale) as u32).max(1);
+        let nh = ((h as f64 * scale) as u32).max(1);
+        (nw, nh)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcOptions<'a, M: Mapper = Nearest> {
+    mapper: M,
+    threads: Threads,
+    palette: Cow<'a, Palette>,
+    variant: Option<Variant>,
+    memory_limit: Option<usize>,
+    limits: Limits,
+    exclusions: Exclusions,
+}
+
+impl Default for ProcOptions<'_> {
+    fn default() -> Self {
+        ProcOptions {
+            mapper: Nearest::new(),
+            threads: Threads::default(),
+            palette: Cow::Owned(Palette::nord()),
+            variant: None,
+            memory_limit: None,
+            limits: Limits::default(),
+            exclusions: Exclusions::default(),
+        }
+    }
+}
+
+impl<'a, M: Mapper> ProcOptions<'a, M> {
+    #[must_use]
+    pub fn new(mapper: M) -> Self {
+        ProcOptions {
+            mapper,
+            threads: Threads::default(),
+            palette: Cow::Owned(Palette::nord()),
+            variant: None,
+            memory_limit: None,
+            limits: Limits::default(),
+            exclusions: Exclusions::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn mapper<Map: Mapper>(self, mapper: Map) -> ProcOptions<'a, Map> {
+        ProcOptions {
+            mapper,
+            threads: self.threads,
+            palette: self.palette,
+            variant: self.variant,
+            memory_limit: self.memory_limit,
+            limits: self.limits,
+            exclusions: self.exclusions,
+        }
+    }
+
+    #[must_use]
+    pub fn copy_with_mapper<Map: Mapper>(&self, mapper: Map) -> ProcOptions<'a, Map> {
+        ProcOptions {
+            mapper,
+            threads: self.threads,
+            palette: self.palette.clone(),
+            variant: self.variant,
+            memory_limit: self.memory_limit,
+            limits: self.limits,
+            exclusions: self.exclusions.clone(),
+        }
+    }
+
+    /// These options with the palette owned rather than borrowed, e.g. to hand them to a
+    /// [`Service`](crate::service::Service) or another thread.
+    #[must_use]
+    pub fn into_owned(self) -> ProcOptions<'static, M> {
+        ProcOptions {
+            mapper: self.mapper,
+            threads: self.threads,
+            palette: Cow::Owned(self.palette.into_owned()),
+            variant: self.variant,
+            memory_limit: self.memory_limit,
+            limits: self.limits,
+            exclusions: self.exclusions,
+        }
+    }
+
+    #[must_use]
+    pub fn threads(mut self, threads: Threads) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// The palette to map to, either borrowed or owned, along with its names, roles and linked
+    /// variants. Slices of colors can be converted with [`Palette::from`].
+    #[must_use]
+    pub fn palette(mut self, palette: impl Into<Cow<'a, Palette>>) -> Self {
+        self.palette = palette.into();
+        self
+    }
+
+    /// Maps to the light or dark variant of the [`palette`](ProcOptions::palette), picked when
+    /// the image is loaded. Has no effect on palettes without linked variants.
+    ///
+    /// Mappers that refer to palette colors, like [`RoleAware::themed`](crate::mappers::RoleAware::themed)
+    /// and [`Nearest::weight`], look them up in whichever variant is picked.
+    ///
+    /// ```
+    /// use mapped::{palette::{self, Variant}, ProcOptions};
+    ///
+    /// let dark = palette::by_name("gruvbox-dark").unwrap().clone();
+    /// let gruvbox = dark.with_variant(palette::by_name("gruvbox-light").unwrap().clone());
+    /// let opts = ProcOptions::default().palette(&gruvbox).variant(Variant::Auto);
+    /// ```
+    #[must_use]
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = Some(variant);
+        self
+    }
+
+    /// Caps the memory used while processing. Images too large to be mapped in one go are
+    /// processed in bands of rows instead, and loading fails with [`ProcError::MemoryLimit`]
+    /// if even that would not fit. Mappers with error diffusion or context can't be processed
+    /// in bands, so for them the whole image has to fit. Images passed to
+    /// [`ProcOptions::load_image`] are not checked.
+    #[must_use]
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    #[must_use]
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Keeps every mapper from mapping pixels to palette colors of `class`: the mapper is handed
+    /// the palette without them, so interpolating and dithering mappers never produce them either.
+    /// Exclusions are ignored if they would leave no color to map to.
+    #[must_use]
+    pub fn exclude_class(mut self, class: ColorClass) -> Self {
+        self.exclusions.classes.push(class);
+        self
+    }
+
+    /// Like [`ProcOptions::exclude_class`], for the palette entry at `index`.
+    #[must_use]
+    pub fn exclude_index(mut self, index: usize) -> Self {
+        self.exclusions.indices.push(index);
+        self
+    }
+
+    fn excluding(&self, palette: &[Rgbx]) -> Excluding<'_, M> {
+        Excluding::new(&self.mapper, palette, &self.exclusions)
+    }
+
+    /// Hash of everything that affects the output: the mapper's `Debug` representation, the
+    /// palette and the dimension limits. Combined with a digest of the input, it identifies a
+    /// processed result without having to produce it.
+    pub fn fingerprint(&self) -> u64
+    where
+        M: fmt::Debug,
+    {
+        let mut conf = format!("{:?}|{:?}|{:?}", self.mapper, self.palette, self.limits);
+        if self.exclusions != Exclusions::default() {
+            conf += format!("|{:?}", self.exclusions).as_str();
+        }
+        if let Some(variant) = self.variant {
+            conf += format!("|{variant:?}").as_str();
+        }
+        fnv1a(&[conf.as_bytes()])
+    }
+
+    // Mappers with error diffusion or context see the whole image at once and can't be streamed
+    fn whole_image(&self) -> bool {
+        self.mapper.error_diffusion().is_some() || self.mapper.uses_context()
+    }
+
+    fn check_memory(&self, (w, h): (u32, u32), color: ColorType) -> Result<(), ProcError> {
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        let bpp = color.bytes_per_pixel() as usize;
+        let (fw, fh) = self.limits.fitted(w, h);
+        let pixels = fw as usize * fh as usize;
+        let mut required = if self.whole_image() {
+            pixels * (bpp + IN_MEMORY_COST)
+        } else {
+            // Decoded image and output buffer, plus a single row being mapped
+            pixels * (bpp + 4) + fw as usize * STREAMING_COST
+        };
+        if (fw, fh) != (w, h) {
+            // Downscaling holds the full decoded image, the vertically resampled one (as f32
+            // RGBA) and the result at once
+            let resize =
+                w as usize * h as usize * bpp + w as usize * fh as usize * 16 + pixels * bpp;
+            required = required.max(resize);
+        }
+        if required > limit {
+            return Err(ProcError::MemoryLimit { required, limit });
+        }
+        Ok(())
+    }
+
+    fn checked(&self) -> bool {
+        self.memory_limit.is_some() || self.limits != Limits::default()
+    }
+
+    pub fn load<F: AsRef<Path>>(
+        self,
+        file: F,
+    ) -> Result<Processor<'a, M>, Box<dyn Error + 'static>> {
+        let data = self.decode_file(file.as_ref())?;
+        Ok(self.load_image(data))
+    }
+
+    pub fn load_bytes(self, buffer: &[u8]) -> Result<Processor<'a, M>, Box<dyn Error + 'static>> {
+        let data = self.decode_bytes(buffer)?;
+        Ok(self.load_image(data))
+    }
+
+    // Decodes an image after checking its header against the limits. Formats whose header isn't
+    // inspected are assumed to decode to 8-bit RGBA, so the decoded image is checked again.
+    pub(crate) fn decode_file(
+        &self,
+        file: &Path,
+    ) -> Result<DynamicImage, Box<dyn Error + 'static>> {
+        if self.checked() {
+            let (dimensions, color) = header(image::io::Reader::open(file)?)?;
+            self.check_dimensions(dimensions, color)?;
+        }
+        let data = image::open(file)?;
+        self.check_dimensions(data.dimensions(), data.color())?;
+        Ok(data)
+    }
+
+    pub(crate) fn decode_bytes(
+        &self,
+        buffer: &[u8],
+    ) -> Result<DynamicImage, Box<dyn Error + 'static>> {
+        if self.checked() {
+            let (dimensions, color) = header(image::io::Reader::new(Cursor::new(buffer)))?;
+            self.check_dimensions(dimensions, color)?;
+        }
+        let data = image::load_from_memory(buffer)?;
+        self.check_dimensions(data.dimensions(), data.color())?;
+        Ok(data)
+    }
+
+    fn check_dimensions(&self, (w, h): (u32, u32), color: ColorType) -> Result<(), ProcError> {
+        if !self.limits.decodable(w, h) {
+            return Err(ProcError::DimensionLimit {
+                width: w,
+                height: h,
+            });
+        }
+        self.check_memory((w, h), color)
+    }
+
+    /// Like [`ProcOptions::load_image`], but enforces the dimension and memory limits like
+    /// [`ProcOptions::load`] does.
+    pub fn try_load_image(self, data: DynamicImage) -> Result<Processor<'a, M>, ProcError> {
+        self.check_dimensions(data.dimensions(), data.color())?;
+        Ok(self.load_image(data))
+    }
+
+    /// Skips the checks done by [`ProcOptions::load`] since the image is already decoded, see
+    /// [`ProcOptions::try_load_image`], but still downscales it if [`Limits::downscale`] is
+    /// enabled and the image exceeds the limits.
+    pub fn load_image(mut self, data: DynamicImage) -> Processor<'a, M> {
+        let data = self.limits.fit(data);
+        if let Some(variant) = self.variant {
+            let variant = match variant {
+                Variant::Auto if mean_luma(&data) < 0.5 => Variant::Dark,
+                Variant::Auto => Variant::Light,
+                other => other,
+            };
+            self.palette = match self.palette {
+                Cow::Borrowed(palette) => Cow::Borrowed(palette.variant(variant)),
+                Cow::Owned(palette) => {
+                    let picked = palette.variant(variant);
+                    match std::ptr::eq(picked, &palette) {
+                        true => Cow::Owned(palette),
+                        false => Cow::Owned(picked.clone()),
+                    }
+                }
+            };
+        }
+        Processor {
+            conf: self,
+            data,
+            prog: Progress::default(),
+            cache: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Exclusions {
+    classes: Vec<ColorClass>,
+    indices: Vec<usize>,
+}
+
+// Hands the wrapped mapper the palette without its excluded entries, so not even interpolating
+// or dithering mappers can produce their colors
+#[derive(Clone)]
+struct Excluding<'m, M: Mapper> {
+    mapper: &'m M,
+    // None when nothing is excluded, or when everything would be
+    allowed: Option<Vec<Rgbx>>,
+}
+
+impl<'m, M: Mapper> Excluding<'m, M> {
+    fn new(mapper: &'m M, palette: &[Rgbx], exclusions: &Exclusions) -> Self {
+        let allowed: Vec<Rgbx> = palette
+            .iter()
+            .enumerate()
+            .filter(|(i, c)| !(exclusions.classes.contains(&c.3) || exclusions.indices.contains(i)))
+            .map(|(_, c)| *c)
+            .collect();
+        let allowed = (!allowed.is_empty() && allowed.len() < palette.len()).then_some(allowed);
+        Excluding { mapper, allowed }
+    }
+
+    fn palette<'a>(&'a self, palette: &'a [Rgbx]) -> &'a [Rgbx] {
+        self.allowed.as_deref().unwrap_or(palette)
+    }
+}
+
+impl<M: Mapper> Mapper for Excluding<'_, M> {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.mapper.predict(self.palette(palette), pixel)
+    }
+
+    fn classify(&self, pixel: &[u8; 4]) -> Option<ColorClass> {
+        self.mapper.classify(pixel)
+    }
+
+    fn predict_class(&self, palette: &[Rgbx], pixel: &[u8; 4], class: ColorClass) -> [u8; 4] {
+        self.mapper
+            .predict_class(self.palette(palette), pixel, class)
+    }
+
+    fn uses_context(&self) -> bool {
+        self.mapper.uses_context()
+    }
+
+    fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+        self.mapper.predict_context(self.palette(palette), ctx)
+    }
+
+    fn prepare(
+        &self,
+        palette: &[Rgbx],
+        pixels: &[[u8; 4]],
+        dimensions: (u32, u32),
+    ) -> Option<Prepared> {
+        self.mapper
+            .prepare(self.palette(palette), pixels, dimensions)
+    }
+
+    fn error_diffusion(&self) -> Option<&Diffusion> {
+        self.mapper.error_diffusion()
+    }
+
+    fn dither_offset(&self, x: u32, y: u32) -> Option<f32> {
+        self.mapper.dither_offset(x, y)
+    }
+}
+
+#[derive(Clone, Default)]
+struct Progress(SignalSender);
+
+impl Progress {
+    fn init(&mut self, size: usize) -> Tracker {
+        let (s, r) = mpsc::channel::<Signal>();
+        self.0.replace(s);
+        Tracker {
+            current: 0,
+            total: size,
+            receiver: r,
+        }
+    }
+    fn get_sender(&self) -> SignalSender {
+        self.0.clone()
+    }
+}
+
+unsafe impl Sync for Progress {}
+
+#[derive(Clone, Default)]
+struct SignalSender(Option<Sender<Signal>>);
+
+impl SignalSender {
+    fn notify(&self) {
+        if let Some(s) = &self.0 {
+            s.send(Signal).unwrap();
+        }
+    }
+}
+
+impl Deref for SignalSender {
+    type Target = Option<Sender<Signal>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SignalSender {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+pub struct Tracker {
+    current: usize,
+    total: usize,
+    receiver: Receiver<Signal>,
+}
+
+struct Signal;
+
+impl Tracker {
+    pub fn percentage(&mut self) -> f32 {
+        self.track();
+        (self.current as f32 / self.total as f32) * 100.0
+    }
+    pub fn current(&mut self) -> usize {
+        self.track();
+        self.current
+    }
+    pub const fn total(&self) -> usize {
+        self.total
+    }
+    fn track(&mut self) {
+        self.current += self.receiver.try_iter().count();
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub enum Threads {
+    Single,
+    #[default]
+    Auto,
+    Rayon,
+    Custom(ThreadCount),
+    Extreme,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadCount(NonZeroUsize);
+
+impl ThreadCount {
+    pub fn new(val: NonZeroUsize) -> Self {
+        ThreadCount(val)
+    }
+
+    pub fn calculate() -> Self {
+        if let Ok(c) = std::thread::available_parallelism() {
+            ThreadCount::new(c)
+        } else {
+            ThreadCount::default()
+        }
+    }
+
+    fn extreme() -> Self {
+        NonZeroUsize::new(2usize.pow((Self::calculate().get() / 2) as u32))
+            .unwrap()
+            .into()
+    }
+
+    fn get(&self) -> usize {
+        self.0.get()
+    }
+}
+
+impl From<NonZeroUsize> for ThreadCount {
+    fn from(value: NonZeroUsize) -> Self {
+        Self(value)
+    }
+}
+
+impl Default for ThreadCount {
+    fn default() -> Self {
+        Self(NonZeroUsize::new(2).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hilbert_walk_covers_image() {
+        for (w, h) in [(1, 1), (8, 8), (13, 5), (3, 20), (20, 4), (100, 37)] {
+            let walk: Vec<_> = hilbert_walk(w, h).collect();
+            assert_eq!(walk.len(), (w * h) as usize);
+            let mut seen = vec![false; (w * h) as usize];
+            for &(x, y) in &walk {
+                assert!(!std::mem::replace(&mut seen[(y * w + x) as usize], true));
+            }
+            if !w.min(h).is_power_of_two() {
+                continue;
+            }
+            for pair in walk.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert_eq!(
+                    a.0.abs_diff(b.0) + a.1.abs_diff(b.1),
+                    1,
+                    "{w}x{h}: {a:?} -> {b:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Reading and writing [pywal](https://github.com/dylanaraps/pywal)'s `colors.json`, so wallpapers
+//! can be themed with the colors wal generated, and palettes can be handed to wal based setups
+//! with `wal --theme colors.json`.
+//!
+//! ```no_run
+//! use mapped::{pywal, ProcOptions};
+//!
+//! let palette = pywal::load_cache()?;
+//! let opts = ProcOptions::default().palette(&palette);
+//! # Ok::<(), mapped::palette::PaletteError>(())
+//! ```
+
+use super::{
+    palette::{self, Palette, PaletteError, Rgbx, Role},
+    theme::{self, TerminalScheme},
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize)]
+struct Colors {
+    special: Special,
+    colors: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct Special {
+    background: String,
+    foreground: String,
+}
+
+/// Parses a `colors.json`. The palette holds `color0` to `color15` in order, classified with
+/// [`classify`](palette::classify) since wal's colors come from the wallpaper rather than
+/// following the ANSI hues. The special background and foreground get their roles, and are added
+/// as extra colors if they aren't among the sixteen.
+pub fn from_json(json: &str) -> Result<Palette, PaletteError> {
+    const SLOTS: [&str; 16] = [
+        "color0", "color1", "color2", "color3", "color4", "color5", "color6", "color7", "color8",
+        "color9", "color10", "color11", "color12", "color13", "color14", "color15",
+    ];
+
+    let wal: Colors = serde_json::from_str(json)?;
+    let rgbx = |code: &str| -> Result<Rgbx, PaletteError> {
+        let [r, g, b] =
+            palette::parse_hex(code.trim()).ok_or_else(|| PaletteError::InvalidHex(code.into()))?;
+        Ok(Rgbx(r, g, b, palette::classify(&[r, g, b, 255])))
+    };
+    let mut colors = Vec::new();
+    let mut names: Vec<&str> = Vec::new();
+    for slot in SLOTS {
+        let code = wal
+            .colors
+            .get(slot)
+            .ok_or(PaletteError::MissingColor(slot))?;
+        colors.push(rgbx(code)?);
+        names.push(slot);
+    }
+    let mut roles = Vec::new();
+    for (role, name, code) in [
+        (Role::Background, "background", &wal.special.background),
+        (Role::Foreground, "foreground", &wal.special.foreground),
+    ] {
+        let color = rgbx(code)?;
+        let index = match colors
+            .iter()
+            .position(|c| c.rgba_array() == color.rgba_array())
+        {
+            Some(i) => i,
+            None => {
+                colors.push(color);
+                names.push(name);
+                colors.len() - 1
+            }
+        };
+        roles.push((role, index));
+    }
+    let palette = names
+        .into_iter()
+        .enumerate()
+        .fold(Palette::new(colors), |palette, (i, name)| {
+            palette.name_color(i, name)
+        });
+    Ok(roles
+        .into_iter()
+        .fold(palette, |palette, (role, i)| palette.assign(role, i)))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, PaletteError> {
+    from_json(&std::fs::read_to_string(path)?)
+}
+
+/// Where wal keeps the colors of the current theme: `$XDG_CACHE_HOME/wal/colors.json`, or
+/// `~/.cache/wal/colors.json`.
+pub fn cache_file() -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache.join("wal").join("colors.json"))
+}
+
+/// Loads the colors of wal's current theme, see [`cache_file`].
+pub fn load_cache() -> Result<Palette, PaletteError> {
+    let path = cache_file()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no cache directory"))?;
+    load(path)
+}
+
+/// Renders `palette` as a `colors.json`, with its colors assigned to terminal slots by
+/// [`TerminalScheme::from_palette`]. Palettes with background and foreground roles keep them.
+/// Returns `None` for an empty palette.
+pub fn to_json(palette: &Palette) -> Option<String> {
+    let mut scheme = TerminalScheme::from_palette(palette)?;
+    if let Some(bg) = palette.role(Role::Background) {
+        scheme.background = bg.rgba_array();
+    }
+    if let Some(fg) = palette.role(Role::Foreground) {
+        scheme.foreground = fg.rgba_array();
+    }
+    let hex = |c: &[u8; 4]| theme::hex(&[c[0], c[1], c[2], 255]);
+    let (bg, fg) = (hex(&scheme.background), hex(&scheme.foreground));
+    let mut out = String::from("{\n  \"wallpaper\": \"\",\n  \"alpha\": \"100\",\n");
+    writeln!(out, "  \"special\": {{").unwrap();
+    writeln!(out, "    \"background\": \"{bg}\",").unwrap();
+    writeln!(out, "    \"foreground\": \"{fg}\",").unwrap();
+    writeln!(out, "    \"cursor\": \"{fg}\"\n  }},\n  \"colors\": {{").unwrap();
+    for (i, c) in scheme.colors.iter().enumerate() {
+        let sep = if i < 15 { "," } else { "" };
+        writeln!(out, "    \"color{i}\": \"{}\"{sep}", hex(c)).unwrap();
+    }
+    out.push_str("  }\n}\n");
+    Some(out)
+}
+
+/// Writes [`to_json`] to `path`. An empty palette is an error, as it has no `color0`.
+pub fn save<P: AsRef<Path>>(palette: &Palette, path: P) -> Result<(), PaletteError> {
+    let json = to_json(palette).ok_or(PaletteError::MissingColor("color0"))?;
+    Ok(std::fs::write(path, json)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::{ColorClass, NORD};
+
+    #[test]
+    fn round_trip() {
+        let json = to_json(&Palette::nord()).unwrap();
+        assert!(json.contains("\"background\": \"#2e3440\""));
+        let wal = from_json(&json).unwrap();
+        assert_eq!(wal.color_name(1), Some("color1"));
+        assert_eq!(wal[1], NORD[7]);
+        assert_eq!(wal.role(Role::Background), Some(NORD[12]));
+        // Nord's foreground isn't the closest match for any terminal slot
+        assert_eq!(wal.len(), 17);
+        assert_eq!(wal.role(Role::Foreground), Some(NORD[0]));
+        assert_eq!(to_json(&Palette::default()), None);
+    }
+
+    #[test]
+    fn special_colors() {
+        let mut json = String::from(
+            r##"{"special": {"background": "#101010", "foreground": "#fafafa"}, "colors": {"##,
+        );
+        for i in 0..16 {
+            let code = if i == 1 { "#cc3333" } else { "#101010" };
+            write!(json, r#""color{i}": "{code}","#).unwrap();
+        }
+        json.push_str(r#""extra": "x"}}"#);
+        let wal = from_json(&json).unwrap();
+        assert_eq!(wal.len(), 17);
+        assert_eq!(wal[1].3, ColorClass::Red);
+        assert_eq!(wal.role(Role::Background), Some(wal[0]));
+        assert_eq!(wal.role(Role::Foreground), Some(wal[16]));
+        assert_eq!(wal.color_name(16), Some("foreground"));
+        assert!(matches!(
+            from_json(&json.replace("color7", "colour7")),
+            Err(PaletteError::MissingColor("color7"))
+        ));
+    }
+}
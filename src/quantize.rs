@@ -0,0 +1,571 @@
+//! Extracting palettes from images by color quantization, so the colors of one image can be
+//! mapped onto others.
+//!
+//! ```no_run
+//! use mapped::{quantize::MedianCut, ProcOptions};
+//!
+//! let source = ProcOptions::default().load("sunset.png")?;
+//! let palette = source.extract_palette(&MedianCut, 8);
+//! let opts = ProcOptions::default().palette(&palette);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use super::{
+    distance::{ColorDistance, Oklab},
+    palette::{self, Palette, Rgbx},
+};
+use ahash::AHashMap;
+use strum_macros::EnumString;
+
+#[cfg(feature = "io")]
+use super::{Mapper, Processor};
+
+/// Pixels more transparent than this are left out of the extracted palette.
+const MIN_ALPHA: u8 = 128;
+
+pub trait Quantizer {
+    /// At most `n` colors representing `pixels`, most common first, each classified with
+    /// [`classify`](palette::classify). Mostly transparent pixels are ignored.
+    fn quantize_iter(&self, pixels: impl Iterator<Item = [u8; 4]>, n: usize) -> Palette;
+
+    /// Like [`quantize_iter`](Quantizer::quantize_iter), for pixels already in memory.
+    fn quantize(&self, pixels: &[[u8; 4]], n: usize) -> Palette {
+        self.quantize_iter(pixels.iter().copied(), n)
+    }
+
+    /// Like [`quantize_iter`](Quantizer::quantize_iter), reading the image's pixels in place
+    /// rather than converting a copy of it to RGBA first.
+    #[cfg(feature = "io")]
+    fn extract(&self, image: &image::DynamicImage, n: usize) -> Palette {
+        use image::{DynamicImage, GenericImageView};
+        match image {
+            DynamicImage::ImageRgba8(buf) => self.quantize_iter(buf.pixels().map(|p| p.0), n),
+            DynamicImage::ImageRgb8(buf) => {
+                self.quantize_iter(buf.pixels().map(|&image::Rgb([r, g, b])| [r, g, b, 255]), n)
+            }
+            _ => self.quantize_iter(image.pixels().map(|(_, _, p)| p.0), n),
+        }
+    }
+}
+
+/// The quantizers with their default settings, for choosing one at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum QuantizerKind {
+    #[default]
+    #[strum(serialize = "median-cut", serialize = "mediancut")]
+    MedianCut,
+    #[strum(serialize = "k-means", serialize = "kmeans")]
+    KMeans,
+    Octree,
+}
+
+impl Quantizer for QuantizerKind {
+    fn quantize_iter(&self, pixels: impl Iterator<Item = [u8; 4]>, n: usize) -> Palette {
+        match self {
+            Self::MedianCut => MedianCut.quantize_iter(pixels, n),
+            Self::KMeans => KMeans::default().quantize_iter(pixels, n),
+            Self::Octree => Octree.quantize_iter(pixels, n),
+        }
+    }
+}
+
+/// Splits the color space into boxes until there are `n` of them, each time cutting the box with
+/// the largest squared error along its most spread out channel. The cut goes where it leaves the
+/// least error on both sides rather than at the plain median, which keeps clusters of similar
+/// colors together. Each box contributes its average color.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MedianCut;
+
+impl Quantizer for MedianCut {
+    fn quantize_iter(&self, pixels: impl Iterator<Item = [u8; 4]>, n: usize) -> Palette {
+        let mut boxes = vec![histogram(pixels)];
+        boxes.retain(|b| !b.is_empty() && n > 0);
+        while boxes.len() < n {
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.len() > 1)
+                .flat_map(|(i, b)| (0..3).map(move |c| (i, c, spread(b, c))))
+                .max_by(|a, b| a.2.total_cmp(&b.2));
+            let Some((i, channel, _)) = widest else {
+                break;
+            };
+            let mut colors = boxes.swap_remove(i);
+            colors.sort_unstable_by_key(|(px, _)| px[channel]);
+            let split = best_cut(&colors, channel);
+            let upper = colors.split_off(split);
+            boxes.extend([colors, upper]);
+        }
+        from_clusters(boxes.iter().map(|colors| {
+            let mut sum = [0usize; 3];
+            let mut count = 0;
+            for (px, n) in colors {
+                (0..3).for_each(|c| sum[c] += px[c] as usize * n);
+                count += n;
+            }
+            (sum.map(|s| ((s + count / 2) / count) as u8), count)
+        }))
+    }
+}
+
+// Squared error of a channel around its mean, weighted by pixel counts
+fn spread(colors: &[([u8; 3], usize)], channel: usize) -> f64 {
+    let sums = colors
+        .iter()
+        .fold(Sums::default(), |sums, c| sums.add(c, channel));
+    sums.error()
+}
+
+// Where to cut colors sorted along `channel` so the two sides have the least total error
+fn best_cut(colors: &[([u8; 3], usize)], channel: usize) -> usize {
+    let total = colors
+        .iter()
+        .fold(Sums::default(), |sums, c| sums.add(c, channel));
+    let mut lower = Sums::default();
+    let mut best = (1, f64::INFINITY);
+    for (k, color) in colors[..colors.len() - 1].iter().enumerate() {
+        lower = lower.add(color, channel);
+        let upper = Sums {
+            n: total.n - lower.n,
+            sum: total.sum - lower.sum,
+            squares: total.squares - lower.squares,
+        };
+        let error = lower.error() + upper.error();
+        if error < best.1 {
+            best = (k + 1, error);
+        }
+    }
+    best.0
+}
+
+#[derive(Clone, Copy, Default)]
+struct Sums {
+    n: f64,
+    sum: f64,
+    squares: f64,
+}
+
+impl Sums {
+    fn add(self, (px, count): &([u8; 3], usize), channel: usize) -> Self {
+        let (v, count) = (px[channel] as f64, *count as f64);
+        Sums {
+            n: self.n + count,
+            sum: self.sum + v * count,
+            squares: self.squares + v * v * count,
+        }
+    }
+
+    fn error(&self) -> f64 {
+        if self.n == 0. {
+            0.
+        } else {
+            self.squares - self.sum * self.sum / self.n
+        }
+    }
+}
+
+/// k-means clustering of the image's colors in OkLab, seeded with k-means++. Slower than
+/// [`MedianCut`], but its clusters follow the colors of the image more closely.
+#[derive(Debug, Clone)]
+pub struct KMeans {
+    iterations: usize,
+    seed: u64,
+}
+
+impl KMeans {
+    /// Maximum number of refinement passes, 20 by default. Stops early once no color changes
+    /// cluster.
+    #[must_use]
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Seed for the initial centroids, 0 by default.
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl Default for KMeans {
+    fn default() -> Self {
+        KMeans {
+            iterations: 20,
+            seed: 0,
+        }
+    }
+}
+
+impl Quantizer for KMeans {
+    fn quantize_iter(&self, pixels: impl Iterator<Item = [u8; 4]>, n: usize) -> Palette {
+        let colors: Vec<([f32; 3], f32)> = histogram(pixels)
+            .into_iter()
+            .map(|([r, g, b], count)| (palette::oklab(&[r, g, b, 255]), count as f32))
+            .collect();
+        let dist = |a: &[f32; 3], b: &[f32; 3]| Oklab.distance(a, b);
+        let nearest = |c: &[f32; 3], centroids: &[[f32; 3]]| {
+            centroids
+                .iter()
+                .map(|m| dist(m, c))
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or(0, |(i, _)| i)
+        };
+
+        // k-means++: each next centroid is drawn with a probability proportional to its pixel
+        // count and squared distance to the closest centroid so far
+        let rng = fastrand::Rng::with_seed(self.seed);
+        let k = n.min(colors.len());
+        if k == 0 {
+            return Palette::default();
+        }
+        let mut centroids: Vec<[f32; 3]> = Vec::with_capacity(k);
+        let mut weights: Vec<f32> = colors.iter().map(|c| c.1).collect();
+        while centroids.len() < k {
+            let total: f32 = weights.iter().sum();
+            let mut target = rng.f32() * total;
+            let pick = weights
+                .iter()
+                .position(|&w| {
+                    target -= w;
+                    target < 0.
+                })
+                .unwrap_or_else(|| weights.iter().rposition(|&w| w > 0.).unwrap_or(0));
+            let centroid = colors[pick].0;
+            centroids.push(centroid);
+            for (w, c) in weights.iter_mut().zip(&colors) {
+                let d = dist(&centroid, &c.0);
+                *w = match centroids.len() {
+                    1 => c.1 * d,
+                    _ => w.min(c.1 * d),
+                };
+            }
+        }
+
+        let mut clusters = vec![0; colors.len()];
+        for pass in 0..=self.iterations {
+            let mut changed = false;
+            for (cluster, c) in clusters.iter_mut().zip(&colors) {
+                let nearest = nearest(&c.0, &centroids);
+                changed |= *cluster != nearest;
+                *cluster = nearest;
+            }
+            if pass > 0 && !changed {
+                break;
+            }
+            let mut sums = vec![([0f32; 3], 0f32); k];
+            for (&cluster, (lab, count)) in clusters.iter().zip(&colors) {
+                let sum = &mut sums[cluster];
+                (0..3).for_each(|i| sum.0[i] += lab[i] * count);
+                sum.1 += count;
+            }
+            for (m, (sum, count)) in centroids.iter_mut().zip(sums) {
+                if count > 0. {
+                    *m = sum.map(|v| v / count);
+                }
+            }
+        }
+
+        let mut sizes = vec![0usize; k];
+        for (&cluster, (_, count)) in clusters.iter().zip(&colors) {
+            sizes[cluster] += *count as usize;
+        }
+        from_clusters(
+            centroids
+                .iter()
+                .zip(sizes)
+                .filter(|(_, size)| *size > 0)
+                .map(|(m, size)| {
+                    let [r, g, b, _] = palette::from_oklab(*m);
+                    ([r, g, b], size)
+                }),
+        )
+    }
+}
+
+/// Octree quantization: pixels are sorted into a tree of RGB octants, and the smallest of the
+/// deepest branches are merged into single colors until `n` are left. Pixels are added one at a
+/// time and the tree is kept to a few thousand colors while it is built, so it is the fastest
+/// option and uses little memory on very large images.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Octree;
+
+impl Quantizer for Octree {
+    fn quantize_iter(&self, pixels: impl Iterator<Item = [u8; 4]>, n: usize) -> Palette {
+        if n == 0 {
+            return Palette::default();
+        }
+        // Halved whenever it grows past the limit, so sorting branches is amortized
+        let limit = MAX_LEAVES.max(2 * n);
+        let mut tree = Tree::default();
+        for [r, g, b, a] in pixels {
+            if a >= MIN_ALPHA {
+                tree.insert([r, g, b]);
+                if tree.leaves > limit {
+                    tree.reduce(limit / 2);
+                }
+            }
+        }
+        tree.reduce(n);
+        from_clusters(
+            tree.nodes
+                .iter()
+                .filter(|node| node.leaf && node.count > 0)
+                .map(|node| {
+                    let count = node.count;
+                    let rgb = node.sum.map(|s| ((s + count / 2) / count) as u8);
+                    (rgb, count as usize)
+                }),
+        )
+    }
+}
+
+/// Colors an [`Octree`] keeps while it's being built, unless asked for more.
+const MAX_LEAVES: usize = 4096;
+
+#[derive(Clone, Default)]
+struct Node {
+    // Indices into the tree's nodes, 0 for none since the root is never a child
+    children: [u32; 8],
+    sum: [u64; 3],
+    count: u64,
+    leaf: bool,
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    // Reusable indices of merged nodes
+    free: Vec<u32>,
+    // Branches by depth, the root being the only one at depth 0
+    branches: [Vec<u32>; 8],
+    leaves: usize,
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Tree {
+            nodes: vec![Node::default()],
+            free: Vec::new(),
+            branches: std::array::from_fn(|depth| if depth == 0 { vec![0] } else { Vec::new() }),
+            leaves: 0,
+        }
+    }
+}
+
+impl Tree {
+    fn insert(&mut self, rgb: [u8; 3]) {
+        let mut node = 0;
+        for depth in 0..8 {
+            if self.nodes[node].leaf {
+                break;
+            }
+            self.nodes[node].count += 1;
+            let bit = |c: u8| ((c >> (7 - depth)) & 1) as usize;
+            let octant = bit(rgb[0]) << 2 | bit(rgb[1]) << 1 | bit(rgb[2]);
+            if self.nodes[node].children[octant] == 0 {
+                let leaf = depth == 7;
+                let child = self.alloc(leaf);
+                match leaf {
+                    true => self.leaves += 1,
+                    false => self.branches[depth + 1].push(child),
+                }
+                self.nodes[node].children[octant] = child;
+            }
+            node = self.nodes[node].children[octant] as usize;
+        }
+        let leaf = &mut self.nodes[node];
+        leaf.count += 1;
+        (0..3).for_each(|c| leaf.sum[c] += rgb[c] as u64);
+    }
+
+    fn alloc(&mut self, leaf: bool) -> u32 {
+        let node = Node {
+            leaf,
+            ..Node::default()
+        };
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i as usize] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            }
+        }
+    }
+
+    // Merges the smallest of the deepest branches into single colors until at most `leaves` are
+    // left
+    fn reduce(&mut self, leaves: usize) {
+        while self.leaves > leaves {
+            let Some(depth) = (0..8).rev().find(|&d| !self.branches[d].is_empty()) else {
+                return;
+            };
+            let mut branches = std::mem::take(&mut self.branches[depth]);
+            branches.sort_unstable_by_key(|&i| std::cmp::Reverse(self.nodes[i as usize].count));
+            while self.leaves > leaves {
+                match branches.pop() {
+                    Some(node) => self.merge(node as usize),
+                    None => break,
+                }
+            }
+            self.branches[depth] = branches;
+        }
+    }
+
+    fn merge(&mut self, node: usize) {
+        let children = std::mem::take(&mut self.nodes[node].children);
+        let mut sum = [0; 3];
+        for i in children.into_iter().filter(|&i| i != 0) {
+            let child = std::mem::take(&mut self.nodes[i as usize]);
+            (0..3).for_each(|c| sum[c] += child.sum[c]);
+            self.free.push(i);
+            self.leaves -= 1;
+        }
+        let node = &mut self.nodes[node];
+        node.sum = sum;
+        node.leaf = true;
+        self.leaves += 1;
+    }
+}
+
+// Distinct opaque colors with their pixel counts, in a stable order
+fn histogram(pixels: impl Iterator<Item = [u8; 4]>) -> Vec<([u8; 3], usize)> {
+    let mut counts: AHashMap<[u8; 3], usize> = AHashMap::new();
+    for [r, g, b, a] in pixels {
+        if a >= MIN_ALPHA {
+            *counts.entry([r, g, b]).or_default() += 1;
+        }
+    }
+    let mut colors: Vec<_> = counts.into_iter().collect();
+    colors.sort_unstable();
+    colors
+}
+
+// Builds the palette from cluster colors and their sizes, largest first
+fn from_clusters(clusters: impl Iterator<Item = ([u8; 3], usize)>) -> Palette {
+    let mut clusters: Vec<_> = clusters.collect();
+    clusters.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    clusters
+        .into_iter()
+        .map(|([r, g, b], _)| Rgbx(r, g, b, palette::classify(&[r, g, b, 255])))
+        .collect()
+}
+
+#[cfg(feature = "io")]
+impl<'a, M: Mapper> Processor<'a, M> {
+    /// Extracts a palette of at most `n` colors from the loaded image.
+    pub fn extract_palette<Q: Quantizer>(&self, quantizer: &Q, n: usize) -> Palette {
+        quantizer.extract(&self.data, n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{palette::ColorClass, rgbx};
+
+    // Thirty reds, blues and whites in three clusters of decreasing size, plus transparent greens
+    fn clusters() -> Vec<[u8; 4]> {
+        let mut pixels = Vec::new();
+        for i in 0..10 {
+            pixels.extend([[195 + i, 30, 30, 255]; 6]);
+            pixels.extend([[20, 20, 215 + i, 255]; 4]);
+            pixels.extend([[235 + i, 240, 240, 255]; 2]);
+        }
+        pixels.extend([[0, 255, 0, 0]; 100]);
+        pixels
+    }
+
+    #[test]
+    fn median_cut() {
+        let pixels = clusters();
+        let palette = MedianCut.quantize(&pixels, 3);
+        assert_eq!(
+            palette.colors(),
+            [
+                rgbx!(200, 30, 30, r),
+                rgbx!(20, 20, 220, b),
+                rgbx!(240, 240, 240, w)
+            ]
+        );
+        assert_eq!(MedianCut.quantize(&pixels, 100).len(), 30);
+        assert_eq!(MedianCut.quantize(&pixels, 1).len(), 1);
+        assert!(MedianCut.quantize(&pixels, 0).is_empty());
+        assert!(MedianCut.quantize(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn k_means() {
+        let pixels = clusters();
+        for seed in 0..4 {
+            let palette = KMeans::default().seed(seed).quantize(&pixels, 3);
+            let classes: Vec<_> = palette.iter().map(|c| c.3).collect();
+            assert_eq!(
+                classes,
+                [ColorClass::Red, ColorClass::Blues, ColorClass::Whites],
+                "{seed}"
+            );
+            assert!(palette[0].euclidian_dist(&[200, 30, 30, 255]) < 2.);
+        }
+        assert_eq!(KMeans::default().quantize(&pixels, 100).len(), 30);
+        assert!(KMeans::default().quantize(&pixels, 0).is_empty());
+        assert!(KMeans::default().quantize(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn octree() {
+        let pixels = clusters();
+        let palette = Octree.quantize(&pixels, 3);
+        let classes: Vec<_> = palette.iter().map(|c| c.3).collect();
+        assert_eq!(
+            classes,
+            [ColorClass::Red, ColorClass::Blues, ColorClass::Whites]
+        );
+        assert_eq!(Octree.quantize(&pixels, 100).len(), 30);
+        assert_eq!(Octree.quantize(&pixels, 1).len(), 1);
+        assert!(Octree.quantize(&pixels, 0).is_empty());
+        assert!(Octree.quantize(&[], 4).is_empty());
+
+        // Reduced while being built
+        let gradient: Vec<[u8; 4]> = (0..1 << 13)
+            .map(|i: u32| [(i >> 5) as u8, (i as u8) << 3, (i >> 1) as u8, 255])
+            .collect();
+        let palette = Octree.quantize(&gradient, 16);
+        assert!((9..=16).contains(&palette.len()), "{}", palette.len());
+    }
+
+    #[test]
+    fn quantizer_kinds() {
+        assert_eq!("Median-Cut".parse(), Ok(QuantizerKind::MedianCut));
+        assert_eq!("kmeans".parse(), Ok(QuantizerKind::KMeans));
+        assert_eq!("octree".parse(), Ok(QuantizerKind::Octree));
+        let pixels = [[10, 10, 10, 255], [250, 250, 250, 255]];
+        assert_eq!(
+            QuantizerKind::Octree.quantize(&pixels, 4),
+            Octree.quantize(&pixels, 4)
+        );
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn extract_in_place() {
+        use image::{DynamicImage, RgbaImage};
+
+        let pixels = clusters();
+        let raw = pixels.iter().flatten().copied().collect();
+        let rgba = DynamicImage::ImageRgba8(RgbaImage::from_raw(20, 11, raw).unwrap());
+        let expected = MedianCut.quantize(&pixels, 3);
+        assert_eq!(MedianCut.extract(&rgba, 3), expected);
+        assert_eq!(MedianCut.extract(&rgba.to_rgb16().into(), 3).len(), 3);
+        // Without the transparent pixels, the other layouts read the same colors
+        let opaque = rgba.crop_imm(0, 0, 20, 6);
+        let expected = MedianCut.quantize(&pixels[..120], 3);
+        assert_eq!(MedianCut.extract(&opaque.to_rgb8().into(), 3), expected);
+        assert_eq!(MedianCut.extract(&opaque.to_rgb32f().into(), 3), expected);
+    }
+}
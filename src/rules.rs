@@ -0,0 +1,199 @@
+//! Data-driven mapping rules.
+//!
+//! A [`RuleMapper`] is loaded from JSON or TOML. Each rule matches inclusive RGB ranges and sends
+//! matching pixels either to a palette entry or to a literal color. Rules are checked in order and
+//! the first match wins; pixels matching no rule are left as is.
+//!
+//! ```toml
+//! [[rules]]
+//! red = [100, 255]
+//! green = [0, 0]
+//! blue = [0, 0]
+//! palette = 8
+//!
+//! [[rules]]
+//! red = [185, 255]
+//! green = [0, 68]
+//! blue = [0, 68]
+//! color = [191, 97, 106]
+//! ```
+
+use super::{
+    mappers::{ManualMap, Target},
+    palette::Rgbx,
+    Mapper,
+};
+use serde::Deserialize;
+use std::{error::Error, fmt, path::Path};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default = "any")]
+    pub red: [u8; 2],
+    #[serde(default = "any")]
+    pub green: [u8; 2],
+    #[serde(default = "any")]
+    pub blue: [u8; 2],
+    /// Index of the palette entry matching pixels map to. Out of range indices leave the pixel
+    /// as is.
+    #[serde(default)]
+    pub palette: Option<usize>,
+    #[serde(default)]
+    pub color: Option<[u8; 3]>,
+}
+
+fn any() -> [u8; 2] {
+    [0, 255]
+}
+
+impl Rule {
+    fn validate(&self, index: usize) -> Result<(), RuleError> {
+        match (self.palette, self.color) {
+            (Some(_), Some(_)) => return Err(RuleError::AmbiguousTarget(index)),
+            (None, None) => return Err(RuleError::MissingTarget(index)),
+            _ => {}
+        }
+        if [self.red, self.green, self.blue]
+            .iter()
+            .any(|[min, max]| min > max)
+        {
+            return Err(RuleError::EmptyRange(index));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum RuleError {
+    MissingTarget(usize),
+    AmbiguousTarget(usize),
+    EmptyRange(usize),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingTarget(i) => write!(f, "rule {i} has neither a palette index nor a color"),
+            Self::AmbiguousTarget(i) => write!(f, "rule {i} has both a palette index and a color"),
+            Self::EmptyRange(i) => {
+                write!(f, "rule {i} has a range whose minimum exceeds its maximum")
+            }
+        }
+    }
+}
+
+impl Error for RuleError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleMapper {
+    rules: Vec<Rule>,
+    map: ManualMap,
+}
+
+impl RuleMapper {
+    pub fn new(rules: Vec<Rule>) -> Result<Self, RuleError> {
+        let mut map = ManualMap::builder();
+        for (i, rule) in rules.iter().enumerate() {
+            rule.validate(i)?;
+            let target = match (rule.palette, rule.color) {
+                (Some(i), _) => Target::Palette(i),
+                (_, Some([r, g, b])) => Target::Color([r, g, b, 255]),
+                (None, None) => unreachable!(),
+            };
+            let [r, g, b] = [rule.red, rule.green, rule.blue].map(|[min, max]| min..=max);
+            map = map.range(r, g, b, target);
+        }
+        Ok(RuleMapper {
+            rules,
+            map: map.build(),
+        })
+    }
+
+    pub fn from_json(spec: &str) -> Result<Self, Box<dyn Error + 'static>> {
+        let doc: Document = serde_json::from_str(spec)?;
+        Ok(Self::new(doc.rules)?)
+    }
+
+    pub fn from_toml(spec: &str) -> Result<Self, Box<dyn Error + 'static>> {
+        let doc: Document = toml::from_str(spec)?;
+        Ok(Self::new(doc.rules)?)
+    }
+
+    /// Picks the parser from the file extension, defaulting to JSON.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'static>> {
+        let spec = std::fs::read_to_string(path.as_ref())?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&spec),
+            _ => Self::from_json(&spec),
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+}
+
+impl Mapper for RuleMapper {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.map.predict(palette, pixel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mappers::ManualMap, palette::NORD};
+
+    #[test]
+    fn matches_manual_map() {
+        let toml = RuleMapper::from_toml(
+            r#"
+            [[rules]]
+            red = [100, 255]
+            green = [0, 0]
+            blue = [0, 0]
+            palette = 8
+
+            [[rules]]
+            red = [185, 255]
+            green = [0, 68]
+            blue = [0, 68]
+            palette = 8
+            "#,
+        )
+        .unwrap();
+        let json = RuleMapper::from_json(
+            r#"{ "rules": [
+                { "red": [100, 255], "green": [0, 0], "blue": [0, 0], "palette": 8 },
+                { "red": [185, 255], "green": [0, 68], "blue": [0, 68], "palette": 8 }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(toml.rules(), json.rules());
+        for px in [[150, 0, 0, 255], [200, 50, 10, 255], [20, 200, 20, 255]] {
+            assert_eq!(
+                toml.predict(&NORD, &px),
+                ManualMap::default().predict(&NORD, &px)
+            );
+        }
+    }
+
+    #[test]
+    fn literal_colors_and_validation() {
+        let rules =
+            RuleMapper::from_json(r#"{ "rules": [{ "green": [200, 255], "color": [1, 2, 3] }] }"#)
+                .unwrap();
+        assert_eq!(rules.predict(&NORD, &[0, 220, 0, 255]), [1, 2, 3, 255]);
+        assert!(RuleMapper::from_json(r#"{ "rules": [{ "red": [0, 10] }] }"#).is_err());
+        assert!(
+            RuleMapper::from_json(r#"{ "rules": [{ "red": [10, 0], "palette": 1 }] }"#).is_err()
+        );
+    }
+}
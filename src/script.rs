@@ -0,0 +1,223 @@
+//! Mappers defined as [Rhai](https://rhai.rs) scripts.
+//!
+//! A script must define a `predict(pixel, palette)` function. `pixel` is an `[r, g, b, a]` array
+//! and `palette` an array of colors exposing `r`, `g`, `b` and `class` properties. The function
+//! may return a palette index, one of the palette colors, an `[r, g, b]`/`[r, g, b, a]` array, or
+//! nothing to leave the pixel unchanged. The helpers `manhattan(color, pixel)`,
+//! `euclidean(color, pixel)` and `nearest(palette, pixel)` are available to scripts, the last one
+//! returning nothing for an empty palette.
+//!
+//! Pixels the script fails on are left unchanged, with the error kept for
+//! [`ScriptMapper::last_error`]. [`ScriptMapper::try_predict`] reports it directly instead.
+//!
+//! ```rhai
+//! fn predict(pixel, palette) {
+//!     if pixel[0] > 185 && pixel[1] < 68 && pixel[2] < 68 {
+//!         return 8;
+//!     }
+//!     nearest(palette, pixel)
+//! }
+//! ```
+//!
+//! Every prediction calls into the interpreter, so wrapping the mapper with
+//! [`Mapper::memoized`] is usually worthwhile.
+
+use super::{palette::Rgbx, Mapper};
+use rhai::{Array, Dynamic, Engine, Scope, AST};
+use std::{
+    error::Error,
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    MissingPredict,
+    /// The script failed while running, with the interpreter's message.
+    Runtime(String),
+    /// The script returned an index past the end of the palette, or a negative one.
+    InvalidIndex(i64),
+    UnsupportedValue,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPredict => {
+                write!(f, "script does not define `fn predict(pixel, palette)`")
+            }
+            Self::Runtime(e) => write!(f, "script mapper failed: {e}"),
+            Self::InvalidIndex(i) => write!(f, "script mapper returned invalid palette index {i}"),
+            Self::UnsupportedValue => write!(f, "script mapper returned an unsupported value"),
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+#[derive(Clone)]
+pub struct ScriptMapper {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    last_error: Arc<Mutex<Option<ScriptError>>>,
+}
+
+impl ScriptMapper {
+    pub fn new(source: &str) -> Result<Self, Box<dyn Error + 'static>> {
+        let engine = Self::engine();
+        let ast = engine.compile(source)?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "predict" && f.params.len() == 2)
+        {
+            return Err(ScriptError::MissingPredict.into());
+        }
+
+        Ok(ScriptMapper {
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            last_error: Arc::default(),
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'static>> {
+        Self::new(&std::fs::read_to_string(path)?)
+    }
+
+    /// The most recent error of a prediction through [`Mapper::predict`], shared between clones.
+    pub fn last_error(&self) -> Option<ScriptError> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Runs the script on `pixel`, reporting failures instead of leaving the pixel unchanged.
+    pub fn try_predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> Result<[u8; 4], ScriptError> {
+        let pal: Array = palette.iter().map(|c| Dynamic::from(*c)).collect();
+        let px: Array = pixel.iter().map(|c| Dynamic::from(*c as i64)).collect();
+        let out = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, "predict", (px, pal))
+            .map_err(|e| ScriptError::Runtime(e.to_string()))?;
+
+        if out.is_unit() {
+            Ok(*pixel)
+        } else if let Some(i) = out.clone().try_cast::<i64>() {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| palette.get(i))
+                .map(Rgbx::rgba_array)
+                .ok_or(ScriptError::InvalidIndex(i))
+        } else if let Some(c) = out.clone().try_cast::<Rgbx>() {
+            Ok(c.rgba_array())
+        } else if let Some(arr) = out.try_cast::<Array>() {
+            Ok(channels(&arr))
+        } else {
+            Err(ScriptError::UnsupportedValue)
+        }
+    }
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .register_type_with_name::<Rgbx>("Color")
+            .register_get("r", |c: &mut Rgbx| c.0 as i64)
+            .register_get("g", |c: &mut Rgbx| c.1 as i64)
+            .register_get("b", |c: &mut Rgbx| c.2 as i64)
+            .register_get("class", |c: &mut Rgbx| format!("{:?}", c.3))
+            .register_fn("manhattan", |c: &mut Rgbx, px: Array| {
+                c.manhattan_dist(&channels(&px)) as i64
+            })
+            .register_fn("euclidean", |c: &mut Rgbx, px: Array| {
+                c.euclidian_dist(&channels(&px)) as f64
+            })
+            .register_fn("nearest", |palette: Array, px: Array| {
+                let px = channels(&px);
+                palette
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| Some((i, c.clone().try_cast::<Rgbx>()?)))
+                    .min_by_key(|(_, c)| c.manhattan_dist(&px))
+                    .map_or(Dynamic::UNIT, |(i, _)| Dynamic::from(i as i64))
+            });
+        engine
+    }
+}
+
+impl fmt::Debug for ScriptMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptMapper").finish_non_exhaustive()
+    }
+}
+
+impl Mapper for ScriptMapper {
+    fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+        self.try_predict(palette, pixel).unwrap_or_else(|e| {
+            *self.last_error.lock().unwrap() = Some(e);
+            *pixel
+        })
+    }
+}
+
+fn channels(arr: &Array) -> [u8; 4] {
+    let get = |i: usize, default: i64| {
+        arr.get(i)
+            .and_then(|v| v.as_int().ok())
+            .unwrap_or(default)
+            .clamp(0, 255) as u8
+    };
+    [get(0, 0), get(1, 0), get(2, 0), get(3, 255)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::NORD;
+
+    #[test]
+    fn nearest_matches_builtin() {
+        let script =
+            ScriptMapper::new("fn predict(pixel, palette) { nearest(palette, pixel) }").unwrap();
+        let px = [200, 40, 40, 255];
+        assert_eq!(
+            script.predict(&NORD, &px),
+            crate::mappers::Nearest::new().predict(&NORD, &px)
+        );
+    }
+
+    #[test]
+    fn missing_predict() {
+        assert!(ScriptMapper::new("fn other(x) { x }").is_err());
+    }
+
+    #[test]
+    fn failures_keep_pixel() {
+        let px = [200, 40, 40, 255];
+        let script =
+            ScriptMapper::new("fn predict(pixel, palette) { nearest(palette, pixel) }").unwrap();
+        assert_eq!(script.predict(&[], &px), px);
+        assert_eq!(script.last_error(), None);
+
+        for (source, err) in [
+            (
+                "fn predict(pixel, palette) { 99 }",
+                ScriptError::InvalidIndex(99),
+            ),
+            (
+                "fn predict(pixel, palette) { -1 }",
+                ScriptError::InvalidIndex(-1),
+            ),
+            (
+                "fn predict(pixel, palette) { 1.5 }",
+                ScriptError::UnsupportedValue,
+            ),
+        ] {
+            let script = ScriptMapper::new(source).unwrap();
+            assert_eq!(script.predict(&NORD, &px), px);
+            assert_eq!(script.last_error(), Some(err.clone()));
+            assert_eq!(script.try_predict(&NORD, &px), Err(err));
+        }
+        let script = ScriptMapper::new("fn predict(pixel, palette) { pixel[9] }").unwrap();
+        assert_eq!(script.clone().predict(&NORD, &px), px);
+        assert!(matches!(script.last_error(), Some(ScriptError::Runtime(_))));
+    }
+}
@@ -0,0 +1,256 @@
+//! An in-process job queue for services built around the crate: jobs are submitted with an
+//! image source and their options, get a [`JobId`], and are processed in order by a pool of
+//! worker threads while their progress can be polled.
+//!
+//! Options own their palette when it is passed by value, so palettes loaded at runtime can be
+//! submitted as they are:
+//!
+//! ```no_run
+//! use mapped::{palette::Palette, service::{Service, Source}, ProcOptions};
+//!
+//! let service: Service = Service::new(4);
+//! let palette = Palette::load_gpl("theme.gpl")?;
+//! let job = service.submit(
+//!     Source::Path("wallpaper.png".into()),
+//!     ProcOptions::default().palette(palette),
+//! );
+//! let wallpaper = service.wait(job).unwrap()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use super::{mappers::Nearest, Mapper, ProcOptions, ProcessedData, Tracker};
+use image::DynamicImage;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JobId(u64);
+
+pub enum Source {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+    Image(DynamicImage),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running(f32),
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// The image could not be loaded.
+    Load(String),
+    /// Processing panicked, e.g. in the mapper. The worker survives and moves on to the next job.
+    Panicked(String),
+}
+
+impl JobError {
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Load(msg) | Self::Panicked(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(msg) => write!(f, "job failed: {msg}"),
+            Self::Panicked(msg) => write!(f, "job panicked: {msg}"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+enum JobState {
+    Queued,
+    Running(Option<Tracker>),
+    Finished(Result<ProcessedData, JobError>),
+}
+
+struct Job<M: Mapper> {
+    id: JobId,
+    source: Source,
+    opts: ProcOptions<'static, M>,
+}
+
+struct Shared<M: Mapper> {
+    queue: Mutex<VecDeque<Job<M>>>,
+    queued: Condvar,
+    jobs: Mutex<HashMap<JobId, JobState>>,
+    finished: Condvar,
+    next_id: AtomicU64,
+    shutdown: AtomicBool,
+}
+
+/// A pool of worker threads processing submitted jobs in FIFO order.
+pub struct Service<M: Mapper + 'static = Nearest> {
+    shared: Arc<Shared<M>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<M: Mapper + 'static> Service<M> {
+    pub fn new(workers: usize) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            queued: Condvar::new(),
+            jobs: Mutex::new(HashMap::new()),
+            finished: Condvar::new(),
+            next_id: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || Self::work(&shared))
+            })
+            .collect();
+
+        Service { shared, workers }
+    }
+
+    /// Queues a job. The options have to own their palette, see
+    /// [`ProcOptions::into_owned`] for options that borrow one.
+    pub fn submit(&self, source: Source, opts: ProcOptions<'static, M>) -> JobId {
+        let id = JobId(self.shared.next_id.fetch_add(1, Ordering::Relaxed));
+        self.shared
+            .jobs
+            .lock()
+            .unwrap()
+            .insert(id, JobState::Queued);
+        self.shared
+            .queue
+            .lock()
+            .unwrap()
+            .push_back(Job { id, source, opts });
+        self.shared.queued.notify_one();
+        id
+    }
+
+    /// Returns `None` for unknown job IDs, including jobs whose results were already taken.
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        Some(match jobs.get_mut(&id)? {
+            JobState::Queued => JobStatus::Queued,
+            JobState::Running(Some(tracker)) => JobStatus::Running(tracker.percentage()),
+            JobState::Running(None) => JobStatus::Running(0.0),
+            JobState::Finished(Ok(_)) => JobStatus::Done,
+            JobState::Finished(Err(e)) => JobStatus::Failed(e.message().to_owned()),
+        })
+    }
+
+    /// Removes and returns the result of a finished job without blocking.
+    pub fn take(&self, id: JobId) -> Option<Result<ProcessedData, JobError>> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        match jobs.get(&id)? {
+            JobState::Finished(_) => match jobs.remove(&id) {
+                Some(JobState::Finished(res)) => Some(res),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Blocks until the job has finished, then removes and returns its result.
+    pub fn wait(&self, id: JobId) -> Option<Result<ProcessedData, JobError>> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        loop {
+            match jobs.get(&id)? {
+                JobState::Finished(_) => match jobs.remove(&id) {
+                    Some(JobState::Finished(res)) => return Some(res),
+                    _ => unreachable!(),
+                },
+                _ => jobs = self.shared.finished.wait(jobs).unwrap(),
+            }
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().len()
+    }
+
+    fn work(shared: &Shared<M>) {
+        loop {
+            let job = {
+                let mut queue = shared.queue.lock().unwrap();
+                loop {
+                    if shared.shutdown.load(Ordering::Acquire) {
+                        return;
+                    }
+                    if let Some(job) = queue.pop_front() {
+                        break job;
+                    }
+                    queue = shared.queued.wait(queue).unwrap();
+                }
+            };
+            let Job { id, source, opts } = job;
+            Self::set(shared, id, JobState::Running(None));
+
+            // A panicking job must not take the worker down with it, or `wait` would never return
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let loaded = match source {
+                    Source::Path(path) => opts.load(path),
+                    Source::Bytes(bytes) => opts.load_bytes(&bytes),
+                    Source::Image(img) => opts.try_load_image(img).map_err(Into::into),
+                };
+                match loaded {
+                    Ok(mut processor) => {
+                        Self::set(shared, id, JobState::Running(Some(processor.gen_tracker())));
+                        Ok(processor.process())
+                    }
+                    Err(e) => Err(JobError::Load(e.to_string())),
+                }
+            }))
+            .unwrap_or_else(|payload| {
+                let msg = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_owned());
+                Err(JobError::Panicked(msg))
+            });
+            Self::set(shared, id, JobState::Finished(result));
+            shared.finished.notify_all();
+        }
+    }
+
+    fn set(shared: &Shared<M>, id: JobId, state: JobState) {
+        shared.jobs.lock().unwrap().insert(id, state);
+    }
+}
+
+impl<M: Mapper + 'static> Default for Service<M> {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl<M: Mapper + 'static> Drop for Service<M> {
+    /// Lets running jobs finish, drops queued ones and joins the worker threads.
+    fn drop(&mut self) {
+        {
+            // Holding the queue lock prevents a worker from missing the wakeup below
+            let _queue = self.shared.queue.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::Release);
+        }
+        self.shared.queued.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
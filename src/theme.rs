@@ -0,0 +1,630 @@
+//! Exporting mapped colors for use in web themes and terminal colorschemes, importing terminal
+//! colorschemes as palettes, and deriving base16 schemes from images.
+
+use super::{
+    palette::{self, ColorClass, Palette, PaletteError, Rgbx, Role},
+    quantize::{KMeans, Quantizer},
+};
+use std::{fmt::Write, path::Path};
+use strum_macros::EnumString;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ThemeFormat {
+    /// `:root` custom properties, `--color-0: #rrggbb;`
+    Css,
+    /// `$color-0: #rrggbb;`
+    Scss,
+    /// A design token file, `{"color-0": {"value": "#rrggbb", "usage": 0.42}}`
+    Json,
+}
+
+pub(crate) fn hex(c: &[u8; 4]) -> String {
+    match c[3] {
+        255 => format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]),
+        a => format!("#{:02x}{:02x}{:02x}{a:02x}", c[0], c[1], c[2]),
+    }
+}
+
+/// Renders `colors` as variables named `color-0`, `color-1`, ... in the given order. The usage of
+/// each color is its pixel count, reported as a share of the total in JSON and as comments
+/// otherwise.
+pub fn export(colors: &[([u8; 4], usize)], format: ThemeFormat) -> String {
+    let total = colors.iter().map(|(_, n)| n).sum::<usize>().max(1) as f32;
+    let mut out = String::new();
+    match format {
+        ThemeFormat::Css | ThemeFormat::Scss => {
+            let (open, prefix, indent) = match format {
+                ThemeFormat::Css => (true, "--", "  "),
+                _ => (false, "$", ""),
+            };
+            if open {
+                out.push_str(":root {\n");
+            }
+            for (i, (c, n)) in colors.iter().enumerate() {
+                let share = *n as f32 / total * 100.;
+                writeln!(
+                    out,
+                    "{indent}{prefix}color-{i}: {}; /* {share:.1}% */",
+                    hex(c)
+                )
+                .unwrap();
+            }
+            if open {
+                out.push_str("}\n");
+            }
+        }
+        ThemeFormat::Json => {
+            out.push_str("{\n");
+            for (i, (c, n)) in colors.iter().enumerate() {
+                let sep = if i + 1 < colors.len() { "," } else { "" };
+                let usage = *n as f32 / total;
+                writeln!(
+                    out,
+                    "  \"color-{i}\": {{ \"value\": \"{}\", \"usage\": {usage:.4} }}{sep}",
+                    hex(c)
+                )
+                .unwrap();
+            }
+            out.push_str("}\n");
+        }
+    }
+    out
+}
+
+#[cfg(feature = "io")]
+impl crate::ProcessedData {
+    /// Exports the colors present in the output, most used first.
+    pub fn export_theme(&self, format: ThemeFormat) -> String {
+        export(&self.used_colors(), format)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum TerminalFormat {
+    Alacritty,
+    Kitty,
+    Foot,
+    Xresources,
+}
+
+// xterm's default ANSI colors, used as targets when picking palette colors for each slot
+const ANSI: [[u8; 4]; 16] = [
+    [0, 0, 0, 255],
+    [205, 0, 0, 255],
+    [0, 205, 0, 255],
+    [205, 205, 0, 255],
+    [0, 0, 238, 255],
+    [205, 0, 205, 255],
+    [0, 205, 205, 255],
+    [229, 229, 229, 255],
+    [127, 127, 127, 255],
+    [255, 0, 0, 255],
+    [0, 255, 0, 255],
+    [255, 255, 0, 255],
+    [92, 92, 255, 255],
+    [255, 0, 255, 255],
+    [0, 255, 255, 255],
+    [255, 255, 255, 255],
+];
+
+const ANSI_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// Canonical names of the colors a scheme needs, in the order of `TerminalScheme::slots`
+const SLOTS: [&str; 18] = [
+    "color0",
+    "color1",
+    "color2",
+    "color3",
+    "color4",
+    "color5",
+    "color6",
+    "color7",
+    "color8",
+    "color9",
+    "color10",
+    "color11",
+    "color12",
+    "color13",
+    "color14",
+    "color15",
+    "background",
+    "foreground",
+];
+
+/// A 16 color terminal scheme with background and foreground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalScheme {
+    pub background: [u8; 4],
+    pub foreground: [u8; 4],
+    /// ANSI colors 0-15, normal then bright.
+    pub colors: [[u8; 4]; 16],
+}
+
+impl TerminalScheme {
+    /// Fills each ANSI slot with the palette color closest to xterm's default for it. The darkest
+    /// palette color becomes the background and the lightest the foreground. Returns `None` for an
+    /// empty palette.
+    pub fn from_palette(palette: &[Rgbx]) -> Option<Self> {
+        let luma = |c: &&Rgbx| c.luma();
+        let background = palette.iter().min_by(|a, b| luma(a).total_cmp(&luma(b)))?;
+        let foreground = palette.iter().max_by(|a, b| luma(a).total_cmp(&luma(b)))?;
+        let colors = ANSI.map(|target| {
+            palette
+                .iter()
+                .min_by(|a, b| {
+                    a.euclidian_dist(&target)
+                        .total_cmp(&b.euclidian_dist(&target))
+                })
+                .unwrap()
+                .rgba_array()
+        });
+
+        Some(TerminalScheme {
+            background: background.rgba_array(),
+            foreground: foreground.rgba_array(),
+            colors,
+        })
+    }
+
+    /// Reads a scheme in any [`TerminalFormat`], the inverse of [`TerminalScheme::export`]. Besides
+    /// the exported layout, this accepts Alacritty's older YAML configs, `0x` prefixed colors and
+    /// `#define`d colors in Xresources, so colors can be taken straight from a terminal's config.
+    /// Lines that aren't colors are skipped.
+    pub fn parse(text: &str, format: TerminalFormat) -> Result<Self, PaletteError> {
+        let mut slots = [None; 18];
+        let mut section = "";
+        let mut defines = Vec::new();
+        for line in text.lines().map(str::trim) {
+            let (key, value) = match format {
+                TerminalFormat::Alacritty | TerminalFormat::Foot => {
+                    if let Some(header) = line.strip_prefix('[') {
+                        section = header.trim_end_matches(']').trim_start_matches("colors.");
+                        continue;
+                    }
+                    match line.split_once(['=', ':']) {
+                        // A YAML section, like `normal:`
+                        Some((key, value)) if value.trim().is_empty() => {
+                            section = key.trim();
+                            continue;
+                        }
+                        Some(pair) => pair,
+                        None => continue,
+                    }
+                }
+                TerminalFormat::Kitty => match line.split_once(char::is_whitespace) {
+                    Some(pair) if !line.starts_with('#') => pair,
+                    _ => continue,
+                },
+                TerminalFormat::Xresources => {
+                    if let Some(define) = line.strip_prefix("#define") {
+                        if let Some((name, value)) = define.trim().split_once(char::is_whitespace) {
+                            defines.push((name, value.trim()));
+                        }
+                        continue;
+                    }
+                    match line.split_once(':') {
+                        // Resource names like `*.color0` or `URxvt*background`
+                        Some((key, value)) if !line.starts_with('!') => {
+                            (key.rsplit(['*', '.']).next().unwrap_or(key), value)
+                        }
+                        _ => continue,
+                    }
+                }
+            };
+            let value = value.trim();
+            // Quoted, or up to a trailing comment
+            let value = match value.strip_prefix(['\'', '"']) {
+                Some(quoted) => quoted.split(['\'', '"']).next().unwrap_or_default(),
+                None => value.split_whitespace().next().unwrap_or_default(),
+            };
+            let value = defines
+                .iter()
+                .find(|(name, _)| *name == value)
+                .map_or(value, |(_, v)| v);
+            let ansi = |i: Option<usize>, offset| i.filter(|i| *i < 8).map(|i| i + offset);
+            let slot = match (format, key.trim()) {
+                (TerminalFormat::Alacritty, key) => match (section, key) {
+                    ("primary", "background") => Some(16),
+                    ("primary", "foreground") => Some(17),
+                    ("normal", name) => ansi(ANSI_NAMES.iter().position(|n| *n == name), 0),
+                    ("bright", name) => ansi(ANSI_NAMES.iter().position(|n| *n == name), 8),
+                    _ => None,
+                },
+                (_, "background") => Some(16),
+                (_, "foreground") => Some(17),
+                (TerminalFormat::Foot, key) => {
+                    match (key.strip_prefix("regular"), key.strip_prefix("bright")) {
+                        (Some(i), _) => ansi(i.parse().ok(), 0),
+                        (_, Some(i)) => ansi(i.parse().ok(), 8),
+                        _ => None,
+                    }
+                }
+                (_, key) => key
+                    .strip_prefix("color")
+                    .and_then(|i| i.parse().ok())
+                    .filter(|i| *i < 16),
+            };
+            let code = value.strip_prefix("0x").unwrap_or(value);
+            if let (Some(slot), Some([r, g, b])) = (slot, palette::parse_hex(code)) {
+                slots[slot] = Some([r, g, b, 255]);
+            }
+        }
+        let mut colors = [[0; 4]; 18];
+        for (i, slot) in slots.into_iter().enumerate() {
+            colors[i] = slot.ok_or(PaletteError::MissingColor(SLOTS[i]))?;
+        }
+        Ok(TerminalScheme {
+            background: colors[16],
+            foreground: colors[17],
+            colors: colors[..16].try_into().unwrap(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, format: TerminalFormat) -> Result<Self, PaletteError> {
+        Self::parse(&std::fs::read_to_string(path)?, format)
+    }
+
+    /// The sixteen colors, named after their ANSI slots (`red`, `bright red`, ...) and classed
+    /// by them, followed by the background and foreground unless they're among the sixteen.
+    /// Black and white slots, the background and the foreground are
+    /// [`Whites`](ColorClass::Whites) or [`Greys`](ColorClass::Greys) by brightness.
+    pub fn to_palette(&self) -> Palette {
+        use ColorClass::*;
+        const HUES: [Option<ColorClass>; 8] = [
+            None,
+            Some(Red),
+            Some(Green),
+            Some(Yellow),
+            Some(Blues),
+            Some(Magenta),
+            Some(Cyan),
+            None,
+        ];
+
+        let rgbx = |c: &[u8; 4], class: Option<ColorClass>| {
+            let shade = if palette::luma(c) >= 0.5 {
+                Whites
+            } else {
+                Greys
+            };
+            Rgbx(c[0], c[1], c[2], class.unwrap_or(shade))
+        };
+        let mut colors: Vec<Rgbx> = (0..16)
+            .map(|i| rgbx(&self.colors[i], HUES[i % 8]))
+            .collect();
+        let mut names: Vec<String> = (0..16)
+            .map(|i| match i {
+                0..=7 => ANSI_NAMES[i].to_string(),
+                _ => format!("bright {}", ANSI_NAMES[i - 8]),
+            })
+            .collect();
+        let mut roles = Vec::new();
+        for (role, name, c) in [
+            (Role::Background, "background", &self.background),
+            (Role::Foreground, "foreground", &self.foreground),
+        ] {
+            let index = self.colors.iter().position(|s| s == c).unwrap_or_else(|| {
+                colors.push(rgbx(c, None));
+                names.push(name.into());
+                colors.len() - 1
+            });
+            roles.push((role, index));
+        }
+        let palette = names
+            .into_iter()
+            .enumerate()
+            .fold(Palette::new(colors), |palette, (i, name)| {
+                palette.name_color(i, name)
+            });
+        roles
+            .into_iter()
+            .fold(palette, |palette, (role, i)| palette.assign(role, i))
+    }
+
+    pub fn export(&self, format: TerminalFormat) -> String {
+        let mut out = String::new();
+        let (bg, fg) = (rgb_hex(&self.background), rgb_hex(&self.foreground));
+        let (normal, bright) = self.colors.split_at(8);
+        match format {
+            TerminalFormat::Alacritty => {
+                writeln!(out, "[colors.primary]").unwrap();
+                writeln!(out, "background = '{bg}'\nforeground = '{fg}'").unwrap();
+                for (table, colors) in [("normal", normal), ("bright", bright)] {
+                    writeln!(out, "\n[colors.{table}]").unwrap();
+                    for (name, c) in ANSI_NAMES.iter().zip(colors) {
+                        writeln!(out, "{name} = '{}'", rgb_hex(c)).unwrap();
+                    }
+                }
+            }
+            TerminalFormat::Kitty => {
+                writeln!(out, "background {bg}\nforeground {fg}").unwrap();
+                for (i, c) in self.colors.iter().enumerate() {
+                    writeln!(out, "color{i} {}", rgb_hex(c)).unwrap();
+                }
+            }
+            TerminalFormat::Foot => {
+                writeln!(out, "[colors]").unwrap();
+                writeln!(out, "background={}\nforeground={}", &bg[1..], &fg[1..]).unwrap();
+                for (kind, colors) in [("regular", normal), ("bright", bright)] {
+                    for (i, c) in colors.iter().enumerate() {
+                        writeln!(out, "{kind}{i}={}", &rgb_hex(c)[1..]).unwrap();
+                    }
+                }
+            }
+            TerminalFormat::Xresources => {
+                writeln!(out, "*.background: {bg}\n*.foreground: {fg}").unwrap();
+                for (i, c) in self.colors.iter().enumerate() {
+                    writeln!(out, "*.color{i}: {}", rgb_hex(c)).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+/// A [base16](https://github.com/tinted-theming/home) scheme. `base00` to `base07` are shades
+/// from the background to the color furthest from it, `base08` to `base0F` the red, orange,
+/// yellow, green, cyan, blue, magenta and brown accents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Base16Scheme {
+    pub name: String,
+    pub author: String,
+    pub colors: [[u8; 4]; 16],
+}
+
+impl Base16Scheme {
+    /// Derives a scheme from the colors of an image. Its most common color sets the tint and the
+    /// darkness of the background, and each accent takes the closest hue among the image's
+    /// saturated colors, or its standard hue if there's none within 30°. Accents and text shades
+    /// are then lightened or darkened to a contrast of at least 4.5 against the background.
+    /// Returns `None` if there are no opaque pixels.
+    pub fn from_pixels(pixels: &[[u8; 4]]) -> Option<Self> {
+        // OkLCh hues of the accents
+        const HUES: [f32; 8] = [29., 55., 105., 142., 195., 260., 328., 50.];
+        // How far each shade is from the background, towards the lightest text
+        const SHADES: [f32; 8] = [0., 0.07, 0.14, 0.38, 0.62, 0.75, 0.87, 1.];
+
+        let extracted = KMeans::default().quantize(pixels, 16);
+        let lch: Vec<[f32; 3]> = extracted
+            .iter()
+            .map(|c| {
+                let [l, a, b] = c.to_oklab();
+                [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.)]
+            })
+            .collect();
+        let &[l, c, h] = lch.first()?;
+        let dark = l < 0.6;
+        let (bg, text) = match dark {
+            true => (0.22, 0.93),
+            false => (0.97, 0.25),
+        };
+        let tint = c.min(0.03);
+
+        let mut colors = [[0; 4]; 16];
+        for (i, t) in SHADES.into_iter().enumerate() {
+            colors[i] = from_lch([bg + (text - bg) * t, tint * (1. - t * 0.6), h]);
+        }
+        let background = colors[0];
+        let saturated: Vec<&[f32; 3]> = lch.iter().filter(|c| c[1] > 0.05).collect();
+        let mut chromas: Vec<f32> = saturated.iter().map(|c| c[1]).collect();
+        chromas.sort_by(f32::total_cmp);
+        let chroma = chromas
+            .get(chromas.len() / 2)
+            .map_or(0.12, |&c| c.clamp(0.08, 0.2));
+        for (i, hue) in HUES.into_iter().enumerate() {
+            let hue_distance = |c: &&&[f32; 3]| {
+                let d = (c[2] - hue).rem_euclid(360.);
+                d.min(360. - d)
+            };
+            let [_, c, h] = match saturated
+                .iter()
+                .min_by(|a, b| hue_distance(a).total_cmp(&hue_distance(b)))
+            {
+                Some(c) if hue_distance(&c) <= 30. => **c,
+                _ => [0., chroma, hue],
+            };
+            let l = match (dark, i) {
+                (true, 7) => 0.58,
+                (true, _) => 0.72,
+                (false, 7) => 0.45,
+                (false, _) => 0.55,
+            };
+            colors[8 + i] = from_lch([l, c.clamp(0.08, 0.2), h]);
+        }
+
+        // Text shades and accents need to be readable on the background
+        for i in (4..8).chain(8..16) {
+            let [mut l, a, b] = palette::oklab(&colors[i]);
+            while palette::contrast(&colors[i], &background) < 4.5 && (0.0..=1.).contains(&l) {
+                l += if dark { 0.01 } else { -0.01 };
+                colors[i] = palette::from_oklab([l, a, b]);
+            }
+        }
+
+        Some(Base16Scheme {
+            name: "Generated".into(),
+            author: "mapped".into(),
+            colors,
+        })
+    }
+
+    pub fn background(&self) -> [u8; 4] {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> [u8; 4] {
+        self.colors[5]
+    }
+
+    /// The scheme in the flat base16 YAML layout.
+    pub fn to_yaml(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "scheme: {}", palette::json_string(&self.name)).unwrap();
+        writeln!(out, "author: {}", palette::json_string(&self.author)).unwrap();
+        for (i, c) in self.colors.iter().enumerate() {
+            writeln!(out, "base0{i:X}: \"{}\"", &rgb_hex(c)[1..]).unwrap();
+        }
+        out
+    }
+
+    /// The scheme as [`Palette::from_base16`] reads it.
+    pub fn to_palette(&self) -> Palette {
+        let palette = Palette::from_base16_colors(self.colors.map(|[r, g, b, _]| [r, g, b]));
+        match (self.name.is_empty(), self.author.is_empty()) {
+            (false, false) => palette.named(&self.name).by(&self.author),
+            (false, true) => palette.named(&self.name),
+            (true, false) => palette.by(&self.author),
+            (true, true) => palette,
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+impl<'a, M: crate::Mapper> crate::Processor<'a, M> {
+    /// Derives a base16 scheme from the loaded image, see [`Base16Scheme::from_pixels`].
+    pub fn base16_scheme(&self) -> Option<Base16Scheme> {
+        let pixels: Vec<[u8; 4]> = self.data.to_rgba8().pixels().map(|p| p.0).collect();
+        Base16Scheme::from_pixels(&pixels)
+    }
+}
+
+// OkLab from lightness, chroma and hue in degrees
+fn from_lch([l, c, h]: [f32; 3]) -> [u8; 4] {
+    let h = h.to_radians();
+    palette::from_oklab([l, c * h.cos(), c * h.sin()])
+}
+
+fn rgb_hex(c: &[u8; 4]) -> String {
+    hex(&[c[0], c[1], c[2], 255])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let colors = [([46, 52, 64, 255], 3), ([236, 239, 244, 204], 1)];
+        assert_eq!(
+            export(&colors, ThemeFormat::Css),
+            ":root {\n  --color-0: #2e3440; /* 75.0% */\n  --color-1: #eceff4cc; /* 25.0% */\n}\n"
+        );
+        assert!(export(&colors, ThemeFormat::Scss).starts_with("$color-0: #2e3440;"));
+        assert!(export(&colors, ThemeFormat::Json)
+            .contains("\"color-1\": { \"value\": \"#eceff4cc\", \"usage\": 0.2500 }\n}"));
+    }
+
+    #[test]
+    fn terminal_scheme() {
+        let scheme = TerminalScheme::from_palette(&crate::palette::NORD).unwrap();
+        assert_eq!(scheme.background, [46, 52, 64, 255]);
+        assert_eq!(scheme.foreground, [236, 239, 244, 255]);
+        assert_eq!(scheme.colors[1], [191, 97, 106, 255]);
+
+        let kitty = scheme.export(TerminalFormat::Kitty);
+        assert!(kitty.starts_with("background #2e3440\nforeground #eceff4\ncolor0 #2e3440\n"));
+        let foot = scheme.export(TerminalFormat::Foot);
+        assert!(foot.contains("\nregular1=bf616a\n"));
+        assert!(TerminalScheme::from_palette(&[]).is_none());
+    }
+
+    #[test]
+    fn terminal_import() {
+        let scheme = TerminalScheme::from_palette(&crate::palette::NORD).unwrap();
+        for format in [
+            TerminalFormat::Alacritty,
+            TerminalFormat::Kitty,
+            TerminalFormat::Foot,
+            TerminalFormat::Xresources,
+        ] {
+            let parsed = TerminalScheme::parse(&scheme.export(format), format).unwrap();
+            assert_eq!(parsed, scheme, "{format:?}");
+        }
+
+        let mut yaml = String::from("colors:\n  primary:\n    background: '0x101010'\n");
+        yaml.push_str("    foreground: '0xfafafa'\n  selection:\n    background: '0x333333'\n");
+        for table in ["normal", "bright"] {
+            yaml.push_str(&format!("  {table}:\n"));
+            for name in ANSI_NAMES {
+                yaml.push_str(&format!("    {name}: '0xcc3333' # {name}\n"));
+            }
+        }
+        let parsed = TerminalScheme::parse(&yaml, TerminalFormat::Alacritty).unwrap();
+        assert_eq!(parsed.background, [16, 16, 16, 255]);
+        assert_eq!(parsed.colors[15], [204, 51, 51, 255]);
+
+        let mut xresources = String::from("! Nord\n#define nord0 #2E3440\n*background: nord0\n");
+        xresources.push_str("URxvt.foreground: #D8DEE9\n");
+        for i in 0..15 {
+            xresources.push_str(&format!("*.color{i}: nord0\n"));
+        }
+        assert!(matches!(
+            TerminalScheme::parse(&xresources, TerminalFormat::Xresources),
+            Err(PaletteError::MissingColor("color15"))
+        ));
+        xresources.push_str("*color15: #ECEFF4\n");
+        let parsed = TerminalScheme::parse(&xresources, TerminalFormat::Xresources).unwrap();
+        assert_eq!(parsed.background, [46, 52, 64, 255]);
+        assert_eq!(parsed.colors[15], [236, 239, 244, 255]);
+
+        let palette = parsed.to_palette();
+        assert_eq!(palette.len(), 17);
+        assert_eq!(palette.color_name(9), Some("bright red"));
+        assert_eq!(palette[15].3, ColorClass::Whites);
+        assert_eq!(palette[1].3, ColorClass::Red);
+        assert_eq!(palette[5].3, ColorClass::Magenta);
+        assert_eq!(palette[14].3, ColorClass::Cyan);
+        assert_eq!(palette.role(Role::Background), Some(palette[0]));
+        assert_eq!(
+            palette.by_name("foreground"),
+            palette.role(Role::Foreground)
+        );
+    }
+
+    #[test]
+    fn base16_from_image() {
+        let mut pixels = vec![[30, 40, 70, 255]; 600];
+        pixels.extend([[200, 60, 50, 255]; 40]);
+        pixels.extend([[90, 170, 80, 255]; 40]);
+        pixels.extend([[10, 10, 10, 0]; 400]);
+        let scheme = Base16Scheme::from_pixels(&pixels).unwrap();
+        let bg = scheme.background();
+        assert!(palette::luma(&bg) < 0.3);
+        assert!(bg[2] > bg[0], "tinted blue: {bg:?}");
+        for c in &scheme.colors[4..] {
+            assert!(palette::contrast(c, &bg) >= 4.5, "{c:?}");
+        }
+        // The red and green accents follow the image's
+        let hue = |c: &[u8; 4]| palette::hsv(c)[0];
+        assert!((hue(&scheme.colors[8]) - hue(&[200, 60, 50, 255])).abs() < 10.);
+        assert!((hue(&scheme.colors[11]) - hue(&[90, 170, 80, 255])).abs() < 10.);
+
+        let palette = scheme.to_palette();
+        assert_eq!(palette.name(), Some("Generated"));
+        assert_eq!(palette.len(), 16);
+        assert_eq!(
+            palette.role(Role::Foreground).map(|c| c.rgba_array()),
+            Some(scheme.foreground())
+        );
+        assert!(scheme.to_yaml().contains("\nbase0F: \""));
+        assert_eq!(Palette::from_base16(&scheme.to_yaml()).unwrap(), palette);
+
+        let quoted = Base16Scheme {
+            name: "Say \"hi\" \\ bye".into(),
+            author: String::new(),
+            ..scheme
+        };
+        let parsed = Palette::from_base16(&quoted.to_yaml()).unwrap();
+        assert_eq!(parsed.name(), Some(quoted.name.as_str()));
+        assert_eq!(parsed, quoted.to_palette());
+
+        let light = Base16Scheme::from_pixels(&[[240, 235, 220, 255]; 10]).unwrap();
+        assert!(palette::luma(&light.background()) > 0.9);
+        assert!(palette::contrast(&light.foreground(), &light.background()) >= 4.5);
+        assert_eq!(Base16Scheme::from_pixels(&[[0, 0, 0, 0]]), None);
+    }
+}
@@ -1,14 +1,35 @@
-use std::{error::Error, time::Instant};
+use std::{error::Error, io::Cursor, time::Instant};
 
-use mapped::{ProcOptions, Threads, KNN};
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use mapped::{
+    analysis,
+    context::PixelContext,
+    mappers::{
+        Bayer, BlueNoiseDither, DiffusionKernel, Dither, GradientMap, Nearest, NoiseSize,
+        OrderedDither, RoleAware, UsageCap,
+    },
+    palette::{self, ColorClass, Palette, Rgbx, Role, Roles, Variant},
+    quantize::{KMeans, MedianCut, QuantizerKind},
+    service::{JobError, Service, Source},
+    Limits, Mapper, ProcError, ProcOptions, ProcessedData, Strategy, Threads,
+};
+
+fn sample() -> Vec<u8> {
+    let img = RgbaImage::from_fn(256, 192, |x, y| {
+        Rgba([x as u8, y as u8, (x as u8).wrapping_add(y as u8), 255])
+    });
+    let mut buf = Cursor::new(Vec::new());
+    img.write_to(&mut buf, ImageOutputFormat::Png).unwrap();
+    buf.into_inner()
+}
 
 #[test]
 fn extreme() -> Result<(), Box<dyn Error>> {
     let i = Instant::now();
     ProcOptions::default()
         .threads(Threads::Extreme)
-        .load("./samples/11.jpg")?
-        .process()?;
+        .load_bytes(&sample())?
+        .process();
     println!("Time elapsed for new API: {}", i.elapsed().as_secs_f64());
     Ok(())
 }
@@ -16,13 +37,12 @@ fn extreme() -> Result<(), Box<dyn Error>> {
 #[test]
 fn tracking() -> Result<(), Box<dyn Error>> {
     let i = Instant::now();
-    let mut opts = ProcOptions::default();
-    opts.threads(Threads::Extreme);
-    let mut p = opts.load("./samples/11.jpg")?;
+    let opts = ProcOptions::default().threads(Threads::Extreme);
+    let mut p = opts.load_bytes(&sample())?;
     let mut track = p.gen_tracker();
     std::thread::scope(|s| {
         s.spawn(move || {
-            p.process().unwrap();
+            p.process();
         });
         loop {
             if track.percentage() == 100.0 {
@@ -42,8 +62,701 @@ fn ray() -> Result<(), Box<dyn Error>> {
     let i = Instant::now();
     ProcOptions::default()
         .threads(Threads::Rayon)
-        .load("./samples/11.jpg")?
-        .process()?;
+        .load_bytes(&sample())?
+        .process();
     println!("Time elapsed for ray API: {}", i.elapsed().as_secs_f64());
     Ok(())
 }
+
+#[test]
+fn premultiplied_texture() -> Result<(), Box<dyn Error>> {
+    let out = ProcOptions::default()
+        .threads(Threads::Single)
+        .load_bytes(&sample())?
+        .process();
+    assert_eq!(out.dimensions(), (256, 192));
+    assert_eq!(out.stride(), 256 * 4);
+    assert_eq!(out.stride() * out.height() as usize, out.buffer_len());
+    assert_eq!(out.to_rgba_premultiplied(), out.raw_buffer());
+
+    let translucent = ProcessedData::from(RgbaImage::from_fn(3, 1, |x, _| {
+        Rgba([200, 100, 50, [128, 0, 255][x as usize]])
+    }));
+    assert_eq!(
+        translucent.to_rgba_premultiplied(),
+        [100, 50, 25, 128, 0, 0, 0, 0, 200, 100, 50, 255]
+    );
+    Ok(())
+}
+
+#[test]
+fn incremental_palette_change() -> Result<(), Box<dyn Error>> {
+    let alt = [
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ];
+    let mut p = ProcOptions::default()
+        .threads(Threads::Single)
+        .load_bytes(&sample())?;
+    assert_eq!(
+        p.process_incremental().raw_buffer(),
+        p.process().raw_buffer()
+    );
+    p.set_palette(Palette::from(&alt));
+    assert_eq!(
+        p.process_incremental().raw_buffer(),
+        p.process().raw_buffer()
+    );
+    // Every thread setting renders the same, dithered or not
+    let custom = Threads::Custom(std::num::NonZeroUsize::new(3).unwrap().into());
+    for threads in [Threads::Single, custom, Threads::Rayon] {
+        let opts = ProcOptions::default().threads(threads);
+        let mut p = opts.clone().load_bytes(&sample())?;
+        assert_eq!(
+            p.process_incremental().raw_buffer(),
+            p.process().raw_buffer()
+        );
+        let ordered = OrderedDither::new(Nearest::new(), Bayer::X4);
+        let mut p = opts.mapper(ordered).load_bytes(&sample())?;
+        assert_eq!(
+            p.process_incremental().raw_buffer(),
+            p.process().raw_buffer()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn multi_palette() -> Result<(), Box<dyn Error>> {
+    let alt = [
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ];
+    let mut p = ProcOptions::default().load_bytes(&sample())?;
+    let alt = Palette::from(&alt);
+    let out = p.process_multi(&[&Palette::nord(), &alt]);
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0].raw_buffer(), p.process().raw_buffer());
+    p.set_palette(&alt);
+    assert_eq!(out[1].raw_buffer(), p.process().raw_buffer());
+    let mut single = ProcOptions::default()
+        .threads(Threads::Single)
+        .load_bytes(&sample())?;
+    assert_eq!(
+        single.process_multi(&[&alt])[0].raw_buffer(),
+        out[1].raw_buffer()
+    );
+    Ok(())
+}
+
+#[test]
+fn comparison_grid() -> Result<(), Box<dyn Error>> {
+    let alt = Palette::from([
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ]);
+    let grid = ProcOptions::default()
+        .load_bytes(&sample())?
+        .compare_palettes(&[("nord", &Palette::nord()), ("mono", &alt)], 2);
+    assert_eq!(grid.width(), 2 * 256 + 3 * 8);
+    assert!(grid.height() > 192);
+    Ok(())
+}
+
+#[test]
+fn job_service() {
+    let service: Service = Service::new(2);
+    let ok = service.submit(
+        Source::Bytes(sample()),
+        ProcOptions::default().threads(Threads::Single),
+    );
+    let bad = service.submit(Source::Bytes(vec![1, 2, 3]), ProcOptions::default());
+    let direct = ProcOptions::default()
+        .load_bytes(&sample())
+        .unwrap()
+        .process();
+    assert_eq!(
+        service.wait(ok).unwrap().unwrap().raw_buffer(),
+        direct.raw_buffer()
+    );
+    assert!(service.wait(bad).unwrap().is_err());
+    assert_eq!(service.status(ok), None);
+
+    // Palettes made at runtime, owned or borrowed
+    let mono = Palette::from([
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ]);
+    let borrowed = ProcOptions::default().palette(&mono);
+    let direct = borrowed.clone().load_bytes(&sample()).unwrap().process();
+    let owned = service.submit(Source::Bytes(sample()), borrowed.into_owned());
+    let moved = service.submit(
+        Source::Bytes(sample()),
+        ProcOptions::default().palette(mono.clone()),
+    );
+    for job in [owned, moved] {
+        assert_eq!(
+            service.wait(job).unwrap().unwrap().raw_buffer(),
+            direct.raw_buffer()
+        );
+    }
+}
+
+#[derive(Clone)]
+struct Panicking;
+
+impl Mapper for Panicking {
+    fn predict(&self, _: &[Rgbx], _: &[u8; 4]) -> [u8; 4] {
+        panic!("no palette today")
+    }
+}
+
+#[test]
+fn job_service_panic() {
+    let service: Service<Panicking> = Service::new(1);
+    let opts = ProcOptions::default().mapper(Panicking);
+    let first = service.submit(Source::Bytes(sample()), opts.clone());
+    let second = service.submit(Source::Bytes(sample()), opts);
+    assert!(matches!(
+        service.wait(first).unwrap(),
+        Err(JobError::Panicked(_))
+    ));
+    // The worker survived to pick up the next job
+    assert!(service.wait(second).unwrap().is_err());
+}
+
+#[test]
+fn memory_limit() -> Result<(), Box<dyn Error>> {
+    let full = ProcOptions::default().load_bytes(&sample())?;
+    let limited = ProcOptions::default()
+        .memory_limit(256 * 192 * 10)
+        .load_bytes(&sample())?;
+    assert_eq!(full.strategy(), Strategy::InMemory);
+    assert!(matches!(limited.strategy(), Strategy::Streaming { .. }));
+    assert_eq!(full.process().raw_buffer(), limited.process().raw_buffer());
+
+    let err = ProcOptions::default()
+        .memory_limit(1024)
+        .load_bytes(&sample())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<ProcError>(),
+        Some(ProcError::MemoryLimit { limit: 1024, .. })
+    ));
+
+    // Wider pixels cost more, and dithering can't be streamed
+    let img = image::load_from_memory(&sample())?;
+    let mut wide = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba16(img.to_rgba16()).write_to(&mut wide, ImageOutputFormat::Png)?;
+    let limited = || ProcOptions::default().memory_limit(256 * 192 * 10);
+    assert!(limited().load_bytes(wide.get_ref()).is_err());
+    assert!(limited()
+        .mapper(Dither::default())
+        .load_bytes(&sample())
+        .is_err());
+    assert!(matches!(
+        limited().try_load_image(img.clone()).map(|p| p.strategy()),
+        Ok(Strategy::Streaming { .. })
+    ));
+    assert!(matches!(
+        ProcOptions::default()
+            .memory_limit(1024)
+            .try_load_image(img),
+        Err(ProcError::MemoryLimit { limit: 1024, .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn dimension_limits() -> Result<(), Box<dyn Error>> {
+    let limits = Limits::default().max_width(128).max_pixels(10_000);
+    let err = ProcOptions::default()
+        .limits(limits)
+        .load_bytes(&sample())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<ProcError>(),
+        Some(&ProcError::DimensionLimit {
+            width: 256,
+            height: 192
+        })
+    );
+
+    let out = ProcOptions::default()
+        .limits(limits.downscale(true))
+        .load_bytes(&sample())?
+        .process();
+    assert!(limits.allows(out.width(), out.height()));
+
+    // Downscaling still refuses to decode images past the decoding bound
+    let err = ProcOptions::default()
+        .limits(limits.downscale(true).max_decoded_pixels(40_000))
+        .load_bytes(&sample())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<ProcError>(),
+        Some(ProcError::DimensionLimit { width: 256, .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn darkest_to_background() -> Result<(), Box<dyn Error>> {
+    let bg = palette::NORD[2];
+    let threshold = ProcOptions::default()
+        .load_bytes(&sample())?
+        .luma_quantile(0.1);
+    let mapper = RoleAware::new(Nearest::new(), Roles::new().assign(Role::Background, bg))
+        .darkest(threshold, Role::Background);
+    let out = ProcOptions::new(mapper).load_bytes(&sample())?.process();
+
+    let total = out.raw_buffer().len() / 4;
+    let dark = out
+        .raw_buffer()
+        .chunks(4)
+        .filter(|px| *px == bg.rgba_array())
+        .count();
+    assert!(dark * 10 >= total && dark * 5 < total);
+    Ok(())
+}
+
+#[test]
+fn palette_remap() -> Result<(), Box<dyn Error>> {
+    let inverted = palette::NORD.map(|c| Rgbx(255 - c.0, 255 - c.1, 255 - c.2, c.3));
+    let out = ProcOptions::default().load_bytes(&sample())?.process();
+    let mut remapped = ProcOptions::default().load_bytes(&sample())?.process();
+
+    remapped.remap(&palette::NORD, &inverted);
+    assert!(remapped
+        .raw_buffer()
+        .chunks(4)
+        .all(|px| inverted.iter().any(|c| c.rgba_array() == px)));
+    remapped.remap(&inverted, &palette::NORD);
+    assert_eq!(remapped.raw_buffer(), out.raw_buffer());
+    Ok(())
+}
+
+#[test]
+fn digest() -> Result<(), Box<dyn Error>> {
+    let opts = ProcOptions::default();
+    let a = opts.clone().load_bytes(&sample())?.process();
+    let b = opts.clone().load_bytes(&sample())?.process();
+    assert_eq!(a.digest(), b.digest());
+    assert_eq!(a.digest(), ProcessedData::from(a.to_image()).digest());
+    let c = opts
+        .clone()
+        .palette(Palette::from(&palette::NORD[..8]))
+        .load_bytes(&sample())?
+        .process();
+    assert_ne!(a.digest(), c.digest());
+
+    assert_eq!(
+        opts.fingerprint(),
+        ProcOptions::default().threads(Threads::Rayon).fingerprint()
+    );
+    assert_ne!(
+        opts.fingerprint(),
+        opts.clone().mapper(Nearest::new().memoized()).fingerprint()
+    );
+    assert_ne!(
+        opts.fingerprint(),
+        opts.clone()
+            .palette(Palette::from(&palette::NORD[..8]))
+            .fingerprint()
+    );
+    Ok(())
+}
+
+#[test]
+fn dithering() {
+    let bw = [
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ];
+    let grey =
+        || DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 64, Rgba([128, 128, 128, 255])));
+
+    let flat = ProcOptions::default()
+        .palette(Palette::from(&bw))
+        .load_image(grey())
+        .process();
+    assert_eq!(flat.used_colors().len(), 1);
+
+    let mut proc = ProcOptions::new(Dither::default())
+        .palette(Palette::from(&bw))
+        .load_image(grey());
+    let dithered = proc.process();
+    let white = dithered
+        .raw_buffer()
+        .chunks(4)
+        .filter(|px| px[0] == 255)
+        .count();
+    assert!((white as f32 / (64. * 64.) - 0.5).abs() < 0.02);
+    assert_eq!(proc.process_incremental().digest(), dithered.digest());
+
+    let stucki = ProcOptions::new(Dither::default().kernel(DiffusionKernel::Stucki))
+        .palette(Palette::from(&bw))
+        .load_image(grey())
+        .process();
+    let white = stucki
+        .raw_buffer()
+        .chunks(4)
+        .filter(|px| px[0] == 255)
+        .count();
+    assert!((white as f32 / (64. * 64.) - 0.5).abs() < 0.02);
+    assert_ne!(stucki.digest(), dithered.digest());
+
+    let serpentine = ProcOptions::new(Dither::default().serpentine(true))
+        .palette(Palette::from(&bw))
+        .load_image(grey())
+        .process();
+    let white = serpentine
+        .raw_buffer()
+        .chunks(4)
+        .filter(|px| px[0] == 255)
+        .count();
+    assert!((white as f32 / (64. * 64.) - 0.5).abs() < 0.02);
+    assert_ne!(serpentine.digest(), dithered.digest());
+
+    let mut proc =
+        ProcOptions::new(Dither::default().kernel(DiffusionKernel::Riemersma { history: 16 }))
+            .palette(Palette::from(&bw))
+            .load_image(grey());
+    let hilbert = proc.process();
+    let white = hilbert
+        .raw_buffer()
+        .chunks(4)
+        .filter(|px| px[0] == 255)
+        .count();
+    assert!((white as f32 / (64. * 64.) - 0.5).abs() < 0.02);
+    assert_eq!(proc.process_incremental().digest(), hilbert.digest());
+}
+
+#[test]
+fn ordered_dithering() {
+    let bw = [
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ];
+    let grey =
+        || DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 64, Rgba([128, 128, 128, 255])));
+    let mapper = OrderedDither::new(Nearest::new(), Bayer::X4).spread(255.);
+
+    let out = ProcOptions::new(mapper.clone())
+        .palette(Palette::from(&bw))
+        .load_image(grey())
+        .process();
+    let white = out.raw_buffer().chunks(4).filter(|px| px[0] == 255).count();
+    assert_eq!(white, 64 * 64 / 2);
+
+    for threads in [Threads::Rayon, Threads::Extreme] {
+        let threaded = ProcOptions::new(mapper.clone().memoized())
+            .threads(threads)
+            .palette(Palette::from(&bw))
+            .load_image(grey())
+            .process();
+        assert_eq!(threaded.digest(), out.digest());
+    }
+    let mut proc = ProcOptions::new(mapper.clone())
+        .palette(Palette::from(&bw))
+        .load_image(grey());
+    assert_eq!(proc.process_incremental().digest(), out.digest());
+
+    let noise = ProcOptions::new(BlueNoiseDither::new(Nearest::new(), NoiseSize::X32).spread(255.))
+        .palette(Palette::from(&bw))
+        .load_image(grey())
+        .process();
+    let white = noise
+        .raw_buffer()
+        .chunks(4)
+        .filter(|px| px[0] == 255)
+        .count();
+    assert!((white as f32 / (64. * 64.) - 0.5).abs() < 0.02);
+}
+
+#[test]
+fn dithering_strength() -> Result<(), Box<dyn Error>> {
+    fn digest<M: Mapper>(mapper: M) -> Result<u64, Box<dyn Error>> {
+        Ok(ProcOptions::new(mapper)
+            .load_bytes(&sample())?
+            .process()
+            .digest())
+    }
+    let flat = digest(Nearest::new())?;
+
+    assert_eq!(digest(Dither::default().strength(0.))?, flat);
+    assert_eq!(
+        digest(OrderedDither::new(Nearest::new(), Bayer::X8).strength(0.))?,
+        flat
+    );
+    let noise = BlueNoiseDither::new(Nearest::new(), NoiseSize::X64);
+    assert_eq!(digest(noise.clone().strength(0.))?, flat);
+    assert_ne!(digest(noise.strength(0.5))?, flat);
+    Ok(())
+}
+
+#[test]
+fn tiled_dithering() -> Result<(), Box<dyn Error>> {
+    let mapper = Dither::default().tiled(32, 8);
+    let single = ProcOptions::new(mapper.clone())
+        .threads(Threads::Single)
+        .load_bytes(&sample())?
+        .process();
+    let untiled = ProcOptions::new(Dither::default())
+        .load_bytes(&sample())?
+        .process();
+    assert_eq!(single.digest(), untiled.digest());
+
+    let tiled = ProcOptions::new(mapper)
+        .threads(Threads::Rayon)
+        .load_bytes(&sample())?
+        .process();
+    assert_eq!(tiled.buffer_len(), single.buffer_len());
+    // Only rows blended across band boundaries may differ
+    let row = tiled.stride();
+    for (y, (a, b)) in tiled
+        .raw_buffer()
+        .chunks(row)
+        .zip(single.raw_buffer().chunks(row))
+        .enumerate()
+    {
+        if y < 32 - 8 {
+            assert_eq!(a, b);
+        }
+    }
+    assert_ne!(tiled.digest(), single.digest());
+    Ok(())
+}
+
+#[test]
+fn context_mapper() -> Result<(), Box<dyn Error>> {
+    // Maps every pixel to the nearest color of its left neighbour
+    #[derive(Clone)]
+    struct Shift;
+
+    impl Mapper for Shift {
+        fn predict(&self, palette: &[Rgbx], pixel: &[u8; 4]) -> [u8; 4] {
+            Nearest::new().predict(palette, pixel)
+        }
+
+        fn uses_context(&self) -> bool {
+            true
+        }
+
+        fn predict_context(&self, palette: &[Rgbx], ctx: &PixelContext<'_>) -> [u8; 4] {
+            self.predict(palette, ctx.neighbor(-1, 0).unwrap_or(ctx.pixel()))
+        }
+    }
+
+    let plain = ProcOptions::default().load_bytes(&sample())?.process();
+    for threads in [Threads::Single, Threads::Rayon] {
+        let shifted = ProcOptions::new(Shift.memoized())
+            .threads(threads)
+            .load_bytes(&sample())?
+            .process();
+        for (a, b) in shifted
+            .raw_buffer()
+            .chunks(shifted.stride())
+            .zip(plain.raw_buffer().chunks(plain.stride()))
+        {
+            assert_eq!(a[4..], b[..b.len() - 4]);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn memoized_palettes() -> Result<(), Box<dyn Error>> {
+    let mono = [
+        Rgbx(0, 0, 0, ColorClass::Greys),
+        Rgbx(255, 255, 255, ColorClass::Whites),
+    ];
+    let (nord, mono) = (Palette::nord(), Palette::from(&mono));
+    let palettes = [&nord, &mono];
+    let mut plain = ProcOptions::default().load_bytes(&sample())?;
+    let expected = plain.process_multi(&palettes);
+    assert_ne!(expected[0].digest(), expected[1].digest());
+
+    let mut memoized = ProcOptions::new(Nearest::new().memoized()).load_bytes(&sample())?;
+    let multi = memoized.process_multi(&palettes);
+    memoized.set_palette(&mono);
+    let switched = memoized.process();
+    assert_eq!(multi[0].digest(), expected[0].digest());
+    assert_eq!(multi[1].digest(), expected[1].digest());
+    assert_eq!(switched.digest(), expected[1].digest());
+    Ok(())
+}
+
+#[test]
+fn capped_dither() {
+    // Mostly reds, with a few greys
+    let img = RgbaImage::from_fn(100, 20, |x, _| match x {
+        0..=89 => Rgba([180 + (x % 30) as u8, 90, 100, 255]),
+        _ => Rgba([60, 65, 80, 255]),
+    });
+    let red = palette::NORD[7].rgba_array();
+    let reds = |data: &ProcessedData| {
+        data.raw_buffer()
+            .chunks_exact(4)
+            .filter(|px| **px == red)
+            .count()
+    };
+    for kernel in [
+        DiffusionKernel::FloydSteinberg,
+        DiffusionKernel::Riemersma { history: 16 },
+    ] {
+        let dither = Dither::new(Nearest::new()).kernel(kernel);
+        let plain = ProcOptions::new(dither.clone())
+            .load_image(DynamicImage::ImageRgba8(img.clone()))
+            .process();
+        assert!(reds(&plain) > 1000);
+        let capped = ProcOptions::new(UsageCap::new(dither).cap(7, 0.2))
+            .load_image(DynamicImage::ImageRgba8(img.clone()))
+            .process();
+        assert!(reds(&capped) <= 400, "{}", reds(&capped));
+    }
+}
+
+#[test]
+fn excluded_targets() -> Result<(), Box<dyn Error>> {
+    let banned = [palette::NORD[7], palette::NORD[9]];
+    let allowed: Vec<Rgbx> = palette::NORD
+        .iter()
+        .filter(|c| !banned.contains(c))
+        .copied()
+        .collect();
+    let opts = ProcOptions::default()
+        .exclude_index(7)
+        .exclude_class(ColorClass::Yellow);
+    let mut p = opts.clone().load_bytes(&sample())?;
+    let out = p.process();
+    assert_eq!(
+        out.raw_buffer(),
+        ProcOptions::default()
+            .palette(Palette::from(&allowed[..]))
+            .load_bytes(&sample())?
+            .process()
+            .raw_buffer()
+    );
+    assert_eq!(p.process_incremental().raw_buffer(), out.raw_buffer());
+    let dithered = opts
+        .clone()
+        .mapper(Dither::default())
+        .load_bytes(&sample())?
+        .process();
+    for (color, _) in dithered.used_colors() {
+        assert!(banned.iter().all(|c| c.rgba_array() != color));
+    }
+    // Interpolating mappers only ever see the allowed colors
+    assert_eq!(
+        opts.mapper(GradientMap::default())
+            .load_bytes(&sample())?
+            .process()
+            .raw_buffer(),
+        ProcOptions::new(GradientMap::default())
+            .palette(Palette::from(&allowed[..]))
+            .load_bytes(&sample())?
+            .process()
+            .raw_buffer()
+    );
+    assert_ne!(
+        ProcOptions::default().fingerprint(),
+        ProcOptions::default().exclude_index(7).fingerprint()
+    );
+    Ok(())
+}
+
+#[test]
+fn palette_type() -> Result<(), Box<dyn Error>> {
+    let nord = Palette::nord();
+    assert_eq!(nord.to_string(), "Nord by Arctic Ice Studio");
+    assert_eq!(nord.by_name("NORD11"), Some(palette::NORD[7]));
+    assert_eq!(nord.color_name(12), Some("nord0"));
+    assert_eq!(Palette::from(&palette::NORD[..]).colors(), nord.colors());
+    assert_eq!(
+        ProcOptions::default()
+            .palette(&nord)
+            .load_bytes(&sample())?
+            .process()
+            .digest(),
+        ProcOptions::default()
+            .load_bytes(&sample())?
+            .process()
+            .digest()
+    );
+    Ok(())
+}
+
+#[test]
+fn palette_variants() {
+    let dark = palette::by_name("gruvbox-dark").unwrap();
+    let light = palette::by_name("gruvbox-light").unwrap();
+    let gruvbox = light.clone().with_variant(dark.clone());
+    let image = |v| {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |x, _| {
+            Rgba([v, v, x as u8 * 20, 255])
+        }))
+    };
+    let digest = |opts: ProcOptions, img| opts.load_image(img).process().digest();
+
+    for (v, expected) in [(30, dark), (220, light)] {
+        let auto = ProcOptions::default()
+            .palette(&gruvbox)
+            .variant(Variant::Auto);
+        let plain = ProcOptions::default().palette(expected);
+        assert_eq!(digest(auto, image(v)), digest(plain, image(v)));
+    }
+    let forced = ProcOptions::default()
+        .palette(&gruvbox)
+        .variant(Variant::Dark);
+    let plain = ProcOptions::default().palette(dark);
+    assert_eq!(digest(forced, image(220)), digest(plain, image(220)));
+    // Palettes without linked variants are left as they are
+    let unthemed = ProcOptions::default()
+        .palette(Palette::new(light.colors()))
+        .variant(Variant::Dark);
+    let plain = ProcOptions::default().palette(light);
+    assert_eq!(digest(unthemed, image(30)), digest(plain, image(30)));
+
+    // Built-in flavors come linked, and themed roles follow the variant being mapped to
+    assert_eq!(dark.variant(Variant::Light).name(), Some("Gruvbox Light"));
+    let frappe = palette::by_name("catppuccin-frappe").unwrap();
+    assert_eq!(
+        frappe.variant(Variant::Light).name(),
+        Some("Catppuccin Latte")
+    );
+    let mapper = RoleAware::themed(Nearest::new(), dark);
+    let bg = |p: &Palette| p.role(Role::Background).unwrap().rgba_array();
+    assert_eq!(mapper.predict(dark, &[10, 10, 10, 255]), bg(dark));
+    assert_eq!(mapper.predict(light, &[250, 250, 250, 255]), bg(light));
+}
+
+#[test]
+fn palette_extraction() -> Result<(), Box<dyn Error>> {
+    let source = ProcOptions::default().load_bytes(&sample())?;
+    let extracted = source.extract_palette(&MedianCut, 8);
+    assert_eq!(extracted.len(), 8);
+    assert_eq!(source.extract_palette(&KMeans::default(), 8).len(), 8);
+    assert!(source.extract_palette(&QuantizerKind::Octree, 8).len() <= 8);
+    let mapped = ProcOptions::default()
+        .palette(&extracted)
+        .load_bytes(&sample())?
+        .process();
+    assert!(mapped.used_colors().len() <= 8);
+    Ok(())
+}
+
+#[test]
+fn dominant_colors() {
+    let img = RgbaImage::from_fn(10, 10, |x, _| match x {
+        0..=6 => Rgba([46, 52, 64, 255]),
+        _ => Rgba([191, 97, 106, 255]),
+    });
+    let colors = analysis::dominant_colors(&DynamicImage::ImageRgba8(img), 4);
+    assert_eq!(colors.len(), 2);
+    assert_eq!(colors[0].color, palette::NORD[12]);
+    assert!((colors[0].share - 70.).abs() < 1e-3);
+}